@@ -0,0 +1,58 @@
+//! 日期换算
+
+/// 公历UTC时间转Unix时间戳
+pub fn to_unix_timestamp(year: u32, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> u64 {
+    // 把3月当作一年的第一个月，闰日就落在年末
+    let (year, month) = if month <= 2 { (year as i64 - 1, month as i64 + 9) } else { (year as i64, month as i64 - 3) };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    (days * 86400 + hour as i64 * 3600 + minute as i64 * 60 + second as i64) as u64
+}
+
+/// CMOS里的BCD码转二进制
+pub fn bcd_to_binary(value: u8) -> u8 {
+    (value & 0x0F) + ((value / 16) * 10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unix_timestamp_known_dates() {
+        assert_eq!(to_unix_timestamp(1970, 1, 1, 0, 0, 0), 0);
+        assert_eq!(to_unix_timestamp(2000, 3, 1, 0, 0, 0), 951868800);
+        assert_eq!(to_unix_timestamp(2023, 5, 30, 12, 34, 56), 1685450096);
+        assert_eq!(to_unix_timestamp(2024, 2, 29, 23, 59, 59), 1709251199);
+        // 2100年不是闰年
+        assert_eq!(to_unix_timestamp(2100, 3, 1, 0, 0, 0) - to_unix_timestamp(2100, 2, 28, 0, 0, 0), 86400);
+    }
+
+    #[test]
+    fn unix_timestamp_counts_every_day() {
+        // 逐日累加和直接换算一致，覆盖闰年和跨年
+        let mut expected = to_unix_timestamp(1999, 1, 1, 0, 0, 0);
+        for year in 1999..2030 {
+            let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+            let days = [31, if leap { 29 } else { 28 }, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+            for (month, len) in days.iter().enumerate() {
+                for day in 1..=*len {
+                    assert_eq!(to_unix_timestamp(year, month as u8 + 1, day, 0, 0, 0), expected);
+                    expected += 86400;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bcd_digits() {
+        assert_eq!(bcd_to_binary(0x00), 0);
+        assert_eq!(bcd_to_binary(0x09), 9);
+        assert_eq!(bcd_to_binary(0x10), 10);
+        assert_eq!(bcd_to_binary(0x59), 59);
+        assert_eq!(bcd_to_binary(0x99), 99);
+    }
+}
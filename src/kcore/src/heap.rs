@@ -0,0 +1,841 @@
+//! 空闲链表分配器
+//!
+//! 本文件由phil-opp.com的版本修改而来。
+//! 改进了空闲区块的排序原则，并增加了dealloc时碎片区块的合并。
+//!
+//! 这里只管链表本身，堆从哪里来由使用者决定：内核堆一次给足，用户堆不够时通过`RegionProvider`映射新的页面
+//!
+//! 使用者愿意通过句柄间接访问的分配可以交给[`LinkedListAllocator::compact`]搬动，把空闲空间挤到一起，
+//! 再用[`LinkedListAllocator::release_tail`]把区域结尾空出来的整页拿走
+
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::{fmt, mem};
+
+/// 把`addr`向上对齐到`align`，`align`必须是2的幂
+pub fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+/// 堆不够用时提供新内存的一方
+pub trait RegionProvider {
+    /// 提供一段至少`min_size`字节的新内存，返回（起始地址，大小），提供不了时返回`None`
+    fn provide(&mut self, min_size: usize) -> Option<(usize, usize)>;
+}
+
+/// 可以搬动的分配，使用者通过句柄找到它，压缩时`ptr`会被改写成新的地址
+///
+/// 按`repr(C)`排列，用户程序的句柄表可以原样交给内核
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Movable {
+    pub ptr: usize,
+    pub size: usize,
+    pub align: usize,
+}
+
+/// 压缩失败的原因，失败时什么都没有搬动
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactError {
+    /// 第几项的布局不合法，或者和空闲区域、别的项重叠
+    BadEntry(usize),
+}
+
+struct ListNode {
+    size: usize,
+    next: Option<&'static mut ListNode>,
+}
+
+impl ListNode {
+    const fn new(size: usize) -> Self {
+        ListNode { size, next: None }
+    }
+
+    fn start_addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn end_addr(&self) -> usize {
+        self.start_addr() + self.size
+    }
+}
+
+pub struct LinkedListAllocator {
+    head: ListNode,
+    size: usize,
+    allocated: usize,
+}
+
+impl fmt::Debug for LinkedListAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LinkedListAllocator [size: {} allocated: {}]", self.size, self.allocated)
+    }
+}
+
+impl Default for LinkedListAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LinkedListAllocator {
+    pub const fn new() -> Self {
+        Self {
+            head: ListNode::new(0),
+            size: 0,
+            allocated: 0,
+        }
+    }
+
+    /// 根据给定堆区间范围初始化
+    ///
+    /// # Safety
+    ///
+    /// 很显然，这个方法是不安全的，因为给定的区间需要确保未被使用，此外这个函数也不能被多次调用
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.add_free_region(heap_start, heap_size);
+        self.size = heap_size;
+    }
+
+    /// 堆的总大小
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// 已经分配出去的大小
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// 将指定的内存区域增加到链表中
+    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
+        // 确保这个空闲区域和链表是适配的
+        assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
+        assert!(size >= mem::size_of::<ListNode>());
+
+        // 先寻找左右两边是否也为空闲
+
+        let (mut left_found, mut right_found) = (false, false);
+        let (mut addr, mut size) = (addr, size);
+        let mut current = &mut self.head;
+        while let Some(ref mut region) = current.next {
+            if !left_found && region.end_addr() == addr {
+                left_found = true;
+                addr = region.start_addr();
+                size += region.size;
+                current.next = region.next.take();
+            } else if !right_found && region.start_addr() == addr + size {
+                right_found = true;
+                size += region.size;
+                current.next = region.next.take();
+            } else if left_found && right_found {
+                break;
+            } else {
+                current = current.next.as_mut().unwrap();
+            }
+        }
+
+        // 创建一个新的链表节点，并把它加入到合适位置（按照大小从大到小排序）
+        let mut node = ListNode::new(size);
+        let mut current = &mut self.head;
+        while let Some(ref mut region) = current.next {
+            if region.size <= node.size {
+                break;
+            }
+            current = current.next.as_mut().unwrap();
+        }
+        node.next = current.next.take();
+        let node_ptr = addr as *mut ListNode;
+        node_ptr.write(node);
+        current.next = Some(&mut *node_ptr);
+    }
+
+    /// 寻找一个满足给定大小的空闲区域，并把它从链表中移除
+    ///
+    /// 返回列表节点和可用区域的起始地址
+    fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
+        let mut current = &mut self.head;
+        // 寻找足够的空间
+        while let Some(ref mut region) = current.next {
+            if let Ok(alloc_start) = Self::alloc_from_region(region, size, align) {
+                // 找到了，返回
+                let next = region.next.take();
+                let ret = Some((current.next.take().unwrap(), alloc_start));
+                current.next = next;
+                return ret;
+            } else {
+                // 这个区域不够大，继续往下找
+                current = current.next.as_mut().unwrap();
+            }
+        }
+
+        // 找不到啊找不到，因为你找不到
+        None
+    }
+
+    /// 尝试使用给定区域进行具有给定大小和对齐方式的分配
+    ///
+    /// 为了对齐跳过的前部空间会作为新的空闲区域还回链表，因此它要么为空，要么能容纳一个ListNode
+    ///
+    /// 成功时返回分配起始地址
+    fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
+        let mut alloc_start = align_up(region.start_addr(), align);
+        let padding = alloc_start - region.start_addr();
+        if padding > 0 && padding < mem::size_of::<ListNode>() {
+            // 前部空间不足以容纳一个ListNode，跳到下一个对齐的位置
+            alloc_start = align_up(region.start_addr() + mem::size_of::<ListNode>(), align);
+        }
+        let alloc_end = alloc_start.checked_add(size).ok_or(())?;
+
+        if alloc_end > region.end_addr() {
+            // 这个区域不够大
+            return Err(());
+        }
+
+        let excess_size = region.end_addr() - alloc_end;
+        if excess_size > 0 && excess_size < mem::size_of::<ListNode>() {
+            // 剩余空间不足以容纳一个ListNode
+            return Err(());
+        }
+
+        Ok(alloc_start)
+    }
+
+    /// 调整给出的布局，使得其内存区域也能满足存储一个链表节点的需求
+    ///
+    /// 返回调整后的布局大小和对齐方式
+    fn size_align(layout: Layout) -> (usize, usize) {
+        let layout = layout
+            .align_to(mem::align_of::<ListNode>())
+            .expect("adjusting alignment failed")
+            .pad_to_align();
+        let size = layout.size().max(mem::size_of::<ListNode>());
+        (size, layout.align())
+    }
+
+    /// 按布局分配，找不到合适的空闲区域时返回空指针
+    ///
+    /// # Safety
+    ///
+    /// 堆里的区间必须一直有效且只归这个分配器管理
+    pub unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        // 进行布局调整
+        let (size, align) = LinkedListAllocator::size_align(layout);
+
+        if let Some((region, alloc_start)) = self.find_region(size, align) {
+            // 找到了，进行分配
+            let alloc_end = alloc_start.checked_add(size).expect("overflow");
+            let (region_start, region_end) = (region.start_addr(), region.end_addr());
+            let excess_size = region_end - alloc_end;
+            if excess_size > 0 {
+                // 有剩余空间，把它加入到链表中
+                self.add_free_region(alloc_end, excess_size);
+            }
+            if alloc_start > region_start {
+                // 为了对齐跳过的前部空间也要还回链表
+                self.add_free_region(region_start, alloc_start - region_start);
+            }
+            self.allocated += layout.size();
+            alloc_start as *mut u8
+        } else {
+            // 没找到，返回空指针
+            core::ptr::null_mut()
+        }
+    }
+
+    /// 分配，空闲空间比要分配的大小还少时，先向`provider`要一段新内存
+    ///
+    /// # Safety
+    ///
+    /// 同[`alloc`](Self::alloc)，另外`provider`给出的区间必须未被使用、且按`ListNode`对齐
+    pub unsafe fn alloc_or_grow(&mut self, layout: Layout, provider: &mut impl RegionProvider) -> *mut u8 {
+        if self.free_space() < layout.size() {
+            match provider.provide(layout.size() - self.free_space()) {
+                Some((start, size)) => self.grow(start, size),
+                None => return core::ptr::null_mut(),
+            }
+        }
+        self.alloc(layout)
+    }
+
+    /// 释放，和左右相邻的空闲区域合并
+    ///
+    /// # Safety
+    ///
+    /// `ptr`必须是这个分配器用同一个`layout`分配出去、还没有释放的
+    pub unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = LinkedListAllocator::size_align(layout);
+
+        self.add_free_region(ptr as usize, size);
+        self.allocated -= layout.size();
+    }
+
+    /// 生长，在已有的基础上生长一定的长度
+    ///
+    /// # Safety
+    ///
+    /// 给定的区间必须未被使用、且按`ListNode`对齐
+    pub unsafe fn grow(&mut self, heap_start: usize, heap_size: usize) {
+        self.add_free_region(heap_start, heap_size);
+        self.size += heap_size
+    }
+
+    pub fn free_space(&self) -> usize {
+        self.size - self.allocated
+    }
+
+    /// 把可以搬动的分配往低地址挪，让空闲空间合并成尽量少、尽量靠后的大块，返回搬动的字节数
+    ///
+    /// 每个区块只会滑进紧挨在它前面的空闲空间，不在`movable`里的分配原地不动，空闲空间只能在它们之间合并。
+    /// 中间要分配临时的表，所以不能用在背后支撑着全局分配器的那个堆上
+    ///
+    /// # Safety
+    ///
+    /// `movable`里的每一项都必须是这个分配器按同样的布局分配出去、还没有释放的，搬动之后不能再通过旧地址访问
+    pub unsafe fn compact(&mut self, movable: &mut [Movable]) -> Result<usize, CompactError> {
+        // 空闲区域和可搬动的区块按地址排好：（起始地址，占用大小，句柄表里的下标）
+        let mut items: Vec<(usize, usize, Option<usize>)> = Vec::new();
+        let mut next = self.head.next.as_deref();
+        while let Some(node) = next {
+            items.push((node.start_addr(), node.size, None));
+            next = node.next.as_deref();
+        }
+        for (index, block) in movable.iter().enumerate() {
+            let layout = Layout::from_size_align(block.size, block.align).map_err(|_| CompactError::BadEntry(index))?;
+            let (size, align) = Self::size_align(layout);
+            if block.ptr == 0 || block.ptr % align != 0 || block.ptr.checked_add(size).is_none() {
+                return Err(CompactError::BadEntry(index));
+            }
+            items.push((block.ptr, size, Some(index)));
+        }
+        items.sort_unstable_by_key(|(start, _, _)| *start);
+        for pair in items.windows(2) {
+            if pair[0].0 + pair[0].1 > pair[1].0 {
+                let index = pair[1].2.or(pair[0].2).unwrap_or_default();
+                return Err(CompactError::BadEntry(index));
+            }
+        }
+
+        // 从低到高扫一遍，`hole`是刚刚扫过的一段连续空闲空间
+        let mut free = Vec::new();
+        let mut hole: Option<(usize, usize)> = None;
+        let mut moved = 0;
+        for (start, size, index) in items {
+            let end = start + size;
+            hole = match (hole, index) {
+                (Some((hole_start, hole_end)), None) if hole_end == start => Some((hole_start, end)),
+                (Some((hole_start, hole_end)), Some(index)) if hole_end == start => {
+                    let block = &mut movable[index];
+                    let (_, align) = Self::size_align(Layout::from_size_align_unchecked(block.size, block.align));
+                    match Self::slide_target(hole_start, start, align) {
+                        Some(target) => {
+                            core::ptr::copy(start as *const u8, target as *mut u8, block.size);
+                            block.ptr = target;
+                            moved += block.size;
+                            if target > hole_start {
+                                // 为了对齐留下的前部空间
+                                free.push((hole_start, target - hole_start));
+                            }
+                            Some((target + size, end))
+                        }
+                        None => {
+                            free.push((hole_start, hole_end - hole_start));
+                            None
+                        }
+                    }
+                }
+                (hole, index) => {
+                    free.extend(hole.map(|(hole_start, hole_end)| (hole_start, hole_end - hole_start)));
+                    index.is_none().then_some((start, end))
+                }
+            };
+        }
+        free.extend(hole.map(|(hole_start, hole_end)| (hole_start, hole_end - hole_start)));
+
+        // 旧的链表节点可能已经被搬来的数据盖掉了，整个重建
+        self.head.next = None;
+        for (start, size) in free {
+            self.add_free_region(start, size);
+        }
+        Ok(moved)
+    }
+
+    /// 紧挨在区块前面的空闲空间从`hole_start`开始时，区块应当搬到哪里；搬不动或者不值得搬时返回`None`
+    ///
+    /// 和分配时一样，为了对齐留在前面的空间和搬走后留在后面的空间都要么为空，要么能容纳一个ListNode
+    fn slide_target(hole_start: usize, block_start: usize, align: usize) -> Option<usize> {
+        let mut target = align_up(hole_start, align);
+        let padding = target - hole_start;
+        if padding > 0 && padding < mem::size_of::<ListNode>() {
+            target = align_up(hole_start + mem::size_of::<ListNode>(), align);
+        }
+        if target + mem::size_of::<ListNode>() > block_start {
+            return None;
+        }
+        Some(target)
+    }
+
+    /// 从结尾正好在`end`的空闲区域里拿走按`page`对齐的整页，返回拿走部分的起始地址，没有可拿的时候返回`None`
+    ///
+    /// 拿走的`[起始地址, end)`不再归分配器管理，使用者可以取消它的映射
+    pub fn release_tail(&mut self, end: usize, page: usize) -> Option<usize> {
+        let mut current = &mut self.head;
+        loop {
+            if current.next.as_ref()?.end_addr() == end {
+                break;
+            }
+            current = current.next.as_mut().unwrap();
+        }
+        let start = current.next.as_ref()?.start_addr();
+        let mut cut = align_up(start, page);
+        if cut > start && cut - start < mem::size_of::<ListNode>() {
+            cut += page;
+        }
+        if cut >= end {
+            return None;
+        }
+        let node = current.next.take().unwrap();
+        current.next = node.next.take();
+        if cut > start {
+            unsafe { self.add_free_region(start, cut - start) };
+        }
+        self.size -= end - cut;
+        Some(cut)
+    }
+
+    /// 沿空闲链表检查每个节点，在跟随指针之前先用`valid(地址, 大小)`检查它
+    ///
+    /// 返回第一个不合法的节点地址
+    pub fn check_free_list(&self, valid: impl Fn(usize, usize) -> bool) -> Result<(), usize> {
+        // 节点数不可能超过这个值，超过了说明链表成环
+        let mut budget = self.size / mem::size_of::<ListNode>() + 1;
+        let mut next = self.head.next.as_deref().map(|node| node as *const ListNode);
+        while let Some(node) = next {
+            let addr = node as usize;
+            if budget == 0 || !valid(addr, mem::size_of::<ListNode>()) {
+                return Err(addr);
+            }
+            let node = unsafe { &*node };
+            if !valid(addr, node.size) {
+                return Err(addr);
+            }
+            budget -= 1;
+            next = node.next.as_deref().map(|node| node as *const ListNode);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{alloc, dealloc, Layout};
+    use std::vec::Vec;
+
+    use super::{align_up, CompactError, LinkedListAllocator, ListNode, Movable, RegionProvider};
+
+    /// 测试用的堆，按页对齐，离开作用域时还给宿主机
+    struct Backing {
+        start: usize,
+        layout: Layout,
+    }
+
+    impl Backing {
+        fn new(size: usize) -> Self {
+            let layout = Layout::from_size_align(size, 4096).unwrap();
+            let start = unsafe { alloc(layout) } as usize;
+            assert_ne!(start, 0);
+            Self { start, layout }
+        }
+
+        fn heap(&self) -> LinkedListAllocator {
+            let mut heap = LinkedListAllocator::new();
+            unsafe { heap.init(self.start, self.layout.size()) };
+            heap
+        }
+    }
+
+    impl Drop for Backing {
+        fn drop(&mut self) {
+            unsafe { dealloc(self.start as *mut u8, self.layout) };
+        }
+    }
+
+    /// 空闲链表里的（起始地址，大小），按链表顺序
+    fn free_regions(heap: &LinkedListAllocator) -> Vec<(usize, usize)> {
+        let mut regions = Vec::new();
+        let mut next = heap.head.next.as_deref();
+        while let Some(node) = next {
+            regions.push((node.start_addr(), node.size));
+            next = node.next.as_deref();
+        }
+        regions
+    }
+
+    /// 空闲链表的不变量：节点都在堆里、互不重叠也不相邻（相邻的应当已经合并）、按大小从大到小排列，
+    /// 空闲的加上已分配的（按调整后的大小）正好是整个堆
+    fn check_invariants(heap: &LinkedListAllocator, ranges: &[(usize, usize)], live: &[(usize, Layout)]) {
+        let regions = free_regions(heap);
+        assert!(regions.windows(2).all(|pair| pair[0].1 >= pair[1].1), "not sorted by size: {:x?}", regions);
+        let mut sorted = regions.clone();
+        sorted.sort();
+        for pair in sorted.windows(2) {
+            assert!(pair[0].0 + pair[0].1 < pair[1].0, "overlapping or uncoalesced: {:x?}", pair);
+        }
+        for (start, size) in regions.iter() {
+            assert!(*size >= core::mem::size_of::<ListNode>());
+            assert!(ranges.iter().any(|(base, len)| start >= base && start + size <= base + len));
+        }
+        let free: usize = regions.iter().map(|(_, size)| size).sum();
+        let used: usize = live.iter().map(|(_, layout)| LinkedListAllocator::size_align(*layout).0).sum();
+        let total: usize = ranges.iter().map(|(_, len)| len).sum();
+        assert_eq!(free + used, total);
+        assert_eq!(heap.allocated(), live.iter().map(|(_, layout)| layout.size()).sum::<usize>());
+        assert!(heap.check_free_list(|addr, size| ranges.iter().any(|(base, len)| addr >= *base && addr + size <= base + len)).is_ok());
+    }
+
+    #[test]
+    fn align_up_rounds_to_power_of_two() {
+        assert_eq!(align_up(0, 8), 0);
+        assert_eq!(align_up(1, 8), 8);
+        assert_eq!(align_up(8, 8), 8);
+        assert_eq!(align_up(4097, 4096), 8192);
+    }
+
+    #[test]
+    fn freeing_neighbours_coalesces() {
+        let backing = Backing::new(4096);
+        let mut heap = backing.heap();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let [a, b, c] = [(); 3].map(|_| unsafe { heap.alloc(layout) });
+        assert!(![a, b, c].contains(&core::ptr::null_mut()));
+
+        // 先放中间的，再放两边的，最后应当回到只有一整块
+        unsafe { heap.dealloc(b, layout) };
+        assert_eq!(free_regions(&heap).len(), 2);
+        unsafe { heap.dealloc(a, layout) };
+        unsafe { heap.dealloc(c, layout) };
+        assert_eq!(free_regions(&heap), vec![(backing.start, 4096)]);
+        assert_eq!(heap.allocated(), 0);
+    }
+
+    #[test]
+    fn large_alignment_returns_padding() {
+        let backing = Backing::new(8 * 4096);
+        let mut heap = backing.heap();
+        let page = Layout::from_size_align(100, 4096).unwrap();
+        let ptrs = [(); 3].map(|_| unsafe { heap.alloc(page) });
+        for ptr in ptrs {
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % 4096, 0);
+        }
+        for ptr in ptrs {
+            unsafe { heap.dealloc(ptr, page) };
+        }
+
+        // 对齐跳过的空间都还回来了，整个堆又能一次分配出去
+        let whole = Layout::from_size_align(8 * 4096, 8).unwrap();
+        let ptr = unsafe { heap.alloc(whole) };
+        assert_eq!(ptr as usize, backing.start);
+        unsafe { heap.dealloc(ptr, whole) };
+    }
+
+    #[test]
+    fn fragmentation_blocks_large_allocation_until_freed() {
+        let backing = Backing::new(1024);
+        let mut heap = backing.heap();
+        let small = Layout::from_size_align(128, 8).unwrap();
+        let ptrs: Vec<*mut u8> = (0..8).map(|_| unsafe { heap.alloc(small) }).collect();
+        assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+
+        // 隔一个放一个：空闲的总量够，但没有一块连续的够
+        for ptr in ptrs.iter().step_by(2) {
+            unsafe { heap.dealloc(*ptr, small) };
+        }
+        let big = Layout::from_size_align(512, 8).unwrap();
+        assert_eq!(heap.free_space(), 512);
+        assert!(unsafe { heap.alloc(big) }.is_null());
+
+        for ptr in ptrs.iter().skip(1).step_by(2) {
+            unsafe { heap.dealloc(*ptr, small) };
+        }
+        assert!(!unsafe { heap.alloc(big) }.is_null());
+    }
+
+    #[test]
+    fn too_small_leftovers_are_skipped() {
+        let backing = Backing::new(256);
+        let mut heap = backing.heap();
+        // 剩下的空间放不下一个节点时不能用这一块
+        let layout = Layout::from_size_align(256 - 8, 8).unwrap();
+        assert!(unsafe { heap.alloc(layout) }.is_null());
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        assert!(!unsafe { heap.alloc(layout) }.is_null());
+    }
+
+    /// 分配并填上和地址有关的内容，作为可搬动的区块登记
+    fn alloc_movable(heap: &mut LinkedListAllocator, size: usize, align: usize, tag: u8) -> Movable {
+        let ptr = unsafe { heap.alloc(Layout::from_size_align(size, align).unwrap()) };
+        assert!(!ptr.is_null());
+        unsafe { core::ptr::write_bytes(ptr, tag, size) };
+        Movable { ptr: ptr as usize, size, align }
+    }
+
+    fn holds(block: &Movable, tag: u8) -> bool {
+        unsafe { core::slice::from_raw_parts(block.ptr as *const u8, block.size) }.iter().all(|b| *b == tag)
+    }
+
+    fn layouts(blocks: &[Movable]) -> Vec<(usize, Layout)> {
+        blocks.iter().map(|b| (b.ptr, Layout::from_size_align(b.size, b.align).unwrap())).collect()
+    }
+
+    #[test]
+    fn compact_slides_blocks_down() {
+        let backing = Backing::new(4096);
+        let mut heap = backing.heap();
+        let mut blocks: Vec<Movable> = (0..8).map(|i| alloc_movable(&mut heap, 100, 8, i as u8)).collect();
+        // 隔一个放一个，留下4个洞
+        for block in blocks.iter().step_by(2) {
+            unsafe { heap.dealloc(block.ptr as *mut u8, Layout::from_size_align(block.size, block.align).unwrap()) };
+        }
+        let mut live: Vec<Movable> = blocks.drain(..).skip(1).step_by(2).collect();
+        assert_eq!(free_regions(&heap).len(), 5);
+
+        let moved = unsafe { heap.compact(&mut live) }.unwrap();
+        assert_eq!(moved, 4 * 100);
+        for (i, block) in live.iter().enumerate() {
+            assert_eq!(block.ptr, backing.start + i * 104);
+            assert!(holds(block, (2 * i + 1) as u8));
+        }
+        // 空闲空间合并成结尾的一整块
+        assert_eq!(free_regions(&heap), vec![(backing.start + 4 * 104, 4096 - 4 * 104)]);
+        check_invariants(&heap, &[(backing.start, 4096)], &layouts(&live));
+    }
+
+    #[test]
+    fn compact_leaves_pinned_blocks_alone() {
+        let backing = Backing::new(4096);
+        let mut heap = backing.heap();
+        let a = alloc_movable(&mut heap, 64, 8, 1);
+        let pinned = alloc_movable(&mut heap, 64, 8, 2);
+        let b = alloc_movable(&mut heap, 64, 8, 3);
+        let c = alloc_movable(&mut heap, 64, 8, 4);
+        unsafe { heap.dealloc(a.ptr as *mut u8, Layout::from_size_align(64, 8).unwrap()) };
+        unsafe { heap.dealloc(b.ptr as *mut u8, Layout::from_size_align(64, 8).unwrap()) };
+
+        // 钉住的区块前面的洞没有可以搬进去的，c只能滑到b原来的位置
+        let mut live = [c];
+        assert_eq!(unsafe { heap.compact(&mut live) }, Ok(64));
+        assert_eq!(live[0].ptr, b.ptr);
+        assert!(holds(&live[0], 4) && holds(&pinned, 2));
+        assert_eq!(free_regions(&heap).len(), 2);
+        check_invariants(&heap, &[(backing.start, 4096)], &layouts(&[pinned, live[0]]));
+    }
+
+    #[test]
+    fn compact_keeps_alignment() {
+        let backing = Backing::new(4 * 4096);
+        let mut heap = backing.heap();
+        let pinned = alloc_movable(&mut heap, 24, 8, 1);
+        let small = alloc_movable(&mut heap, 64, 8, 2);
+        let mut page = alloc_movable(&mut heap, 100, 4096, 3);
+        unsafe { heap.dealloc(small.ptr as *mut u8, Layout::from_size_align(64, 8).unwrap()) };
+        let before = page.ptr;
+        assert_eq!(unsafe { heap.compact(core::slice::from_mut(&mut page)) }, Ok(0));
+        // 钉住的区块后面到下一页之间没有对齐的空位，不动
+        assert_eq!(page.ptr, before);
+        assert!(holds(&page, 3));
+        check_invariants(&heap, &[(backing.start, 4 * 4096)], &layouts(&[pinned, page]));
+
+        // 钉住的区块释放之后，开头就是对齐的空位
+        unsafe { heap.dealloc(pinned.ptr as *mut u8, Layout::from_size_align(24, 8).unwrap()) };
+        assert_eq!(unsafe { heap.compact(core::slice::from_mut(&mut page)) }, Ok(100));
+        assert_eq!(page.ptr, backing.start);
+        assert!(holds(&page, 3));
+        check_invariants(&heap, &[(backing.start, 4 * 4096)], &layouts(&[page]));
+    }
+
+    #[test]
+    fn compact_rejects_bad_entries() {
+        let backing = Backing::new(4096);
+        let mut heap = backing.heap();
+        let a = alloc_movable(&mut heap, 64, 8, 1);
+        let before = free_regions(&heap);
+        // 和空闲区域重叠、互相重叠、布局不合法的都拒绝，什么都不动
+        let free = Movable { ptr: a.ptr + 128, size: 64, align: 8 };
+        assert_eq!(unsafe { heap.compact(&mut [a, free]) }, Err(CompactError::BadEntry(1)));
+        assert!(unsafe { heap.compact(&mut [a, a]) }.is_err());
+        assert_eq!(unsafe { heap.compact(&mut [Movable { ptr: a.ptr, size: 64, align: 3 }]) }, Err(CompactError::BadEntry(0)));
+        assert_eq!(free_regions(&heap), before);
+        assert!(holds(&a, 1));
+    }
+
+    #[test]
+    fn release_tail_takes_whole_pages() {
+        let backing = Backing::new(4 * 4096);
+        let mut heap = backing.heap();
+        let end = backing.start + 4 * 4096;
+        let block = alloc_movable(&mut heap, 5000, 8, 7);
+        assert_eq!(heap.release_tail(end, 4096), Some(backing.start + 2 * 4096));
+        assert_eq!(heap.size(), 2 * 4096);
+        check_invariants(&heap, &[(backing.start, 2 * 4096)], &layouts(&[block]));
+        // 结尾已经不是空闲的了
+        assert_eq!(heap.release_tail(end, 4096), None);
+        assert_eq!(heap.release_tail(backing.start + 2 * 4096, 4096), None);
+    }
+
+    /// 随机碎片化之后全部交给压缩：空闲空间应当只剩每块后备内存结尾的一整块，再把其中的整页拿走
+    fn fragment_and_compact(seed: u64) {
+        const HEAP: usize = 16 * 4096;
+        let mut rng = Rng(seed);
+        let backing = Backing::new(HEAP);
+        let mut heap = backing.heap();
+        let mut live: Vec<(Movable, u8)> = Vec::new();
+        for step in 0..2000 {
+            if live.is_empty() || rng.below(3) != 0 {
+                let size = 1 + rng.below(300);
+                let align = 1 << rng.below(6);
+                let tag = step as u8;
+                let ptr = unsafe { heap.alloc(Layout::from_size_align(size, align).unwrap()) };
+                if !ptr.is_null() {
+                    unsafe { core::ptr::write_bytes(ptr, tag, size) };
+                    live.push((Movable { ptr: ptr as usize, size, align }, tag));
+                }
+            } else {
+                let (block, _) = live.swap_remove(rng.below(live.len()));
+                unsafe { heap.dealloc(block.ptr as *mut u8, Layout::from_size_align(block.size, block.align).unwrap()) };
+            }
+        }
+        let mut blocks: Vec<Movable> = live.iter().map(|(block, _)| *block).collect();
+        unsafe { heap.compact(&mut blocks) }.unwrap();
+        for (block, (_, tag)) in blocks.iter().zip(live.iter()) {
+            assert!(holds(block, *tag), "seed {}: block moved to {:#x} was clobbered", seed, block.ptr);
+        }
+        check_invariants(&heap, &[(backing.start, HEAP)], &layouts(&blocks));
+        // 对齐留下的小空隙之外，空闲空间都在结尾
+        let regions = free_regions(&heap);
+        let (tail_start, tail_size) = *regions.iter().max_by_key(|(start, _)| *start).unwrap();
+        assert_eq!(tail_start + tail_size, backing.start + HEAP);
+        let highest = blocks.iter().map(|block| block.ptr + block.size).max().unwrap_or(backing.start);
+        assert!(highest <= tail_start);
+
+        let cut = heap.release_tail(backing.start + HEAP, 4096).unwrap();
+        assert_eq!(cut % 4096, 0);
+        assert!(cut >= tail_start && cut - tail_start < 4096 + core::mem::size_of::<ListNode>());
+        check_invariants(&heap, &[(backing.start, cut - backing.start)], &layouts(&blocks));
+    }
+
+    #[test]
+    fn fragment_then_compact_releases_tail_pages() {
+        for seed in [3, 0x1234_5678, 0xC0FF_EE00_D15E_A5E5] {
+            fragment_and_compact(seed);
+        }
+    }
+
+    /// 每次提供固定大小的一块
+    struct Chunks {
+        backings: Vec<Backing>,
+        chunk: usize,
+        limit: usize,
+    }
+
+    impl RegionProvider for Chunks {
+        fn provide(&mut self, min_size: usize) -> Option<(usize, usize)> {
+            if self.backings.len() >= self.limit {
+                return None;
+            }
+            let size = align_up(min_size, self.chunk);
+            let backing = Backing::new(size);
+            let start = backing.start;
+            self.backings.push(backing);
+            Some((start, size))
+        }
+    }
+
+    #[test]
+    fn alloc_or_grow_asks_the_provider() {
+        let mut heap = LinkedListAllocator::new();
+        let mut chunks = Chunks { backings: Vec::new(), chunk: 4096, limit: 2 };
+        let layout = Layout::from_size_align(3000, 8).unwrap();
+        let a = unsafe { heap.alloc_or_grow(layout, &mut chunks) };
+        assert!(!a.is_null());
+        assert_eq!(chunks.backings.len(), 1);
+        // 剩下的不够，再要一块
+        let b = unsafe { heap.alloc_or_grow(layout, &mut chunks) };
+        assert!(!b.is_null());
+        assert_eq!(chunks.backings.len(), 2);
+        assert_eq!(heap.size(), 2 * 4096);
+        // 提供方拒绝时返回空指针
+        assert!(unsafe { heap.alloc_or_grow(layout, &mut chunks) }.is_null());
+    }
+
+    /// xorshift64，测试里够用了
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    /// 随机分配、释放几千次，每一步之后检查空闲链表，并确认已分配的内容没有被破坏
+    fn random_workload(seed: u64, steps: usize, grow: bool) {
+        const HEAP: usize = 64 * 1024;
+        let mut rng = Rng(seed);
+        let mut chunks = Chunks { backings: vec![Backing::new(HEAP)], chunk: 4096, limit: 8 };
+        let mut heap = chunks.backings[0].heap();
+        let mut live: Vec<(usize, Layout)> = Vec::new();
+        for step in 0..steps {
+            if live.is_empty() || rng.below(3) != 0 {
+                // 偶尔来一个大块或者大对齐
+                let max_size = if rng.below(10) == 0 { 4096 } else { 256 };
+                let max_shift = if rng.below(20) == 0 { 13 } else { 5 };
+                let size = 1 + rng.below(max_size);
+                let align = 1 << rng.below(max_shift);
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let ptr = unsafe { if grow { heap.alloc_or_grow(layout, &mut chunks) } else { heap.alloc(layout) } };
+                if !ptr.is_null() {
+                    assert_eq!(ptr as usize % align, 0);
+                    for (other, other_layout) in live.iter() {
+                        assert!(ptr as usize + size <= *other || other + other_layout.size() <= ptr as usize, "step {}: overlap", step);
+                    }
+                    unsafe { core::ptr::write_bytes(ptr, (ptr as usize >> 3) as u8, size) };
+                    live.push((ptr as usize, layout));
+                }
+            } else {
+                let (ptr, layout) = live.swap_remove(rng.below(live.len()));
+                let pattern = (ptr >> 3) as u8;
+                let bytes = unsafe { core::slice::from_raw_parts(ptr as *const u8, layout.size()) };
+                assert!(bytes.iter().all(|b| *b == pattern), "step {}: block {:#x} was clobbered", step, ptr);
+                unsafe { heap.dealloc(ptr as *mut u8, layout) };
+            }
+            let ranges: Vec<(usize, usize)> = chunks.backings.iter().map(|b| (b.start, b.layout.size())).collect();
+            check_invariants(&heap, &ranges, &live);
+        }
+        for (ptr, layout) in live.drain(..) {
+            unsafe { heap.dealloc(ptr as *mut u8, layout) };
+        }
+        // 全部释放后每块后备内存各自合并成一整块
+        let ranges: Vec<(usize, usize)> = chunks.backings.iter().map(|b| (b.start, b.layout.size())).collect();
+        check_invariants(&heap, &ranges, &[]);
+        assert_eq!(heap.allocated(), 0);
+    }
+
+    #[test]
+    fn random_alloc_free_keeps_invariants() {
+        for seed in [1, 0x9E37_79B9_7F4A_7C15, 0xDEAD_BEEF] {
+            random_workload(seed, 3000, false);
+        }
+    }
+
+    #[test]
+    fn random_alloc_free_with_growth_keeps_invariants() {
+        random_workload(42, 3000, true);
+    }
+}
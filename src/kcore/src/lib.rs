@@ -0,0 +1,17 @@
+//! 内核里和硬件、锁都无关的纯逻辑：空闲链表分配器、路径规范化、日期换算、符号表查找
+//!
+//! 这个crate只用`core`和`alloc`，不需要启动QEMU，在宿主机上就能测试。仓库根目录的`.cargo/config`
+//! 把目标固定成了内核的目标，所以要在仓库外面运行：
+//!
+//! ```text
+//! cd /tmp && cargo test --manifest-path <仓库>/src/kcore/Cargo.toml
+//! ```
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod date;
+pub mod heap;
+pub mod path;
+pub mod symbol;
@@ -0,0 +1,256 @@
+//! Path helpers shared by the kernel and user programs.
+//!
+//! Paths are plain `/`-separated strings. [`standardize`] turns an absolute path into its canonical form,
+//! resolving `.` and `..` and dropping empty components.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Returns the directory component of a pathname.
+///
+/// Given a pathname, this function returns the leading component of the pathname, up to and including the last slash `/`.
+/// If there is no slash in the pathname, the entire pathname is returned.
+///
+/// # Examples
+///
+/// ```
+/// use cinea_os_kcore::path::dirname;
+///
+/// assert_eq!(dirname("/usr/bin/gcc"), "/usr/bin");
+/// assert_eq!(dirname("/usr/bin/"), "/usr/bin");
+/// assert_eq!(dirname("/usr/"), "/usr");
+/// assert_eq!(dirname("/"), "/");
+/// assert_eq!(dirname("gcc"), "gcc");
+/// ```
+///
+/// # Arguments
+///
+/// * `pathname`: A string slice containing the pathname to extract the directory component from.
+///
+/// # Returns
+///
+/// The directory component of the pathname.
+///
+/// # Safety
+///
+/// This function is safe to use as long as the `pathname` argument is a valid null-terminated string.
+/// If the `pathname` argument is not a valid null-terminated string, this function may cause undefined behavior.
+pub fn dirname(pathname: &str) -> &str {
+    let n = pathname.len();
+    let i = match pathname.rfind('/') {
+        Some(0) => 1, // 根目录
+        Some(i) => i,
+        None => n,
+    };
+    &pathname[0..i]
+}
+
+/// Returns the filename component of a pathname.
+///
+/// Given a pathname, this function returns the trailing component of the pathname, after the last slash `/`.
+/// If there is no slash in the pathname, the entire pathname is returned.
+///
+/// # Examples
+///
+/// ```
+/// use cinea_os_kcore::path::filename;
+///
+/// assert_eq!(filename("/usr/bin/gcc"), "gcc");
+/// assert_eq!(filename("/usr/bin/"), "");
+/// assert_eq!(filename("/usr/"), "");
+/// assert_eq!(filename("/"), "");
+/// assert_eq!(filename("gcc"), "gcc");
+/// ```
+///
+/// # Arguments
+///
+/// * `pathname`: A string slice containing the pathname to extract the filename component from.
+///
+/// # Returns
+///
+/// The filename component of the pathname.
+///
+/// # Safety
+///
+/// This function is safe to use as long as the `pathname` argument is a valid null-terminated string.
+/// If the `pathname` argument is not a valid null-terminated string, this function may cause undefined behavior.
+pub fn filename(pathname: &str) -> &str {
+    let n = pathname.len();
+    let i = match pathname.rfind('/') {
+        Some(i) => i + 1,
+        None => 0,
+    };
+    &pathname[i..n]
+}
+
+/// Returns the absolute path of a pathname.
+///
+/// Given a pathname and the current working directory, this function returns the absolute path of the pathname.
+/// If the pathname is already an absolute path, it is returned unchanged.
+/// Otherwise, the pathname is resolved relative to the current working directory.
+///
+/// # Examples
+///
+/// ```
+/// use cinea_os_kcore::path::realpath;
+///
+/// assert_eq!(realpath("/usr/bin/gcc", "/home/user"), "/usr/bin/gcc");
+/// assert_eq!(realpath("gcc", "/usr/bin"), "/usr/bin/gcc");
+/// assert_eq!(realpath("gcc", "/usr/bin/"), "/usr/bin/gcc");
+/// ```
+///
+/// # Arguments
+///
+/// * `pathname`: A string slice containing the pathname to resolve.
+/// * `current_dir`: A string slice containing the current working directory to resolve the pathname relative to.
+///
+/// # Returns
+///
+/// The absolute path of the pathname.
+///
+/// # Safety
+///
+/// This function is safe to use as long as the `pathname` and `current_dir` arguments are valid null-terminated strings.
+/// If the `pathname` or `current_dir` arguments are not valid null-terminated strings, this function may cause undefined behavior.
+pub fn realpath(pathname: &str, current_dir: &str) -> String {
+    if pathname.starts_with('/') {
+        pathname.into()
+    } else {
+        path_combine(current_dir, pathname)
+    }
+}
+
+pub fn path_combine(path1: &str, path2: &str) -> String {
+    let sep = if path1.ends_with('/') { "" } else { "/" };
+    alloc::format!("{}{}{}", path1, sep, path2)
+}
+
+/// Returned when a `..` would leave the root directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EscapesRoot;
+
+/// Resolves `.` and `..` in a list of path components, in place.
+///
+/// A `..` removes the component before it. Fails with [`EscapesRoot`] when there is nothing left to remove.
+pub fn resolve_dots(components: &mut Vec<&str>) -> Result<(), EscapesRoot> {
+    let mut i = 0usize;
+    while i < components.len() {
+        if components[i] == "." {
+            components.remove(i);
+        } else if components[i] == ".." {
+            if i == 0 {
+                return Err(EscapesRoot);
+            }
+            components.remove(i);
+            components.remove(i - 1);
+            i -= 1;
+        } else {
+            i += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the canonical form of an absolute path.
+///
+/// Empty components are dropped and `.`/`..` are resolved. The result starts with `/` and has no trailing slash,
+/// except for the root directory itself, which is returned as an empty string.
+///
+/// # Examples
+///
+/// ```
+/// use cinea_os_kcore::path::standardize;
+///
+/// assert_eq!(standardize("/usr//bin/./gcc").unwrap(), "/usr/bin/gcc");
+/// assert_eq!(standardize("/usr/bin/../lib/").unwrap(), "/usr/lib");
+/// assert!(standardize("/..").is_err());
+/// ```
+pub fn standardize(path: &str) -> Result<String, EscapesRoot> {
+    let mut components: Vec<_> = path.split('/').filter(|x| !x.is_empty()).collect();
+    resolve_dots(&mut components)?;
+    components.insert(0, "");
+    Ok(components.join("/"))
+}
+
+/// Resolves `path` for a process confined to the directory `root`.
+///
+/// Both `path` and `current_dir` are seen from inside the jail, a relative `path` is taken relative to `current_dir`.
+/// The result is the canonical absolute path outside the jail, `/` for the root directory itself.
+/// Fails with [`EscapesRoot`] when a `..` would climb above `root`.
+///
+/// # Examples
+///
+/// ```
+/// use cinea_os_kcore::path::jail;
+///
+/// assert_eq!(jail("/srv", "/", "etc/motd").unwrap(), "/srv/etc/motd");
+/// assert!(jail("/srv", "/home", "../../etc").is_err());
+/// ```
+pub fn jail(root: &str, current_dir: &str, path: &str) -> Result<String, EscapesRoot> {
+    let inner = standardize(&realpath(path, current_dir))?;
+    let outer = alloc::format!("{}{}", standardize(root)?, inner);
+    Ok(if outer.is_empty() { "/".into() } else { outer })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dirname_and_filename_split_at_last_slash() {
+        for (path, dir, file) in [
+            ("/usr/bin/gcc", "/usr/bin", "gcc"),
+            ("/usr/bin/", "/usr/bin", ""),
+            ("/", "/", ""),
+            ("/gcc", "/", "gcc"),
+            ("gcc", "gcc", "gcc"),
+        ] {
+            assert_eq!(dirname(path), dir, "dirname({:?})", path);
+            assert_eq!(filename(path), file, "filename({:?})", path);
+        }
+    }
+
+    #[test]
+    fn realpath_keeps_absolute_paths() {
+        assert_eq!(realpath("/etc/passwd", "/home"), "/etc/passwd");
+        assert_eq!(realpath("passwd", "/etc"), "/etc/passwd");
+        assert_eq!(realpath("passwd", "/"), "/passwd");
+        assert_eq!(path_combine("/a/", "b"), "/a/b");
+    }
+
+    #[test]
+    fn standardize_resolves_dots() {
+        assert_eq!(standardize("/").unwrap(), "");
+        assert_eq!(standardize("//a///b//").unwrap(), "/a/b");
+        assert_eq!(standardize("/a/./b/.").unwrap(), "/a/b");
+        assert_eq!(standardize("/a/b/../../c").unwrap(), "/c");
+        assert_eq!(standardize("/a/..").unwrap(), "");
+        assert_eq!(standardize("/a/../..").unwrap_err(), EscapesRoot);
+        assert_eq!(standardize("/../a").unwrap_err(), EscapesRoot);
+    }
+
+    #[test]
+    fn jail_prefixes_the_root() {
+        assert_eq!(jail("/", "/", "/etc/passwd").unwrap(), "/etc/passwd");
+        assert_eq!(jail("/", "/", "/").unwrap(), "/");
+        assert_eq!(jail("/srv/www", "/", "/").unwrap(), "/srv/www");
+        assert_eq!(jail("/srv/www/", "/cgi", "run/../log").unwrap(), "/srv/www/cgi/log");
+        assert_eq!(jail("/srv/www", "/a/b", "..").unwrap(), "/srv/www/a");
+    }
+
+    #[test]
+    fn jail_blocks_escapes() {
+        for (dir, path) in [("/", ".."), ("/", "../../etc"), ("/home", "../../etc"), ("/", "/../srv"), ("/a", "b/../../../x")] {
+            assert_eq!(jail("/srv/www", dir, path), Err(EscapesRoot), "jail({:?}, {:?})", dir, path);
+        }
+    }
+
+    #[test]
+    fn resolve_dots_works_on_relative_components() {
+        let mut components = vec!["a", "..", "b", ".", "c", ".."];
+        resolve_dots(&mut components).unwrap();
+        assert_eq!(components, ["b"]);
+        let mut components = vec!["..", "a"];
+        assert!(resolve_dots(&mut components).is_err());
+    }
+}
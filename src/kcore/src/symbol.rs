@@ -0,0 +1,131 @@
+//! Symbol tables of ELF images, used to turn code addresses into `function+offset`.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Finds the function containing `addr` in a table of `(start, end, name)` sorted by start.
+///
+/// Returns the name and the offset of `addr` into the function.
+///
+/// # Examples
+///
+/// ```
+/// use cinea_os_kcore::symbol::lookup;
+///
+/// let symbols = [(0x1000, 0x1040, "main"), (0x1040, 0x1100, "helper")];
+/// assert_eq!(lookup(&symbols, 0x1044), Some(("helper", 4)));
+/// assert_eq!(lookup(&symbols, 0x1100), None);
+/// ```
+pub fn lookup<S: AsRef<str>>(symbols: &[(u64, u64, S)], addr: u64) -> Option<(&str, u64)> {
+    let idx = symbols.partition_point(|(start, _, _)| *start <= addr).checked_sub(1)?;
+    let (start, end, name) = &symbols[idx];
+    if addr < *end {
+        Some((name.as_ref(), addr - start))
+    } else {
+        None
+    }
+}
+
+/// Turns a legacy Rust mangled name into a readable path, without the trailing hash.
+///
+/// Names that are not mangled, or that cannot be parsed, are returned unchanged.
+///
+/// # Examples
+///
+/// ```
+/// use cinea_os_kcore::symbol::demangle;
+///
+/// assert_eq!(demangle("_ZN4core9panicking5panic17h0123456789abcdefE"), "core::panicking::panic");
+/// assert_eq!(demangle("memcpy"), "memcpy");
+/// ```
+pub fn demangle(name: &str) -> String {
+    demangle_legacy(name).unwrap_or_else(|| name.into())
+}
+
+fn demangle_legacy(name: &str) -> Option<String> {
+    let mut rest = name.strip_prefix("__ZN").or_else(|| name.strip_prefix("_ZN"))?;
+    let mut components = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let len: usize = rest[..digits].parse().ok()?;
+        let component = rest.get(digits..digits + len)?;
+        components.push(component);
+        rest = &rest[digits + len..];
+    }
+    if let Some(last) = components.last() {
+        if last.len() == 17 && last.starts_with('h') && last[1..].chars().all(|c| c.is_ascii_hexdigit()) {
+            components.pop();
+        }
+    }
+    let components = components.into_iter().map(unescape).collect::<Option<Vec<_>>>()?;
+    Some(components.join("::"))
+}
+
+/// Replaces the `$..$` escapes and `..` of one path component.
+fn unescape(component: &str) -> Option<String> {
+    let mut rest = component.strip_prefix("_$").map_or(component, |rest| &component[component.len() - rest.len() - 1..]);
+    let mut out = String::new();
+    while !rest.is_empty() {
+        if let Some(tail) = rest.strip_prefix('$') {
+            let end = tail.find('$')?;
+            out.push(match &tail[..end] {
+                "SP" => '@',
+                "BP" => '*',
+                "RF" => '&',
+                "LT" => '<',
+                "GT" => '>',
+                "LP" => '(',
+                "RP" => ')',
+                "C" => ',',
+                code => char::from_u32(u32::from_str_radix(code.strip_prefix('u')?, 16).ok()?)?,
+            });
+            rest = &tail[end + 1..];
+        } else if let Some(tail) = rest.strip_prefix("..") {
+            out.push_str("::");
+            rest = tail;
+        } else {
+            let ch = rest.chars().next()?;
+            out.push(ch);
+            rest = &rest[ch.len_utf8()..];
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_finds_the_enclosing_function() {
+        let symbols = [(0x10, 0x20, String::from("a")), (0x20, 0x28, String::from("b")), (0x40, 0x50, String::from("c"))];
+        assert_eq!(lookup(&symbols, 0x0F), None);
+        assert_eq!(lookup(&symbols, 0x10), Some(("a", 0)));
+        assert_eq!(lookup(&symbols, 0x27), Some(("b", 7)));
+        // 函数之间的空隙
+        assert_eq!(lookup(&symbols, 0x30), None);
+        assert_eq!(lookup(&symbols, 0x4F), Some(("c", 0xF)));
+        assert_eq!(lookup::<String>(&[], 0x10), None);
+    }
+
+    #[test]
+    fn demangle_legacy_names() {
+        assert_eq!(
+            demangle("_ZN8cinea_os7syskrnl4proc7Process5spawn17h9f0a1b2c3d4e5f60E"),
+            "cinea_os::syskrnl::proc::Process::spawn"
+        );
+        assert_eq!(
+            demangle("_ZN70_$LT$alloc..vec..Vec$LT$T$C$A$GT$$u20$as$u20$core..ops..drop..Drop$GT$4drop17h0123456789abcdefE"),
+            "<alloc::vec::Vec<T,A> as core::ops::drop::Drop>::drop"
+        );
+        assert_eq!(demangle("_ZN4core3ptr13drop_in_place17h0123456789abcdefE"), "core::ptr::drop_in_place");
+        assert_eq!(demangle("_ZN3foo28_$u7b$$u7b$closure$u7d$$u7d$17h0123456789abcdefE"), "foo::{{closure}}");
+    }
+
+    #[test]
+    fn demangle_leaves_other_names_alone() {
+        for name in ["_start", "memcpy", "_ZN3foo", "_ZN99fooE", "_ZN3f$ooE", "_ZN4$u$E"] {
+            assert_eq!(demangle(name), name);
+        }
+    }
+}
@@ -38,9 +38,14 @@ pub fn init(bootinfo: &'static BootInfo) {
     // 加载内存
     println!("\n\nInitializing the memory...\n");
     syskrnl::memory::init(bootinfo);
+    syskrnl::workqueue::init();
 
     // 启用各类IO设备
     syskrnl::io::ahci::init();
+    syskrnl::fs::initramfs::init();
+    syskrnl::ksyms::init();
+    syskrnl::config::init();
+    syskrnl::schedule::init();
     syskrnl::time::init();
     syskrnl::task::keyboard::init();
     syskrnl::io::mouse::init();
@@ -14,6 +14,8 @@ use core::panic::PanicInfo;
 use bootloader::{BootInfo, entry_point};
 use x86::int;
 
+use cinea_os_sysapi::syscall::SpawnFlags;
+
 use cinea_os::{debugln, hlt_loop, println, syskrnl};
 use cinea_os::syskrnl::task::executor::Executor;
 use cinea_os::syskrnl::task::keyboard::key_presses_handler;
@@ -38,24 +40,32 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     cinea_os::init(boot_info);
 
     println!("Cinea OS v1.0-dev by Cinea (Zhang Yao) cineazhan@icloud.com");
-    println!("System Uptime：{:.5} s\n", syskrnl::time::uptime());
+    println!("System Uptime：{:.5} s", syskrnl::time::uptime());
+    println!("{}\n", syskrnl::sysrq::HELP);
 
     //println!("我是内核，我即将启动用户进程并将CPU调整到环三！");
 
-    let subp = include_bytes!("../dsk/bin/shell");
-    let args: Vec<&str> = vec![];
-    let mut flag = 0;
-    loop {
-        unsafe { int!(0x81) };
-        debugln!("I come back with flag=={}", flag);
-        #[allow(unused)]
-        if flag > 0 {
-            break;
-        } else {
-            flag = 1;
-        } // 确保不会无尽循环启动shell
-        syskrnl::proc::Process::spawn(subp, args.as_ptr() as usize, 0, 0).unwrap();
-        panic!("The process is Cracked.");
+    // initramfs和数据盘里都没有shell时不启动它，0号进程照常处理键盘
+    match syskrnl::fs::read_to_end("/bin/shell") {
+        Ok(subp) => {
+            let args: Vec<&str> = vec![];
+            let mut flag = 0;
+            loop {
+                unsafe { int!(0x81) };
+                debugln!("I come back with flag=={}", flag);
+                #[allow(unused)]
+                if flag > 0 {
+                    break;
+                } else {
+                    flag = 1;
+                } // 确保不会无尽循环启动shell
+                let flags = SpawnFlags::UNKILLABLE;
+                let args_ptr = args.as_ptr() as usize;
+                syskrnl::proc::Process::spawn("/bin/shell", subp.as_slice(), args_ptr, 0, 0, flags, None, None, Vec::new(), false).unwrap();
+                panic!("The process is Cracked.");
+            }
+        }
+        Err(err) => println!("[WARN] cannot read /bin/shell ({:?}), no shell started", err),
     }
 
     // let mut window_instance = window::init_window_gui("测试 GUI 窗口渲染", rgb888!(0xffffffu32)).expect("获取窗口实例失败");
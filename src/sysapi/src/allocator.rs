@@ -26,18 +26,111 @@
 //! # Note
 //!
 //! This allocator is intended for use in user processes only.
+//!
+//! [`MovableHeap`] hands out blocks reached through handles, so the kernel may slide them together
+//! and give whole free pages back with [`MovableHeap::compact`].
 
+use alloc::vec::Vec;
 use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::null_mut;
+
+pub use cinea_os_kcore::heap::Movable;
+
+use crate::syscall::Compaction;
 
 /// Userspace process heap memory allocator
 pub struct UserProcAllocator;
 
 unsafe impl GlobalAlloc for UserProcAllocator{
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        crate::syscall::alloc(layout.size(), layout.align()) as *mut u8
+        match crate::syscall::alloc(layout.size(), layout.align()) {
+            // out of memory, let the `alloc` error handling take over
+            0 => null_mut(),
+            ptr => ptr as *mut u8,
+        }
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         crate::syscall::free(ptr as usize, layout.size(), layout.align());
     }
-}
\ No newline at end of file
+}
+
+/// Handle to a block of a [`MovableHeap`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MovableHandle(usize);
+
+/// Heap blocks reached through handles, which lets [`compact`](MovableHeap::compact) move them.
+///
+/// Borrows of a block hold the heap, so no reference survives a compaction. Blocks are freed with the heap.
+#[derive(Default)]
+pub struct MovableHeap {
+    /// Freed slots have a null `ptr` and are reused
+    blocks: Vec<Movable>,
+}
+
+impl MovableHeap {
+    pub const fn new() -> Self {
+        Self { blocks: Vec::new() }
+    }
+
+    /// Allocate a zeroed block, `None` when out of memory.
+    pub fn alloc(&mut self, layout: Layout) -> Option<MovableHandle> {
+        let ptr = unsafe { UserProcAllocator.alloc_zeroed(layout) };
+        if ptr.is_null() {
+            return None;
+        }
+        let block = Movable { ptr: ptr as usize, size: layout.size(), align: layout.align() };
+        match self.blocks.iter().position(|b| b.ptr == 0) {
+            Some(slot) => {
+                self.blocks[slot] = block;
+                Some(MovableHandle(slot))
+            }
+            None => {
+                self.blocks.push(block);
+                Some(MovableHandle(self.blocks.len() - 1))
+            }
+        }
+    }
+
+    fn block(&self, handle: MovableHandle) -> Option<Movable> {
+        self.blocks.get(handle.0).copied().filter(|b| b.ptr != 0)
+    }
+
+    /// Contents of a live block.
+    pub fn bytes(&self, handle: MovableHandle) -> Option<&[u8]> {
+        self.block(handle).map(|b| unsafe { core::slice::from_raw_parts(b.ptr as *const u8, b.size) })
+    }
+
+    pub fn bytes_mut(&mut self, handle: MovableHandle) -> Option<&mut [u8]> {
+        self.block(handle).map(|b| unsafe { core::slice::from_raw_parts_mut(b.ptr as *mut u8, b.size) })
+    }
+
+    /// Free a block, the handle becomes invalid and may be handed out again.
+    pub fn free(&mut self, handle: MovableHandle) {
+        if let Some(b) = self.block(handle) {
+            unsafe { UserProcAllocator.dealloc(b.ptr as *mut u8, Layout::from_size_align_unchecked(b.size, b.align)) };
+            self.blocks[handle.0].ptr = 0;
+        }
+    }
+
+    /// Let the kernel slide the live blocks down and unmap the free pages left at the end of the heap.
+    ///
+    /// Other allocations of the process stay put and keep the free space around them. Returns `None` when the kernel refused.
+    pub fn compact(&mut self) -> Option<Compaction> {
+        let (slots, mut table): (Vec<usize>, Vec<Movable>) =
+            self.blocks.iter().enumerate().filter(|(_, b)| b.ptr != 0).map(|(slot, b)| (slot, *b)).unzip();
+        let result = unsafe { crate::syscall::heap_compact(&mut table) }?;
+        for (slot, block) in slots.into_iter().zip(table) {
+            self.blocks[slot] = block;
+        }
+        Some(result)
+    }
+}
+
+impl Drop for MovableHeap {
+    fn drop(&mut self) {
+        for slot in 0..self.blocks.len() {
+            self.free(MovableHandle(slot));
+        }
+    }
+}
@@ -4,6 +4,7 @@
 //!
 //! - `EXIT`: Exit the current process.
 //! - `SPAWN`: Spawn a new process.
+//! - `EXEC`: Replace the current program, keeping the process.
 //! - `READ`: Read from a file descriptor.
 //! - `WRITE`: Write to a file descriptor.
 //! - `OPEN`: Open a file.
@@ -40,11 +41,19 @@ use core::alloc::Layout;
 
 use serde::{Deserialize, Serialize};
 
+/// Returned by the kernel for a syscall number it does not implement, e.g. when a program
+/// was built against a newer `call.rs` than the running kernel
+pub const NO_SUCH_SYSCALL: usize = usize::MAX - 0x100;
+
 /// exit the process
 pub const EXIT: usize = 0x1;
 pub const SPAWN: usize = 0x2;
+/// replace the current program with another one, keeping the pid (1): a0-postcarded (path, args) ret-0 never seen on success, postcarded false on failure
+pub const EXEC: usize = 0x3;
 pub const INFO: usize = 0x7;
+/// duplicate an opened file handle, sharing its offset (1): a0-handle ret-postcarded Result
 pub const DUP: usize = 0x8;
+/// delete a file or an empty directory (1): a0-postcarded path ret-postcarded Result
 pub const DELETE: usize = 0x9;
 pub const STOP: usize = 0xA;
 pub const SLEEP: usize = 0xB;
@@ -59,12 +68,35 @@ pub const PANIC: usize = 0xF;
 pub const NO_SCHE: usize = 0x10;
 /// resume schedule
 pub const CON_SCHE: usize = 0x11;
+/// round-trip a _TestSerde (1): a0-enveloped _TestSerde ret-enveloped _TestSerde
 pub const TEST_SERDE: usize = 0x12;
 pub const REGISTER_TIMER: usize = 0x13;
 pub const READ_TIME: usize = 0x14;
+/// set the niceness of a process (2): a0-pid(0 for self) a1-nice(-10..=10) ret-ExitCode
+pub const SETPRIORITY: usize = 0x15;
+/// move a process into a process group (2): a0-pid(0 for self) a1-pgid(0 for a new group led by pid) ret-ExitCode
+pub const SETPGID: usize = 0x16;
+/// make a process group the console foreground (1): a0-pgid ret-ExitCode
+pub const TCSETPGRP: usize = 0x17;
+/// run the kernel self tests, root only (0): ret-ExitCode
+pub const SELFTEST: usize = 0x18;
+/// show or hide the kernel status bar, root only (1): a0-on(0/1) ret-ExitCode
+pub const STATUSBAR: usize = 0x19;
+/// query physical memory and kernel heap usage (0): ret-postcarded MemInfo
+pub const MEMINFO: usize = 0x1A;
+/// nanoseconds since boot (0): ret-ns. See `vdso::fast_now_ns` for the syscall-free version
+pub const UPTIME: usize = 0x1B;
+/// reboot the machine, root only (0): ret-ExitCode, only returns on failure
+pub const REBOOT: usize = 0x1C;
+/// power off the machine, root only (0): ret-ExitCode, only returns on failure
+pub const SHUTDOWN: usize = 0x1D;
+/// wall-clock time from the RTC (0): ret-Unix timestamp in seconds
+pub const REALTIME: usize = 0x1E;
+/// set the scheduler time slice, root only (1): a0-milliseconds ret-ExitCode
+pub const SETQUANTUM: usize = 0x1F;
 /// list files and directories in specified directory.
 ///
-/// format: (2): a0-len,a1-postcarded FE ret-postcarded Vec-FE
+/// format: (1): a0-enveloped path ret-enveloped Result<Vec-FE>, see [`crate::envelope`]
 ///
 /// *Not recommend for mannual use.*
 pub const LIST: usize = 0x20;
@@ -75,6 +107,10 @@ pub const READ: usize = 0x24;
 pub const WRITE_PATH: usize = 0x25;
 pub const READ_PATH: usize = 0x26;
 pub const SPAWN_FROM_PATH: usize = 0x27;
+/// move the offset of an opened file (3): a0-handle a1-offset(isize) a2-whence ret-postcarded Result
+pub const SEEK: usize = 0x28;
+/// write several user buffers to an opened file in order (3): a0-handle a1-IoVec array a2-count ret-postcarded (written, Option-FileError)
+pub const WRITEV: usize = 0x29;
 pub const CREATE_WINDOW: usize = 0x30;
 pub const DISPLAY_FONT_STRING: usize = 0x31;
 pub const LOAD_FONT: usize = 0x32;
@@ -82,6 +118,62 @@ pub const REMOVE_FONT: usize = 0x33;
 pub const DESTROY_WINDOW: usize = 0x34;
 pub const GUI_SUBSCRIBE_TIME_UPDATE: usize = 0x35;
 pub const GUI_SUBSCRIBE_KEYBOARD: usize = 0x36;
+/// CPU usage of the current process (0): ret-postcarded Rusage
+pub const RUSAGE: usize = 0x40;
+/// interrupt and scheduling counters since boot (0): ret-postcarded IrqStat
+pub const IRQSTAT: usize = 0x41;
+/// CPU ticks of the current process and its exited children (0): ret-postcarded Times
+pub const TIMES: usize = 0x42;
+/// ask a process to terminate, root or its parent only (1): a0-pid ret-ExitCode
+pub const KILL: usize = 0x43;
+/// rename the current process (1): a0-enveloped name ret-enveloped ()
+pub const SET_PROC_NAME: usize = 0x44;
+/// list running processes (1): a0-enveloped () ret-enveloped Vec-ProcInfo
+pub const PROC_LIST: usize = 0x45;
+/// register the handler the first EXIT jumps to, 0 to clear (1): a0-handler address ret-ExitCode
+pub const ATEXIT_SET: usize = 0x46;
+/// change page access rights of the caller's memory (3): a0-addr a1-len a2-ProtFlags ret-ExitCode
+pub const MPROTECT: usize = 0x47;
+/// name of a process, None for the caller (1): a0-enveloped Option-pid ret-enveloped Option-String
+pub const GET_PROC_NAME: usize = 0x48;
+/// uptime, load averages, CPU split, frames and process count (0): ret-postcarded SysInfo
+pub const SYSINFO: usize = 0x49;
+/// register the handler fatal faults jump to, 0 to clear (1): a0-handler address ret-ExitCode
+pub const FAULT_HANDLER_SET: usize = 0x4A;
+/// get or set the flags of a handle or of its number (3): a0-handle a1-command a2-flags ret-postcarded Result of the flags
+pub const FCNTL: usize = 0x4B;
+/// wait until one of several handles is ready (3): a0-PollFd array a1-count a2-timeout in ticks ret-postcarded Result of the ready mask
+pub const POLL: usize = 0x4C;
+/// list the user-accessible mappings of a process, root only (1): a0-enveloped pid ret-enveloped Option-Vec-MapRange
+pub const PAGEMAP: usize = 0x4D;
+/// mapped and maximal size of the caller's stack, it grows on demand (0): ret-postcarded StackSize
+pub const STACKSIZE: usize = 0x4E;
+/// write out console output still held back by line buffering (0): ret-ExitCode
+pub const FLUSH: usize = 0x4F;
+/// turn line buffering of console output on or off, root only (1): a0-on(0/1) ret-ExitCode
+pub const CONSOLE_BUFFERED: usize = 0x50;
+/// where a program was loaded, None for the caller, other processes root only (1): a0-enveloped Option-pid ret-enveloped Option-ImageInfo
+pub const IMAGEINFO: usize = 0x51;
+/// create a message queue owned by the caller (1): a0-capacity in messages ret-postcarded Result of the queue id
+pub const MSGQ_CREATE: usize = 0x52;
+/// enqueue one message, waiting for room unless NONBLOCK (4): a0-queue id a1-buf a2-len a3-MsgFlags ret-postcarded Result
+pub const MSGQ_SEND: usize = 0x53;
+/// wait for the next message and copy it out (3): a0-queue id a1-buf a2-buf len ret-postcarded Result of the message length
+pub const MSGQ_RECV: usize = 0x54;
+/// spawn with the child's standard handles replaced (1): a0-postcarded (path, args, flags, limits, jail, Vec-(child, parent)) ret-postcarded bool
+pub const SPAWN_REDIRECTED: usize = 0x55;
+/// slide movable heap blocks down and unmap freed tail pages (2): a0-Movable array a1-count ret-postcarded Option-Compaction
+pub const HEAP_COMPACT: usize = 0x56;
+/// spawn a program and sleep until it exits (1): a0-postcarded (path, args, flags, limits) ret-ExitCode of the child, ExecError if not spawned
+pub const RUN: usize = 0x57;
+/// change the caller's own resource limits, only root may loosen them (1): a0-postcarded Limits ret-ExitCode
+pub const SETRLIMIT: usize = 0x58;
+/// resource limits of the caller (0): ret-postcarded Limits
+pub const GETRLIMIT: usize = 0x59;
+/// take the keyboard into raw mode or give it back, foreground only (1): a0-raw(0/1) ret-ExitCode
+pub const KEYMODE: usize = 0x5A;
+/// take the next raw key event, waiting unless NONBLOCK (1): a0-KeyFlags ret-postcarded Result of the KeyEvent
+pub const READKEY: usize = 0x5B;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SysCallResult {
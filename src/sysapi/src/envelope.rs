@@ -0,0 +1,117 @@
+//! Versioned request/response envelopes for the serde-based system calls.
+//!
+//! A call sends a [`Request`] and gets back a [`Response`], both serialized with the same
+//! mechanism as every other serde call (see [`crate::call::syscall_serialized`]).
+//! The version travels in front of the body, so the kernel can refuse a body it does not
+//! understand with [`STATUS_BAD_VERSION`] before trying to decode it.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use cinea_os_sysapi::call::LIST;
+//! use cinea_os_sysapi::envelope::{user_call, EnvelopeError};
+//! use cinea_os_sysapi::fs::{FileEntry, FileError};
+//!
+//! let entries: Result<Result<Vec<FileEntry>, FileError>, EnvelopeError> = user_call(LIST, "/bin");
+//! ```
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// The envelope version this build of the kernel and userland speak.
+pub const ENVELOPE_VERSION: u16 = 1;
+
+/// The call succeeded, the body is present.
+pub const STATUS_OK: i32 = 0;
+/// The request was sent with a version the kernel does not understand.
+pub const STATUS_BAD_VERSION: i32 = -1;
+/// The request could not be decoded.
+pub const STATUS_MALFORMED: i32 = -2;
+/// The request could not be read from the caller's memory.
+pub const STATUS_BAD_ADDRESS: i32 = -3;
+/// The caller is not allowed to make the call, e.g. a root-only call from another user.
+pub const STATUS_PERMISSION: i32 = -4;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Request<T> {
+    pub version: u16,
+    pub body: T,
+}
+
+impl<T> Request<T> {
+    pub fn new(body: T) -> Self {
+        Self { version: ENVELOPE_VERSION, body }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Response<T> {
+    pub version: u16,
+    pub status: i32,
+    pub body: Option<T>,
+}
+
+impl<T> Response<T> {
+    pub fn ok(body: T) -> Self {
+        Self { version: ENVELOPE_VERSION, status: STATUS_OK, body: Some(body) }
+    }
+
+    pub fn err(status: i32) -> Self {
+        Self { version: ENVELOPE_VERSION, status, body: None }
+    }
+}
+
+/// Why an enveloped call did not produce a body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvelopeError {
+    /// The other side speaks a version we do not understand.
+    BadVersion(u16),
+    /// The buffer could not be decoded.
+    Malformed,
+    /// The kernel answered with a non-zero status.
+    Status(i32),
+}
+
+impl EnvelopeError {
+    /// The status the kernel replies with for this error.
+    pub fn status(&self) -> i32 {
+        match self {
+            EnvelopeError::BadVersion(_) => STATUS_BAD_VERSION,
+            EnvelopeError::Malformed => STATUS_MALFORMED,
+            EnvelopeError::Status(status) => *status,
+        }
+    }
+}
+
+/// Decode a serialized [`Request`], checking the version before the body is touched.
+pub fn decode_request<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, EnvelopeError> {
+    let (version, rest) = postcard::take_from_bytes::<u16>(bytes).map_err(|_| EnvelopeError::Malformed)?;
+    if version != ENVELOPE_VERSION {
+        return Err(EnvelopeError::BadVersion(version));
+    }
+    postcard::from_bytes(rest).map_err(|_| EnvelopeError::Malformed)
+}
+
+/// Decode a serialized [`Response`] into its body.
+pub fn decode_response<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, EnvelopeError> {
+    let (version, rest) = postcard::take_from_bytes::<u16>(bytes).map_err(|_| EnvelopeError::Malformed)?;
+    if version != ENVELOPE_VERSION {
+        return Err(EnvelopeError::BadVersion(version));
+    }
+    let (status, rest) = postcard::take_from_bytes::<i32>(rest).map_err(|_| EnvelopeError::Malformed)?;
+    let body: Option<T> = postcard::from_bytes(rest).map_err(|_| EnvelopeError::Malformed)?;
+    match (status, body) {
+        (STATUS_OK, Some(body)) => Ok(body),
+        (STATUS_OK, None) => Err(EnvelopeError::Malformed),
+        (status, _) => Err(EnvelopeError::Status(status)),
+    }
+}
+
+/// Make an enveloped system call: wrap `req` in a [`Request`] and unwrap the [`Response`].
+pub fn user_call<TReq, TResp>(num: usize, req: TReq) -> Result<TResp, EnvelopeError>
+    where TReq: Serialize, TResp: DeserializeOwned {
+    let encoded = crate::call::syscall_serialized(&Request::new(req));
+    let ret = unsafe { crate::syscall!(num, encoded) };
+    let data = crate::call::syscall_deserialized_prepare(ret);
+    decode_response(data.as_slice())
+}
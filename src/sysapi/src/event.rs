@@ -1,4 +1,5 @@
 use core::arch::asm;
+use core::sync::atomic::AtomicU32;
 
 use crate::{event_call, syscall};
 use crate::call::{GUI_SUBSCRIBE_KEYBOARD, GUI_SUBSCRIBE_TIME_UPDATE, REGISTER_TIMER};
@@ -7,19 +8,69 @@ use crate::syscall::log;
 pub const KEYBOARD_INPUT: usize = 0x00;
 pub const SLEEP_WAKEUP: usize = 0x01;
 pub const GUI_PROGRAM: usize = 0x02;
+/// sleep while a u32 in user memory still holds the expected value (2): a0-addr a1-expected ret-0 after wakeup
+pub const FUTEX_WAIT: usize = 0x03;
+/// wake processes sleeping on a user address (2): a0-addr a1-max count ret-number woken
+pub const FUTEX_WAKE: usize = 0x04;
+
+/// Returned by `KEYBOARD_INPUT` when the caller is not in the console foreground group.
+pub const NOT_FOREGROUND: usize = usize::MAX;
+
+/// Returned by `FUTEX_WAIT` when the value no longer matched, so the caller did not sleep.
+pub const FUTEX_AGAIN: usize = usize::MAX - 1;
+/// Returned by the futex calls when the address is unaligned or not mapped.
+pub const FUTEX_FAULT: usize = usize::MAX - 2;
+/// Returned by a sleep or wait that was cut short because the process is being terminated.
+pub const SLEEP_INTERRUPTED: usize = usize::MAX - 3;
+
+/// Sleep until woken by [`futex_wake`], but only if `word` still holds `expected`.
+///
+/// Returns 0 after a wakeup, or [`FUTEX_AGAIN`] at once if the value had already changed.
+/// Like any futex, a wakeup does not promise anything about the value: check it again.
+///
+/// Waiters are keyed by physical address. Processes share no writable memory yet, but
+/// every process maps the same read-only [`crate::vdso`] page, so a word there can be
+/// used to wait for a wakeup from another process.
+pub fn futex_wait(word: &AtomicU32, expected: u32) -> usize {
+    unsafe { event_call!(FUTEX_WAIT, word.as_ptr(), expected) }
+}
+
+/// Wake at most `n` processes sleeping on `word`, returns how many were woken.
+pub fn futex_wake(word: &AtomicU32, n: usize) -> usize {
+    unsafe { event_call!(FUTEX_WAKE, word.as_ptr(), n) }
+}
 
-pub fn sleep(million_seconds: usize) {
-    unsafe { event_call!(SLEEP_WAKEUP, million_seconds); }
+/// Sleep for `million_seconds` milliseconds.
+///
+/// Returns `false` if the sleep was cut short by [`crate::syscall::kill`]; the kernel ends
+/// the process right after, so this is mostly a hint for the last words.
+pub fn sleep(million_seconds: usize) -> bool {
+    unsafe { event_call!(SLEEP_WAKEUP, million_seconds) != SLEEP_INTERRUPTED }
 }
 
+/// Read a key, waiting until the process is in the console foreground group.
 pub fn getch(display_back: bool) -> char {
+    loop {
+        if let Some(ch) = try_getch(display_back) {
+            return ch;
+        }
+        sleep(50);
+    }
+}
+
+/// Read a key, or return `None` at once if the process is in the background.
+pub fn try_getch(display_back: bool) -> Option<char> {
     unsafe {
-        let res = char::from_u32_unchecked(event_call!(KEYBOARD_INPUT) as u32);
+        let res = event_call!(KEYBOARD_INPUT);
+        if res == NOT_FOREGROUND {
+            return None;
+        }
+        let res = char::from_u32_unchecked(res as u32);
         if display_back {
             let mut buf = [0u8;4];
             log(char::encode_utf8(res, &mut buf).as_bytes());
         }
-        res
+        Some(res)
     }
 }
 
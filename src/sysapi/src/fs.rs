@@ -4,137 +4,33 @@ use alloc::vec::Vec;
 use core::fmt::Debug;
 
 use bitflags::bitflags;
+pub use cinea_os_kcore::path::{dirname, filename, path_combine, realpath};
+use cinea_os_kcore::path::{resolve_dots, standardize};
 use serde::{Deserialize, Serialize};
 use ufmt::uDebug;
 
 use FileError::BadRelatePathError;
 
 use crate::call::*;
+use crate::envelope::user_call;
 use crate::fs::FileError::NotAFileError;
+use crate::syscall::{Limits, SpawnFlags};
 use crate::time::{Date, DateTime};
+use crate::ExitCode;
 
 pub trait FileIO: Send + Sync {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()>;
     fn write(&mut self, buf: &[u8]) -> Result<usize, ()>;
-}
 
-/// Returns the directory component of a pathname.
-///
-/// Given a pathname, this function returns the leading component of the pathname, up to and including the last slash `/`.
-/// If there is no slash in the pathname, the entire pathname is returned.
-///
-/// # Examples
-///
-/// ```
-/// use crate::fs::dirname;
-///
-/// assert_eq!(dirname("/usr/bin/gcc"), "/usr/bin");
-/// assert_eq!(dirname("/usr/bin/"), "/usr/bin");
-/// assert_eq!(dirname("/usr/"), "/usr");
-/// assert_eq!(dirname("/"), "/");
-/// assert_eq!(dirname("gcc"), "gcc");
-/// ```
-///
-/// # Arguments
-///
-/// * `pathname`: A string slice containing the pathname to extract the directory component from.
-///
-/// # Returns
-///
-/// The directory component of the pathname.
-///
-/// # Safety
-///
-/// This function is safe to use as long as the `pathname` argument is a valid null-terminated string.
-/// If the `pathname` argument is not a valid null-terminated string, this function may cause undefined behavior.
-pub fn dirname(pathname: &str) -> &str {
-    let n = pathname.len();
-    let i = match pathname.rfind('/') {
-        Some(0) => 1, // 根目录
-        Some(i) => i,
-        None => n,
-    };
-    &pathname[0..i]
-}
-
-/// Returns the filename component of a pathname.
-///
-/// Given a pathname, this function returns the trailing component of the pathname, after the last slash `/`.
-/// If there is no slash in the pathname, the entire pathname is returned.
-///
-/// # Examples
-///
-/// ```
-/// use crate::fs::filename;
-///
-/// assert_eq!(filename("/usr/bin/gcc"), "gcc");
-/// assert_eq!(filename("/usr/bin/"), "");
-/// assert_eq!(filename("/usr/"), "");
-/// assert_eq!(filename("/"), "");
-/// assert_eq!(filename("gcc"), "gcc");
-/// ```
-///
-/// # Arguments
-///
-/// * `pathname`: A string slice containing the pathname to extract the filename component from.
-///
-/// # Returns
-///
-/// The filename component of the pathname.
-///
-/// # Safety
-///
-/// This function is safe to use as long as the `pathname` argument is a valid null-terminated string.
-/// If the `pathname` argument is not a valid null-terminated string, this function may cause undefined behavior.
-pub fn filename(pathname: &str) -> &str {
-    let n = pathname.len();
-    let i = match pathname.rfind('/') {
-        Some(i) => i + 1,
-        None => 0,
-    };
-    &pathname[i..n]
-}
-
-/// Returns the absolute path of a pathname.
-///
-/// Given a pathname and the current working directory, this function returns the absolute path of the pathname.
-/// If the pathname is already an absolute path, it is returned unchanged.
-/// Otherwise, the pathname is resolved relative to the current working directory.
-///
-/// # Examples
-///
-/// ```
-/// use crate::fs::realpath;
-///
-/// assert_eq!(realpath("/usr/bin/gcc", "/home/user"), "/usr/bin/gcc");
-/// assert_eq!(realpath("gcc", "/usr/bin"), "/usr/bin/gcc");
-/// assert_eq!(realpath("gcc", "/usr/bin/"), "/usr/bin/gcc");
-/// ```
-///
-/// # Arguments
-///
-/// * `pathname`: A string slice containing the pathname to resolve.
-/// * `current_dir`: A string slice containing the current working directory to resolve the pathname relative to.
-///
-/// # Returns
-///
-/// The absolute path of the pathname.
-///
-/// # Safety
-///
-/// This function is safe to use as long as the `pathname` and `current_dir` arguments are valid null-terminated strings.
-/// If the `pathname` or `current_dir` arguments are not valid null-terminated strings, this function may cause undefined behavior.
-pub fn realpath(pathname: &str, current_dir: &str) -> String {
-    if pathname.starts_with('/') {
-        pathname.into()
-    } else {
-        path_combine(current_dir, pathname)
+    /// Whether a `read` would return at once instead of waiting for data.
+    fn readable(&self) -> bool {
+        true
     }
-}
 
-pub fn path_combine(path1: &str, path2: &str) -> String {
-    let sep = if path1.ends_with('/') { "" } else { "/" };
-    alloc::format!("{}{}{}", path1, sep, path2)
+    /// Whether a `write` would return at once instead of waiting for room.
+    fn writable(&self) -> bool {
+        true
+    }
 }
 
 /// Error types that can occur when interacting with the filesystem.
@@ -157,6 +53,26 @@ pub enum FileError {
     OpenMethodError,
     /// Returned when a device I/O error occurs.
     DeviceIOError,
+    /// Returned when trying to seek on a handle that has no position, e.g. the console.
+    NotSeekableError,
+    /// Returned when a pointer handed to the kernel cannot be accessed.
+    BadAddressError,
+    /// Returned when a seek would move the offset before the start of the file or past `u32::MAX`, the largest FAT file.
+    InvalidSeekError,
+    /// Returned when a vectored write has more than [`MAX_IOV`] segments.
+    TooManySegmentsError,
+    /// Returned when a vectored write has more than [`MAX_IOV_BYTES`] bytes in total.
+    TooLargeError,
+    /// Returned when trying to modify a file on a read-only filesystem, such as the initramfs.
+    ReadOnlyError,
+    /// Returned when the kernel cannot decode the arguments of a call.
+    MalformedError,
+    /// Returned when the process already has as many open files as its limits allow.
+    TooManyFilesError,
+    /// Returned by a nonblocking handle when the call would have to wait, e.g. reading an empty console.
+    WouldBlockError,
+    /// Returned when [`poll`] is given more than [`MAX_POLL_FDS`] handles.
+    TooManyHandlesError,
     /// Returned for miscellaneous OS errors.
     OSError,
 }
@@ -174,6 +90,16 @@ impl uDebug for FileError {
             FileError::FileBusyError => w.write_str("FileBusyError"),
             FileError::OpenMethodError => w.write_str("OpenMethodError"),
             FileError::DeviceIOError => w.write_str("DeviceIOError"),
+            FileError::NotSeekableError => w.write_str("NotSeekableError"),
+            FileError::BadAddressError => w.write_str("BadAddressError"),
+            FileError::InvalidSeekError => w.write_str("InvalidSeekError"),
+            FileError::TooManySegmentsError => w.write_str("TooManySegmentsError"),
+            FileError::TooLargeError => w.write_str("TooLargeError"),
+            FileError::ReadOnlyError => w.write_str("ReadOnlyError"),
+            FileError::MalformedError => w.write_str("MalformedError"),
+            FileError::TooManyFilesError => w.write_str("TooManyFilesError"),
+            FileError::WouldBlockError => w.write_str("WouldBlockError"),
+            FileError::TooManyHandlesError => w.write_str("TooManyHandlesError"),
             FileError::OSError => w.write_str("OSError"),
         }
     }
@@ -194,6 +120,30 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Flags used when opening a file.
+    #[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct OpenFlags: u32 {
+        /// Open the file for writing.
+        const WRITE  = 0x01;
+        /// Every write goes to the end of the file, regardless of the current offset.
+        const APPEND = 0x02;
+        /// Reads and writes that would wait fail with [`FileError::WouldBlockError`] instead.
+        const NONBLOCK = 0x04;
+    }
+}
+
+/// Reference point of a `seek`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(usize)]
+pub enum Whence {
+    /// Seek from the start of the file.
+    Set = 0,
+    /// Seek from the current offset.
+    Current = 1,
+    /// Seek from the end of the file.
+    End = 2,
+}
 
 /// Represents metadata information for a file or directory.
 ///
@@ -267,6 +217,36 @@ impl Metadata {
         }
     }
 
+    /// Initialize the object for a virtual node that has no backing dir entry, such as `/dev/null`
+    pub fn new_virtual(path: &str, is_dir: bool) -> Self {
+        let name = String::from(filename(path));
+        let epoch = Date::new(1980, 1, 1);
+        let midnight = DateTime { date: epoch, time: crate::time::Time::new(0, 0, 0, 0) };
+        let mut attributes = FileAttributes::SYSTEM;
+        if is_dir {
+            attributes |= FileAttributes::DIRECTORY;
+        }
+        Self {
+            path: String::from(path),
+            short_file_name: name.clone(),
+            file_name: name,
+            attributes,
+            is_dir,
+            is_file: !is_dir,
+            len: 0,
+            created: midnight,
+            accessed: epoch,
+            modified: midnight,
+        }
+    }
+
+    /// Mark a virtual node as a read-only file of `len` bytes, such as a file in the initramfs
+    pub fn read_only(mut self, len: u64) -> Self {
+        self.attributes |= FileAttributes::READ_ONLY;
+        self.len = len;
+        self
+    }
+
     /// Returns the short file name.
     pub fn short_file_name(&self) -> &str {
         &self.short_file_name
@@ -314,37 +294,20 @@ impl Metadata {
 }
 
 pub fn process_relative_path(splited_path: &mut Vec<&str>) -> Result<(), FileError> {
-    let mut i = 0usize;
-    while i < splited_path.len() {
-        if splited_path[i] == "." {
-            splited_path.remove(i);
-        } else if splited_path[i] == ".." {
-            if i == 0 { return Err(BadRelatePathError) }
-            splited_path.remove(i);
-            splited_path.remove(i - 1);
-            i -= 1;
-        } else {
-            i += 1;
-        }
-    }
-    Ok(())
+    resolve_dots(splited_path).map_err(|_| BadRelatePathError)
 }
 
 pub fn path_standardize(path: &str) -> Result<String, FileError> {
-    let path = String::from(path);
-    let mut spilted_path: Vec<_> = path.split('/').filter(|x| { x.len() > 0 }).collect();
-    process_relative_path(&mut spilted_path)?;
-    spilted_path.insert(0, "");
-    Ok(spilted_path.join("/"))
+    standardize(path).map_err(|_| BadRelatePathError)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileDevice(usize);
 
 impl FileDevice {}
 
 /// Filesystem entry representing a file, directory, or device node.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum FileEntry {
     /// Entry for a directory.
     Dir(Metadata),
@@ -374,7 +337,7 @@ impl FileEntry {
         match self {
             FileEntry::Dir(dir) => {
                 // 调用系统调用查询
-                let ret: Result<Result<Vec<Self>, FileError>, _> = syscall_with_serdeser!(LIST, dir.path);
+                let ret: Result<Result<Vec<Self>, FileError>, _> = user_call(LIST, dir.path.as_str());
                 match ret {
                     Err(_) => Err(FileError::OSError),
                     Ok(ret) => ret
@@ -387,7 +350,7 @@ impl FileEntry {
 
 pub fn list(path: &str) -> Result<Vec<FileEntry>, FileError> {
     // 调用系统调用查询
-    let ret: Result<Result<Vec<FileEntry>, FileError>, _> = syscall_with_serdeser!(LIST, path);
+    let ret: Result<Result<Vec<FileEntry>, FileError>, _> = user_call(LIST, path);
     match ret {
         Err(_) => Err(FileError::OSError),
         Ok(ret) => ret
@@ -395,7 +358,153 @@ pub fn list(path: &str) -> Result<Vec<FileEntry>, FileError> {
 }
 
 pub fn open(path: &str, write: bool) -> Result<usize, FileError> {
-    let ret: Result<Result<usize, FileError>, _> = syscall_with_serdeser!(OPEN, (String::from(path), write));
+    let flags = if write { OpenFlags::WRITE } else { OpenFlags::empty() };
+    open_with_flags(path, flags)
+}
+
+pub fn open_with_flags(path: &str, flags: OpenFlags) -> Result<usize, FileError> {
+    let ret: Result<Result<usize, FileError>, _> = syscall_with_serdeser!(OPEN, (String::from(path), flags.bits()));
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Move the offset of an opened file, returns the new offset.
+///
+/// Handles that share an offset (see [`dup`]) move together.
+/// Seeking before the start of the file fails with [`FileError::InvalidSeekError`].
+/// Seeking past the end is allowed: a later read returns 0 bytes, and a later write fills the gap with zeros.
+/// Devices such as the console fail with [`FileError::NotSeekableError`].
+pub fn seek(handle: usize, offset: isize, whence: Whence) -> Result<usize, FileError> {
+    let ret: Result<Result<usize, FileError>, _> = syscall_with_deserialize!(SEEK, handle, offset, whence as usize);
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Maximum number of segments in one [`write_vectored`] call
+pub const MAX_IOV: usize = 64;
+/// Maximum total bytes in one [`write_vectored`] call
+pub const MAX_IOV_BYTES: usize = 1 << 20;
+
+/// One segment of a vectored write, laid out as the kernel reads it
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct IoVec {
+    pub ptr: usize,
+    pub len: usize,
+}
+
+/// Write several buffers to a handle in order with a single syscall, returning the total bytes written.
+///
+/// At most [`MAX_IOV`] buffers and [`MAX_IOV_BYTES`] bytes; larger requests fail before anything is written.
+/// If buffer `k` cannot be written, buffers `0..k` stay written and `Err((written, error))` tells how far it got.
+pub fn write_vectored(handle: usize, bufs: &[&[u8]]) -> Result<usize, (usize, FileError)> {
+    let iov: Vec<IoVec> = bufs.iter().map(|buf| IoVec { ptr: buf.as_ptr() as usize, len: buf.len() }).collect();
+    let ret: Result<(usize, Option<FileError>), _> = syscall_with_deserialize!(WRITEV, handle, iov.as_ptr() as usize, iov.len());
+    match ret {
+        Err(_) => Err((0, FileError::OSError)),
+        Ok((written, None)) => Ok(written),
+        Ok((written, Some(err))) => Err((written, err)),
+    }
+}
+
+/// Maximum number of handles in one [`poll`] call, one bit each in the result
+pub const MAX_POLL_FDS: usize = 64;
+/// [`poll`] timeout that waits until a handle is ready
+pub const POLL_FOREVER: usize = usize::MAX;
+
+bitflags! {
+    /// What a [`poll`] caller waits for on one handle.
+    #[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct PollEvents: u32 {
+        /// A read would not wait.
+        const READ  = 0x01;
+        /// A write would not wait.
+        const WRITE = 0x02;
+    }
+}
+
+/// One handle of a [`poll`] call, laid out as the kernel reads it
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PollFd {
+    pub fd: usize,
+    pub events: usize,
+}
+
+impl PollFd {
+    pub fn new(fd: usize, events: PollEvents) -> Self {
+        Self { fd, events: events.bits() as usize }
+    }
+}
+
+/// Wait until at least one of the handles is ready, returns a mask with bit `i` set when `fds[i]` is.
+///
+/// Regular files are always ready; devices such as the console are ready when a read or write would not wait.
+/// `timeout` is in timer ticks, 0 only checks and [`POLL_FOREVER`] never gives up; an empty mask means it timed out.
+/// At most [`MAX_POLL_FDS`] handles, more fail with [`FileError::TooManyHandlesError`].
+pub fn poll(fds: &[PollFd], timeout: usize) -> Result<u64, FileError> {
+    let ret: Result<Result<u64, FileError>, _> = syscall_with_deserialize!(POLL, fds.as_ptr() as usize, fds.len(), timeout);
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Handle numbers below this belong to the standard devices every process starts with, 0 is stdout.
+/// They cannot be closed, but [`spawn_from_path_redirected`] can point them elsewhere in the child.
+pub const STDIO_HANDLES: usize = 4;
+
+/// `fcntl` command: read the flags of a handle
+pub const F_GETFL: usize = 0;
+/// `fcntl` command: replace the flags of a handle; only [`OpenFlags::APPEND`] and [`OpenFlags::NONBLOCK`] can change
+pub const F_SETFL: usize = 1;
+/// `fcntl` command: read the descriptor flags of a handle number, see [`FD_CLOEXEC`]
+pub const F_GETFD: usize = 2;
+/// `fcntl` command: replace the descriptor flags of a handle number
+pub const F_SETFD: usize = 3;
+/// Descriptor flag: close this handle number in the new program on `exec`, and do not hand it to spawned children.
+pub const FD_CLOEXEC: usize = 1;
+
+fn fcntl(handle: usize, cmd: usize, arg: usize) -> Result<usize, FileError> {
+    let ret: Result<Result<usize, FileError>, _> = syscall_with_deserialize!(FCNTL, handle, cmd, arg);
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
+/// Flags of an opened handle, as given at open or changed by [`set_flags`].
+pub fn get_flags(handle: usize) -> Result<OpenFlags, FileError> {
+    fcntl(handle, F_GETFL, 0).map(|bits| OpenFlags::from_bits_truncate(bits as u32))
+}
+
+/// Change the flags of an opened handle, returns the flags now in effect.
+///
+/// Handles that share an offset (see [`dup`]) share their flags too. Whether the handle can write is fixed at open.
+pub fn set_flags(handle: usize, flags: OpenFlags) -> Result<OpenFlags, FileError> {
+    fcntl(handle, F_SETFL, flags.bits() as usize).map(|bits| OpenFlags::from_bits_truncate(bits as u32))
+}
+
+/// Whether the handle number is closed on `exec` and kept from spawned children.
+pub fn get_cloexec(handle: usize) -> Result<bool, FileError> {
+    fcntl(handle, F_GETFD, 0).map(|flags| flags & FD_CLOEXEC != 0)
+}
+
+/// Mark the handle number to be closed on `exec` and kept from spawned children, or clear the mark.
+///
+/// Unlike [`set_flags`] this belongs to the number alone: a [`dup`] of the handle is inherited as usual.
+/// Handles are inherited by default. The standard handles below 4 cannot be marked.
+pub fn set_cloexec(handle: usize, cloexec: bool) -> Result<(), FileError> {
+    fcntl(handle, F_SETFD, if cloexec { FD_CLOEXEC } else { 0 }).map(|_| ())
+}
+
+/// Duplicate a handle. The new handle shares the offset of the old one; the file is closed with the last of them.
+pub fn dup(handle: usize) -> Result<usize, FileError> {
+    let ret: Result<Result<usize, FileError>, _> = syscall_with_deserialize!(DUP, handle);
     match ret {
         Err(_) => Err(FileError::OSError),
         Ok(ret) => ret
@@ -446,6 +555,15 @@ pub fn info(path: &str) -> Result<Metadata, FileError> {
     }
 }
 
+/// Delete a file or an empty directory.
+pub fn delete(path: &str) -> Result<(), FileError> {
+    let ret: Result<Result<(), FileError>, _> = syscall_with_serdeser!(DELETE, String::from(path));
+    match ret {
+        Err(_) => Err(FileError::OSError),
+        Ok(ret) => ret
+    }
+}
+
 pub fn read_all_from_path(path: &str) -> Result<Vec<u8>, FileError> {
     let metadata = info(path)?;
     if !metadata.is_file() { return Err(NotAFileError); }
@@ -456,7 +574,64 @@ pub fn read_all_from_path(path: &str) -> Result<Vec<u8>, FileError> {
 }
 
 pub fn spawn_from_path(path: &str, args: Vec<String>) -> bool {
-    let ret:Result<bool,_> = syscall_with_serdeser!(SPAWN_FROM_PATH,(String::from(path),args));
+    spawn_from_path_with_flags(path, args, SpawnFlags::empty())
+}
+
+/// Replace the current program with the one at `path`, keeping the pid, parent and open files.
+///
+/// Returning at all means the program could not be loaded, and the caller keeps running unchanged.
+pub fn exec_from_path(path: &str, args: Vec<String>) {
+    let _ret:Result<bool,_> = syscall_with_serdeser!(EXEC,(String::from(path),args));
+}
+
+/// Spawn a program from the filesystem with extra `SpawnFlags`.
+pub fn spawn_from_path_with_flags(path: &str, args: Vec<String>, flags: SpawnFlags) -> bool {
+    spawn_from_path_with_limits(path, args, flags, None)
+}
+
+/// Spawn a program from the filesystem with extra `SpawnFlags`, sandboxed by `limits`.
+///
+/// The child also inherits the caller's own limits; unless the caller is root it cannot loosen them.
+pub fn spawn_from_path_with_limits(path: &str, args: Vec<String>, flags: SpawnFlags, limits: Option<Limits>) -> bool {
+    spawn_with_root(path, args, flags, limits, None)
+}
+
+/// Spawn a program confined to the directory `root`, otherwise like [`spawn_from_path_with_limits`].
+///
+/// The child sees `root` as `/`: its paths are resolved inside it and `..` cannot climb above it, and its own
+/// children stay inside as well. `root` is resolved inside the caller's jail, so a confined process can never widen
+/// its own. Root only, and `root` must be an existing directory.
+pub fn spawn_from_path_in_jail(path: &str, args: Vec<String>, flags: SpawnFlags, limits: Option<Limits>, root: &str) -> bool {
+    spawn_with_root(path, args, flags, limits, Some(String::from(root)))
+}
+
+/// Spawn a program whose standard handles are replaced before it runs, otherwise like [`spawn_from_path_with_limits`].
+///
+/// Each `(child, parent)` pair makes the child's handle `child` (below [`STDIO_HANDLES`]) share the caller's handle `parent`,
+/// offset included, e.g. `(0, file)` sends the child's output to `file`. The caller may close its own handle afterwards.
+/// If any pair is invalid nothing is spawned.
+pub fn spawn_from_path_redirected(path: &str, args: Vec<String>, flags: SpawnFlags, limits: Option<Limits>, redirects: &[(usize, usize)]) -> bool {
+    let ret: Result<bool, _> =
+        syscall_with_serdeser!(SPAWN_REDIRECTED, (String::from(path), args, flags.bits(), limits, Option::<String>::None, redirects.to_vec()));
+    matches!(ret, Ok(true))
+}
+
+/// Run a program to completion: spawn it and sleep until it exits, returns its exit code.
+///
+/// [`ExitCode::ExecError`] means nothing was spawned. A child that did not call exit itself reports
+/// [`ExitCode::Failure`], [`ExitCode::LimitExceeded`] when it used up a limit, or [`ExitCode::PageFaultError`] when it crashed.
+pub fn run(path: &str, args: Vec<String>) -> ExitCode {
+    run_with_limits(path, args, SpawnFlags::empty(), None)
+}
+
+/// Run a program to completion with extra `SpawnFlags` and `limits`, otherwise like [`run`].
+pub fn run_with_limits(path: &str, args: Vec<String>, flags: SpawnFlags, limits: Option<Limits>) -> ExitCode {
+    let encoded = syscall_serialized(&(String::from(path), args, flags.bits(), limits));
+    ExitCode::from(unsafe { crate::syscall!(RUN, encoded) })
+}
+
+fn spawn_with_root(path: &str, args: Vec<String>, flags: SpawnFlags, limits: Option<Limits>, root: Option<String>) -> bool {
+    let ret:Result<bool,_> = syscall_with_serdeser!(SPAWN_FROM_PATH,(String::from(path),args,flags.bits(),limits,root));
     match ret {
         Ok(true) => true,
         _ => false
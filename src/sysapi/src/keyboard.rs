@@ -0,0 +1,98 @@
+//! Raw keyboard input.
+//!
+//! Normally keys are decoded into characters and delivered to whoever reads the console, see [`crate::event::getch`].
+//! A full-screen program can take the keyboard into raw mode instead: every press and release then queues
+//! a [`KeyEvent`] for it, with no echo and no line buffering, until it switches back or exits.
+
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+
+use crate::call::{KEYMODE, READKEY};
+use crate::syscall;
+use crate::ExitCode;
+
+/// Most key events kept for the raw reader, later ones are dropped until it reads.
+pub const RAW_KEY_QUEUE_SIZE: usize = 128;
+
+bitflags! {
+    /// Modifier keys held, and the caps lock state, right after the event.
+    #[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Modifiers: u8 {
+        const LSHIFT = 0x01;
+        const RSHIFT = 0x02;
+        const LCTRL = 0x04;
+        const RCTRL = 0x08;
+        const LALT = 0x10;
+        const RALT = 0x20;
+        const CAPSLOCK = 0x40;
+    }
+}
+
+impl Modifiers {
+    pub fn shift(&self) -> bool {
+        self.intersects(Modifiers::LSHIFT | Modifiers::RSHIFT)
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.intersects(Modifiers::LCTRL | Modifiers::RCTRL)
+    }
+
+    pub fn alt(&self) -> bool {
+        self.intersects(Modifiers::LALT | Modifiers::RALT)
+    }
+}
+
+/// One key press or release.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyEvent {
+    /// Scan code set 1 code without the release bit; keys sent with the `0xE0` prefix have `0xE000` added.
+    pub scancode: u16,
+    /// `true` for a press (make code), `false` for a release (break code).
+    pub pressed: bool,
+    /// Position of the key in `pc_keyboard::KeyCode`, the same number GUI programs get for keys without a character.
+    pub keycode: u16,
+    /// Character the press produces with the current modifiers, if any. Ctrl with a letter gives a control character.
+    pub ch: Option<char>,
+    pub modifiers: Modifiers,
+}
+
+/// Errors of [`read_key`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyError {
+    /// The caller has not taken the keyboard into raw mode.
+    NotRaw,
+    /// No event is queued and [`KeyFlags::NONBLOCK`] was given.
+    WouldBlock,
+    /// The process was asked to terminate while waiting.
+    Interrupted,
+    /// The kernel reply could not be decoded.
+    OSError,
+}
+
+bitflags! {
+    /// Flags of [`read_key`].
+    #[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct KeyFlags: u32 {
+        /// Fail with [`KeyError::WouldBlock`] instead of waiting for a key.
+        const NONBLOCK = 0x01;
+    }
+}
+
+/// Take the keyboard into raw mode, or give it back to the console.
+///
+/// Only a process in the console foreground group may switch raw mode on, and only while no other process
+/// has it; switching on again drops the events still queued. The kernel gives the keyboard back when the owner exits.
+pub fn set_raw(raw: bool) -> Result<(), ExitCode> {
+    let res = unsafe { syscall!(KEYMODE, raw as usize) };
+    if res == ExitCode::Success as usize {
+        Ok(())
+    } else {
+        Err(ExitCode::from(res))
+    }
+}
+
+/// Take the next key event, waiting for one unless `flags` has [`KeyFlags::NONBLOCK`].
+pub fn read_key(flags: KeyFlags) -> Result<KeyEvent, KeyError> {
+    let ret: Result<Result<KeyEvent, KeyError>, _> = syscall_with_deserialize!(READKEY, flags.bits());
+    ret.unwrap_or(Err(KeyError::OSError))
+}
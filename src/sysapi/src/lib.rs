@@ -43,11 +43,16 @@ pub mod call;
 pub mod event;
 
 pub mod allocator;
+pub mod envelope;
 pub mod fs;
+pub mod keyboard;
+pub mod msgq;
 pub mod syscall;
 pub mod time;
 pub mod stdin;
+pub mod sync;
 pub mod gui;
+pub mod vdso;
 
 /// 进程退出代码
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -60,6 +65,9 @@ pub enum ExitCode {
     OpenError = 128,
     ReadError = 129,
     ExecError = 130,
+    PermissionError = 131,
+    /// 超出了创建时设置的资源限制
+    LimitExceeded = 132,
     PageFaultError = 200,
     ShellExit = 255,
 }
@@ -73,6 +81,8 @@ impl From<usize> for ExitCode {
             128 => ExitCode::OpenError,
             129 => ExitCode::ReadError,
             130 => ExitCode::ExecError,
+            131 => ExitCode::PermissionError,
+            132 => ExitCode::LimitExceeded,
             200 => ExitCode::PageFaultError,
             255 => ExitCode::ShellExit,
             _ => ExitCode::Failure,
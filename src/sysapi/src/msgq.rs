@@ -0,0 +1,65 @@
+//! Message queues: discrete messages between processes.
+//!
+//! Unlike a byte stream, every send enqueues one whole message and every receive takes one whole message out,
+//! so message boundaries survive. Messages are copied into kernel-owned buffers, sender and receiver share no memory.
+//! A queue belongs to the process that created it and is destroyed when that process exits;
+//! anyone who knows its id may send and receive.
+
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+
+use crate::call::{MSGQ_CREATE, MSGQ_RECV, MSGQ_SEND};
+
+/// Most messages a queue can hold
+pub const MAX_MSGQ_DEPTH: usize = 64;
+/// Largest message, in bytes
+pub const MAX_MSG_SIZE: usize = 4096;
+
+/// Errors of the message queue calls.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MsgqError {
+    /// There is no queue with this id, or it was destroyed while waiting.
+    NoSuchQueue,
+    /// The capacity is 0 or over [`MAX_MSGQ_DEPTH`].
+    BadCapacity,
+    /// Too many queues exist already.
+    TooManyQueues,
+    /// The message is over [`MAX_MSG_SIZE`], or the next message does not fit the receive buffer;
+    /// in the latter case it stays in the queue.
+    TooLarge,
+    /// The queue is full and [`MsgFlags::NONBLOCK`] was given.
+    WouldBlock,
+    /// The process was asked to terminate while waiting.
+    Interrupted,
+    /// The buffer is not mapped in the caller.
+    BadAddress,
+    /// The kernel reply could not be decoded.
+    OSError,
+}
+
+bitflags! {
+    /// Flags of [`send`].
+    #[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct MsgFlags: u32 {
+        /// Fail with [`MsgqError::WouldBlock`] instead of waiting when the queue is full.
+        const NONBLOCK = 0x01;
+    }
+}
+
+/// Create a queue holding at most `capacity` messages, returns its id.
+pub fn create(capacity: usize) -> Result<usize, MsgqError> {
+    let ret: Result<Result<usize, MsgqError>, _> = syscall_with_deserialize!(MSGQ_CREATE, capacity);
+    ret.unwrap_or(Err(MsgqError::OSError))
+}
+
+/// Enqueue `msg` as one message, waiting for room unless `flags` has [`MsgFlags::NONBLOCK`].
+pub fn send(qid: usize, msg: &[u8], flags: MsgFlags) -> Result<(), MsgqError> {
+    let ret: Result<Result<(), MsgqError>, _> = syscall_with_deserialize!(MSGQ_SEND, qid, msg.as_ptr() as usize, msg.len(), flags.bits());
+    ret.unwrap_or(Err(MsgqError::OSError))
+}
+
+/// Wait for the next message and copy it into `buf`, returns its length.
+pub fn recv(qid: usize, buf: &mut [u8]) -> Result<usize, MsgqError> {
+    let ret: Result<Result<usize, MsgqError>, _> = syscall_with_deserialize!(MSGQ_RECV, qid, buf.as_mut_ptr() as usize, buf.len());
+    ret.unwrap_or(Err(MsgqError::OSError))
+}
@@ -0,0 +1,81 @@
+//! Userland synchronization built on the kernel futex calls.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::event::{futex_wait, futex_wake};
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+/// Locked, and somebody may be sleeping in the kernel waiting for it
+const CONTENDED: u32 = 2;
+
+/// A mutex that sleeps in the kernel instead of spinning when it is contended.
+///
+/// Uncontended lock and unlock never enter the kernel. The lock word must live in memory
+/// every contending process can see, e.g. a shared page.
+pub struct FutexMutex<T> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for FutexMutex<T> {}
+unsafe impl<T: Send> Send for FutexMutex<T> {}
+
+impl<T> FutexMutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self { state: AtomicU32::new(UNLOCKED), data: UnsafeCell::new(data) }
+    }
+
+    /// Take the lock without sleeping, or return `None` if it is held.
+    pub fn try_lock(&self) -> Option<FutexMutexGuard<'_, T>> {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| FutexMutexGuard { mutex: self })
+    }
+
+    /// Take the lock, sleeping in the kernel while another process holds it.
+    pub fn lock(&self) -> FutexMutexGuard<'_, T> {
+        if let Some(guard) = self.try_lock() {
+            return guard;
+        }
+        // Mark the lock contended so the holder knows to wake us, then sleep until it is free
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            futex_wait(&self.state, CONTENDED);
+        }
+        FutexMutexGuard { mutex: self }
+    }
+
+    fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex_wake(&self.state, 1);
+        }
+    }
+}
+
+/// Releases the [`FutexMutex`] when dropped.
+pub struct FutexMutexGuard<'a, T> {
+    mutex: &'a FutexMutex<T>,
+}
+
+impl<T> Deref for FutexMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for FutexMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for FutexMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
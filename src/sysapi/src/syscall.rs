@@ -56,13 +56,23 @@
 use alloc::string::String;
 use alloc::vec::Vec;
 use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
+use bitflags::bitflags;
 use serde::{Deserialize, Serialize};
 
+use crate::allocator::Movable;
 use crate::call::*;
+use crate::envelope::{user_call, EnvelopeError};
 use crate::ExitCode;
 use crate::syscall;
 
+/// Send a [`_TestSerde`] to the kernel and get its answer back, the reference enveloped call.
+#[doc(hidden)]
+pub fn test_serde(obj: _TestSerde) -> Result<_TestSerde, EnvelopeError> {
+    user_call(TEST_SERDE, obj)
+}
+
 pub fn log(buf: &[u8]) -> Option<usize> {
     let ptr = buf.as_ptr() as usize;
     let len = buf.len();
@@ -90,6 +100,65 @@ pub fn exit(code: ExitCode) -> ! {
     unreachable!() // 避免编译器报错
 }
 
+static ATEXIT_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// Where the kernel sends the first [`exit`]: run the handler, then exit again with the same code.
+extern "sysv64" fn atexit_trampoline(code: usize) -> ! {
+    let handler = ATEXIT_HANDLER.swap(0, Ordering::SeqCst);
+    if handler != 0 {
+        let handler: fn() = unsafe { core::mem::transmute(handler) };
+        handler();
+    }
+    unsafe { syscall!(EXIT, code) };
+    unreachable!()
+}
+
+/// Run `handler` once when the process calls [`exit`] or returns from `main`, e.g. to flush output.
+///
+/// A later call replaces the handler. It does not run when the process is killed or crashes.
+pub fn atexit(handler: fn()) -> Result<(), ExitCode> {
+    ATEXIT_HANDLER.store(handler as usize, Ordering::SeqCst);
+    let res = unsafe { syscall!(ATEXIT_SET, atexit_trampoline as usize) };
+    if res == ExitCode::Success as usize {
+        Ok(())
+    } else {
+        ATEXIT_HANDLER.store(0, Ordering::SeqCst);
+        Err(ExitCode::from(res))
+    }
+}
+
+/// A handler for fatal faults, called with the faulting address (0 for a protection fault)
+/// and the instruction pointer of the faulting instruction.
+pub type FaultHandler = extern "sysv64" fn(fault_addr: usize, rip: usize) -> !;
+
+/// Run `handler` instead of being killed when the process hits a page fault or protection fault.
+///
+/// The handler runs on the faulting stack and should clean up and [`exit`]; it must not return.
+/// If the handler itself faults, the process is killed. `None` removes the handler.
+pub fn set_fault_handler(handler: Option<FaultHandler>) -> Result<(), ExitCode> {
+    let res = unsafe { syscall!(FAULT_HANDLER_SET, handler.map_or(0, |handler| handler as usize)) };
+    if res == ExitCode::Success as usize {
+        Ok(())
+    } else {
+        Err(ExitCode::from(res))
+    }
+}
+
+/// Change the access rights of `len` bytes of the process's own memory starting at `addr`.
+///
+/// `addr` must be page aligned; the range is rounded up to whole pages. Pages without
+/// [`ProtFlags::READ`] cannot be touched from user mode at all, and x86 cannot map a page
+/// writable but not readable, so `WRITE` implies `READ`. Only code and stack pages can be
+/// changed; ranges touching the heap fail with [`ExitCode::PermissionError`].
+pub fn mprotect(addr: usize, len: usize, prot: ProtFlags) -> Result<(), ExitCode> {
+    let res = unsafe { syscall!(MPROTECT, addr, len, prot.bits()) };
+    if res == ExitCode::Success as usize {
+        Ok(())
+    } else {
+        Err(ExitCode::from(res))
+    }
+}
+
 pub fn sleep(seconds: f64) {
     unsafe {
         syscall!(SLEEP, seconds.to_bits());
@@ -111,6 +180,218 @@ pub fn spawn(number: usize, args: &[&str]) -> Result<(), ExitCode> {
     }
 }
 
+bitflags! {
+    /// Flags used when spawning a process.
+    #[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct SpawnFlags: u32 {
+        /// The process is never chosen by the OOM killer. Root only.
+        const UNKILLABLE = 0x01;
+        /// The process leads a new process group which becomes the console foreground.
+        /// Only allowed from the foreground group.
+        const FOREGROUND = 0x02;
+    }
+}
+
+/// Resource limits a process is spawned with, `None` means unlimited.
+///
+/// Children inherit their parent's limits. Only root may loosen them, everyone else can only tighten.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Limits {
+    /// Bytes the process heap may grow to
+    pub max_heap: Option<usize>,
+    /// Open file handles, including the ones inherited at spawn
+    pub max_fds: Option<usize>,
+    /// Timer ticks the process may run before it is terminated with [`ExitCode::LimitExceeded`]
+    pub max_cpu_ticks: Option<usize>,
+    /// Children alive at the same time
+    pub max_children: Option<usize>,
+    /// Bytes the stack may grow to, it can never grow into the program image. See [`stack_size`]
+    pub max_stack: Option<usize>,
+}
+
+impl Limits {
+    /// Keep the tighter bound of each limit.
+    pub fn tighten(&self, other: &Limits) -> Limits {
+        fn min(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, None) => a,
+                (None, b) => b,
+            }
+        }
+        Limits {
+            max_heap: min(self.max_heap, other.max_heap),
+            max_fds: min(self.max_fds, other.max_fds),
+            max_cpu_ticks: min(self.max_cpu_ticks, other.max_cpu_ticks),
+            max_children: min(self.max_children, other.max_children),
+            max_stack: min(self.max_stack, other.max_stack),
+        }
+    }
+}
+
+bitflags! {
+    /// Access rights of pages, see [`mprotect`].
+    #[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct ProtFlags: u32 {
+        const READ = 0x01;
+        const WRITE = 0x02;
+        const EXEC = 0x04;
+    }
+}
+
+bitflags! {
+    /// Page table flags of a mapped range, see [`pagemap`].
+    #[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct MapFlags: u32 {
+        const PRESENT = 0x01;
+        const WRITABLE = 0x02;
+        const USER = 0x04;
+        const NO_EXECUTE = 0x08;
+        /// Mapped by a 2 MiB or 1 GiB page instead of 4 KiB ones.
+        const HUGE = 0x10;
+    }
+}
+
+/// Consecutive mapped pages with the same flags, as listed by [`pagemap`]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapRange {
+    pub start: u64,
+    /// One past the last byte
+    pub end: u64,
+    pub flags: MapFlags,
+}
+
+/// Where the program of a process was loaded, as reported by [`image_info`]
+///
+/// Addresses inside the program are `code_addr` plus the address in the program file, so a symbolizer
+/// subtracts `code_addr` from a faulting address before looking it up in the program's own symbol table.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageInfo {
+    /// Start of the program image
+    pub code_addr: u64,
+    /// Entry point, relative to `code_addr`
+    pub entry_point: u64,
+    /// End of the executable code, relative to `code_addr`
+    pub text_end: u64,
+    /// End of the mapped program image
+    pub image_end: u64,
+    /// Initial stack pointer, the stack grows down from here
+    pub stack_addr: u64,
+    /// Lowest stack address mapped so far
+    pub stack_bottom: u64,
+    /// Whether the vDSO page is mapped at [`crate::vdso::VDSO_ADDR`]
+    pub vdso: bool,
+}
+
+/// At most this many ranges are listed by [`pagemap`], the rest of the address space is left out
+pub const MAX_PAGEMAP_RANGES: usize = 512;
+
+/// Physical memory and kernel heap usage, in bytes
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct MemInfo {
+    pub total: usize,
+    pub free: usize,
+    pub heap_total: usize,
+    pub heap_used: usize,
+}
+
+/// Result of [`heap_compact`], in bytes
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Compaction {
+    /// Bytes copied to slide blocks down
+    pub moved: usize,
+    /// Bytes of free pages at the end of heap regions given back to the system
+    pub released: usize,
+}
+
+/// Stack of a process, in bytes
+///
+/// The stack starts small and the kernel maps more pages below it when the program touches them.
+/// Touching memory below `max` is a stack overflow and kills the process.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct StackSize {
+    /// Bytes mapped so far
+    pub current: usize,
+    /// Bytes the stack may grow to
+    pub max: usize,
+}
+
+/// CPU usage of a process, counted in timer ticks
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct Rusage {
+    /// Ticks the process actually ran, a slice given up early only counts the ticks it used
+    pub cpu_ticks: usize,
+    /// Time slices the process was given
+    pub slices: usize,
+    /// Times the process gave up the CPU itself (waiting, sleeping)
+    pub voluntary_switches: usize,
+    /// Times the process was preempted at the end of its slice
+    pub involuntary_switches: usize,
+}
+
+/// CPU time of a process and of its children that have exited, in timer ticks
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct Times {
+    pub ticks: usize,
+    pub children_ticks: usize,
+}
+
+/// A running process as listed by [`proc_list`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProcInfo {
+    pub pid: usize,
+    pub parent: usize,
+    /// The binary's file name unless the process renamed itself with [`set_proc_name`]
+    pub name: String,
+    /// Arguments the process was started with, without the program name
+    pub cmdline: Vec<String>,
+}
+
+/// Fraction bits of the fixed-point load averages in [`SysInfo`]
+pub const LOAD_SHIFT: u32 = 16;
+
+/// System-wide load picture
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct SysInfo {
+    pub uptime_ns: usize,
+    /// 1, 5 and 15 minute averages of the runnable process count, fixed point with [`LOAD_SHIFT`] fraction bits
+    pub loads: [usize; 3],
+    /// Share of the last second spent idle, in user code and in the kernel, in percent
+    pub idle_percent: usize,
+    pub user_percent: usize,
+    pub kernel_percent: usize,
+    /// Ticks since boot spent idle, in user code and in the kernel
+    pub idle_ticks: usize,
+    pub user_ticks: usize,
+    pub kernel_ticks: usize,
+    pub total_frames: usize,
+    pub free_frames: usize,
+    pub procs: usize,
+}
+
+impl SysInfo {
+    /// Load average `i` (0 for 1 minute, 1 for 5, 2 for 15) split into whole part and hundredths.
+    pub fn load(&self, i: usize) -> (usize, usize) {
+        let hundredths = (self.loads[i] * 100 + (1 << (LOAD_SHIFT - 1))) >> LOAD_SHIFT;
+        (hundredths / 100, hundredths % 100)
+    }
+}
+
+/// Kernel-wide interrupt and scheduling counters since boot
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct IrqStat {
+    /// Interrupts handled per IRQ line
+    pub irqs: [usize; 16],
+    pub syscalls: usize,
+    pub context_switches: usize,
+    /// Current scheduler time slice in milliseconds
+    pub quantum_ms: usize,
+    pub path_cache_hits: usize,
+    pub path_cache_misses: usize,
+    /// Deferred work items dropped because the kernel work queue was full
+    pub work_dropped: usize,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PanicInfoLocation {
     line: u32,
@@ -140,6 +421,7 @@ pub fn panic(raw_info: &core::panic::PanicInfo) -> usize {
     unsafe { syscall!(PANIC, syscall_serialized(&info)) }
 }
 
+/// Allocate heap memory, returns 0 when the system is out of memory.
 pub fn alloc(size: usize, align: usize) -> usize {
     unsafe { syscall!(ALLOC, size, align) }
 }
@@ -148,6 +430,230 @@ pub fn free(ptr: usize, size: usize, align: usize) {
     unsafe { syscall!(FREE, ptr, size, align) };
 }
 
+/// Slide the heap blocks listed in `table` down into the free space before them and unmap the whole free pages
+/// this leaves at the end of the heap. Allocations not listed stay where they are.
+///
+/// Every `ptr` in `table` is rewritten to where its block now lives. Returns `None` without moving anything
+/// when an entry is not a live allocation with that layout, the table is not mapped, or the kernel checks
+/// heap canaries (`heap_debug`). See [`crate::allocator::MovableHeap`] for a safe wrapper.
+///
+/// # Safety
+///
+/// Nothing may reach the listed blocks through their old addresses afterwards, or hold a reference into them meanwhile.
+pub unsafe fn heap_compact(table: &mut [Movable]) -> Option<Compaction> {
+    let ret: Result<Option<Compaction>, _> = syscall_with_deserialize!(HEAP_COMPACT, table.as_mut_ptr() as usize, table.len());
+    ret.ok().flatten()
+}
+
+/// Set the niceness (-10..=10) of a process, `pid` 0 means the current process.
+///
+/// Lowering the priority of yourself or your children is always allowed, raising it requires root.
+pub fn set_priority(pid: usize, nice: i8) -> Result<(), ExitCode> {
+    let res = unsafe { syscall!(SETPRIORITY, pid, nice as isize) };
+    if res == ExitCode::Success as usize {
+        Ok(())
+    } else {
+        Err(ExitCode::from(res))
+    }
+}
+
+/// Move a process into a process group, `pid` 0 means the current process and `pgid` 0 means a new group led by `pid`.
+pub fn set_pgid(pid: usize, pgid: usize) -> Result<(), ExitCode> {
+    let res = unsafe { syscall!(SETPGID, pid, pgid) };
+    if res == ExitCode::Success as usize {
+        Ok(())
+    } else {
+        Err(ExitCode::from(res))
+    }
+}
+
+/// Make a process group the console foreground, only the foreground group may read the keyboard.
+pub fn set_foreground(pgid: usize) -> Result<(), ExitCode> {
+    let res = unsafe { syscall!(TCSETPGRP, pgid) };
+    if res == ExitCode::Success as usize {
+        Ok(())
+    } else {
+        Err(ExitCode::from(res))
+    }
+}
+
+/// Run the kernel self tests (allocator stress test, process create/exit round-trip). Root only.
+pub fn selftest() -> Result<(), ExitCode> {
+    let res = unsafe { syscall!(SELFTEST) };
+    if res == ExitCode::Success as usize {
+        Ok(())
+    } else {
+        Err(ExitCode::from(res))
+    }
+}
+
+/// Show or hide the kernel status bar (uptime, memory, process count). Root only.
+pub fn set_status_bar(on: bool) -> Result<(), ExitCode> {
+    let res = unsafe { syscall!(STATUSBAR, on as usize) };
+    if res == ExitCode::Success as usize {
+        Ok(())
+    } else {
+        Err(ExitCode::from(res))
+    }
+}
+
+/// Write out console output that line buffering is still holding back.
+///
+/// Console writes are shown once they end a line, fill the buffer, or the process waits for a key;
+/// call this after a prompt that should appear before some other work.
+pub fn flush() {
+    unsafe { syscall!(FLUSH) };
+}
+
+/// Turn line buffering of console output on (the default) or off, e.g. to debug a program
+/// that dies before its output is flushed. Root only.
+pub fn set_console_buffered(on: bool) -> Result<(), ExitCode> {
+    let res = unsafe { syscall!(CONSOLE_BUFFERED, on as usize) };
+    if res == ExitCode::Success as usize {
+        Ok(())
+    } else {
+        Err(ExitCode::from(res))
+    }
+}
+
+/// Reboot the machine. Root only; only returns if the reboot was refused.
+pub fn reboot() -> ExitCode {
+    ExitCode::from(unsafe { syscall!(REBOOT) })
+}
+
+/// Power off the machine. Root only; only returns if the shutdown was refused.
+///
+/// Every other process is asked to exit first: one that registered an atexit handler is sent to it with
+/// [`ExitCode::Failure`] and has about a second to finish; whatever is left after that is terminated.
+/// [`reboot`] does the same teardown.
+pub fn shutdown() -> ExitCode {
+    ExitCode::from(unsafe { syscall!(SHUTDOWN) })
+}
+
+/// Wall-clock time as a Unix timestamp in seconds, read from the RTC.
+pub fn realtime() -> u64 {
+    unsafe { syscall!(REALTIME) as u64 }
+}
+
+/// Nanoseconds since boot. Costs a full syscall, prefer [`crate::vdso::fast_now_ns`].
+pub fn uptime_ns() -> u64 {
+    unsafe { syscall!(UPTIME) as u64 }
+}
+
+/// Query how much physical memory exists and remains, plus the kernel heap usage.
+pub fn meminfo() -> MemInfo {
+    let ret: Result<MemInfo, _> = syscall_with_deserialize!(MEMINFO);
+    ret.expect("Read meminfo failed.")
+}
+
+/// Ask process `pid` to terminate. Root may end any process, others only their children.
+///
+/// A target blocked in a sleep or wait is woken at once and exits the next time it runs.
+/// Processes spawned with [`SpawnFlags::UNKILLABLE`] cannot be killed at all and give
+/// [`ExitCode::PermissionError`].
+pub fn kill(pid: usize) -> Result<(), ExitCode> {
+    let res = unsafe { syscall!(KILL, pid) };
+    if res == ExitCode::Success as usize {
+        Ok(())
+    } else {
+        Err(ExitCode::from(res))
+    }
+}
+
+/// Set the scheduler time slice in milliseconds. Root only.
+///
+/// The running process keeps what is left of its current slice, the new length applies from the next slice.
+pub fn set_quantum(ms: usize) -> Result<(), ExitCode> {
+    let res = unsafe { syscall!(SETQUANTUM, ms) };
+    if res == ExitCode::Success as usize {
+        Ok(())
+    } else {
+        Err(ExitCode::from(res))
+    }
+}
+
+/// CPU usage of the current process.
+pub fn rusage() -> Rusage {
+    let ret: Result<Rusage, _> = syscall_with_deserialize!(RUSAGE);
+    ret.expect("Read rusage failed.")
+}
+
+/// Mapped and maximal size of the current process's stack.
+pub fn stack_size() -> StackSize {
+    let ret: Result<StackSize, _> = syscall_with_deserialize!(STACKSIZE);
+    ret.expect("Read stack size failed.")
+}
+
+/// Resource limits of the current process.
+pub fn limits() -> Limits {
+    let ret: Result<Limits, _> = syscall_with_deserialize!(GETRLIMIT);
+    ret.expect("Read limits failed.")
+}
+
+/// Replace the resource limits of the current process. Children spawned later inherit them.
+///
+/// Without root every limit must stay the same or get lower, otherwise nothing changes and
+/// [`ExitCode::PermissionError`] is returned. Resources already over a new limit are kept but cannot grow.
+pub fn set_limits(limits: Limits) -> Result<(), ExitCode> {
+    let res = unsafe { syscall!(SETRLIMIT, syscall_serialized(&limits)) };
+    if res == ExitCode::Success as usize {
+        Ok(())
+    } else {
+        Err(ExitCode::from(res))
+    }
+}
+
+/// Timer ticks used by the current process and by its exited children.
+pub fn times() -> Times {
+    let ret: Result<Times, _> = syscall_with_deserialize!(TIMES);
+    ret.expect("Read times failed.")
+}
+
+/// Rename the current process. The kernel strips control characters and keeps at most 32 bytes.
+pub fn set_proc_name(name: &str) -> Result<(), EnvelopeError> {
+    user_call(SET_PROC_NAME, name)
+}
+
+/// Name of process `pid`, or of the current process when `pid` is `None`.
+/// Returns `Ok(None)` when no such process is running.
+pub fn proc_name(pid: Option<usize>) -> Result<Option<String>, EnvelopeError> {
+    user_call(GET_PROC_NAME, pid)
+}
+
+/// Load layout of process `pid`, or of the current process when `pid` is `None`.
+///
+/// Returns `Ok(None)` when no such process is running. Only root may look at other processes,
+/// everyone else gets `Err(EnvelopeError::Status(STATUS_PERMISSION))`.
+pub fn image_info(pid: Option<usize>) -> Result<Option<ImageInfo>, EnvelopeError> {
+    user_call(IMAGEINFO, pid)
+}
+
+/// All running processes, ordered by pid.
+pub fn proc_list() -> Result<Vec<ProcInfo>, EnvelopeError> {
+    user_call(PROC_LIST, ())
+}
+
+/// Pages of process `pid` that user code can reach, in address order, for debugging. Root only.
+///
+/// Only the parts of the page table that are user-accessible at every level are walked, so the kernel's own
+/// mappings do not show up. Returns `Ok(None)` when no such process is running,
+/// and `Err(EnvelopeError::Status(STATUS_PERMISSION))` for callers other than root.
+pub fn pagemap(pid: usize) -> Result<Option<Vec<MapRange>>, EnvelopeError> {
+    user_call(PAGEMAP, pid)
+}
+
+/// Interrupt, syscall and context switch counters since boot.
+pub fn irqstat() -> IrqStat {
+    let ret: Result<IrqStat, _> = syscall_with_deserialize!(IRQSTAT);
+    ret.expect("Read irqstat failed.")
+}
+
+/// Uptime, load averages, where the CPU went, free frames and the process count.
+pub fn sysinfo() -> SysInfo {
+    let ret: Result<SysInfo, _> = syscall_with_deserialize!(SYSINFO);
+    ret.expect("Read sysinfo failed.")
+}
+
 pub fn stop_schedule() {
     unsafe { syscall!(NO_SCHE) };
 }
@@ -0,0 +1,174 @@
+//! A vDSO-lite: a read-only page the kernel maps into every process at [`VDSO_ADDR`].
+//!
+//! The kernel updates the page from the timer interrupt. User space can compute the
+//! monotonic time from it with plain loads and `RDTSC`, without a syscall round trip.
+//!
+//! The page is guarded by a sequence counter (seqlock):
+//!
+//! - The writer makes `seq` odd, updates the fields, then makes `seq` even again.
+//! - A reader retries while `seq` is odd, or when it changed during the read.
+//!
+//! Every field is an atomic, so a torn read is never observed. It is just thrown away
+//! and read again.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use cinea_os_sysapi::vdso::fast_now_ns;
+//!
+//! let start = fast_now_ns();
+//! // ... work ...
+//! let elapsed = fast_now_ns() - start;
+//! ```
+
+use core::sync::atomic::{fence, AtomicU64, AtomicU8, Ordering};
+
+/// Fixed virtual address of the vDSO page in every process
+pub const VDSO_ADDR: u64 = 0x0000_7F00_0000_0000;
+/// `b"CINEAVDS"`, marks a valid vDSO page
+pub const VDSO_MAGIC: u64 = u64::from_le_bytes(*b"CINEAVDS");
+/// Layout version of [`VdsoData`]
+pub const VDSO_VERSION: u64 = 2;
+
+/// Layout of the vDSO page, shared by the kernel and user space
+#[repr(C)]
+pub struct VdsoData {
+    pub magic: AtomicU64,
+    pub version: AtomicU64,
+    /// Sequence counter, odd while the kernel is updating the page
+    pub seq: AtomicU64,
+    /// Timer ticks since boot
+    pub ticks: AtomicU64,
+    /// Nanoseconds between two timer ticks
+    pub tick_ns: AtomicU64,
+    /// Nanoseconds since boot at the last tick
+    pub base_ns: AtomicU64,
+    /// TSC value at the last tick
+    pub base_tsc: AtomicU64,
+    /// Fixed-point TSC to nanoseconds factor: `ns = (clocks * tsc_mult) >> tsc_shift`, 0 if unknown
+    pub tsc_mult: AtomicU64,
+    /// See `tsc_mult`
+    pub tsc_shift: AtomicU64,
+}
+
+/// A consistent copy of the timing fields of [`VdsoData`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VdsoSnapshot {
+    pub ticks: u64,
+    pub tick_ns: u64,
+    pub base_ns: u64,
+    pub base_tsc: u64,
+    pub tsc_mult: u64,
+    pub tsc_shift: u64,
+}
+
+impl VdsoData {
+    pub const fn new() -> Self {
+        Self {
+            magic: AtomicU64::new(VDSO_MAGIC),
+            version: AtomicU64::new(VDSO_VERSION),
+            seq: AtomicU64::new(0),
+            ticks: AtomicU64::new(0),
+            tick_ns: AtomicU64::new(0),
+            base_ns: AtomicU64::new(0),
+            base_tsc: AtomicU64::new(0),
+            tsc_mult: AtomicU64::new(0),
+            tsc_shift: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether the page was written by a kernel speaking the same layout
+    pub fn is_valid(&self) -> bool {
+        self.magic.load(Ordering::Relaxed) == VDSO_MAGIC && self.version.load(Ordering::Relaxed) == VDSO_VERSION
+    }
+
+    /// Publish new timing values. There must be a single writer (the timer interrupt).
+    pub fn write(&self, snapshot: VdsoSnapshot) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.ticks.store(snapshot.ticks, Ordering::Relaxed);
+        self.tick_ns.store(snapshot.tick_ns, Ordering::Relaxed);
+        self.base_ns.store(snapshot.base_ns, Ordering::Relaxed);
+        self.base_tsc.store(snapshot.base_tsc, Ordering::Relaxed);
+        self.tsc_mult.store(snapshot.tsc_mult, Ordering::Relaxed);
+        self.tsc_shift.store(snapshot.tsc_shift, Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    /// Read a consistent snapshot, retrying while the writer is active
+    pub fn read(&self) -> VdsoSnapshot {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 1 {
+                core::hint::spin_loop();
+                continue;
+            }
+            let snapshot = VdsoSnapshot {
+                ticks: self.ticks.load(Ordering::Relaxed),
+                tick_ns: self.tick_ns.load(Ordering::Relaxed),
+                base_ns: self.base_ns.load(Ordering::Relaxed),
+                base_tsc: self.base_tsc.load(Ordering::Relaxed),
+                tsc_mult: self.tsc_mult.load(Ordering::Relaxed),
+                tsc_shift: self.tsc_shift.load(Ordering::Relaxed),
+            };
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == seq {
+                return snapshot;
+            }
+        }
+    }
+}
+
+impl VdsoSnapshot {
+    /// Nanoseconds since boot at TSC value `tsc`.
+    ///
+    /// The TSC delta is capped at one tick, so the result never runs past the next tick
+    /// and stays monotonic.
+    pub fn now_ns(&self, tsc: u64) -> u64 {
+        let delta = match self.tsc_mult {
+            0 => 0,
+            mult => {
+                let clocks = tsc.saturating_sub(self.base_tsc) as u128;
+                ((clocks * mult as u128) >> self.tsc_shift.min(127)).min(self.tick_ns as u128) as u64
+            }
+        };
+        self.base_ns + delta
+    }
+}
+
+fn rdtsc() -> u64 {
+    unsafe {
+        core::arch::x86_64::_mm_lfence();
+        core::arch::x86_64::_rdtsc()
+    }
+}
+
+/// Whether the kernel mapped the vDSO page into this process: 0 not asked yet, 1 mapped, 2 missing
+static MAPPED: AtomicU8 = AtomicU8::new(0);
+
+/// Asks the kernel once, touching an unmapped [`VDSO_ADDR`] would be a fatal page fault
+fn is_mapped() -> bool {
+    match MAPPED.load(Ordering::Relaxed) {
+        0 => {
+            let mapped = matches!(crate::syscall::image_info(None), Ok(Some(info)) if info.vdso);
+            MAPPED.store(if mapped { 1 } else { 2 }, Ordering::Relaxed);
+            mapped
+        }
+        state => state == 1,
+    }
+}
+
+/// Nanoseconds since boot, read from the vDSO page without a syscall.
+///
+/// Falls back to [`crate::syscall::uptime_ns`] if the page isn't mapped or isn't a valid vDSO page.
+pub fn fast_now_ns() -> u64 {
+    if !is_mapped() {
+        return crate::syscall::uptime_ns();
+    }
+    let data = unsafe { &*(VDSO_ADDR as *const VdsoData) };
+    if !data.is_valid() {
+        return crate::syscall::uptime_ns();
+    }
+    data.read().now_ns(rdtsc())
+}
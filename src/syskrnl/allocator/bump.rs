@@ -1,7 +1,7 @@
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::ptr;
 
-use super::{align_up, Locked};
+use super::{align_up, HeapAllocator, Locked};
 
 pub struct BumpAllocator {
     heap_start: usize,
@@ -20,20 +20,31 @@ impl BumpAllocator {
             allocations: 0,
         }
     }
+}
 
+impl HeapAllocator for BumpAllocator {
     /// 根据给定堆区间范围初始化Bump Allocator
     ///
     /// 很显然，这个方法是不安全的，因为给定的区间需要确保未被使用，此外这个函数也不能被多次调用
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         self.heap_start = heap_start.clone();
         self.heap_end = heap_start + heap_size;
         self.next = heap_start.clone();
     }
+
+    fn size(&self) -> usize {
+        self.heap_end - self.heap_start
+    }
+
+    /// 只有全部释放后空间才会被回收，因此已分配的大小就是已经用掉的部分
+    fn allocated(&self) -> usize {
+        self.next - self.heap_start
+    }
 }
 
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut bump = self.lock(); // get a mutable reference
+        let mut bump = self.lock_irqsave(); // get a mutable reference
 
         let alloc_start = align_up(bump.next.clone(), layout.align());
         let alloc_end = match alloc_start.checked_add(layout.size()) {
@@ -51,7 +62,7 @@ unsafe impl GlobalAlloc for Locked<BumpAllocator> {
     }
 
     unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
-        let mut bump = self.lock(); // get a mutable reference
+        let mut bump = self.lock_irqsave(); // get a mutable reference
 
         bump.allocations -= 1;
         if bump.allocations == 0 {
@@ -0,0 +1,102 @@
+/// 用户堆的调试模式，只在打开`heap_debug`特性时编译
+///
+/// 每个区块前后各放一个金丝雀字，释放时检查并用毒化字节填满整个区块；
+/// 沿空闲链表前进之前先检查节点是否落在进程的堆区域内。
+/// 发现问题时报告并杀死进程，不让损坏的链表把内核也带下水
+use core::alloc::Layout;
+
+use crate::syskrnl::proc;
+use crate::syskrnl::uaccess;
+use crate::{debugln, println};
+
+use super::linked_list::LinkedListAllocator;
+
+/// 金丝雀字
+pub const CANARY: u64 = 0xCA4A_121E_5AFE_C0DE;
+/// 释放后区块被填满的字节
+pub const POISON: u8 = 0xDD;
+const CANARY_SIZE: usize = core::mem::size_of::<u64>();
+
+/// 检查出的堆损坏
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapCorruption {
+    /// 区块前面的金丝雀被改写了（越界写到了区块前面）
+    Underflow { ptr: usize, actual: u64 },
+    /// 区块后面的金丝雀被改写了（越界写到了区块后面）
+    Overflow { ptr: usize, actual: u64 },
+    /// 指针根本不可访问
+    BadPointer { ptr: usize },
+    /// 空闲链表的节点不在进程的堆里
+    FreeList { node: usize },
+}
+
+/// 加上金丝雀后实际向分配器申请的布局，以及用户区块在其中的偏移
+///
+/// 前面留出的空间不小于对齐，返回给用户的地址仍然满足原来的对齐
+pub fn guarded_layout(layout: Layout) -> Option<(Layout, usize)> {
+    let front = layout.align().max(CANARY_SIZE);
+    let size = front.checked_add(layout.size())?.checked_add(CANARY_SIZE)?;
+    Some((Layout::from_size_align(size, front).ok()?, front))
+}
+
+/// 在新分配的区块前后放好金丝雀，返回交给用户的地址
+///
+/// `base`必须是按`guarded_layout`分配出来的
+pub unsafe fn arm(base: *mut u8, layout: Layout) -> *mut u8 {
+    let (_, front) = guarded_layout(layout).unwrap();
+    let ptr = base.add(front);
+    (ptr.sub(CANARY_SIZE) as *mut u64).write_unaligned(CANARY);
+    (ptr.add(layout.size()) as *mut u64).write_unaligned(CANARY);
+    ptr
+}
+
+fn read_canary(addr: usize, ptr: usize) -> Result<u64, HeapCorruption> {
+    let mut buf = [0u8; CANARY_SIZE];
+    uaccess::copy_from_user(&mut buf, addr as u64).map_err(|_| HeapCorruption::BadPointer { ptr })?;
+    Ok(u64::from_ne_bytes(buf))
+}
+
+/// 检查用户交回的区块，完好时毒化它，返回要交还分配器的地址和布局
+pub unsafe fn disarm(ptr: *mut u8, layout: Layout) -> Result<(*mut u8, Layout), HeapCorruption> {
+    let (inner, front) = guarded_layout(layout).ok_or(HeapCorruption::BadPointer { ptr: ptr as usize })?;
+    let addr = ptr as usize;
+    let front_canary = read_canary(addr.wrapping_sub(CANARY_SIZE), addr)?;
+    if front_canary != CANARY {
+        return Err(HeapCorruption::Underflow { ptr: addr, actual: front_canary });
+    }
+    let back_canary = read_canary(addr + layout.size(), addr)?;
+    if back_canary != CANARY {
+        return Err(HeapCorruption::Overflow { ptr: addr, actual: back_canary });
+    }
+    let base = ptr.sub(front);
+    core::ptr::write_bytes(base, POISON, inner.size());
+    Ok((base, inner))
+}
+
+/// 检查进程的空闲链表，每个节点都必须整个落在某个堆区域里
+pub fn check_free_list(allocator: &LinkedListAllocator, regions: &[(u64, usize)]) -> Result<(), HeapCorruption> {
+    let inside = |addr: usize, size: usize| {
+        regions
+            .iter()
+            .any(|(start, len)| addr as u64 >= *start && (addr as u64).saturating_add(size as u64) <= start + *len as u64)
+    };
+    allocator.check_free_list(inside).map_err(|node| HeapCorruption::FreeList { node })
+}
+
+/// 报告堆损坏，并让当前进程在系统调用返回时退出
+pub fn report(corruption: HeapCorruption) {
+    let pid = proc::id();
+    match corruption {
+        HeapCorruption::Underflow { ptr, actual } | HeapCorruption::Overflow { ptr, actual } => {
+            let side = if matches!(corruption, HeapCorruption::Underflow { .. }) { "before its start" } else { "past its end" };
+            println!(
+                "[HEAP] pid {}: block {:#x} was overwritten {}, canary expected {:#018x}, found {:#018x}",
+                pid, ptr, side, CANARY, actual
+            );
+        }
+        HeapCorruption::BadPointer { ptr } => println!("[HEAP] pid {}: freeing inaccessible pointer {:#x}", pid, ptr),
+        HeapCorruption::FreeList { node } => println!("[HEAP] pid {}: free list points outside the heap at {:#x}", pid, node),
+    }
+    debugln!("[HEAP] pid {} ({}): {:?}, killing the process", pid, proc::name_of(pid), corruption);
+    proc::exit_on_return();
+}
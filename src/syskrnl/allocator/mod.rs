@@ -1,6 +1,11 @@
 use alloc::alloc::{GlobalAlloc, Layout};
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
 use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, Ordering};
 
+use x86_64::instructions::interrupts;
 use x86_64::structures::paging::page::PageRangeInclusive;
 use x86_64::structures::paging::{OffsetPageTable, PhysFrame};
 use x86_64::{
@@ -8,11 +13,17 @@ use x86_64::{
     PhysAddr, VirtAddr,
 };
 
+use cinea_os_kcore::heap::align_up;
+#[cfg(feature = "bump_allocator")]
+use bump::BumpAllocator;
+#[cfg(not(feature = "bump_allocator"))]
 use linked_list::LinkedListAllocator;
 
 use crate::{debugln, syskrnl};
 
 pub mod bump;
+#[cfg(feature = "heap_debug")]
+pub mod heap_guard;
 pub mod linked_list;
 
 #[derive(Debug)]
@@ -30,10 +41,88 @@ impl<A> Locked<A> {
     pub fn lock(&self) -> spin::MutexGuard<A> {
         self.inner.lock()
     }
+
+    pub fn try_lock(&self) -> Option<spin::MutexGuard<A>> {
+        self.inner.try_lock()
+    }
+
+    /// 持锁期间关中断，持锁的进程不会被调度走，别人也就不会在这把锁上空转
+    pub fn lock_irqsave(&self) -> IrqSafeGuard<spin::MutexGuard<A>> {
+        IrqSafeGuard::new(|| self.inner.lock())
+    }
+
+    /// 进程上下文里上锁，锁被占用时停机等下一个中断，而不是空转
+    ///
+    /// 只有一个CPU，持锁的一方被打断后在这里空转永远等不到它释放。系统调用没有自己的内核调度上下文，
+    /// 进程只保存了一份用户态现场，半路切走会丢掉外层的内核栈（见`syscall_handler`），所以不能通过调度器让出，
+    /// 只能`hlt`等中断，让时钟中断有机会回到持锁的一方。每次重试最多多等一个Tick，争用只在持锁时被打断才会出现，代价可以接受。
+    /// 中断上下文里不能停机，仍然用`lock`
+    pub fn lock_halting(&self) -> spin::MutexGuard<A> {
+        loop {
+            if let Some(guard) = self.inner.try_lock() {
+                return guard;
+            }
+            syskrnl::time::halt();
+        }
+    }
 }
 
-fn align_up(addr: usize, align: usize) -> usize {
-    (addr + align.clone() - 1) & !(align - 1)
+/// 持有期间关闭中断的锁守卫，释放时恢复原来的中断状态，因此可以嵌套
+///
+/// 中断状态属于当前CPU，守卫不能被送到别处释放
+pub struct IrqSafeGuard<G> {
+    guard: ManuallyDrop<G>,
+    were_enabled: bool,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<G> IrqSafeGuard<G> {
+    /// 先关中断再上锁
+    pub fn new(lock: impl FnOnce() -> G) -> Self {
+        let were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        Self {
+            guard: ManuallyDrop::new(lock()),
+            were_enabled,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<G: Deref> Deref for IrqSafeGuard<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &**self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for IrqSafeGuard<G> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut **self.guard
+    }
+}
+
+impl<G> Drop for IrqSafeGuard<G> {
+    fn drop(&mut self) {
+        // 先解锁再开中断
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.were_enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+/// 可以用作内核堆的分配器
+pub trait HeapAllocator {
+    /// 根据给定堆区间范围初始化分配器
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize);
+
+    /// 堆的总大小
+    fn size(&self) -> usize;
+
+    /// 已经分配出去的大小
+    fn allocated(&self) -> usize;
 }
 
 pub struct Dummy;
@@ -48,18 +137,39 @@ unsafe impl GlobalAlloc for Dummy {
     }
 }
 
+/// 内核堆使用的分配器，启用`bump_allocator`特性时换成只分配不回收的Bump Allocator
+#[cfg(feature = "bump_allocator")]
+pub type KernelHeapAllocator = BumpAllocator;
+#[cfg(not(feature = "bump_allocator"))]
+pub type KernelHeapAllocator = LinkedListAllocator;
+
 #[global_allocator]
-pub static ALLOCATOR: Locked<LinkedListAllocator> = Locked::new(LinkedListAllocator::new());
+pub static ALLOCATOR: Locked<KernelHeapAllocator> = Locked::new(KernelHeapAllocator::new());
 
 pub const HEAP_START: usize = 0x_0001_0000_0000;
 pub const HEAP_SIZE: usize = 40 * 1024 * 1024; // 40 MiB
 
 pub fn avaliable_memory_size() -> usize {
-    let lock = ALLOCATOR.lock();
+    let lock = ALLOCATOR.lock_irqsave();
     lock.size() - lock.allocated()
 }
 
+/// 内核堆是否已经初始化（或正在初始化）
+static HEAP_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// 初始化内核堆，重复调用时直接返回
 pub fn init_heap(mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> Result<(), MapToError<Size4KiB>> {
+    if HEAP_INITIALIZED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    let res = map_heap(mapper, frame_allocator);
+    if res.is_err() {
+        HEAP_INITIALIZED.store(false, Ordering::SeqCst);
+    }
+    res
+}
+
+fn map_heap(mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> Result<(), MapToError<Size4KiB>> {
     syskrnl::proc::init_process_addr((HEAP_START + HEAP_SIZE) as u64);
 
     let page_range = {
@@ -71,36 +181,98 @@ pub fn init_heap(mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut impl
     };
 
     for page in page_range {
+        // 已经映射过的页保持原样
+        if mapper.translate_page(page).is_ok() {
+            continue;
+        }
         let frame = frame_allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
         let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
         unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
     }
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.lock_irqsave().init(HEAP_START, HEAP_SIZE);
     }
 
     Ok(())
 }
 
+/// 检查给用户用的页面范围：不能溢出，也不能和内核堆、物理内存映射重叠
+fn check_user_range(addr: u64, size: usize) -> Result<(), ()> {
+    let end = addr.checked_add(size as u64).ok_or(())?;
+    let overlaps = |(start, stop): (u64, u64)| addr < stop && start < end;
+    let heap = (HEAP_START as u64, (HEAP_START + HEAP_SIZE) as u64);
+    if overlaps(heap) || overlaps(syskrnl::memory::phys_mem_range()) {
+        debugln!("rejected page range {:#x}..{:#x}: overlaps kernel memory", addr, end);
+        return Err(());
+    }
+    Ok(())
+}
+
 // TODO: Replace `free` by `dealloc`
-pub fn dealloc_pages(addr: u64, size: usize) {
-    let mapper = syskrnl::memory::mapper();
+/// 释放进程`pid`的一段内存，返回没能取消映射的页数
+///
+/// 范围必须完整地记录在这个进程的内存区域里，否则记下日志、什么也不做，返回错误
+pub fn dealloc_pages(pid: usize, addr: u64, size: usize) -> Result<usize, ()> {
+    if !syskrnl::proc::owns_range(pid, addr, size) {
+        debugln!("dealloc_pages: pid {} does not own {:#x}+{:#x}, refusing to unmap", pid, addr, size);
+        return Err(());
+    }
+    dealloc_pages_from_mapper(&mut syskrnl::memory::mapper(), addr, size)
+}
+
+/// 内核自己取消一段映射，不检查归属，返回没能取消映射的页数
+///
+/// 只用于内核内部不属于任何进程的映射；仍然不能碰内核堆和物理内存映射
+pub fn kernel_unmap_range(addr: u64, size: usize) -> Result<usize, ()> {
+    dealloc_pages_from_mapper(&mut syskrnl::memory::mapper(), addr, size)
+}
+
+/// 取消页面的映射，并归还对应的帧，返回没能取消映射的页数
+///
+/// 范围和内核堆或物理内存映射重叠时什么也不做，返回错误
+pub fn dealloc_pages_from_mapper(mapper: &mut OffsetPageTable, addr: u64, size: usize) -> Result<usize, ()> {
+    if size == 0 {
+        return Ok(0);
+    }
+    check_user_range(addr, size)?;
     let pages: PageRangeInclusive<Size4KiB> = {
         let start_page = Page::containing_address(VirtAddr::new(addr));
         let end_page = Page::containing_address(VirtAddr::new(addr + (size as u64) - 1));
         Page::range_inclusive(start_page, end_page)
     };
+    let mut failed = 0;
     for page in pages {
-        if let Ok((_frame, mapping)) = mapper.unmap(page) {
+        if let Ok((frame, mapping)) = mapper.unmap(page) {
             mapping.flush();
+            syskrnl::memory::deallocate_frame(frame);
         } else {
-            //debug!("Could not unmap {:?}", page);
+            failed += 1;
         }
     }
+    if failed > 0 {
+        debugln!("Could not unmap {} pages in {:#x}+{:#x}", failed, addr, size);
+    }
+    Ok(failed)
 }
 
+/// 分配页面，新映射的页面会被清零
 pub fn alloc_pages(mapper: &mut OffsetPageTable, addr: u64, size: usize) -> Result<(), ()> {
+    alloc_pages_with_zeroing(mapper, addr, size, true)
+}
+
+/// 分配页面，`zeroing`决定是否清零新映射的页面
+///
+/// 帧可能来自之前被使用过的内存，不清零会把旧数据泄露给新的进程；
+/// 只有在页面马上会被完整覆盖时（例如加载ELF的代码段）才应当跳过清零
+///
+/// 失败时已经映射的页面会被撤销，不会留下一半的映射；
+/// 范围和内核堆或物理内存映射重叠时直接返回错误
+pub fn alloc_pages_with_zeroing(mapper: &mut OffsetPageTable, addr: u64, size: usize, zeroing: bool) -> Result<(), ()> {
+    if size == 0 {
+        return Ok(());
+    }
+    check_user_range(addr, size)?;
     let mut frame_allocator = syskrnl::memory::heaped_frame_allocator();
     let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
     let pages = {
@@ -108,29 +280,67 @@ pub fn alloc_pages(mapper: &mut OffsetPageTable, addr: u64, size: usize) -> Resu
         let end_page = Page::containing_address(VirtAddr::new(addr + (size as u64) - 1));
         Page::range_inclusive(start_page, end_page)
     };
-    for page in pages {
+    for (i, page) in pages.enumerate() {
         //debugln!("Alloc page {:?}", page);
         if let Some(frame) = frame_allocator.allocate_frame() {
             //debugln!("Alloc frame {:?}", frame);
             unsafe {
-                if let Ok(mapping) = mapper.map_to(page, frame, flags, &mut frame_allocator) {
+                if let Ok(mapping) = mapper.map_to(page, frame, flags, &mut *frame_allocator) {
                     //debugln!("Mapped {:?} to {:?}", page, frame);
                     mapping.flush();
                 } else {
                     debugln!("Could not map {:?} to {:?}", page, frame);
+                    syskrnl::memory::deallocate_frame(frame);
+                    let _ = dealloc_pages_from_mapper(mapper, addr, i * 4096);
                     return Err(());
                 }
             }
+            if zeroing {
+                zero_frame(frame);
+            }
         } else {
             debugln!("Could not allocate frame for {:?}", page);
+            let _ = dealloc_pages_from_mapper(mapper, addr, i * 4096);
             return Err(());
         }
     }
     Ok(())
 }
 
+/// 修改已经映射的页面的权限，`flags`里的PRESENT总会被加上
+///
+/// 范围和内核堆或物理内存映射重叠、或者有页面没有映射时返回错误，
+/// 出错之前处理过的页面保持新的权限
+pub fn protect_pages(mapper: &mut OffsetPageTable, addr: u64, size: usize, flags: PageTableFlags) -> Result<(), ()> {
+    if size == 0 {
+        return Ok(());
+    }
+    check_user_range(addr, size)?;
+    let pages: PageRangeInclusive<Size4KiB> = {
+        let start_page = Page::containing_address(VirtAddr::new(addr));
+        let end_page = Page::containing_address(VirtAddr::new(addr + (size as u64) - 1));
+        Page::range_inclusive(start_page, end_page)
+    };
+    for page in pages {
+        match unsafe { mapper.update_flags(page, flags | PageTableFlags::PRESENT) } {
+            Ok(flush) => flush.flush(),
+            Err(_) => {
+                debugln!("Could not protect {:?}", page);
+                return Err(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 通过物理内存映射清零一个帧，不依赖当前激活的页表
+fn zero_frame(frame: PhysFrame) {
+    let ptr: *mut u8 = syskrnl::memory::phys_to_virt(frame.start_address()).as_mut_ptr();
+    unsafe { core::ptr::write_bytes(ptr, 0, frame.size() as usize) };
+}
+
 pub fn alloc_pages_to_known_phys(mapper: &mut OffsetPageTable, addr: u64, size: usize, phys_start: u64, user_accessible: bool) -> Result<(), ()> {
-    let mut frame_allocator = syskrnl::memory::frame_allocator();
+    let mut frame_allocator = syskrnl::memory::heaped_frame_allocator();
     let mut flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
     if user_accessible {
         flags |= PageTableFlags::USER_ACCESSIBLE
@@ -145,7 +355,7 @@ pub fn alloc_pages_to_known_phys(mapper: &mut OffsetPageTable, addr: u64, size:
         let frame = PhysFrame::containing_address(PhysAddr::new(phys_start + 0x1000 * i as u64));
         //debugln!("Alloc frame {:?}", frame);
         unsafe {
-            if let Ok(mapping) = mapper.map_to(page, frame, flags, &mut frame_allocator) {
+            if let Ok(mapping) = mapper.map_to(page, frame, flags, &mut *frame_allocator) {
                 //debugln!("Mapped {:?} to {:?}", page, frame);
                 mapping.flush();
             } else {
@@ -170,30 +380,252 @@ pub unsafe fn fix_page_fault_in_userspace(mapper: &mut OffsetPageTable) {
 
     for page in pages {
         let frame = mapper.unmap(page).expect("unmap fail 5623");
-        let mapping = mapper.map_to(page, frame.0, flags, &mut frame_allocator).expect("map fail 78523");
+        let mapping = mapper.map_to(page, frame.0, flags, &mut *frame_allocator).expect("map fail 78523");
         mapping.flush();
     }
 }
 
-#[allow(dead_code)]
-pub fn test_allocator() {
+/// 内核堆的压力测试，返回是否通过
+pub fn test_allocator() -> bool {
     use alloc::boxed::Box;
     use alloc::rc::Rc;
     use alloc::vec;
     use alloc::vec::Vec;
 
+    let mut passed = true;
+
     let heap_value = Box::new(831);
     println!("heap_value is at {:p}", heap_value);
+    passed &= *heap_value == 831;
 
     let mut vec = Vec::new();
     for i in 0..500 {
         vec.push(i)
     }
     println!("vec at {:p}", vec.as_slice());
+    passed &= vec.iter().sum::<usize>() == 499 * 500 / 2;
 
     let reference_counted = Rc::new(vec![1, 2, 3]);
     let cloned_reference = reference_counted.clone();
     println!("current reference count is {}", Rc::strong_count(&cloned_reference));
     core::mem::drop(reference_counted);
     println!("reference count is {} now", Rc::strong_count(&cloned_reference));
+    passed &= Rc::strong_count(&cloned_reference) == 1;
+
+    // 大量不同大小的分配，全部释放后可用内存应当回到原样
+    let before = avaliable_memory_size();
+    let mut boxes = Vec::with_capacity(1000);
+    for i in 0..1000usize {
+        boxes.push(vec![i as u8; i % 97 + 1]);
+    }
+    passed &= boxes.iter().enumerate().all(|(i, b)| b.len() == i % 97 + 1 && b.iter().all(|x| *x == i as u8));
+    drop(boxes);
+    // Bump Allocator只有全部释放后才会回收，内核里总有别的分配还活着
+    #[cfg(not(feature = "bump_allocator"))]
+    {
+        passed &= avaliable_memory_size() == before;
+    }
+    #[cfg(feature = "bump_allocator")]
+    let _ = before;
+
+    println!("allocator stress test {}", if passed { "passed" } else { "FAILED" });
+    passed
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::alloc::{GlobalAlloc, Layout};
+
+    use crate::syskrnl;
+
+    use super::bump::BumpAllocator;
+    use super::linked_list::LinkedListAllocator;
+    use super::{
+        alloc_pages, dealloc_pages, dealloc_pages_from_mapper, init_heap, kernel_unmap_range, protect_pages, HeapAllocator, Locked, ALLOCATOR,
+        HEAP_SIZE, HEAP_START,
+    };
+
+    /// 不论哪个被选为全局分配器（见`bump_allocator`特性），两个分配器都在这里测一遍
+    fn exercise_heap_allocator<A: HeapAllocator>(allocator: &Locked<A>)
+    where
+        Locked<A>: GlobalAlloc,
+    {
+        static mut HEAP: [u64; 512] = [0; 512];
+        let (start, size) = unsafe { (HEAP.as_ptr() as usize, core::mem::size_of_val(&HEAP)) };
+        unsafe { allocator.lock().init(start, size) };
+        assert_eq!(allocator.lock().size(), size);
+
+        let layout = Layout::from_size_align(64, 16).unwrap();
+        let a = unsafe { allocator.alloc(layout) };
+        let b = unsafe { allocator.alloc(layout) };
+        assert!(!a.is_null() && !b.is_null() && a != b);
+        assert_eq!(a as usize % 16, 0);
+        assert_eq!(b as usize % 16, 0);
+        assert!(allocator.lock().allocated() >= 128);
+        unsafe {
+            allocator.dealloc(a, layout);
+            allocator.dealloc(b, layout);
+        }
+    }
+
+    #[test_case]
+    fn test_bump_allocator() {
+        exercise_heap_allocator(&Locked::new(BumpAllocator::new()));
+        println!("[ok]  Allocator bump allocator")
+    }
+
+    #[test_case]
+    fn test_linked_list_allocator() {
+        exercise_heap_allocator(&Locked::new(LinkedListAllocator::new()));
+        println!("[ok]  Allocator linked list allocator")
+    }
+
+    #[test_case]
+    fn test_linked_list_large_align() {
+        static mut HEAP: [u64; 4096] = [0; 4096];
+        let (start, size) = unsafe { (HEAP.as_ptr() as usize, core::mem::size_of_val(&HEAP)) };
+        let allocator = Locked::new(LinkedListAllocator::new());
+        unsafe { allocator.lock().init(start, size) };
+
+        let page = Layout::from_size_align(100, 4096).unwrap();
+        let ptrs = [(); 3].map(|_| unsafe { allocator.alloc(page) });
+        for ptr in ptrs {
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % 4096, 0);
+        }
+        for ptr in ptrs {
+            unsafe { allocator.dealloc(ptr, page) };
+        }
+
+        // 对齐跳过的空间都还回来了，整个堆又能一次分配出去
+        let whole = Layout::from_size_align(size, 8).unwrap();
+        let ptr = unsafe { allocator.alloc(whole) };
+        assert_eq!(ptr as usize, start);
+        unsafe { allocator.dealloc(ptr, whole) };
+        println!("[ok]  Allocator linked list large align")
+    }
+
+    #[test_case]
+    fn test_alloc_pages_zeroed() {
+        let addr = 0x0003_0000_0000u64;
+        let size = 2 * 4096;
+        let mut mapper = syskrnl::memory::mapper();
+
+        alloc_pages(&mut mapper, addr, size).unwrap();
+        let buf = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, size) };
+        assert!(buf.iter().all(|b| *b == 0));
+        buf.fill(0xAB);
+        dealloc_pages_from_mapper(&mut mapper, addr, size).unwrap();
+
+        alloc_pages(&mut mapper, addr, size).unwrap();
+        let buf = unsafe { core::slice::from_raw_parts(addr as *const u8, size) };
+        assert!(buf.iter().all(|b| *b == 0));
+        dealloc_pages_from_mapper(&mut mapper, addr, size).unwrap();
+        println!("[ok]  Allocator alloc_pages zeroed")
+    }
+
+    #[test_case]
+    fn test_protect_pages() {
+        use x86_64::structures::paging::mapper::{Translate, TranslateResult};
+        use x86_64::structures::paging::PageTableFlags;
+        use x86_64::VirtAddr;
+
+        let addr = 0x0003_0000_0000u64;
+        let mut mapper = syskrnl::memory::mapper();
+        alloc_pages(&mut mapper, addr, 4096).unwrap();
+        protect_pages(&mut mapper, addr, 4096, PageTableFlags::USER_ACCESSIBLE).unwrap();
+        match mapper.translate(VirtAddr::new(addr)) {
+            TranslateResult::Mapped { flags, .. } => {
+                assert!(flags.contains(PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE));
+                assert!(!flags.contains(PageTableFlags::WRITABLE));
+            }
+            _ => panic!("page lost its mapping"),
+        }
+        dealloc_pages_from_mapper(&mut mapper, addr, 4096).unwrap();
+        // 没有映射的页面和内核的范围都不行
+        assert!(protect_pages(&mut mapper, addr, 4096, PageTableFlags::WRITABLE).is_err());
+        assert!(protect_pages(&mut mapper, HEAP_START as u64, 4096, PageTableFlags::WRITABLE).is_err());
+        println!("[ok]  Allocator protect_pages")
+    }
+
+    #[test_case]
+    fn test_alloc_pages_rejects_kernel_ranges() {
+        let (phys_start, _) = syskrnl::memory::phys_mem_range();
+        let mut mapper = syskrnl::memory::mapper();
+        assert!(alloc_pages(&mut mapper, HEAP_START as u64, 4096).is_err());
+        // 只有最后一页落进内核堆
+        assert!(alloc_pages(&mut mapper, HEAP_START as u64 - 4096, 2 * 4096).is_err());
+        assert!(alloc_pages(&mut mapper, phys_start, 4096).is_err());
+        drop(mapper);
+        assert!(kernel_unmap_range(HEAP_START as u64, 4096).is_err());
+        assert!(kernel_unmap_range(phys_start, 4096).is_err());
+        // 堆还好好的
+        let probe = alloc::boxed::Box::new(0x5Au8);
+        assert_eq!(*probe, 0x5A);
+        println!("[ok]  Allocator rejects kernel page ranges")
+    }
+
+    #[test_case]
+    fn test_dealloc_pages_checks_owner() {
+        use x86_64::structures::paging::mapper::Translate;
+        use x86_64::VirtAddr;
+
+        // 内核进程不拥有任何区域，内核堆更不能被当作进程内存释放
+        assert!(dealloc_pages(0, HEAP_START as u64, 4096).is_err());
+        assert!(syskrnl::memory::mapper().translate_addr(VirtAddr::new(HEAP_START as u64)).is_some());
+        let probe = alloc::boxed::Box::new(0xA5u8);
+        assert_eq!(*probe, 0xA5);
+        // 没有映射的页面被数出来
+        assert_eq!(kernel_unmap_range(0x0003_0000_0000, 2 * 4096), Ok(2));
+        println!("[ok]  Allocator dealloc_pages checks owner")
+    }
+
+    #[test_case]
+    fn test_lock_halting() {
+        let locked = Locked::new(0usize);
+        *locked.lock_halting() += 1;
+        // 守卫释放后锁可以再拿到
+        assert!(locked.inner.try_lock().is_some());
+        assert_eq!(*locked.lock_halting(), 1);
+        println!("[ok]  Locked lock_halting")
+    }
+
+    #[test_case]
+    fn test_init_heap_twice() {
+        let boxed = alloc::boxed::Box::new(42u64);
+        let allocated = ALLOCATOR.lock().allocated();
+        let mut frame_allocator = syskrnl::memory::heaped_frame_allocator();
+        assert!(init_heap(&mut *syskrnl::memory::mapper(), &mut *frame_allocator).is_ok());
+        // 已有的分配不受影响
+        assert_eq!(ALLOCATOR.lock().size(), HEAP_SIZE);
+        assert_eq!(ALLOCATOR.lock().allocated(), allocated);
+        assert_eq!(*boxed, 42);
+        println!("[ok]  Allocator init_heap twice")
+    }
+
+    #[test_case]
+    fn test_heap_lock_survives_timer() {
+        use x86_64::instructions::interrupts;
+
+        let were_enabled = interrupts::are_enabled();
+        let ticks = syskrnl::time::ticks();
+        {
+            let outer = ALLOCATOR.lock_irqsave();
+            assert!(!interrupts::are_enabled());
+            // free_frames里面还有一层守卫，嵌套的守卫释放后中断仍然是关的
+            syskrnl::memory::free_frames();
+            assert!(!interrupts::are_enabled());
+            // 持锁的时间远超一个时间片，时钟中断不会进来把我们调度走
+            syskrnl::time::nanowait(20_000_000);
+            assert_eq!(syskrnl::time::ticks(), ticks);
+            drop(outer);
+        }
+        assert_eq!(interrupts::are_enabled(), were_enabled);
+        // 中断恢复后时钟继续走，堆也还能用
+        syskrnl::time::sleep(0.005);
+        assert!(syskrnl::time::ticks() > ticks);
+        let boxed = alloc::boxed::Box::new([0u8; 64]);
+        assert_eq!(boxed.len(), 64);
+        println!("[ok]  Allocator heap lock survives timer")
+    }
 }
@@ -0,0 +1,56 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+
+use spin::Mutex;
+
+use crate::{debugln, syskrnl};
+
+/// 启动配置文件，每行一个`键 = 值`，`#`开头的行是注释
+const CONFIG_PATH: &str = "/sys/kernel.cfg";
+
+static CONFIG: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// 解析配置文本，格式不对的行直接忽略
+pub fn parse(text: &str) -> BTreeMap<String, String> {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (String::from(key.trim()), String::from(value.trim())))
+        .collect()
+}
+
+/// 读取启动配置，文件不存在时全部使用默认值
+pub fn init() {
+    match syskrnl::fs::read_to_end(CONFIG_PATH) {
+        Ok(data) => match core::str::from_utf8(data.as_slice()) {
+            Ok(text) => *CONFIG.lock() = parse(text),
+            Err(_) => debugln!("config: {} is not valid utf8, using defaults", CONFIG_PATH),
+        },
+        Err(_) => debugln!("config: {} not found, using defaults", CONFIG_PATH),
+    }
+}
+
+/// 读取配置项
+pub fn get(key: &str) -> Option<String> {
+    CONFIG.lock().get(key).cloned()
+}
+
+/// 读取数值配置项，不是数字时视为没有配置
+pub fn get_usize(key: &str) -> Option<usize> {
+    get(key).and_then(|value| value.parse().ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+
+    #[test_case]
+    fn test_parse_config() {
+        let config = parse("# comment\nsched.quantum_ms = 25\n\nbroken line\n  a=b  \n");
+        assert_eq!(config.get("sched.quantum_ms").map(|v| v.as_str()), Some("25"));
+        assert_eq!(config.get("a").map(|v| v.as_str()), Some("b"));
+        assert_eq!(config.len(), 2);
+        println!("[ok]  Parse kernel config");
+    }
+}
@@ -6,11 +6,13 @@ use crate::syskrnl;
 
 use super::service;
 
-pub fn dispatcher(event_id: usize, arg1: usize, _arg2: usize, _arg3: usize, _arg4: usize) -> usize {
+pub fn dispatcher(event_id: usize, arg1: usize, arg2: usize, _arg3: usize, _arg4: usize) -> usize {
     interrupts::without_interrupts(|| match event_id {
         KEYBOARD_INPUT => service::keyboard_input(),
         SLEEP_WAKEUP => service::sleep_wakeup(arg1, false),
         GUI_PROGRAM => service::gui_wakeup(),
-        _ => syskrnl::proc::id(),
+        FUTEX_WAIT => syskrnl::futex::wait(arg1, arg2 as u32),
+        FUTEX_WAKE => syskrnl::futex::wake(arg1, arg2),
+        _ => service::no_such_call(event_id),
     })
 }
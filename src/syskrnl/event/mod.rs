@@ -6,9 +6,14 @@ use spin::Mutex;
 
 pub use call::dispatcher;
 pub use service::GUI_EID_START;
+use service::CHILD_EID_START;
+
+use cinea_os_sysapi::event::{KEYBOARD_INPUT, SLEEP_INTERRUPTED};
+use cinea_os_sysapi::ExitCode;
 
 use crate::syskrnl;
 use crate::syskrnl::proc::SCHEDULER;
+use crate::syskrnl::schedule::ProcessScheduler;
 
 pub mod call;
 mod service;
@@ -19,6 +24,47 @@ pub fn register_timer(million_seconds: usize) {
 
 pub static NEED_CHECK_EVENT_DATA: AtomicBool = AtomicBool::new(false);
 
+/// 打断进程正在进行的睡眠或等待，它醒来时得到`SLEEP_INTERRUPTED`
+///
+/// 睡眠的定时器照常到期，那时等待队列里已经没有它了
+pub fn interrupt(pid: usize) {
+    let mut queue = EVENT_QUEUE.lock();
+    let waiting = queue.remove(pid) | syskrnl::futex::remove(pid);
+    if waiting {
+        NEED_CHECK_EVENT_DATA.store(true, Ordering::Relaxed);
+        EVENT_DATA.lock().insert(pid, SLEEP_INTERRUPTED);
+        SCHEDULER.lock().wakeup(pid);
+    }
+}
+
+/// 等子进程`child`退出的事件
+pub fn child_event(child: usize) -> EventType {
+    CHILD_EID_START + child
+}
+
+/// 当前进程睡到子进程`child`退出为止，返回接下来运行的进程
+pub fn wait_child(child: usize) -> usize {
+    EVENT_QUEUE.lock().wait_for(child_event(child))
+}
+
+/// 进程退出时叫醒在`RUN`里等它的父进程，退出代码直接写进父进程保存的rax
+///
+/// 父进程可能不是从时钟中断恢复的，所以不走`EVENT_DATA`，还要清掉那里以前留下的返回值，免得盖掉退出代码
+pub fn child_exited(pid: usize, code: ExitCode) {
+    let waiter = EVENT_QUEUE.lock().wakeup(child_event(pid));
+    if let Some(waiter) = waiter {
+        EVENT_DATA.lock().remove(&waiter);
+        syskrnl::proc::set_return_value(waiter, code as usize);
+        SCHEDULER.lock().wakeup(waiter);
+    }
+}
+
+/// 进程退出时清掉它的等待和还没取走的返回值，免得复用这个PID的进程收到
+pub fn remove(pid: usize) {
+    EVENT_QUEUE.lock().remove(pid);
+    EVENT_DATA.lock().remove(&pid);
+}
+
 lazy_static! {
     pub static ref EVENT_DATA: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
     pub static ref EVENT_QUEUE: Mutex<EventQueue> = Mutex::new(EventQueue::new());
@@ -56,12 +102,19 @@ impl EventQueue {
     /// 返回值是下一个进程的pid
     pub fn wait_for(&mut self, event: EventType) -> usize {
         // debugln!("Wait for {}, {}", event, syskrnl::proc::id());
+        self.wait_in(&mut **SCHEDULER.lock(), syskrnl::proc::id(), event)
+    }
+
+    /// 在给定的调度器里让正在运行的`pid`等待某事件，测试可以传自己的调度器
+    ///
+    /// 返回值是下一个进程的pid
+    pub fn wait_in(&mut self, scheduler: &mut dyn ProcessScheduler, pid: usize, event: EventType) -> usize {
         // 标识当前进程为“等待”，停止其调度
-        let next = SCHEDULER.lock().wait();
+        let next = scheduler.wait();
         // 注册事件等待
-        self.wait(syskrnl::proc::id(), event);
+        self.wait(pid, event);
         // 返回下一个进程的PID
-        return next;
+        next
     }
 
     /// 等待某事件，但不立即停止该进程
@@ -74,6 +127,17 @@ impl EventQueue {
         self.wait(syskrnl::proc::id(), event);
     }
 
+    /// 把进程从所有事件的等待队列中移除，返回它之前是否在等待
+    fn remove(&mut self, pid: usize) -> bool {
+        let mut found = false;
+        for queue in self.queue.values_mut() {
+            let len = queue.len();
+            queue.retain(|waiter| *waiter != pid);
+            found |= queue.len() != len;
+        }
+        found
+    }
+
     /// 根据事件唤醒进程
     ///
     /// 返回值是下一个进程的pid
@@ -81,6 +145,10 @@ impl EventQueue {
         if let Some(queue) = self.queue.get_mut(&event) {
             if queue.len() == 0 {
                 None
+            } else if event == KEYBOARD_INPUT {
+                // 键盘输入只交给控制台前台进程组
+                let pos = queue.iter().position(|pid| syskrnl::proc::in_foreground(*pid))?;
+                queue.remove(pos)
             } else {
                 if let Some(fp) = queue.iter().position(|x| *x == self.front_proc) {
                     queue.remove(fp).unwrap();
@@ -108,3 +176,22 @@ impl EventQueue {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::EventQueue;
+
+    #[test_case]
+    fn test_event_queue_remove() {
+        let mut queue = EventQueue::new();
+        queue.wait(7, 1_000_001);
+        queue.wait(8, 1_000_001);
+        queue.wait(7, 2_000_007);
+        assert!(queue.remove(7));
+        assert!(!queue.remove(7));
+        // 被移除的进程不会再被唤醒
+        assert_eq!(queue.wakeup(1_000_001), Some(8));
+        assert_eq!(queue.wakeup(2_000_007), None);
+        println!("[ok]  event queue remove");
+    }
+}
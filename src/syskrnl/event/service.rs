@@ -1,6 +1,8 @@
-use cinea_os_sysapi::event::KEYBOARD_INPUT;
+use cinea_os_sysapi::call::NO_SUCH_SYSCALL;
+use cinea_os_sysapi::event::{KEYBOARD_INPUT, NOT_FOREGROUND};
 use core::sync::atomic::{AtomicUsize, Ordering};
 
+use crate::debugln;
 use crate::syskrnl;
 use crate::syskrnl::event::EVENT_QUEUE;
 use crate::syskrnl::proc;
@@ -10,12 +12,23 @@ use crate::syskrnl::proc;
 // 0..1_000_000 - 裸EID
 // 1_000_000..2_000_000 - Sleep
 // 2_000_000..3_000_000 - GUI
+// 3_000_000..4_000_000 - 子进程退出
 //
 
 const SLEEP_EID_START: usize = 1_000_000;
 pub const GUI_EID_START: usize = 2_000_000;
+pub const CHILD_EID_START: usize = 3_000_000;
 
 pub fn keyboard_input() -> usize {
+    if !proc::in_foreground(proc::id()) {
+        // 后台进程不能抢键盘输入，不等待，直接返回NOT_FOREGROUND
+        let mut regs = proc::registers();
+        regs.rax = NOT_FOREGROUND;
+        proc::set_registers(regs);
+        return proc::id();
+    }
+    // 等输入之前先让用户看到没有换行的提示符
+    syskrnl::io::console_flush();
     EVENT_QUEUE.lock().wait_for(KEYBOARD_INPUT)
 }
 
@@ -42,3 +55,12 @@ pub fn gui_wakeup() -> usize {
     let next = EVENT_QUEUE.lock().wait_for(eid);
     next
 }
+
+/// 未知的事件调用号，不挂起，直接返回`NO_SUCH_SYSCALL`
+pub fn no_such_call(n: usize) -> usize {
+    debugln!("event call: unknown number {:#x} from pid {}", n, proc::id());
+    let mut regs = proc::registers();
+    regs.rax = NO_SUCH_SYSCALL;
+    proc::set_registers(regs);
+    proc::id()
+}
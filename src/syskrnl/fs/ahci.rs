@@ -53,6 +53,11 @@ impl AhciDeviceReader {
         }
     }
 
+    /// 磁盘的总字节数
+    pub fn size(&self) -> usize {
+        self.max_sector as usize * SECTOR_SIZE
+    }
+
     pub fn set_position(&mut self, pos: usize) -> Result<(), ()> {
         if pos > self.max_sector as usize * SECTOR_SIZE {
             return Err(());
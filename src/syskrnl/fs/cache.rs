@@ -0,0 +1,128 @@
+//! 路径缓存
+//!
+//! 每次访问文件都要从根目录开始逐级遍历目录，目录较深时这是文件系统调用的主要开销。
+//! 这里把规范路径映射到目录项的元数据（以及目录的子项列表），最多保存`CACHE_CAPACITY`项，
+//! 满了以后淘汰最久没有使用的一项。
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use cinea_os_sysapi::fs::{dirname, FileEntry, Metadata};
+
+use crate::syskrnl::allocator::Locked;
+
+const CACHE_CAPACITY: usize = 128;
+
+static CACHE_HITS: AtomicUsize = AtomicUsize::new(0);
+static CACHE_MISSES: AtomicUsize = AtomicUsize::new(0);
+
+static PATH_CACHE: Locked<PathCache> = Locked::new(PathCache::new());
+
+#[derive(Clone)]
+struct CacheEntry {
+    /// 最后一次使用的时间戳
+    stamp: u64,
+    metadata: Metadata,
+    /// 如果是目录并且已经列出过，保存其子项
+    children: Option<Vec<FileEntry>>,
+}
+
+pub struct PathCache {
+    entries: BTreeMap<String, CacheEntry>,
+    clock: u64,
+}
+
+impl PathCache {
+    const fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    fn touch(&mut self, path: &str) -> Option<&CacheEntry> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(path).map(|entry| {
+            entry.stamp = clock;
+            &*entry
+        })
+    }
+
+    fn insert(&mut self, path: &str, metadata: Metadata, children: Option<Vec<FileEntry>>) {
+        if !self.entries.contains_key(path) && self.entries.len() >= CACHE_CAPACITY {
+            // 淘汰最久没有使用的一项
+            if let Some(oldest) = self.entries.iter().min_by_key(|(_, entry)| entry.stamp).map(|(path, _)| path.clone()) {
+                self.entries.remove(oldest.as_str());
+            }
+        }
+        self.clock += 1;
+        let stamp = self.clock;
+        self.entries.insert(String::from(path), CacheEntry { stamp, metadata, children });
+    }
+
+    /// 父目录的子项列表里也有这一项的元数据，需要一起作废
+    fn invalidate_parent_listing(&mut self, path: &str) {
+        if let Some(parent) = self.entries.get_mut(dirname(path)) {
+            parent.children = None;
+        }
+    }
+}
+
+/// 查询路径的元数据
+pub fn get_metadata(path: &str) -> Option<Metadata> {
+    let mut lock = PATH_CACHE.lock();
+    let ret = lock.touch(path).map(|entry| entry.metadata.clone());
+    count(ret.is_some());
+    ret
+}
+
+/// 查询目录的子项
+pub fn get_children(path: &str) -> Option<Vec<FileEntry>> {
+    let mut lock = PATH_CACHE.lock();
+    let ret = lock.touch(path).and_then(|entry| entry.children.clone());
+    count(ret.is_some());
+    ret
+}
+
+/// 缓存路径的元数据
+pub fn put_metadata(path: &str, metadata: Metadata) {
+    let mut lock = PATH_CACHE.lock();
+    let children = lock.entries.get(path).and_then(|entry| entry.children.clone());
+    lock.insert(path, metadata, children);
+}
+
+/// 缓存目录的元数据和子项
+pub fn put_children(path: &str, metadata: Metadata, children: Vec<FileEntry>) {
+    PATH_CACHE.lock().insert(path, metadata, Some(children));
+}
+
+/// 路径的元数据发生了变化（例如写入改变了长度）
+pub fn invalidate(path: &str) {
+    let mut lock = PATH_CACHE.lock();
+    lock.entries.remove(path);
+    lock.invalidate_parent_listing(path);
+}
+
+/// 路径被删除，连同其所有后代一起作废
+pub fn invalidate_tree(path: &str) {
+    let mut lock = PATH_CACHE.lock();
+    let prefix = if path.ends_with('/') { String::from(path) } else { alloc::format!("{}/", path) };
+    lock.entries.retain(|key, _| key != path && !key.starts_with(prefix.as_str()));
+    lock.invalidate_parent_listing(path);
+}
+
+fn count(hit: bool) {
+    if hit {
+        CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+    } else {
+        CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 缓存命中与未命中的次数
+pub fn stats() -> (usize, usize) {
+    (CACHE_HITS.load(Ordering::Relaxed), CACHE_MISSES.load(Ordering::Relaxed))
+}
@@ -1,16 +1,23 @@
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
 use alloc::string::String;
+use alloc::vec::Vec;
 
 use lazy_static::lazy_static;
 use spin::Mutex;
 
-use cinea_os_sysapi::fs::{FileError, FileIO};
+use cinea_os_sysapi::fs::{FileEntry, FileError, FileIO, Metadata};
+
+/// 设备挂载点
+pub const DEV_DIR: &str = "/dev";
 
 lazy_static! {
     static ref DEVICE_TABLE: Mutex<BTreeMap<String, Box::<dyn FileIO>>> = {
         let mut m: BTreeMap<String, Box<dyn FileIO>> = BTreeMap::new();
 
+        m.insert(String::from("/dev/console"), Box::new(crate::syskrnl::io::ConsoleDevice));
+        m.insert(String::from("/dev/null"), Box::new(NullDevice));
+        m.insert(String::from("/dev/random"), Box::new(RandomDevice));
         m.insert(String::from("/dev/stdout"), Box::new(crate::syskrnl::io::StdOutDevice));
         m.insert(String::from("/dev/uptime"), Box::new(crate::syskrnl::time::UpTimeDevice));
 
@@ -18,10 +25,67 @@ lazy_static! {
     };
 }
 
+/// 读到的永远是 EOF，写入的内容全部丢弃
+pub struct NullDevice;
+
+impl FileIO for NullDevice {
+    fn read(&mut self, _buf: &mut [u8]) -> Result<usize, ()> {
+        Ok(0)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        Ok(buf.len())
+    }
+}
+
+/// 读取时用熵源填满缓冲区
+pub struct RandomDevice;
+
+impl FileIO for RandomDevice {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        crate::syskrnl::random::fill(buf);
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        Ok(buf.len())
+    }
+}
+
 pub fn is_device(path: &str) -> bool {
     DEVICE_TABLE.lock().contains_key(path)
 }
 
+/// 设备节点的元数据
+pub fn metadata(path: &str) -> Option<Metadata> {
+    if path == DEV_DIR {
+        Some(Metadata::new_virtual(path, true))
+    } else if is_device(path) {
+        Some(Metadata::new_virtual(path, false))
+    } else {
+        None
+    }
+}
+
+/// 列出 /dev 下的所有设备
+pub fn list() -> Vec<FileEntry> {
+    DEVICE_TABLE
+        .lock()
+        .keys()
+        .map(|path| FileEntry::File(Metadata::new_virtual(path.as_str(), false)))
+        .collect()
+}
+
+/// 读设备是否不用等待，没有这个设备时也不用等，读的时候报错
+pub fn readable(path: &str) -> bool {
+    DEVICE_TABLE.lock().get(path).map_or(true, |device| device.readable())
+}
+
+/// 写设备是否不用等待
+pub fn writable(path: &str) -> bool {
+    DEVICE_TABLE.lock().get(path).map_or(true, |device| device.writable())
+}
+
 pub fn read(path: &str, buf: &mut [u8]) -> Result<usize, FileError> {
     let mut lock = DEVICE_TABLE.lock();
     match lock.get_mut(path) {
@@ -44,6 +108,21 @@ pub fn write(path: &str, buf: &[u8]) -> Result<usize, FileError> {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::{list, read, write};
+
+    #[test_case]
+    fn test_dev_null_and_random() {
+        let mut buf = [0xAAu8; 16];
+        assert_eq!(read("/dev/null", &mut buf).ok(), Some(0));
+        assert_eq!(write("/dev/null", &buf).ok(), Some(16));
+        assert_eq!(read("/dev/random", &mut buf).ok(), Some(16));
+        assert!(list().iter().any(|entry| matches!(entry, cinea_os_sysapi::fs::FileEntry::File(m) if m.file_name() == "null")));
+        println!("[ok]  Device null and random");
+    }
+}
+
 // use byteorder::{ByteOrder, LittleEndian};
 //
 // const DEVICE_FILE_SIG: [u8; 5] = [b'C', b'E', b'D', b'V', b'C'];
@@ -0,0 +1,332 @@
+//! 启动时装载的只读内存文件系统（initramfs）
+//!
+//! 归档是ustar格式，由构建脚本把`dsk/bin`、`dsk/etc`和内核符号表（`/boot/kernel`）打包，作为第二块AHCI磁盘交给QEMU。
+//! 内核启动时把它整个读进内存并建立索引，只读地叠加在`/`上：归档里有的路径由它提供，
+//! 其余的路径仍然落到数据盘上。更换用户程序只需要重新打包归档，不需要重新编译内核
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use fatfs::Read;
+use spin::Mutex;
+
+use cinea_os_sysapi::fs::{FileEntry, FileError, Metadata};
+
+use crate::{debugln, println};
+
+use super::ahci::AhciDeviceReader;
+
+const BLOCK_SIZE: usize = 512;
+/// 归档所在的AHCI端口，0号是数据盘
+const INITRAMFS_PORT: u64 = 1;
+/// 每次从磁盘读取的大小，不能超过AHCI读取器的缓存
+const READ_CHUNK: usize = 16 << 10;
+
+/// 解析归档时发现的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveError {
+    /// 头部不完整，或者文件内容越过了归档末尾
+    Truncated,
+    /// 头部校验和不对
+    BadChecksum,
+    /// 不是ustar格式
+    BadMagic,
+    /// 大小字段不是合法的八进制数
+    BadSize,
+    /// 文件名不是合法的UTF-8
+    BadName,
+}
+
+enum Node {
+    /// 文件内容在归档中的位置
+    File(Range<usize>),
+    Dir,
+}
+
+/// 解析好的归档，路径都是以`/`开头的绝对路径
+pub struct Archive {
+    data: Vec<u8>,
+    nodes: BTreeMap<String, Node>,
+}
+
+/// 读取以NUL结尾的字段
+fn field(bytes: &[u8]) -> Result<&str, ArchiveError> {
+    let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..len]).map_err(|_| ArchiveError::BadName)
+}
+
+/// 读取八进制数字段，前后可以有空格和NUL
+fn octal(bytes: &[u8]) -> Result<usize, ArchiveError> {
+    let text = field(bytes).map_err(|_| ArchiveError::BadSize)?.trim_matches(|c| c == ' ' || c == '\0');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    usize::from_str_radix(text, 8).map_err(|_| ArchiveError::BadSize)
+}
+
+/// 校验和按校验和字段全是空格来计算
+fn checksum_ok(header: &[u8]) -> Result<bool, ArchiveError> {
+    let expected = octal(&header[148..156]).map_err(|_| ArchiveError::BadChecksum)?;
+    let sum: usize = header
+        .iter()
+        .enumerate()
+        .map(|(i, b)| if (148..156).contains(&i) { b' ' as usize } else { *b as usize })
+        .sum();
+    Ok(sum == expected)
+}
+
+/// 把归档里的名字规范成绝对路径，归档根目录本身返回`None`
+fn normalize(name: &str) -> Option<String> {
+    let parts: Vec<&str> = name.split('/').filter(|part| !part.is_empty() && *part != ".").collect();
+    if parts.is_empty() {
+        None
+    } else {
+        Some(format!("/{}", parts.join("/")))
+    }
+}
+
+fn parent_of(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(pos) => &path[..pos],
+    }
+}
+
+impl Archive {
+    /// 解析ustar归档，任何越界或格式错误都返回错误，不会读到归档之外
+    pub fn parse(data: Vec<u8>) -> Result<Self, ArchiveError> {
+        let mut nodes = BTreeMap::new();
+        let mut pos = 0usize;
+        loop {
+            let header = data.get(pos..pos + BLOCK_SIZE).ok_or(ArchiveError::Truncated)?;
+            // 全零的块表示归档结束
+            if header.iter().all(|b| *b == 0) {
+                break;
+            }
+            if &header[257..262] != b"ustar" {
+                return Err(ArchiveError::BadMagic);
+            }
+            if !checksum_ok(header)? {
+                return Err(ArchiveError::BadChecksum);
+            }
+            let size = octal(&header[124..136])?;
+            let name = field(&header[0..100])?;
+            let prefix = field(&header[345..500])?;
+            let name = if prefix.is_empty() { String::from(name) } else { format!("{}/{}", prefix, name) };
+
+            let start = pos + BLOCK_SIZE;
+            let end = start.checked_add(size).ok_or(ArchiveError::BadSize)?;
+            if end > data.len() {
+                return Err(ArchiveError::Truncated);
+            }
+            if let Some(path) = normalize(name.as_str()) {
+                match header[156] {
+                    b'0' | 0 => {
+                        nodes.insert(path, Node::File(start..end));
+                    }
+                    b'5' => {
+                        nodes.insert(path, Node::Dir);
+                    }
+                    // 链接、设备等其他类型不支持，跳过
+                    _ => debugln!("initramfs: skipping {} of type {}", path, header[156]),
+                }
+            }
+            // 内容按块对齐
+            pos = end.checked_add(BLOCK_SIZE - 1).ok_or(ArchiveError::BadSize)? / BLOCK_SIZE * BLOCK_SIZE;
+        }
+
+        // 归档里可以不写出目录，从文件路径补上
+        let files: Vec<String> = nodes.keys().cloned().collect();
+        for path in files {
+            let mut dir = parent_of(path.as_str());
+            while dir != "/" {
+                if let Some(Node::File(_)) = nodes.get(dir) {
+                    return Err(ArchiveError::BadName);
+                }
+                nodes.entry(String::from(dir)).or_insert(Node::Dir);
+                dir = parent_of(dir);
+            }
+        }
+        Ok(Self { data, nodes })
+    }
+
+    fn metadata(&self, path: &str) -> Option<Metadata> {
+        match self.nodes.get(path)? {
+            Node::File(range) => Some(Metadata::new_virtual(path, false).read_only(range.len() as u64)),
+            Node::Dir => Some(Metadata::new_virtual(path, true).read_only(0)),
+        }
+    }
+
+    fn list(&self, path: &str) -> Option<Vec<FileEntry>> {
+        // 根目录仍然由数据盘提供
+        if !matches!(self.nodes.get(path), Some(Node::Dir)) {
+            return None;
+        }
+        let children = self
+            .nodes
+            .keys()
+            .filter(|child| parent_of(child.as_str()) == path)
+            .filter_map(|child| self.metadata(child.as_str()))
+            .map(|data| if data.is_dir() { FileEntry::Dir(data) } else { FileEntry::File(data) })
+            .collect();
+        Some(children)
+    }
+
+    fn read_at(&self, path: &str, offset: usize, buf: &mut [u8]) -> Option<Result<usize, FileError>> {
+        match self.nodes.get(path)? {
+            Node::Dir => Some(Err(FileError::NotAFileError)),
+            Node::File(range) => {
+                let content = &self.data[range.clone()];
+                if offset >= content.len() {
+                    return Some(Ok(0));
+                }
+                let len = buf.len().min(content.len() - offset);
+                buf[..len].copy_from_slice(&content[offset..offset + len]);
+                Some(Ok(len))
+            }
+        }
+    }
+}
+
+static INITRAMFS: Mutex<Option<Archive>> = Mutex::new(None);
+
+/// 从AHCI磁盘读出归档，按头部里的大小逐个文件读到结束块为止，不多占内核堆
+///
+/// 头部坏了或者内容越过磁盘末尾时就停在那里，留给`Archive::parse`报告错误
+fn load() -> Result<Vec<u8>, ()> {
+    let mut reader = AhciDeviceReader::new(INITRAMFS_PORT)?;
+    // 读取器每次会多读一个扇区，最后一个扇区不读，免得越过磁盘末尾
+    let disk = reader.size().saturating_sub(BLOCK_SIZE);
+    let mut data = Vec::new();
+    while data.len() + BLOCK_SIZE <= disk {
+        let start = data.len();
+        data.resize(start + BLOCK_SIZE, 0);
+        reader.read_exact(&mut data[start..])?;
+        let header = &data[start..];
+        // 全零的块表示归档结束
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+        let size = match octal(&header[124..136]) {
+            Ok(size) => size,
+            Err(_) => break,
+        };
+        // 内容按块对齐
+        let padded = match size.checked_add(BLOCK_SIZE - 1) {
+            Some(size) => size / BLOCK_SIZE * BLOCK_SIZE,
+            None => break,
+        };
+        let content = data.len();
+        if padded > disk - content {
+            break;
+        }
+        data.resize(content + padded, 0);
+        for chunk in data[content..].chunks_mut(READ_CHUNK) {
+            reader.read_exact(chunk)?;
+        }
+    }
+    Ok(data)
+}
+
+/// 读取并挂载initramfs，必须在任何进程启动前调用
+pub fn init() {
+    let data = match load() {
+        Ok(data) => data,
+        Err(_) => {
+            debugln!("initramfs: no archive on ahci port {}", INITRAMFS_PORT);
+            return;
+        }
+    };
+    match Archive::parse(data) {
+        Ok(archive) => {
+            debugln!("initramfs: {} entries", archive.nodes.len());
+            *INITRAMFS.lock() = Some(archive);
+        }
+        Err(err) => println!("[WARN] initramfs is broken ({:?}), not mounted", err),
+    }
+}
+
+/// 路径是否在initramfs里，在的话它是只读的
+pub fn contains(path: &str) -> bool {
+    INITRAMFS.lock().as_ref().is_some_and(|archive| archive.nodes.contains_key(path))
+}
+
+/// initramfs中路径的元数据
+pub fn metadata(path: &str) -> Option<Metadata> {
+    INITRAMFS.lock().as_ref()?.metadata(path)
+}
+
+/// 列出initramfs中的目录，目录不在其中时返回`None`
+pub fn list(path: &str) -> Option<Vec<FileEntry>> {
+    INITRAMFS.lock().as_ref()?.list(path)
+}
+
+/// 从initramfs中的文件读取，文件不在其中时返回`None`
+pub fn read_at(path: &str, offset: usize, buf: &mut [u8]) -> Option<Result<usize, FileError>> {
+    INITRAMFS.lock().as_ref()?.read_at(path, offset, buf)
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+
+    use super::{Archive, ArchiveError, BLOCK_SIZE};
+
+    fn header(name: &str, size: usize, kind: u8) -> Vec<u8> {
+        let mut block = alloc::vec![0u8; BLOCK_SIZE];
+        block[..name.len()].copy_from_slice(name.as_bytes());
+        let size = alloc::format!("{:011o}", size);
+        block[124..135].copy_from_slice(size.as_bytes());
+        block[156] = kind;
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+        block[148..156].copy_from_slice(b"        ");
+        let sum: usize = block.iter().map(|b| *b as usize).sum();
+        block[148..155].copy_from_slice(alloc::format!("{:06o}\0", sum).as_bytes());
+        block
+    }
+
+    fn archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (name, content) in files {
+            data.extend(header(name, content.len(), b'0'));
+            data.extend_from_slice(content);
+            data.resize((data.len() + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE, 0);
+        }
+        data.resize(data.len() + 2 * BLOCK_SIZE, 0);
+        data
+    }
+
+    #[test_case]
+    fn test_initramfs_parse() {
+        let archive = Archive::parse(archive(&[("./bin/hello", b"\x7fBIN"), ("etc/motd", b"hi")])).unwrap();
+        assert!(archive.metadata("/bin").unwrap().is_dir());
+        assert_eq!(archive.metadata("/bin/hello").unwrap().len(), 4);
+        assert_eq!(archive.list("/bin").unwrap().len(), 1);
+        assert!(archive.list("/").is_none());
+        let mut buf = [0u8; 8];
+        assert!(matches!(archive.read_at("/etc/motd", 1, &mut buf), Some(Ok(1))));
+        assert_eq!(buf[0], b'i');
+        assert!(archive.read_at("/etc/nothing", 0, &mut buf).is_none());
+        println!("[ok]  initramfs parse and read");
+    }
+
+    #[test_case]
+    fn test_initramfs_rejects_bad_archives() {
+        let good = archive(&[("bin/hello", &[0x90; 600])]);
+        // 内容被截断、头部不完整
+        assert_eq!(Archive::parse(good[..BLOCK_SIZE + 100].to_vec()).err(), Some(ArchiveError::Truncated));
+        assert_eq!(Archive::parse(good[..100].to_vec()).err(), Some(ArchiveError::Truncated));
+        // 大小字段被改坏
+        let mut bad = good.clone();
+        bad[124..135].copy_from_slice(b"77777777777");
+        assert!(Archive::parse(bad).is_err());
+        let mut bad = good.clone();
+        bad[124] = b'9';
+        assert!(Archive::parse(bad).is_err());
+        println!("[ok]  initramfs rejects bad archives");
+    }
+}
@@ -1,6 +1,8 @@
 mod ahci;
 mod ata;
+pub mod cache;
 pub mod device;
+pub mod initramfs;
 mod oem;
 mod time;
 mod wrap;
@@ -2,6 +2,8 @@
 
 use alloc::collections::BTreeMap;
 use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
@@ -9,16 +11,20 @@ use fatfs::{DirEntry, Read, Seek, SeekFrom, Write};
 use lazy_static::lazy_static;
 use spin::Mutex;
 
+use cinea_os_kcore::path::jail;
 use cinea_os_sysapi::fs as fsapi;
-use cinea_os_sysapi::fs::FileError::{NotAFileError, OSError};
-use cinea_os_sysapi::fs::{dirname, filename, path_combine, realpath, FileEntry, Metadata};
+use cinea_os_sysapi::fs::FileError::{InvalidSeekError, NotAFileError, NotSeekableError, OSError};
+use cinea_os_sysapi::fs::{dirname, filename, path_combine, realpath, FileEntry, Metadata, OpenFlags, PollEvents, PollFd, Whence};
 use fsapi::FileError::{self, NotADirError, NotFoundError, RootDirError};
 
-use crate::syskrnl::fs::device::is_device;
+use crate::{debugln, syskrnl};
+use crate::syskrnl::fs::device::{self, is_device};
+use crate::syskrnl::fs::initramfs;
 use crate::syskrnl::proc;
 use crate::syskrnl::proc::{file_handles, set_dir};
 
 use super::ahci::AhciDeviceReader;
+use super::cache;
 use super::oem::Cp437Converter;
 use super::time::CosTimeProvider;
 
@@ -100,23 +106,79 @@ where
     }
 }
 
+/// 把路径转换为规范的绝对路径，作为路径缓存的键
+fn canonical_path(path: &str) -> Result<String, FileError> {
+    fsapi::path_standardize(realpath(path, proc::dir().as_str()).as_str())
+}
+
+/// 把进程给出的路径换成内核用的绝对路径：相对路径按工作目录补全，再放到进程的根目录下面
+///
+/// 系统调用收到的路径都要先经过这里；`..`越过进程的根目录时失败
+pub fn resolve(path: &str) -> Result<String, FileError> {
+    jail(proc::root().as_str(), proc::dir().as_str(), path).map_err(|_| FileError::BadRelatePathError)
+}
+
 /// 获取路径元数据
 pub fn metadata(path: &str) -> Result<Metadata, FileError> {
+    let key = canonical_path(path)?;
+    if let Some(data) = device::metadata(key.as_str()) {
+        return Ok(data);
+    }
+    if let Some(data) = initramfs::metadata(key.as_str()) {
+        return Ok(data);
+    }
+    if let Some(data) = cache::get_metadata(key.as_str()) {
+        return Ok(data);
+    }
     let lock = DATA_DISK_FS.lock();
     let entry = seekpath(path, lock.root_dir())?;
-    Ok(fsapi::Metadata::from_dir_entry(entry, path))
+    let data = fsapi::Metadata::from_dir_entry(entry, path);
+    cache::put_metadata(key.as_str(), data.clone());
+    Ok(data)
+}
+
+fn entry_name(entry: &FileEntry) -> Option<&str> {
+    match entry {
+        FileEntry::Dir(data) | FileEntry::File(data) => Some(data.file_name()),
+        FileEntry::Device(_) => None,
+    }
 }
 
 /// 列出目录下的文件
+///
+/// initramfs和数据盘上都有的目录，两边的内容合在一起列出，同名的以initramfs为准
 pub fn list(path: &str) -> Result<Vec<FileEntry>, FileError> {
+    let key = canonical_path(path)?;
+    if key == device::DEV_DIR {
+        return Ok(device::list());
+    }
+    if let Some(mut children) = initramfs::list(key.as_str()) {
+        if let Ok(on_disk) = list_disk(path, key.as_str()) {
+            for entry in on_disk {
+                if !children.iter().any(|child| entry_name(child) == entry_name(&entry)) {
+                    children.push(entry);
+                }
+            }
+        }
+        return Ok(children);
+    }
+    list_disk(path, key.as_str())
+}
+
+/// 列出数据盘上的目录
+fn list_disk(path: &str, key: &str) -> Result<Vec<FileEntry>, FileError> {
+    if let Some(children) = cache::get_children(key) {
+        return Ok(children);
+    }
     let lock = DATA_DISK_FS.lock();
     let entry = seekpath(path, lock.root_dir())?;
     if !entry.is_dir() {
         return Err(FileError::NotADirError);
     }
+    let dir = entry.to_dir();
+    let data = fsapi::Metadata::from_dir_entry(entry, path);
 
-    let result: Vec<FileEntry> = entry
-        .to_dir()
+    let result: Vec<FileEntry> = dir
         .iter()
         .filter(|dir_entry| dir_entry.is_ok())
         .map(|dir_entry| {
@@ -129,6 +191,7 @@ pub fn list(path: &str) -> Result<Vec<FileEntry>, FileError> {
             }
         })
         .collect();
+    cache::put_children(key, data, result.clone());
     Ok(result)
 }
 
@@ -149,13 +212,60 @@ pub fn change_dir(path: &str) -> Result<(), FileError> {
     }
 }
 
-/// 打开文件句柄
+/// 打开的文件；dup出来的句柄号指向同一个对象，共享读写位置
 #[derive(Clone, Debug)]
 pub struct OpenFileHandle {
-    pub id: usize,
     pub path: String,
     pub write: bool,
     pub device: bool,
+    /// 读写位置
+    pub offset: usize,
+    /// 追加模式：每次写入前都移动到文件末尾
+    pub append: bool,
+    /// 非阻塞：要等待的读写直接返回`WouldBlockError`
+    pub nonblock: bool,
+    /// 进程自带的系统设备句柄，没有登记在系统文件表里
+    pub system: bool,
+}
+
+impl OpenFileHandle {
+    pub fn new(path: String, flags: OpenFlags, device: bool) -> Self {
+        Self {
+            path,
+            write: flags.contains(OpenFlags::WRITE),
+            device,
+            offset: 0,
+            append: flags.contains(OpenFlags::APPEND),
+            nonblock: flags.contains(OpenFlags::NONBLOCK),
+            system: false,
+        }
+    }
+
+    /// 进程自带的系统设备句柄
+    pub fn system(path: String, flags: OpenFlags) -> Self {
+        Self { system: true, ..Self::new(path, flags, true) }
+    }
+
+    /// 句柄当前的打开方式
+    pub fn flags(&self) -> OpenFlags {
+        let mut flags = OpenFlags::empty();
+        flags.set(OpenFlags::WRITE, self.write);
+        flags.set(OpenFlags::APPEND, self.append);
+        flags.set(OpenFlags::NONBLOCK, self.nonblock);
+        flags
+    }
+}
+
+/// 进程句柄表中的一项，引用计数即指向它的句柄号个数
+pub type FileHandleRef = Arc<Mutex<OpenFileHandle>>;
+
+fn new_handle(path: String, flags: OpenFlags, device: bool) -> FileHandleRef {
+    Arc::new(Mutex::new(OpenFileHandle::new(path, flags, device)))
+}
+
+/// 取出句柄号对应的文件，不持有句柄表的锁
+fn handle(id: usize) -> Result<FileHandleRef, FileError> {
+    file_handles().lock().get(&id).cloned().ok_or(NotFoundError)
 }
 
 /// 系统文件表-条目
@@ -167,48 +277,49 @@ pub struct SystemFileEntry {
 
 static USER_FILE_HANDLER_ID: AtomicUsize = AtomicUsize::new(4);
 
+/// 关机时等待磁盘操作结束的次数，每次等一个时钟中断
+const SYNC_TRIES: usize = 100;
+
+/// FAT的文件最长`u32::MAX`字节，句柄位置不能超过它
+const MAX_FILE_OFFSET: usize = u32::MAX as usize;
+
+/// 在文件末尾之后写入时用来补空洞
+static ZEROS: [u8; 4096] = [0; 4096];
+
 lazy_static! {
     static ref SYSTEM_FILE_TABLE: Mutex<BTreeMap<String, SystemFileEntry >> = Mutex::new(BTreeMap::new());
 }
 
-fn register_opened_file(path: String, write: bool, device: bool) -> Result<usize, FileError> {
+/// 把句柄放进当前进程的句柄表，超过进程的句柄数限制时失败
+fn insert_handle(file: FileHandleRef) -> Result<usize, FileError> {
+    let fh = file_handles();
+    let mut fh = fh.lock();
+    if proc::limits().max_fds.map_or(false, |max| fh.len() >= max) {
+        return Err(FileError::TooManyFilesError);
+    }
+    let new_id = USER_FILE_HANDLER_ID.fetch_add(1, Ordering::Relaxed);
+    fh.insert(new_id, file);
+    Ok(new_id)
+}
+
+fn register_opened_file(path: String, flags: OpenFlags, device: bool) -> Result<usize, FileError> {
     let mut lock = SYSTEM_FILE_TABLE.lock();
     if let Some(sft) = lock.get_mut(path.as_str()) {
         if sft.mutex {
             Err(FileError::FileBusyError)
         } else {
-            let fh = proc::file_handles();
-            let new_id = USER_FILE_HANDLER_ID.fetch_add(1, Ordering::Relaxed);
-            fh.lock().insert(
-                new_id,
-                OpenFileHandle {
-                    id: new_id,
-                    path,
-                    write,
-                    device,
-                },
-            );
+            let new_id = insert_handle(new_handle(path, flags, device))?;
             sft.share += 1;
             Ok(new_id)
         }
     } else {
+        let new_id = insert_handle(new_handle(path.clone(), flags, device))?;
         lock.insert(
             path.clone(),
             SystemFileEntry {
                 path: path.clone(),
                 share: 1,
-                mutex: write,
-            },
-        );
-        let fh = proc::file_handles();
-        let new_id = USER_FILE_HANDLER_ID.fetch_add(1, Ordering::Relaxed);
-        fh.lock().insert(
-            new_id,
-            OpenFileHandle {
-                id: new_id,
-                path,
-                write,
-                device,
+                mutex: flags.contains(OpenFlags::WRITE),
             },
         );
         Ok(new_id)
@@ -216,34 +327,51 @@ fn register_opened_file(path: String, write: bool, device: bool) -> Result<usize
 }
 
 /// 打开文件（内核级）
-pub fn open(path: &str, write: bool) -> Result<usize, FileError> {
+pub fn open(path: &str, flags: OpenFlags) -> Result<usize, FileError> {
     let path = fsapi::path_standardize(path)?;
 
     // Device Check
     if is_device(path.as_str()) {
-        return register_opened_file(path, write, true);
+        return register_opened_file(path, flags, true);
     }
 
     let data = metadata(path.as_str())?;
     if !data.is_file() {
         Err(FileError::NotAFileError)
+    } else if flags.contains(OpenFlags::WRITE) && initramfs::contains(canonical_path(path.as_str())?.as_str()) {
+        Err(FileError::ReadOnlyError)
     } else {
-        register_opened_file(path, write, false)
+        register_opened_file(path, flags, false)
     }
 }
 
 /// 关闭文件（内核）
 pub fn close(id: usize) -> Result<(), FileError> {
-    if id < 4 {
+    if id < fsapi::STDIO_HANDLES {
         return Err(NotFoundError);
     } // 不允许关闭系统设备
     let fh = proc::file_handles();
-    let mut lock = fh.lock();
-    if !lock.contains_key(&id) {
-        return Err(NotFoundError);
+    let file = fh.lock().remove(&id).ok_or(NotFoundError)?;
+    proc::set_cloexec(id, false);
+    release(file)
+}
+
+/// 进程退出时关闭它的句柄表里剩下的句柄
+pub fn release_all(handles: BTreeMap<usize, FileHandleRef>) {
+    for (id, file) in handles {
+        if release(file).is_err() {
+            debugln!("release: handle {} is not in the system file table", id);
+        }
+    }
+}
+
+/// 句柄号已经从句柄表里拿掉，这是指向文件的最后一个句柄的话，文件真正关闭
+fn release(file: FileHandleRef) -> Result<(), FileError> {
+    // 还有别的句柄号（dup或者子进程继承的）指向它的话，文件并没有真正关闭；系统设备没有登记在系统文件表里
+    if Arc::strong_count(&file) > 1 || file.lock().system {
+        return Ok(());
     }
-    let path = lock.get(&id).unwrap().path.clone();
-    lock.remove(&id);
+    let path = file.lock().path.clone();
     let mut lock = SYSTEM_FILE_TABLE.lock();
     let sft = lock.get_mut(path.as_str());
     if sft.is_none() {
@@ -259,7 +387,127 @@ pub fn close(id: usize) -> Result<(), FileError> {
     }
 }
 
-fn write_all_path(path: &str, buf: &[u8]) -> Result<usize, FileError> {
+/// 按重定向表取出当前进程的句柄，交给子进程：（子进程的句柄号，父进程的句柄）
+///
+/// 只能重定向系统设备的句柄号，有一项的句柄不存在就整体失败
+pub fn redirections(table: &[(usize, usize)]) -> Result<Vec<(usize, FileHandleRef)>, FileError> {
+    table
+        .iter()
+        .map(|&(child, parent)| if child < fsapi::STDIO_HANDLES { Ok((child, handle(parent)?)) } else { Err(NotFoundError) })
+        .collect()
+}
+
+/// 复制句柄，新旧句柄号共享同一个打开的文件（包括读写位置）
+pub fn dup(id: usize) -> Result<usize, FileError> {
+    insert_handle(handle(id)?)
+}
+
+/// 读取或修改句柄的打开方式（`F_GETFL`/`F_SETFL`）或句柄号的标志（`F_GETFD`/`F_SETFD`），返回修改后的值
+///
+/// 见`cinea_os_sysapi::fs::set_flags`和`cinea_os_sysapi::fs::set_cloexec`
+pub fn fcntl(id: usize, cmd: usize, arg: usize) -> Result<usize, FileError> {
+    let file = handle(id)?;
+    match cmd {
+        fsapi::F_GETFL | fsapi::F_SETFL => {
+            let mut handle = file.lock();
+            if cmd == fsapi::F_SETFL {
+                let flags = OpenFlags::from_bits_truncate(arg as u32);
+                handle.append = flags.contains(OpenFlags::APPEND);
+                handle.nonblock = flags.contains(OpenFlags::NONBLOCK);
+            }
+            Ok(handle.flags().bits() as usize)
+        }
+        fsapi::F_GETFD => Ok(if proc::cloexec(id) { fsapi::FD_CLOEXEC } else { 0 }),
+        fsapi::F_SETFD => {
+            // 和close一样，系统设备不能动
+            if id < fsapi::STDIO_HANDLES {
+                return Err(NotFoundError);
+            }
+            proc::set_cloexec(id, arg & fsapi::FD_CLOEXEC != 0);
+            Ok(arg & fsapi::FD_CLOEXEC)
+        }
+        _ => Err(OSError),
+    }
+}
+
+/// 执行新程序前关闭当前进程带`FD_CLOEXEC`标志的句柄
+pub fn close_on_exec() {
+    for id in proc::cloexec_fds() {
+        if close(id).is_err() {
+            debugln!("exec: cannot close handle {}", id);
+        }
+    }
+}
+
+/// 等到设备就绪，非阻塞的句柄不等，直接返回`WouldBlockError`
+///
+/// 等的时候不能持有句柄的锁，共享这个句柄的进程会关着中断空转
+fn wait_device(file: &FileHandleRef, ready: fn(&str) -> bool) -> Result<(), FileError> {
+    let (path, nonblock) = {
+        let handle = file.lock();
+        (handle.path.clone(), handle.nonblock)
+    };
+    while !ready(path.as_str()) {
+        if nonblock {
+            return Err(FileError::WouldBlockError);
+        }
+        syskrnl::time::halt();
+    }
+    Ok(())
+}
+
+/// 句柄是否就绪：普通文件总是就绪，设备看读写会不会等待
+fn ready(id: usize, events: PollEvents) -> Result<bool, FileError> {
+    let file = handle(id)?;
+    let (path, device) = {
+        let handle = file.lock();
+        (handle.path.clone(), handle.device)
+    };
+    let readable = events.contains(PollEvents::READ) && (!device || device::readable(path.as_str()));
+    let writable = events.contains(PollEvents::WRITE) && (!device || device::writable(path.as_str()));
+    Ok(readable || writable)
+}
+
+/// 等到`fds`中至少一个句柄就绪，返回就绪句柄的掩码，第i位对应`fds[i]`；`timeout`是时钟中断数，超时返回0
+///
+/// 等的时候让出CPU，被要求结束的进程不再等
+pub fn poll(fds: &[PollFd], timeout: usize) -> Result<u64, FileError> {
+    if fds.len() > fsapi::MAX_POLL_FDS {
+        return Err(FileError::TooManyHandlesError);
+    }
+    let start = syskrnl::time::ticks();
+    loop {
+        let mut mask = 0;
+        for (i, fd) in fds.iter().enumerate() {
+            if ready(fd.fd, PollEvents::from_bits_truncate(fd.events as u32))? {
+                mask |= 1 << i;
+            }
+        }
+        if mask != 0 || syskrnl::time::ticks() - start >= timeout || proc::terminate_pending() {
+            return Ok(mask);
+        }
+        syskrnl::time::halt();
+    }
+}
+
+/// 关机前同步文件系统：等正在进行的磁盘操作结束，等不到就放弃，返回是否等到了
+///
+/// fatfs的写入直接落到磁盘上，没有要写回的缓存，拿到锁就说明没有写到一半的文件
+pub fn sync() -> bool {
+    for _ in 0..SYNC_TRIES {
+        if DATA_DISK_FS.try_lock().is_some() {
+            return true;
+        }
+        syskrnl::time::halt();
+    }
+    false
+}
+
+/// 从指定位置开始写入文件，位置超过文件末尾时中间补零
+fn write_path_at(path: &str, offset: usize, buf: &[u8]) -> Result<usize, FileError> {
+    if initramfs::contains(canonical_path(path)?.as_str()) {
+        return Err(FileError::ReadOnlyError);
+    }
     let lock = DATA_DISK_FS.lock();
     let root = lock.root_dir();
     let file = seekpath(path, root)?;
@@ -268,62 +516,81 @@ fn write_all_path(path: &str, buf: &[u8]) -> Result<usize, FileError> {
     }
     let mut file = file.to_file();
 
-    if file.seek(SeekFrom::Start(0)).is_err() {
-        return Err(OSError);
+    // fatfs会把超过末尾的位置截到末尾
+    let pos = match file.seek(SeekFrom::Start(offset as u64)) {
+        Ok(pos) => pos as usize,
+        Err(_) => return Err(OSError),
+    };
+    // 空洞可能有几个GiB，一段一段地补
+    let mut gap = offset - pos;
+    while gap > 0 {
+        let len = gap.min(ZEROS.len());
+        if file.write_all(&ZEROS[..len]).is_err() {
+            return Err(OSError);
+        }
+        gap -= len;
     }
-    match file.write_all(buf) {
+    let ret = match file.write_all(buf) {
         Err(_) => Err(OSError),
         Ok(()) => Ok(buf.len()),
-    }
+    };
+    // 长度和修改时间变了
+    cache::invalidate(path);
+    ret
 }
 
 fn write_all_device(path: &str, buf: &[u8]) -> Result<usize, FileError> {
     super::device::write(path, buf)
 }
 
-/// 全部写
-/// FIXME：暂不提供部分写、指定指针等功能
+/// 写入文件，从句柄的当前位置开始，写完后移动句柄位置
+///
+/// 以追加模式打开的句柄总是写到文件末尾
 pub fn write_all(id: usize, buf: &[u8]) -> Result<usize, FileError> {
-    let fh = file_handles();
-    let fh_lock = fh.lock();
-    if fh_lock.contains_key(&id) {
-        let handle = fh_lock.get(&id).unwrap();
-        if handle.write {
-            if handle.device {
-                write_all_device(handle.path.as_str(), buf)
-            } else {
-                write_all_path(handle.path.as_str(), buf)
-            }
-        } else {
-            Err(FileError::OpenMethodError)
-        }
+    let file = handle(id)?;
+    let device = file.lock().device;
+    if device {
+        wait_device(&file, device::writable)?;
+    }
+    let mut handle = file.lock();
+    if !handle.write {
+        return Err(FileError::OpenMethodError);
+    }
+    if handle.device {
+        write_all_device(handle.path.as_str(), buf)
     } else {
-        Err(NotFoundError)
+        if handle.append {
+            handle.offset = metadata(handle.path.as_str())?.len() as usize;
+        }
+        let len = write_path_at(handle.path.as_str(), handle.offset, buf)?;
+        handle.offset += len;
+        Ok(len)
     }
 }
 
-/// 全部写（必须已经打开文件）
-/// FIXME：暂不提供部分写、指定指针等功能
-pub fn write_with_path(path: &str, buf: &[u8]) -> Result<usize, FileError> {
-    let path = fsapi::path_standardize(path)?;
+/// 根据路径查找当前进程已经打开的句柄
+fn handle_id_by_path(path: &str) -> Option<usize> {
     let fh = file_handles();
     let fh_lock = fh.lock();
-    if let Some(handle) = fh_lock.iter().find(|x| (*x).1.path == path) {
-        if handle.1.write {
-            if handle.1.device {
-                write_all_device(path.as_str(), buf)
-            } else {
-                write_all_path(path.as_str(), buf)
-            }
-        } else {
-            Err(FileError::OpenMethodError)
-        }
-    } else {
-        Err(NotFoundError)
+    fh_lock.iter().find(|x| (*x).1.lock().path == path).map(|x| *x.0)
+}
+
+/// 写入文件（必须已经打开文件）
+pub fn write_with_path(path: &str, buf: &[u8]) -> Result<usize, FileError> {
+    let path = fsapi::path_standardize(path)?;
+    match handle_id_by_path(path.as_str()) {
+        Some(id) => write_all(id, buf),
+        None => Err(NotFoundError),
     }
 }
 
-fn read_path(path: &str, store: &mut [u8]) -> Result<usize, FileError> {
+/// 从指定位置开始读取文件，直到读满缓冲区或者读到文件末尾
+///
+/// 位置在文件末尾之后时什么也读不到，返回0
+fn read_path_at(path: &str, offset: usize, store: &mut [u8]) -> Result<usize, FileError> {
+    if let Some(ret) = initramfs::read_at(canonical_path(path)?.as_str(), offset, store) {
+        return ret;
+    }
     let lock = DATA_DISK_FS.lock();
     let root = lock.root_dir();
     let file = seekpath(path, root)?;
@@ -332,55 +599,109 @@ fn read_path(path: &str, store: &mut [u8]) -> Result<usize, FileError> {
     }
     let mut file = file.to_file();
 
-    if file.seek(SeekFrom::Start(0)).is_err() {
-        return Err(OSError);
+    match file.seek(SeekFrom::Start(offset as u64)) {
+        Ok(pos) if pos as usize == offset => {}
+        Ok(_) => return Ok(0),
+        Err(_) => return Err(OSError),
     }
-    let mut buf = [0u8; 1024];
     let mut pos = 0usize;
-    while let Ok(len) = file.read(&mut buf) {
-        if len > 0 {
-            store[pos..pos + len].copy_from_slice(&buf[..len]);
-            pos += len;
-            buf.fill(0);
-        } else {
-            return Ok(pos);
+    while pos < store.len() {
+        match file.read(&mut store[pos..]) {
+            Ok(0) => break,
+            Ok(len) => pos += len,
+            Err(_) => return Err(OSError),
         }
     }
-    Err(OSError)
+    Ok(pos)
+}
+
+/// 读取整个文件（内核级），不需要先打开
+pub fn read_to_end(path: &str) -> Result<Vec<u8>, FileError> {
+    let data = metadata(path)?;
+    if !data.is_file() {
+        return Err(NotAFileError);
+    }
+    let mut buf = vec![0u8; data.len() as usize];
+    let len = read_path_at(path, 0, buf.as_mut_slice())?;
+    buf.truncate(len);
+    Ok(buf)
 }
 
 fn read_device(path: &str, buf: &mut [u8]) -> Result<usize, FileError> {
     super::device::read(path, buf)
 }
 
+/// 读取文件，从句柄的当前位置开始，读完后移动句柄位置
 pub fn read(id: usize, buf: &mut [u8]) -> Result<usize, FileError> {
-    let fh = file_handles();
-    let fh_lock = fh.lock();
-    if fh_lock.contains_key(&id) {
-        let handle = fh_lock.get(&id).unwrap();
-        if handle.device {
-            read_device(handle.path.as_str(), buf)
-        } else {
-            read_path(handle.path.as_str(), buf)
-        }
+    let file = handle(id)?;
+    let device = file.lock().device;
+    if device {
+        wait_device(&file, device::readable)?;
+    }
+    let mut handle = file.lock();
+    if handle.device {
+        read_device(handle.path.as_str(), buf)
     } else {
-        Err(NotFoundError)
+        let len = read_path_at(handle.path.as_str(), handle.offset, buf)?;
+        handle.offset += len;
+        Ok(len)
     }
 }
 
 pub fn read_with_path(path: &str, buf: &mut [u8]) -> Result<usize, FileError> {
     let path = fsapi::path_standardize(path)?;
-    let fh = file_handles();
-    let fh_lock = fh.lock();
-    if let Some(handle) = fh_lock.iter().find(|x| (*x).1.path == path) {
-        if handle.1.device {
-            read_device(path.as_str(), buf)
-        } else {
-            read_path(path.as_str(), buf)
-        }
-    } else {
-        Err(NotFoundError)
+    match handle_id_by_path(path.as_str()) {
+        Some(id) => read(id, buf),
+        None => Err(NotFoundError),
+    }
+}
+
+/// 移动句柄的读写位置，返回新的位置
+///
+/// 设备句柄（如控制台）没有位置的概念，不可移动。移到文件开头之前是错误，
+/// 移到文件末尾之后是允许的：之后读到0字节，写则在中间补零
+pub fn seek(id: usize, offset: isize, whence: usize) -> Result<usize, FileError> {
+    let file = handle(id)?;
+    let mut handle = file.lock();
+    if handle.device {
+        return Err(NotSeekableError);
+    }
+    let base = match whence {
+        x if x == Whence::Set as usize => 0,
+        x if x == Whence::Current as usize => handle.offset as isize,
+        x if x == Whence::End as usize => metadata(handle.path.as_str())?.len() as isize,
+        _ => return Err(OSError),
+    };
+    let new_offset = base.checked_add(offset).ok_or(InvalidSeekError)?;
+    if new_offset < 0 || new_offset as usize > MAX_FILE_OFFSET {
+        return Err(InvalidSeekError);
+    }
+    handle.offset = new_offset as usize;
+    Ok(handle.offset)
+}
+
+/// 删除文件或空目录
+pub fn delete(path: &str) -> Result<(), FileError> {
+    let path = canonical_path(path)?;
+    if path.len() <= 1 {
+        return Err(RootDirError);
+    }
+    if SYSTEM_FILE_TABLE.lock().contains_key(path.as_str()) {
+        return Err(FileError::FileBusyError);
     }
+    if initramfs::contains(path.as_str()) {
+        return Err(FileError::ReadOnlyError);
+    }
+
+    let lock = DATA_DISK_FS.lock();
+    let ret = match lock.root_dir().remove(&path[1..]) {
+        Ok(()) => Ok(()),
+        Err(fatfs::Error::NotFound) => Err(NotFoundError),
+        Err(_) => Err(OSError),
+    };
+    // 即使删除失败也作废缓存，下次重新从磁盘读取
+    cache::invalidate_tree(path.as_str());
+    ret
 }
 
 pub fn info(path: &str) -> Result<Metadata, FileError> {
@@ -400,6 +721,132 @@ mod tests {
         println!("[ok]  FileSystem AHCI AHCI_Reader")
     }
 
+    #[test_case]
+    fn test_path_cache_invalidated_on_delete() {
+        use super::{delete, list, DATA_DISK_FS};
+        use super::FileError::NotFoundError;
+
+        DATA_DISK_FS.lock().root_dir().create_dir("cachetest").unwrap();
+        list("/cachetest").unwrap();
+        // 第二次应当命中缓存
+        let (hits, _) = super::cache::stats();
+        list("/cachetest").unwrap();
+        assert_eq!(super::cache::stats().0, hits + 1);
+
+        delete("/cachetest").unwrap();
+        assert!(matches!(list("/cachetest"), Err(NotFoundError)));
+        println!("[ok]  FileSystem path cache invalidation")
+    }
+
+    #[test_case]
+    fn test_seek_and_reread() {
+        use super::{close, delete, dup, open, read, seek, write_all, DATA_DISK_FS};
+        use super::FileError::InvalidSeekError;
+        use cinea_os_sysapi::fs::{OpenFlags, Whence};
+
+        let content: alloc::vec::Vec<u8> = (0..40u8).map(|i| b'a' + i % 26).collect();
+        DATA_DISK_FS.lock().root_dir().create_file("seektest.txt").unwrap();
+        let fd = open("/seektest.txt", OpenFlags::WRITE).unwrap();
+        assert_eq!(write_all(fd, &content).unwrap(), content.len());
+
+        // 按7字节一块读完整个文件
+        assert_eq!(seek(fd, 0, Whence::Set as usize).unwrap(), 0);
+        let mut data = alloc::vec::Vec::new();
+        let mut chunk = [0u8; 7];
+        loop {
+            let len = read(fd, &mut chunk).unwrap();
+            if len == 0 {
+                break;
+            }
+            data.extend_from_slice(&chunk[..len]);
+        }
+        assert_eq!(data, content);
+
+        // 退回去重读，dup出来的句柄共享位置
+        let fd2 = dup(fd).unwrap();
+        assert_eq!(seek(fd2, -10, Whence::Current as usize).unwrap(), 30);
+        assert_eq!(read(fd, &mut chunk).unwrap(), 7);
+        assert_eq!(&chunk, &content[30..37]);
+        assert!(matches!(seek(fd, -100, Whence::Current as usize), Err(InvalidSeekError)));
+
+        // 末尾之后读到0字节，写则补零
+        assert_eq!(seek(fd, 5, Whence::End as usize).unwrap(), 45);
+        assert_eq!(read(fd, &mut chunk).unwrap(), 0);
+        write_all(fd, b"z").unwrap();
+        assert_eq!(seek(fd, 40, Whence::Set as usize).unwrap(), 40);
+        assert_eq!(read(fd, &mut chunk).unwrap(), 6);
+        assert_eq!(&chunk[..6], &[0, 0, 0, 0, 0, b'z']);
+
+        close(fd).unwrap();
+        close(fd2).unwrap();
+        delete("/seektest.txt").unwrap();
+        println!("[ok]  FileSystem seek and reread")
+    }
+
+    #[test_case]
+    fn test_redirections() {
+        use super::{close, open, redirections, release_all};
+        use super::FileError::NotFoundError;
+        use alloc::collections::BTreeMap;
+        use cinea_os_sysapi::fs::OpenFlags;
+
+        let fd = open("/dev/null", OpenFlags::WRITE).unwrap();
+        let redirects = redirections(&[(0, fd)]).unwrap();
+        assert_eq!(redirects.len(), 1);
+        assert!(redirects[0].1.lock().path == "/dev/null");
+        // 子进程只能换系统句柄号，源句柄必须存在，错一项整体失败
+        assert!(matches!(redirections(&[(0, fd), (fd, fd)]), Err(NotFoundError)));
+        assert!(matches!(redirections(&[(0, fd), (1, usize::MAX)]), Err(NotFoundError)));
+        // 模拟子进程退出时放掉它那一份
+        release_all(redirects.into_iter().collect::<BTreeMap<_, _>>());
+        close(fd).unwrap();
+        println!("[ok]  FileSystem spawn redirections")
+    }
+
+    #[test_case]
+    fn test_nonblocking_console_read() {
+        use super::{close, fcntl, open, read};
+        use super::FileError::WouldBlockError;
+        use cinea_os_sysapi::fs::{OpenFlags, F_GETFL, F_SETFL};
+
+        // 没有缓冲的键盘输入，非阻塞的读马上返回
+        let fd = open("/dev/console", OpenFlags::NONBLOCK).unwrap();
+        let mut buf = [0u8; 8];
+        assert!(matches!(read(fd, &mut buf), Err(WouldBlockError)));
+        close(fd).unwrap();
+
+        // 打开后再设置
+        let fd = open("/dev/console", OpenFlags::empty()).unwrap();
+        assert_eq!(fcntl(fd, F_GETFL, 0).unwrap(), 0);
+        let flags = (OpenFlags::NONBLOCK | OpenFlags::WRITE).bits() as usize;
+        assert_eq!(fcntl(fd, F_SETFL, flags).unwrap(), OpenFlags::NONBLOCK.bits() as usize);
+        assert!(matches!(read(fd, &mut buf), Err(WouldBlockError)));
+        close(fd).unwrap();
+        println!("[ok]  FileSystem nonblocking console read")
+    }
+
+    #[test_case]
+    fn test_poll_readiness() {
+        use super::{close, delete, open, poll, DATA_DISK_FS};
+        use cinea_os_sysapi::fs::{OpenFlags, PollEvents, PollFd};
+
+        DATA_DISK_FS.lock().root_dir().create_file("polltest.txt").unwrap();
+        let file = open("/polltest.txt", OpenFlags::empty()).unwrap();
+        let console = open("/dev/console", OpenFlags::empty()).unwrap();
+        // 控制台没有输入，读不就绪，写就绪；普通文件总是就绪
+        let fds = [PollFd::new(console, PollEvents::READ), PollFd::new(file, PollEvents::READ)];
+        assert_eq!(poll(&fds, 0).unwrap(), 0b10);
+        assert_eq!(poll(&fds[..1], 0).unwrap(), 0);
+        assert_eq!(poll(&[PollFd::new(console, PollEvents::WRITE)], 0).unwrap(), 1);
+        // 超时按时钟中断计
+        assert_eq!(poll(&fds[..1], 2).unwrap(), 0);
+
+        close(console).unwrap();
+        close(file).unwrap();
+        delete("/polltest.txt").unwrap();
+        println!("[ok]  FileSystem poll readiness")
+    }
+
     #[test_case]
     fn test_process_relative_path() {
         let mut test_set1 = vec!["foo", "bar"];
@@ -0,0 +1,149 @@
+//! 用户内存地址上的等待/唤醒（futex）
+//!
+//! 等待者按用户地址对应的物理地址（物理帧加页内偏移）分桶存放，
+//! 两个进程映射同一个物理页时也能互相同步，目前只有只读的vDSO页是这样。
+//! 检查值和入队都在桶锁里完成，唤醒者不可能插在两者之间
+
+use alloc::vec::Vec;
+
+use spin::Mutex;
+
+use cinea_os_sysapi::event::{FUTEX_AGAIN, FUTEX_FAULT};
+
+use crate::syskrnl;
+use crate::syskrnl::proc::{self, Access, SCHEDULER};
+use crate::syskrnl::uaccess;
+
+const BUCKETS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Waiter {
+    key: u64,
+    pid: usize,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_BUCKET: Mutex<Vec<Waiter>> = Mutex::new(Vec::new());
+static TABLE: [Mutex<Vec<Waiter>>; BUCKETS] = [EMPTY_BUCKET; BUCKETS];
+
+fn bucket(key: u64) -> &'static Mutex<Vec<Waiter>> {
+    // 低两位总是0（四字节对齐）
+    &TABLE[(key >> 2) as usize % BUCKETS]
+}
+
+/// 用户地址对应的物理地址，地址没有对齐或者没有映射时失败
+///
+/// vDSO页不在进程自己的内存区域里，单独认
+fn key_of(addr: usize) -> Result<u64, ()> {
+    let ptr = if syskrnl::vdso::contains(addr as u64, 4) && proc::has_vdso() {
+        addr as u64
+    } else {
+        proc::translate_user_ptr(proc::id(), addr as u64, 4, Access::Read).map_err(|_| ())? as u64
+    };
+    if ptr % 4 != 0 {
+        return Err(());
+    }
+    unsafe { syskrnl::memory::translate_addr(ptr) }.ok_or(())
+}
+
+/// 设置当前进程这次调用的返回值
+fn set_return(value: usize) {
+    let mut regs = proc::registers();
+    regs.rax = value;
+    proc::set_registers(regs);
+}
+
+/// 入队并挂起，返回下一个运行的进程
+fn enqueue(bucket: &mut Vec<Waiter>, key: u64, pid: usize) -> usize {
+    bucket.push(Waiter { key, pid });
+    SCHEDULER.lock().wait()
+}
+
+/// 唤醒最多`n`个等在`key`上的进程，先来的先醒，返回唤醒的个数
+fn dequeue(bucket: &mut Vec<Waiter>, key: u64, n: usize) -> usize {
+    let mut woken = 0;
+    while woken < n {
+        match bucket.iter().position(|waiter| waiter.key == key) {
+            Some(pos) => {
+                let waiter = bucket.remove(pos);
+                SCHEDULER.lock().wakeup(waiter.pid);
+                woken += 1;
+            }
+            None => break,
+        }
+    }
+    woken
+}
+
+/// `addr`处的u32仍然等于`expected`时挂起当前进程，返回下一个运行的进程
+///
+/// 值已经变了的话不挂起，返回`FUTEX_AGAIN`
+pub fn wait(addr: usize, expected: u32) -> usize {
+    let pid = proc::id();
+    let key = match key_of(addr) {
+        Ok(key) => key,
+        Err(_) => {
+            set_return(FUTEX_FAULT);
+            return pid;
+        }
+    };
+    let mut bucket = bucket(key).lock();
+    let mut value = [0u8; 4];
+    if uaccess::copy_from_user(&mut value, addr as u64).is_err() {
+        set_return(FUTEX_FAULT);
+        return pid;
+    }
+    if u32::from_ne_bytes(value) != expected {
+        set_return(FUTEX_AGAIN);
+        return pid;
+    }
+    // 被唤醒后从这里返回0
+    set_return(0);
+    enqueue(&mut bucket, key, pid)
+}
+
+/// 唤醒最多`n`个等在`addr`上的进程，返回值是唤醒的个数
+pub fn wake(addr: usize, n: usize) -> usize {
+    let pid = proc::id();
+    match key_of(addr) {
+        Ok(key) => {
+            let woken = dequeue(&mut bucket(key).lock(), key, n);
+            set_return(woken);
+        }
+        Err(_) => set_return(FUTEX_FAULT),
+    }
+    pid
+}
+
+/// 把进程从所有等待队列中移除，返回它之前是否在等待
+pub fn remove(pid: usize) -> bool {
+    let mut found = false;
+    for bucket in TABLE.iter() {
+        let mut bucket = bucket.lock();
+        let len = bucket.len();
+        bucket.retain(|waiter| waiter.pid != pid);
+        found |= bucket.len() != len;
+    }
+    found
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+
+    use super::{bucket, remove, Waiter};
+
+    #[test_case]
+    fn test_futex_buckets() {
+        // 不经过调度器，直接检查等待队列
+        let key = 0xdead_0000;
+        bucket(key).lock().extend([Waiter { key, pid: 9 }, Waiter { key: key + 4, pid: 10 }, Waiter { key, pid: 11 }]);
+        remove(9);
+        let pids: Vec<usize> = bucket(key).lock().iter().filter(|w| w.key == key).map(|w| w.pid).collect();
+        assert_eq!(pids, [11]);
+        remove(10);
+        remove(11);
+        assert!(bucket(key).lock().iter().all(|w| w.pid < 9 || w.pid > 11));
+        println!("[ok]  futex wait table");
+    }
+}
@@ -11,11 +11,11 @@ use rusttype::{point, Rect, ScaledGlyph};
 use spin::{Mutex, RwLock};
 use tinybmp::{Bmp, ChannelMasks, RawBmp, RawPixel};
 use volatile::Volatile;
-use x86_64::instructions::interrupts;
 use x86_64::structures::paging::{FrameAllocator, OffsetPageTable, Page, Size4KiB};
 use x86_64::VirtAddr;
 
 use crate::rgb888;
+use crate::syskrnl::allocator::IrqSafeGuard;
 use crate::syskrnl::graphic::color::alpha_mix;
 use crate::syskrnl::graphic::font::get_font;
 use crate::syskrnl::graphic::text::TEXT_WRITER;
@@ -472,7 +472,5 @@ pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
 
     // 防止死锁
-    interrupts::without_interrupts(|| {
-        TEXT_WRITER.lock().write_fmt(args).unwrap();
-    })
+    IrqSafeGuard::new(|| TEXT_WRITER.lock()).write_fmt(args).unwrap();
 }
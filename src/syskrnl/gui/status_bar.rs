@@ -1,8 +1,30 @@
 use crate::rgb888;
+use crate::syskrnl::allocator::{HeapAllocator, ALLOCATOR};
 use crate::syskrnl::graphic::{GL, WIDTH};
-use crate::syskrnl::io;
+use crate::syskrnl::gui::WINDOW_MANAGER;
+use crate::syskrnl::schedule::loadavg;
 use crate::syskrnl::time::raw_time;
+use crate::syskrnl::workqueue::{self, WorkItem};
+use crate::syskrnl::{io, memory, proc, time, vga_buffer};
 use alloc::format;
+use alloc::string::String;
+use cinea_os_sysapi::syscall::LOAD_SHIFT;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// 状态栏是否开启
+static ENABLED: AtomicBool = AtomicBool::new(true);
+/// 时钟的冒号是否显示
+static SHOW_COLON: AtomicBool = AtomicBool::new(true);
+/// 待完成的绘制工作，由中断设置，交给工作队列完成
+static PENDING: AtomicU8 = AtomicU8::new(0);
+
+const PENDING_CLOCK: u8 = 1;
+const PENDING_STATS: u8 = 2;
+
+/// 统计信息在状态栏中的位置（右半边）
+const STATS_POS: usize = WIDTH / 2 + 64;
+const STATS_WIDTH: usize = WIDTH - STATS_POS;
 
 pub fn show_status_bar() {
     GL.read()[0].lock().display_rect(0, 0, WIDTH, 18, rgb888!(0x37474Fu32));
@@ -13,11 +35,86 @@ pub fn show_status_bar() {
     };
 }
 
-pub fn update_status_bar_time(show_colon: bool) {
+/// 半秒一次，由RTC中断调用，只记录工作
+pub fn request_time_update(show_colon: bool) {
+    SHOW_COLON.store(show_colon, Ordering::Relaxed);
+    request(PENDING_CLOCK);
+}
+
+/// 一秒一次，由PIT中断调用，只记录工作
+pub fn request_stats_update() {
+    request(PENDING_STATS);
+}
+
+/// 记下工作，之前没有待完成的工作时才放入工作队列，多次请求合并成一次绘制
+///
+/// 队列满了也没关系，0号进程的空闲循环总会检查待完成的工作
+fn request(work: u8) {
+    if PENDING.fetch_or(work, Ordering::Relaxed) == 0 {
+        workqueue::push(WorkItem::new(run_pending_work, 0));
+    }
+}
+
+fn run_pending_work(_: usize) {
+    // 绘制要拿图层的锁，而0号进程可能正拿着锁被抢占，所以只在0号进程里画；
+    // 在别的进程的系统调用返回前轮到这项工作时留着不动，等0号进程的空闲循环
+    if proc::id() == 0 {
+        run_pending();
+    }
+}
+
+/// 开关状态栏
+pub fn set_enabled(on: bool) {
+    ENABLED.store(on, Ordering::SeqCst);
+    request(PENDING_CLOCK | PENDING_STATS);
+}
+
+/// 有进程持有窗口（直接写显存）时，状态栏自动让出
+fn framebuffer_owned() -> bool {
+    without_interrupts(|| WINDOW_MANAGER.try_lock().map_or(false, |manager| manager.has_windows()))
+}
+
+/// 完成中断留下的绘制工作，只能在非中断上下文中调用
+pub fn run_pending() {
+    let pending = PENDING.swap(0, Ordering::Relaxed);
+    if pending == 0 {
+        return;
+    }
+    let visible = ENABLED.load(Ordering::SeqCst) && !framebuffer_owned();
+
     if io::VIDEO_MODE.lock().is_text() {
+        let line = if visible { stats_line() } else { String::new() };
+        vga_buffer::write_status_row(line.as_str());
         return;
     }
 
+    if pending & PENDING_CLOCK != 0 {
+        update_status_bar_time(SHOW_COLON.load(Ordering::Relaxed), visible);
+    }
+    if pending & PENDING_STATS != 0 {
+        update_status_bar_stats(visible);
+    }
+}
+
+/// 运行时间、1分钟负载、内核堆、空闲帧、进程数
+fn stats_line() -> String {
+    let allocator = ALLOCATOR.lock_irqsave();
+    let (used, total) = (allocator.allocated(), allocator.size());
+    drop(allocator);
+    let load = (loadavg::loads()[0] * 100) >> LOAD_SHIFT;
+    format!(
+        "up {}s ld {}.{:02} heap {}/{}K frm {} proc {}",
+        time::uptime() as usize,
+        load / 100,
+        load % 100,
+        used >> 10,
+        total >> 10,
+        memory::free_frames(),
+        proc::count()
+    )
+}
+
+fn update_status_bar_time(show_colon: bool, visible: bool) {
     let time = raw_time();
 
     let time_str = if show_colon {
@@ -30,13 +127,20 @@ pub fn update_status_bar_time(show_colon: bool) {
         let p_lock = GL.read();
         let mut lock = p_lock[1].lock();
         lock.clear_rect(0, 330, 128, 20);
-        // lock.clear_rect(200, 270, 256, 35);
-        lock.display_font_string(time_str.as_str(), 0, (WIDTH / 2) - ((12 * 8) / 2), 16.0, 16, rgb888!(0xffffffu32));
-        // lock.display_font_string(
-        //     time_str.as_str(), 200, (WIDTH / 2) - ((24 * 8) / 2), 32.0, 32, rgb888!(0xffffffu32),
-        // );
-        drop(lock);
-        drop(p_lock);
-        // GD.lock().render(200, 270, 235, 530);
+        if visible {
+            lock.display_font_string(time_str.as_str(), 0, (WIDTH / 2) - ((12 * 8) / 2), 16.0, 16, rgb888!(0xffffffu32));
+        }
+    }
+}
+
+fn update_status_bar_stats(visible: bool) {
+    // 先格式化好，持锁期间只写一行
+    let line = if visible { stats_line() } else { String::new() };
+
+    unsafe {
+        let p_lock = GL.read();
+        let mut lock = p_lock[1].lock();
+        lock.clear_rect(0, STATS_POS, STATS_WIDTH, 18);
+        lock.display_font_string(line.as_str(), 0, STATS_POS, 16.0, 16, rgb888!(0xffffffu32));
     }
 }
@@ -107,6 +107,11 @@ impl WindowManager {
         }
     }
 
+    /// 是否有进程持有窗口
+    pub fn has_windows(&self) -> bool {
+        self.layout.layouts.iter().any(|layout| layout.2)
+    }
+
     fn draw_window_frame(&self, x: usize, y: usize, window: &Window, writer: &mut graphic::Writer, active: bool) {
         let main_color = if active { rgb888!(0x262A10u32) } else { rgb888!(0x54442Bu32) };
         writer.display_rect(x, y, WINDOW_WIDTH, 20, main_color);
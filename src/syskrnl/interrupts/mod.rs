@@ -7,9 +7,12 @@ use spin::Mutex;
 use x86_64::registers::control::Cr3;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, InterruptStackFrameValue, PageFaultErrorCode};
 
+use cinea_os_sysapi::syscall::IrqStat;
+
 use crate::syskrnl::gui::panic;
 use crate::syskrnl::io::qemu::qemu_print;
 use crate::syskrnl::proc::{Registers, SCHEDULER};
+use crate::syskrnl::schedule::loadavg;
 use crate::syskrnl::time;
 use crate::syskrnl::time::ticks;
 use crate::{debugln, println, syskrnl};
@@ -24,6 +27,29 @@ fn interrupt_index(irq: u8) -> u8 {
 /// 默认IRQ处理器
 fn default_irq_handler() {}
 
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// 每条IRQ线处理过的中断数
+static IRQ_COUNTS: [AtomicUsize; 16] = [ZERO_COUNT; 16];
+/// 处理过的系统调用数
+static SYSCALL_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// 进程切换次数
+static CONTEXT_SWITCHES: AtomicUsize = AtomicUsize::new(0);
+
+/// 开机以来的中断、系统调用和调度统计
+pub fn irq_stat() -> IrqStat {
+    let (path_cache_hits, path_cache_misses) = syskrnl::fs::cache::stats();
+    IrqStat {
+        irqs: core::array::from_fn(|irq| IRQ_COUNTS[irq].load(Ordering::Relaxed)),
+        syscalls: SYSCALL_COUNT.load(Ordering::Relaxed),
+        context_switches: CONTEXT_SWITCHES.load(Ordering::Relaxed),
+        quantum_ms: syskrnl::schedule::quantum_ms(),
+        path_cache_hits,
+        path_cache_misses,
+        work_dropped: syskrnl::workqueue::dropped(),
+    }
+}
+
 lazy_static! {
     pub static ref IRQ_HANDLERS: Mutex<[fn(); 16]> = Mutex::new([default_irq_handler; 16]);
 
@@ -36,10 +62,10 @@ lazy_static! {
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(syskrnl::gdt::DOUBLE_FAULT_IST_INDEX);
             idt.page_fault
-                .set_handler_fn(page_fault_handler)
+                .set_handler_fn(core::mem::transmute(wrapped_page_fault_handler as *mut fn()))
                 .set_stack_index(syskrnl::gdt::PAGE_FAULT_IST_INDEX);
             idt.general_protection_fault
-                .set_handler_fn(general_protection_fault_handler)
+                .set_handler_fn(core::mem::transmute(wrapped_general_protection_fault_handler as *mut fn()))
                 .set_stack_index(syskrnl::gdt::GENERAL_PROTECTION_FAULT_IST_INDEX);
             // PIT刷新中断
             idt[interrupt_index(0) as usize]
@@ -80,6 +106,7 @@ lazy_static! {
 macro_rules! irq_handler {
     ($handler:ident, $irq:expr) => {
         pub extern "x86-interrupt" fn $handler(_stack_frame: InterruptStackFrame) {
+            IRQ_COUNTS[$irq].fetch_add(1, Ordering::Relaxed);
             let handlers = IRQ_HANDLERS.lock();
             handlers[$irq]();
             unsafe {
@@ -117,45 +144,19 @@ pub fn init_idt() {
 
 /// 调试异常处理函数
 extern "x86-interrupt" fn breakpoint_handler(_stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", _stack_frame);
-    qemu_print(format!("EXCEPTION: BREAKPOINT\n{:#?}\n", _stack_frame).as_str());
+    let rip = syskrnl::ksyms::describe(_stack_frame.instruction_pointer.as_u64());
+    println!("EXCEPTION: BREAKPOINT at {}\n{:#?}", rip, _stack_frame);
+    qemu_print(format!("EXCEPTION: BREAKPOINT at {}\n{:#?}\n", rip, _stack_frame).as_str());
 }
 
 /// 双重异常处理函数
 extern "x86-interrupt" fn double_fault_handler(_stack_frame: InterruptStackFrame, _error_code: u64) -> ! {
-    println!("EXCEPTION: DOUBLE FAULT\n{:#?}", _stack_frame);
-    qemu_print(format!("EXCEPTION: DOUBLE FAULT\n{:#?}\n", _stack_frame).as_str());
+    let rip = syskrnl::ksyms::describe(_stack_frame.instruction_pointer.as_u64());
+    println!("EXCEPTION: DOUBLE FAULT at {}\n{:#?}", rip, _stack_frame);
+    qemu_print(format!("EXCEPTION: DOUBLE FAULT at {}\n{:#?}\n", rip, _stack_frame).as_str());
     loop {}
 }
 
-/// 一般保护异常处理函数
-extern "x86-interrupt" fn general_protection_fault_handler(stack_frame: InterruptStackFrame, error_code: u64) {
-    debugln!(
-        "EXCEPTION: GENERAL PROTECTION FAULT\nStack Frame: {:#?}\nError: {:?}\n",
-        stack_frame,
-        error_code
-    );
-
-    let panic_desc = format!("Stack Frame: {:#?}\nError: {:?}\n", stack_frame, error_code);
-    let panic_info = panic::PanicInfo::new("一般保护异常 General Protection", panic_desc.as_str());
-
-    panic::handle_panic(&panic_info);
-}
-
-/// 页错异常处理函数
-extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, _error_code: PageFaultErrorCode) {
-    use x86_64::registers::control::Cr2;
-
-    qemu_print(format!("EXCEPTION: PAGE FAULT\n").as_str());
-    qemu_print(format!("Accessed Address: {:?}\n", Cr2::read()).as_str());
-    qemu_print(format!("{:#?}\n", stack_frame).as_str());
-
-    let panic_desc = format!("Accessed Address: {:?}\n{:#?}\n", Cr2::read(), stack_frame);
-    let panic_info = panic::PanicInfo::new("页错异常 Page Fault", panic_desc.as_str());
-
-    panic::handle_panic(&panic_info);
-}
-
 // 裸函数包装器，用于把暂存寄存器的值保存到堆栈
 // See（不是第一版怎么还有这种好东西）: https://os.phil-opp.com/returning-from-exceptions/#a-naked-wrapper-function
 macro_rules! wrap {
@@ -205,6 +206,169 @@ macro_rules! wrap {
     };
 }
 
+// 带错误码的异常使用的包装器，处理完后要先弹出错误码再返回
+macro_rules! wrap_with_error_code {
+    ($fn: ident => $w:ident) => {
+        #[naked]
+        pub unsafe extern "sysv64" fn $w() {
+            asm!(
+                "push rax",
+                "push rcx",
+                "push rdx",
+                "push rbx",
+                "push rbp",
+                "push rsi",
+                "push rdi",
+                "push r8",
+                "push r9",
+                "push r10",
+                "push r11",
+                "push r12",
+                "push r13",
+                "push r14",
+                "push r15",
+                "mov rsi, rsp", // Arg #2: register list
+                "mov rdi, rsp", // Arg #1: error code and interupt frame
+                "add rdi, 15 * 8",
+                "call {}",
+                "pop r15",
+                "pop r14",
+                "pop r13",
+                "pop r12",
+                "pop r11",
+                "pop r10",
+                "pop r9",
+                "pop r8",
+                "pop rdi",
+                "pop rsi",
+                "pop rbp",
+                "pop rbx",
+                "pop rdx",
+                "pop rcx",
+                "pop rax",
+                "add rsp, 8", // 错误码
+                "iretq",
+                sym $fn,
+                options(noreturn)
+            );
+        }
+    };
+}
+
+/// 带错误码的异常栈帧
+#[repr(C)]
+struct ExceptionStackFrame {
+    error_code: u64,
+    stack_frame: InterruptStackFrame,
+}
+
+/// 用户进程触发异常时杀死它并切换到下一个进程，返回是否处理了
+///
+/// 进程注册了异常处理函数的话改为跳到处理函数，`fault_addr`作为参数交给它。
+/// 内核自己触发的异常仍然交给panic处理
+fn kill_faulting_process(stack_frame: &mut InterruptStackFrame, regs: &mut Registers, fault_addr: u64) -> bool {
+    let user_mode = stack_frame.code_segment & 3 == 3;
+    if !user_mode || syskrnl::proc::id() == 0 {
+        return false;
+    }
+    let (rip, rsp) = (stack_frame.instruction_pointer.as_u64(), stack_frame.stack_pointer.as_u64());
+    if let Some((handler, new_rsp)) = syskrnl::proc::enter_fault_handler(fault_addr, rip, rsp) {
+        unsafe {
+            stack_frame.as_mut().update(|frame| {
+                frame.instruction_pointer = x86_64::VirtAddr::new(handler);
+                frame.stack_pointer = x86_64::VirtAddr::new(new_rsp);
+            })
+        };
+        regs.rdi = fault_addr as usize;
+        regs.rsi = rip as usize;
+        return true;
+    }
+    let report = syskrnl::proc::crash_report(stack_frame.instruction_pointer.as_u64(), regs.rbp as u64);
+    println!("{}", report);
+    debugln!("{}", report);
+    syskrnl::proc::set_exit_code(cinea_os_sysapi::ExitCode::PageFaultError);
+    let next_pid = syskrnl::proc::exit();
+    unsafe {
+        switch_context_to(next_pid, stack_frame, regs);
+    }
+    true
+}
+
+wrap_with_error_code!(general_protection_fault_handler => wrapped_general_protection_fault_handler);
+
+/// 一般保护异常处理函数
+extern "sysv64" fn general_protection_fault_handler(frame: &mut ExceptionStackFrame, regs: &mut Registers) {
+    let (stack_frame, error_code) = (&mut frame.stack_frame, frame.error_code);
+    debugln!(
+        "EXCEPTION: GENERAL PROTECTION FAULT\nStack Frame: {:#?}\nError: {:?}\n",
+        stack_frame,
+        error_code
+    );
+
+    if kill_faulting_process(stack_frame, regs, 0) {
+        return;
+    }
+
+    let rip = syskrnl::ksyms::describe(stack_frame.instruction_pointer.as_u64());
+    let panic_desc = format!("RIP: {}\nStack Frame: {:#?}\nError: {:?}\n", rip, stack_frame, error_code);
+    let panic_info = panic::PanicInfo::new("一般保护异常 General Protection", panic_desc.as_str());
+
+    panic::handle_panic(&panic_info);
+}
+
+wrap_with_error_code!(page_fault_handler => wrapped_page_fault_handler);
+
+/// 页错异常处理函数
+extern "sysv64" fn page_fault_handler(frame: &mut ExceptionStackFrame, regs: &mut Registers) {
+    use x86_64::registers::control::Cr2;
+
+    let (stack_frame, error_code) = (&mut frame.stack_frame, PageFaultErrorCode::from_bits_truncate(frame.error_code));
+
+    // 用户栈按需往下长，映射好之后重新执行出错的指令；内核替进程写栈上还没碰过的地方时也一样
+    if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        let (fault_addr, rsp) = (Cr2::read().as_u64(), stack_frame.stack_pointer.as_u64());
+        let grown = if stack_frame.code_segment & 3 == 3 {
+            syskrnl::proc::grow_stack(fault_addr, rsp)
+        } else if syskrnl::uaccess::fixup(stack_frame.instruction_pointer.as_u64()).is_some() {
+            syskrnl::proc::grow_stack(fault_addr, fault_addr)
+        } else {
+            Ok(false)
+        };
+        match grown {
+            Ok(true) => return,
+            Err(limit) if stack_frame.code_segment & 3 == 3 => {
+                println!("pid {} stack overflow: {:#x} is below the stack limit {:#x}", syskrnl::proc::id(), fault_addr, limit);
+            }
+            _ => {}
+        }
+    }
+
+    // 内核访问用户内存时出错，从恢复地址继续执行，由复制函数返回错误
+    if let Some(recovery_ip) = syskrnl::uaccess::fixup(stack_frame.instruction_pointer.as_u64()) {
+        unsafe {
+            stack_frame
+                .as_mut()
+                .update(|frame| frame.instruction_pointer = x86_64::VirtAddr::new(recovery_ip))
+        };
+        return;
+    }
+
+    qemu_print(format!("EXCEPTION: PAGE FAULT\n").as_str());
+    qemu_print(format!("Accessed Address: {:?}\n", Cr2::read()).as_str());
+    qemu_print(format!("Error: {:?}\n", error_code).as_str());
+    qemu_print(format!("{:#?}\n", stack_frame).as_str());
+
+    if kill_faulting_process(stack_frame, regs, Cr2::read().as_u64()) {
+        return;
+    }
+
+    let rip = syskrnl::ksyms::describe(stack_frame.instruction_pointer.as_u64());
+    let panic_desc = format!("RIP: {}\nAccessed Address: {:?}\n{:#?}\n", rip, Cr2::read(), stack_frame);
+    let panic_info = panic::PanicInfo::new("页错异常 Page Fault", panic_desc.as_str());
+
+    panic::handle_panic(&panic_info);
+}
+
 wrap!(syscall_handler => wrapped_syscall_handler);
 
 extern "sysv64" fn syscall_handler(stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
@@ -214,16 +378,32 @@ extern "sysv64" fn syscall_handler(stack_frame: &mut InterruptStackFrame, regs:
     let arg2 = regs.rsi;
     let arg3 = regs.rdx;
     let arg4 = regs.r8;
+    SYSCALL_COUNT.fetch_add(1, Ordering::Relaxed);
 
-    if n == cinea_os_sysapi::call::SPAWN {
-        // 保存现场
+    if n == cinea_os_sysapi::call::SPAWN || n == cinea_os_sysapi::call::EXIT || n == cinea_os_sysapi::call::RUN {
+        // 保存现场，EXIT可能要先跳到退出处理函数，RUN要从这里睡到子进程退出
         syskrnl::proc::set_stack_frame(**stack_frame);
         syskrnl::proc::set_registers(*regs);
     }
 
     let res = syskrnl::syscall::dispatcher(n, arg1, arg2, arg3, arg4);
 
-    if n == cinea_os_sysapi::call::EXIT {
+    // 内核代码里嵌套的系统调用不能在这里切走，外层系统调用还拿着内核栈和锁，标志留给最外层返回用户态时处理
+    let user_mode = stack_frame.code_segment & 3 == 3;
+    let exiting = user_mode && (syskrnl::proc::take_exit_on_return() || syskrnl::proc::terminate_pending());
+    if n != cinea_os_sysapi::call::EXIT && exiting {
+        // 系统调用里发现进程已经无法继续运行，或者它已经被要求结束，直接结束它
+        let next_pid = syskrnl::proc::exit();
+        unsafe {
+            switch_context_to(next_pid, stack_frame, regs);
+        }
+    } else if n == cinea_os_sysapi::call::EXEC && res == 0 {
+        // 映像已经换掉，从新程序的入口开始运行
+        let pid = syskrnl::proc::id();
+        unsafe {
+            switch_context_to(pid, stack_frame, regs);
+        }
+    } else if n == cinea_os_sysapi::call::EXIT {
         // 恢复现场
         debugln!("恢复现场");
         debugln!("额外信息：{:?}", SCHEDULER.lock());
@@ -235,10 +415,17 @@ extern "sysv64" fn syscall_handler(stack_frame: &mut InterruptStackFrame, regs:
         regs.rax = res;
     }
 
+    // 返回用户态之前处理中断留下的工作
+    syskrnl::workqueue::run_pending();
+
     unsafe { pics::PICS.lock().notify_end_of_interrupt(0x80) };
 }
 
 unsafe fn switch_context_to(pid: usize, stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
+    if pid != syskrnl::proc::id() {
+        CONTEXT_SWITCHES.fetch_add(1, Ordering::Relaxed);
+        syskrnl::proc::start_slice(pid);
+    }
     syskrnl::proc::set_id(pid);
     let sf = syskrnl::proc::stack_frame();
     //stack_frame.as_mut().write(sf);
@@ -250,53 +437,85 @@ unsafe fn switch_context_to(pid: usize, stack_frame: &mut InterruptStackFrame, r
 
 pub static SCHEDULE: AtomicBool = AtomicBool::new(false);
 static LAST_SCHEDULE: AtomicUsize = AtomicUsize::new(0);
-pub static NO_SCHEDULE: AtomicBool = AtomicBool::new(false);
+/// 上一次取到的可运行进程数
+static LAST_RUNNABLE: AtomicUsize = AtomicUsize::new(0);
 
 wrap!(clock_handler => wrapped_clock_handler);
 
+/// 调度被关掉时不轮换，关得太久（超过1000个Tick）就强行恢复
+fn may_preempt() -> bool {
+    if !syskrnl::schedule::is_disabled() {
+        return true;
+    }
+    if ticks() - LAST_SCHEDULE.load(Ordering::SeqCst) > 1000 {
+        // 强行恢复调度
+        syskrnl::schedule::force_enable();
+        return true;
+    }
+    false
+}
+
 /// 时钟中断处理程序
 extern "sysv64" fn clock_handler(stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
     // 先把时钟发过去
+    IRQ_COUNTS[0].fetch_add(1, Ordering::Relaxed);
     let handlers = IRQ_HANDLERS.lock();
     handlers[0]();
 
-    if let Some(pid) = time::check_wakeup() {
-        SCHEDULER.lock().wakeup(pid);
-    }
-
-    if SCHEDULE.load(Ordering::SeqCst) && ticks() - LAST_SCHEDULE.load(Ordering::SeqCst) > 10 {
-        let mut schedule = || {
-            if NO_SCHEDULE.load(Ordering::SeqCst) {
-                if ticks() - LAST_SCHEDULE.load(Ordering::SeqCst) > 1000 {
-                    // 强行恢复调度
-                    NO_SCHEDULE.store(false, Ordering::SeqCst);
-                } else {
-                    return;
-                }
-            }
+    // 负载统计按这个Tick里正在运行的进程来算
+    let user_mode = stack_frame.code_segment & 3 == 3;
+    let kind = loadavg::classify(syskrnl::proc::id(), user_mode);
+
+    // 到期的定时器（包括进程的睡眠）和时间片。时间片用完才轮换，提前让出CPU的进程只记它实际用掉的tick
+    let schedule = SCHEDULE.load(Ordering::SeqCst);
+    let next = syskrnl::schedule::tick(
+        &time::Pit,
+        &time::timer::TIMERS,
+        &SCHEDULER,
+        || schedule && syskrnl::proc::charge_tick(),
+        may_preempt,
+    );
 
-            let next_pid = SCHEDULER.lock().timeup();
-            // debugln!("Schedule: next_pid = {}; now_pid = {}", next_pid, syskrnl::proc::id());
+    // 调度器正被别处拿着时沿用上一次的可运行进程数
+    let runnable = match SCHEDULER.try_lock() {
+        Some(scheduler) => {
+            let runnable = scheduler.runnable();
+            LAST_RUNNABLE.store(runnable, Ordering::Relaxed);
+            runnable
+        }
+        None => LAST_RUNNABLE.load(Ordering::Relaxed),
+    };
+    loadavg::tick(kind, runnable);
 
-            if next_pid != syskrnl::proc::id() {
-                syskrnl::proc::set_stack_frame(**stack_frame);
-                syskrnl::proc::set_registers(*regs);
+    if let Some(next_pid) = next {
+        // debugln!("Schedule: next_pid = {}; now_pid = {}", next_pid, syskrnl::proc::id());
+        if next_pid != syskrnl::proc::id() {
+            syskrnl::proc::set_stack_frame(**stack_frame);
+            syskrnl::proc::set_registers(*regs);
+            syskrnl::proc::count_switch(false);
 
-                unsafe {
-                    switch_context_to(next_pid, stack_frame, regs);
-                }
+            unsafe {
+                switch_context_to(next_pid, stack_frame, regs);
             }
+        } else {
+            syskrnl::proc::start_slice(next_pid);
+        }
 
-            let lock = syskrnl::event::EVENT_DATA.lock();
-            if lock.contains_key(&next_pid) {
-                regs.rax = *lock.get(&next_pid).unwrap();
-            }
-            drop(lock);
+        let lock = syskrnl::event::EVENT_DATA.lock();
+        if lock.contains_key(&next_pid) {
+            regs.rax = *lock.get(&next_pid).unwrap();
+        }
+        drop(lock);
 
-            LAST_SCHEDULE.store(ticks(), Ordering::SeqCst);
-        };
+        // 被要求结束的进程一轮到它就退出
+        if syskrnl::proc::terminate_pending() {
+            let next_pid = syskrnl::proc::exit();
+            unsafe {
+                switch_context_to(next_pid, stack_frame, regs);
+            }
+        }
 
-        schedule();
+        LAST_SCHEDULE.store(ticks(), Ordering::SeqCst);
     }
 
     unsafe { pics::PICS.lock().notify_end_of_interrupt(interrupt_index(0) as u8) };
@@ -325,7 +544,15 @@ extern "sysv64" fn proc_wait(stack_frame: &mut InterruptStackFrame, regs: &mut R
     syskrnl::proc::set_stack_frame(**stack_frame);
     syskrnl::proc::set_registers(*regs);
 
-    let next_pid = syskrnl::event::dispatcher(n, arg1, arg2, arg3, arg4);
+    // 已经被要求结束的进程不能再睡下去
+    let next_pid = if syskrnl::proc::terminate_pending() {
+        syskrnl::proc::exit()
+    } else {
+        syskrnl::event::dispatcher(n, arg1, arg2, arg3, arg4)
+    };
+    if next_pid != syskrnl::proc::id() {
+        syskrnl::proc::count_switch(true);
+    }
 
     // 恢复现场
     unsafe {
@@ -1,5 +1,8 @@
+use alloc::string::String;
 use cinea_os_sysapi::fs::FileIO;
 use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crossbeam::queue::ArrayQueue;
 use lazy_static::lazy_static;
 use spin::Mutex;
 
@@ -44,6 +47,15 @@ macro_rules! debugln {
 
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
+    // 进程还没输出的内容先输出，保持先后顺序；缓冲区正被占用时（比如在里面出了错）不等
+    if let Some(mut buf) = CONSOLE_OUTPUT.try_lock() {
+        flush_locked(&mut buf);
+    }
+    write_screen(args);
+}
+
+/// 输出到屏幕，不经过缓冲区
+fn write_screen(args: fmt::Arguments) {
     // 向Qemu也发送一份
     qemu::_qemu_print(args);
 
@@ -67,6 +79,103 @@ macro_rules! println {
 
 pub struct StdOutDevice;
 
+/// 控制台输出缓冲区的大小（字节），放不下时先输出
+const CONSOLE_OUTPUT_SIZE: usize = 4096;
+
+/// 进程的输出是否按行缓冲，关掉之后每次写都直接输出，方便调试
+static CONSOLE_BUFFERED: AtomicBool = AtomicBool::new(true);
+
+lazy_static! {
+    /// 进程写到控制台、还没有输出的内容
+    static ref CONSOLE_OUTPUT: Mutex<String> = Mutex::new(String::with_capacity(CONSOLE_OUTPUT_SIZE));
+}
+
+/// 进程写控制台：按行缓冲时先放进缓冲区，遇到换行或者缓冲区满了才一次输出
+pub fn console_write(s: &str) {
+    if !CONSOLE_BUFFERED.load(Ordering::SeqCst) {
+        _print(format_args!("{}", s));
+        return;
+    }
+    let mut buf = CONSOLE_OUTPUT.lock();
+    if buf.len() + s.len() > CONSOLE_OUTPUT_SIZE {
+        flush_locked(&mut buf);
+    }
+    if s.len() > CONSOLE_OUTPUT_SIZE {
+        write_screen(format_args!("{}", s));
+        return;
+    }
+    buf.push_str(s);
+    if s.contains('\n') {
+        flush_locked(&mut buf);
+    }
+}
+
+/// 输出缓冲区里还没输出的内容，比如没有换行的提示符
+pub fn console_flush() {
+    flush_locked(&mut CONSOLE_OUTPUT.lock());
+}
+
+/// 打开或关闭按行缓冲，关闭前先把缓冲区里的内容输出
+pub fn set_console_buffered(on: bool) {
+    let mut buf = CONSOLE_OUTPUT.lock();
+    flush_locked(&mut buf);
+    CONSOLE_BUFFERED.store(on, Ordering::SeqCst);
+}
+
+fn flush_locked(buf: &mut String) {
+    if !buf.is_empty() {
+        write_screen(format_args!("{}", buf.as_str()));
+        buf.clear();
+    }
+}
+
+/// 控制台输入缓冲区的大小（字节）
+const CONSOLE_INPUT_SIZE: usize = 256;
+
+lazy_static! {
+    /// 没有进程在等键盘事件时敲下的字符，UTF-8编码；键盘任务和系统调用都不持锁
+    static ref CONSOLE_INPUT: ArrayQueue<u8> = ArrayQueue::new(CONSOLE_INPUT_SIZE);
+}
+
+/// 键盘任务调用，放不下整个字符时丢弃
+pub fn push_console_input(ch: char) {
+    let mut bytes = [0u8; 4];
+    let bytes = ch.encode_utf8(&mut bytes).as_bytes();
+    if CONSOLE_INPUT.capacity() - CONSOLE_INPUT.len() < bytes.len() {
+        return;
+    }
+    for byte in bytes {
+        let _ = CONSOLE_INPUT.push(*byte);
+    }
+}
+
+/// 控制台：写入的内容输出到屏幕，读出的是缓冲的键盘输入
+pub struct ConsoleDevice;
+
+impl FileIO for ConsoleDevice {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        // 读输入之前先让用户看到提示
+        console_flush();
+        let mut len = 0;
+        while len < buf.len() {
+            match CONSOLE_INPUT.pop() {
+                Some(byte) => buf[len] = byte,
+                None => break,
+            }
+            len += 1;
+        }
+        Ok(len)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        StdOutDevice.write(buf)
+    }
+
+    fn readable(&self) -> bool {
+        !CONSOLE_INPUT.is_empty()
+    }
+}
+
 impl FileIO for StdOutDevice {
     fn read(&mut self, _buf: &mut [u8]) -> Result<usize, ()> {
         Ok(0)
@@ -79,9 +188,24 @@ impl FileIO for StdOutDevice {
                 Err(())
             }
             Ok(s) => {
-                _print(format_args!("{}", s));
+                console_write(s);
                 Ok(buf.len())
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{console_flush, console_write, CONSOLE_OUTPUT};
+
+    #[test_case]
+    fn test_console_line_buffering() {
+        console_flush();
+        // 没有换行时留在缓冲区里，换行时整行一起输出
+        console_write("[ok]  Console ");
+        assert_eq!(CONSOLE_OUTPUT.lock().as_str(), "[ok]  Console ");
+        console_write("line buffering\n");
+        assert!(CONSOLE_OUTPUT.lock().is_empty());
+    }
+}
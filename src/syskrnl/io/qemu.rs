@@ -18,7 +18,8 @@ pub fn qemu_print(content: &str) {
     }
 }
 
-struct QemuWriter();
+/// 不拿锁直接写串口，紧急情况下（比如SysRq）也能用
+pub struct QemuWriter();
 
 impl fmt::Write for QemuWriter {
     fn write_str(&mut self, s: &str) -> Result<(), core::fmt::Error> {
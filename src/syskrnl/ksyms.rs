@@ -0,0 +1,93 @@
+//! 内核自己的符号表，让崩溃信息里的地址显示成`函数名+偏移`
+//!
+//! 引导程序不会把内核的ELF交给内核，所以由构建脚本把只剩符号表的内核ELF放进initramfs的`/boot/kernel`，
+//! 启动时读出来解析。没有这个文件时照常启动，地址就只显示成数字
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use object::{Object, ObjectSymbol, SymbolKind};
+use spin::Once;
+
+use cinea_os_kcore::symbol::{demangle, lookup};
+
+use crate::{debugln, syskrnl};
+
+/// 只剩符号表的内核ELF在initramfs中的位置
+const KERNEL_IMAGE: &str = "/boot/kernel";
+/// 保留的内核符号表大小上限，超出的符号不再保留
+const MAX_KERNEL_SYMBOLS_SIZE: usize = 1 << 20;
+
+static SYMBOLS: Once<Vec<(u64, u64, String)>> = Once::new();
+
+/// 取出ELF中的函数符号，名字解码成可读的路径，按起始地址排好序
+pub fn function_symbols(obj: &object::File, max_size: usize) -> Vec<(u64, u64, String)> {
+    let mut size = 0;
+    let mut symbols: Vec<_> = obj
+        .symbols()
+        .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.size() > 0)
+        .filter_map(|symbol| {
+            let name = demangle(symbol.name().ok()?);
+            Some((symbol.address(), symbol.address() + symbol.size(), name))
+        })
+        .take_while(|(_, _, name)| {
+            size += name.len() + 2 * core::mem::size_of::<u64>();
+            size <= max_size
+        })
+        .collect();
+    symbols.sort_by_key(|(start, _, _)| *start);
+    symbols
+}
+
+/// 读取并解析内核符号表，要在initramfs装载之后调用
+pub fn init() {
+    let symbols = match syskrnl::fs::read_to_end(KERNEL_IMAGE) {
+        Ok(data) => match object::File::parse(data.as_slice()) {
+            Ok(obj) => function_symbols(&obj, MAX_KERNEL_SYMBOLS_SIZE),
+            Err(_) => {
+                debugln!("ksyms: {} is not a valid ELF", KERNEL_IMAGE);
+                Vec::new()
+            }
+        },
+        Err(_) => {
+            debugln!("ksyms: {} not found, kernel addresses will not be symbolized", KERNEL_IMAGE);
+            Vec::new()
+        }
+    };
+    debugln!("ksyms: loaded {} kernel symbols", symbols.len());
+    SYMBOLS.call_once(|| symbols);
+}
+
+/// 查找内核地址所在的函数，返回函数名和偏移；符号表还没加载时返回`None`
+pub fn symbolize(addr: u64) -> Option<(&'static str, u64)> {
+    lookup(SYMBOLS.get()?, addr)
+}
+
+/// 把内核地址格式化成`函数名+偏移 (地址)`，找不到符号时只有地址
+pub fn describe(addr: u64) -> String {
+    match symbolize(addr) {
+        Some((name, offset)) => format!("{}+{:#x} ({:#x})", name, offset, addr),
+        None => format!("{:#x}", addr),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::format;
+    use alloc::string::String;
+
+    use super::{describe, symbolize};
+
+    #[test_case]
+    fn test_describe_kernel_address() {
+        let addr = describe as fn(u64) -> String as u64;
+        let text = describe(addr);
+        assert!(text.contains(format!("{:#x}", addr).as_str()));
+        // 构建时没有放符号表也能通过，放了的话必须解析到自己
+        if symbolize(addr).is_some() {
+            assert!(text.starts_with("cinea_os::syskrnl::ksyms::describe+0x0 "));
+        }
+        println!("[ok]  Describe kernel address");
+    }
+}
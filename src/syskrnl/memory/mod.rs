@@ -4,21 +4,34 @@ use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
 use bootloader::BootInfo;
+use spin::{Mutex, MutexGuard, Once};
 use x86_64::instructions::interrupts;
 use x86_64::registers::control::Cr3;
-use x86_64::structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PhysFrame, Size4KiB, Translate};
+use x86_64::structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PageTableFlags, PhysFrame, Size4KiB, Translate};
 use x86_64::{structures::paging::PageTable, PhysAddr, VirtAddr};
 
+use crate::syskrnl::allocator::IrqSafeGuard;
 use crate::{println, syskrnl};
 
 pub mod graphic_support;
+pub mod oom;
 
 pub static mut PHYS_MEM_OFFSET: u64 = 0;
 pub static mut MEMORY_MAP: Option<&MemoryMap> = None;
-pub static mut MAPPER: Option<OffsetPageTable<'static>> = None;
+/// 内核页表的映射器，只建一次
+static MAPPER: Once<Mutex<OffsetPageTable<'static>>> = Once::new();
+/// 帧分配器，第一次使用时建立，之后一直复用
+static FRAME_ALLOCATOR: Once<Mutex<HeapedBootInfoFrameAllocator>> = Once::new();
 
 pub static MEMORY_SIZE: AtomicU64 = AtomicU64::new(0);
 static ALLOCATED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+/// 被归还的帧，分配时优先使用
+static RECYCLED_FRAMES: Mutex<Vec<PhysFrame>> = Mutex::new(Vec::new());
+
+/// 持有期间关中断，帧分配不会被调度打断
+fn recycled_frames() -> IrqSafeGuard<MutexGuard<'static, Vec<PhysFrame>>> {
+    IrqSafeGuard::new(|| RECYCLED_FRAMES.lock())
+}
 
 pub fn memory_size() -> u64 {
     MEMORY_SIZE.load(Ordering::Relaxed)
@@ -39,12 +52,13 @@ pub fn init(bootinfo: &'static BootInfo) {
 
         unsafe { PHYS_MEM_OFFSET = bootinfo.physical_memory_offset };
         unsafe { MEMORY_MAP.replace(&bootinfo.memory_map) };
-        unsafe { MAPPER.replace(OffsetPageTable::new(active_page_table(), VirtAddr::new(PHYS_MEM_OFFSET))) };
+        MAPPER.call_once(|| Mutex::new(unsafe { OffsetPageTable::new(active_page_table(), VirtAddr::new(PHYS_MEM_OFFSET)) }));
 
         let mut mapper = mapper();
+        // 堆还没有建好，先用不需要堆的分配器
         let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&bootinfo.memory_map) };
 
-        syskrnl::allocator::init_heap(mapper, &mut frame_allocator).expect("heap initialization failed");
+        syskrnl::allocator::init_heap(&mut *mapper, &mut frame_allocator).expect("heap initialization failed");
 
         syskrnl::graphic::enter_wide_mode(&mut mapper, &mut frame_allocator); // 因为需要分配显存，就放在这里了
 
@@ -53,8 +67,9 @@ pub fn init(bootinfo: &'static BootInfo) {
     });
 }
 
-pub fn mapper() -> &'static mut OffsetPageTable<'static> {
-    unsafe { MAPPER.as_mut().unwrap() }
+/// 内核页表的映射器，持有期间关中断
+pub fn mapper() -> IrqSafeGuard<MutexGuard<'static, OffsetPageTable<'static>>> {
+    IrqSafeGuard::new(|| MAPPER.get().expect("memory not initialized").lock())
 }
 
 /// 返回用于激活Level 4页表的引用。
@@ -135,6 +150,15 @@ pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
     VirtAddr::new(addr.as_u64() + phys_mem_offset)
 }
 
+/// 物理内存映射占用的虚拟地址范围，覆盖内存映射表里最高的物理地址
+pub fn phys_mem_range() -> (u64, u64) {
+    let offset = unsafe { PHYS_MEM_OFFSET };
+    let phys_end = unsafe { MEMORY_MAP }
+        .map(|map| map.iter().map(|region| region.range.end_addr()).max().unwrap_or(0))
+        .unwrap_or(0);
+    (offset, offset + phys_end)
+}
+
 pub fn virt_to_phys(addr: VirtAddr) -> Option<PhysAddr> {
     mapper().translate_addr(addr)
 }
@@ -158,6 +182,49 @@ pub unsafe fn create_page_table(frame: PhysFrame) -> &'static mut PageTable {
     &mut *page_table_ptr // unsafe
 }
 
+/// 页表里用户态能访问的映射，地址相邻且标志相同的页合并成一段：（起始地址，结束地址，标志）
+///
+/// 上层表项没有`USER_ACCESSIBLE`时下面的页用户态都访问不到，整个跳过，所以内核自己的映射不会出现。
+/// 最多返回`max`段，之后的地址空间不再查看。调用者要保证走页表期间页表不被回收
+pub fn user_mappings(p4: PhysFrame, max: usize) -> Vec<(u64, u64, PageTableFlags)> {
+    let mut ranges = Vec::new();
+    walk_user_mappings(p4.start_address(), 4, 0, &mut ranges, max);
+    ranges
+}
+
+/// 走一级页表，`base`是这张表覆盖的起始地址；段数到了上限时返回`false`
+fn walk_user_mappings(table: PhysAddr, level: u32, base: u64, ranges: &mut Vec<(u64, u64, PageTableFlags)>, max: usize) -> bool {
+    let table: &PageTable = unsafe { &*phys_to_virt(table).as_ptr() };
+    let span = 4096u64 << (9 * (level - 1));
+    let shown = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::USER_ACCESSIBLE
+        | PageTableFlags::NO_EXECUTE
+        | PageTableFlags::HUGE_PAGE;
+    for (i, entry) in table.iter().enumerate() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        let mut addr = base + i as u64 * span;
+        if level == 4 && i >= 256 {
+            // 高半部分的规范地址
+            addr |= 0xFFFF_0000_0000_0000;
+        }
+        if level == 1 || flags.contains(PageTableFlags::HUGE_PAGE) {
+            let flags = flags & shown;
+            match ranges.last_mut() {
+                Some(last) if last.1 == addr && last.2 == flags => last.1 = addr + span,
+                _ if ranges.len() >= max => return false,
+                _ => ranges.push((addr, addr + span, flags)),
+            }
+        } else if flags.contains(PageTableFlags::USER_ACCESSIBLE) && !walk_user_mappings(entry.addr(), level - 1, addr, ranges, max) {
+            return false;
+        }
+    }
+    true
+}
+
 /// 帧分配器，返回BootLoader的内存映射中的可用帧
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
@@ -187,6 +254,9 @@ impl BootInfoFrameAllocator {
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        if let Some(frame) = recycled_frames().pop() {
+            return Some(frame);
+        }
         let next = ALLOCATED_FRAMES.fetch_add(1, Ordering::SeqCst);
         //debug!("Allocate frame {} / {}", next, self.usable_frames().count());
 
@@ -194,8 +264,46 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     }
 }
 
-pub fn frame_allocator() -> BootInfoFrameAllocator {
-    unsafe { BootInfoFrameAllocator::init(MEMORY_MAP.unwrap()) }
+/// 归还一个不再使用的帧
+pub fn deallocate_frame(frame: PhysFrame) {
+    recycled_frames().push(frame);
+}
+
+/// 可用的物理帧总数
+fn usable_frame_count() -> usize {
+    unsafe { MEMORY_MAP.unwrap() }
+        .iter()
+        .filter(|r| r.region_type == MemoryRegionType::Usable)
+        .map(|r| ((r.range.end_addr() - r.range.start_addr()) / 4096) as usize)
+        .sum()
+}
+
+/// 剩余的物理帧数：从未分配过的加上被归还的
+pub fn free_frames() -> usize {
+    let allocated = ALLOCATED_FRAMES.load(Ordering::Relaxed);
+    usable_frame_count().saturating_sub(allocated) + recycled_frames().len()
+}
+
+/// 不等锁的`free_frames`，回收列表正被占用时返回`None`，供SysRq这类不能等锁的地方使用
+pub fn try_free_frames() -> Option<usize> {
+    let allocated = ALLOCATED_FRAMES.load(Ordering::Relaxed);
+    let recycled = RECYCLED_FRAMES.try_lock()?.len();
+    Some(usable_frame_count().saturating_sub(allocated) + recycled)
+}
+
+/// 可用物理帧总数
+pub fn total_frames() -> usize {
+    usable_frame_count()
+}
+
+/// 可用物理内存总量（字节）
+pub fn total_memory() -> usize {
+    usable_frame_count() * 4096
+}
+
+/// 剩余物理内存（字节）
+pub fn free_memory() -> usize {
+    free_frames() * 4096
 }
 
 /// 帧分配器，返回BootLoader的内存映射中的可用帧
@@ -235,6 +343,9 @@ impl HeapedBootInfoFrameAllocator {
 
 unsafe impl FrameAllocator<Size4KiB> for HeapedBootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        if let Some(frame) = recycled_frames().pop() {
+            return Some(frame);
+        }
         let next = ALLOCATED_FRAMES.fetch_add(1, Ordering::SeqCst);
         //debug!("Allocate frame {} / {}", next, self.usable_frames().count());
 
@@ -242,6 +353,33 @@ unsafe impl FrameAllocator<Size4KiB> for HeapedBootInfoFrameAllocator {
     }
 }
 
-pub fn heaped_frame_allocator() -> HeapedBootInfoFrameAllocator {
-    unsafe { HeapedBootInfoFrameAllocator::init(MEMORY_MAP.unwrap()) }
+/// 帧分配器，持有期间关中断；只能在堆建好之后使用
+///
+/// 分配器只建一次，可用帧的列表不用每次都从内存映射表重新算
+pub fn heaped_frame_allocator() -> IrqSafeGuard<MutexGuard<'static, HeapedBootInfoFrameAllocator>> {
+    IrqSafeGuard::new(|| {
+        FRAME_ALLOCATOR
+            .call_once(|| Mutex::new(unsafe { HeapedBootInfoFrameAllocator::init(MEMORY_MAP.unwrap()) }))
+            .lock()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use x86_64::structures::paging::FrameAllocator;
+
+    use super::{deallocate_frame, free_frames, heaped_frame_allocator};
+
+    #[test_case]
+    fn test_frame_allocator_is_shared() {
+        let free = free_frames();
+        // 每次取到的都是同一个分配器，不会把同一个帧发两次
+        let first = heaped_frame_allocator().allocate_frame().unwrap();
+        let second = heaped_frame_allocator().allocate_frame().unwrap();
+        assert_ne!(first, second);
+        deallocate_frame(first);
+        deallocate_frame(second);
+        assert_eq!(free_frames(), free);
+        println!("[ok]  Memory frame allocator is shared");
+    }
 }
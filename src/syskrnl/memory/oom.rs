@@ -0,0 +1,37 @@
+use x86_64::structures::paging::OffsetPageTable;
+
+use crate::syskrnl::allocator::alloc_pages_with_zeroing;
+use crate::syskrnl::proc;
+use crate::{debugln, println};
+
+/// 物理内存耗尽时，杀死占用内存最多的用户进程来腾出空间
+///
+/// 返回是否有进程被杀死
+pub fn reclaim() -> bool {
+    match proc::oom_victim() {
+        Some((pid, resident)) => {
+            let name = proc::name_of(pid);
+            println!("[OOM] out of memory, killing process {} ({}, {} KB resident)", pid, name, resident >> 10);
+            debugln!("[OOM] out of memory, killing process {} ({}, {} KB resident)", pid, name, resident >> 10);
+            proc::kill(pid);
+            true
+        }
+        None => {
+            println!("[OOM] out of memory, but no process can be killed");
+            false
+        }
+    }
+}
+
+/// 为用户进程分配页面，内存不足时杀死一个进程后重试一次
+///
+/// 只有用户态引起的分配（创建进程、堆的生长）才应当使用它，内核自身的分配不会触发OOM处理
+pub fn alloc_user_pages(mapper: &mut OffsetPageTable, addr: u64, size: usize, zeroing: bool) -> Result<(), ()> {
+    alloc_pages_with_zeroing(mapper, addr, size, zeroing).or_else(|_| {
+        if reclaim() {
+            alloc_pages_with_zeroing(mapper, addr, size, zeroing)
+        } else {
+            Err(())
+        }
+    })
+}
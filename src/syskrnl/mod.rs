@@ -6,15 +6,25 @@ pub mod syscall;
 
 pub mod allocator;
 pub mod clock;
+pub mod config;
 pub mod event;
 pub mod fs;
+pub mod futex;
 pub mod gdt;
 pub mod graphic;
 pub mod gui;
 pub mod interrupts;
+pub mod ksyms;
 pub mod memory;
+pub mod msgq;
+pub mod power;
 pub mod proc;
+pub mod random;
 pub mod schedule;
+pub mod sysrq;
 pub mod task;
 pub mod time;
+pub mod uaccess;
+pub mod vdso;
 pub mod vga_buffer;
+pub mod workqueue;
@@ -0,0 +1,131 @@
+//! 消息队列：按条收发的进程间通信
+//!
+//! 每次发送的内容作为一条完整的消息入队，接收时整条取出，消息的边界不会丢。
+//! 消息复制在内核自己的缓冲区里，收发双方不需要共享内存。
+//! 队列属于创建它的进程，进程退出时一起销毁；知道队列号的进程都可以收发
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::Mutex;
+
+use cinea_os_sysapi::msgq::{MsgFlags, MsgqError, MAX_MSGQ_DEPTH, MAX_MSG_SIZE};
+
+use crate::syskrnl;
+use crate::syskrnl::proc;
+
+/// 系统中同时存在的队列数上限
+const MAX_QUEUES: usize = 32;
+
+struct Queue {
+    owner: usize,
+    capacity: usize,
+    messages: VecDeque<Vec<u8>>,
+}
+
+static QUEUES: Mutex<BTreeMap<usize, Queue>> = Mutex::new(BTreeMap::new());
+/// 队列号不重复使用，销毁的队列号不会落到新队列上
+static NEXT_QID: AtomicUsize = AtomicUsize::new(1);
+
+/// 为进程创建一个最多容纳`capacity`条消息的队列，返回队列号
+fn create_for(owner: usize, capacity: usize) -> Result<usize, MsgqError> {
+    if capacity == 0 || capacity > MAX_MSGQ_DEPTH {
+        return Err(MsgqError::BadCapacity);
+    }
+    let mut queues = QUEUES.lock();
+    if queues.len() >= MAX_QUEUES {
+        return Err(MsgqError::TooManyQueues);
+    }
+    let qid = NEXT_QID.fetch_add(1, Ordering::SeqCst);
+    queues.insert(qid, Queue { owner, capacity, messages: VecDeque::new() });
+    Ok(qid)
+}
+
+/// 为当前进程创建队列
+pub fn create(capacity: usize) -> Result<usize, MsgqError> {
+    create_for(proc::id(), capacity)
+}
+
+/// 不等待地入队，队列满了返回`Ok(false)`
+fn try_send(qid: usize, msg: &[u8]) -> Result<bool, MsgqError> {
+    let mut queues = QUEUES.lock();
+    let queue = queues.get_mut(&qid).ok_or(MsgqError::NoSuchQueue)?;
+    if queue.messages.len() >= queue.capacity {
+        return Ok(false);
+    }
+    queue.messages.push_back(msg.to_vec());
+    Ok(true)
+}
+
+/// 不等待地取出下一条消息，队列空时返回`Ok(None)`；消息比`max_len`长时留在队列里
+fn try_recv(qid: usize, max_len: usize) -> Result<Option<Vec<u8>>, MsgqError> {
+    let mut queues = QUEUES.lock();
+    let queue = queues.get_mut(&qid).ok_or(MsgqError::NoSuchQueue)?;
+    match queue.messages.front() {
+        None => Ok(None),
+        Some(msg) if msg.len() > max_len => Err(MsgqError::TooLarge),
+        Some(_) => Ok(queue.messages.pop_front()),
+    }
+}
+
+/// 入队一条消息，队列满时让出CPU等待，除非指定了`NONBLOCK`
+pub fn send(qid: usize, msg: &[u8], flags: MsgFlags) -> Result<(), MsgqError> {
+    if msg.len() > MAX_MSG_SIZE {
+        return Err(MsgqError::TooLarge);
+    }
+    loop {
+        if try_send(qid, msg)? {
+            return Ok(());
+        }
+        if flags.contains(MsgFlags::NONBLOCK) {
+            return Err(MsgqError::WouldBlock);
+        }
+        if proc::terminate_pending() {
+            return Err(MsgqError::Interrupted);
+        }
+        syskrnl::time::halt();
+    }
+}
+
+/// 取出下一条消息，队列空时让出CPU等待
+pub fn recv(qid: usize, max_len: usize) -> Result<Vec<u8>, MsgqError> {
+    loop {
+        if let Some(msg) = try_recv(qid, max_len)? {
+            return Ok(msg);
+        }
+        if proc::terminate_pending() {
+            return Err(MsgqError::Interrupted);
+        }
+        syskrnl::time::halt();
+    }
+}
+
+/// 销毁进程创建的所有队列，还没取走的消息一起丢弃
+pub fn remove(pid: usize) {
+    QUEUES.lock().retain(|_, queue| queue.owner != pid);
+}
+
+#[cfg(test)]
+mod test {
+    use cinea_os_sysapi::msgq::MsgqError;
+
+    use super::{create_for, remove, try_recv, try_send};
+
+    #[test_case]
+    fn test_msgq_boundaries() {
+        // 不经过调度器，用不存在的进程号当主人
+        let qid = create_for(99, 2).unwrap();
+        assert_eq!(create_for(99, 0), Err(MsgqError::BadCapacity));
+        assert_eq!(try_send(qid, b"ping"), Ok(true));
+        assert_eq!(try_send(qid, b""), Ok(true));
+        assert_eq!(try_send(qid, b"full"), Ok(false));
+        assert_eq!(try_recv(qid, 3), Err(MsgqError::TooLarge));
+        assert_eq!(try_recv(qid, 16).unwrap().as_deref(), Some(&b"ping"[..]));
+        assert_eq!(try_recv(qid, 0).unwrap().as_deref(), Some(&b""[..]));
+        assert_eq!(try_recv(qid, 16), Ok(None));
+        remove(99);
+        assert_eq!(try_send(qid, b"gone"), Err(MsgqError::NoSuchQueue));
+        println!("[ok]  Message queue boundaries");
+    }
+}
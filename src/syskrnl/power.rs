@@ -0,0 +1,110 @@
+use x86::io::{inb, outb, outw};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::tables::lidt;
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::syskrnl::graphic::{GD, HEIGHT, WIDTH};
+use crate::syskrnl::interrupts::SCHEDULE;
+use crate::syskrnl::io::VIDEO_MODE;
+use crate::syskrnl::{fs, proc, time};
+use crate::{debugln, hlt_loop};
+
+/// 进程收到退出要求后，留给退出处理函数的时间（秒）
+const GRACE_SECONDS: f64 = 1.0;
+/// 强制结束之后再等的时间（秒）
+const KILL_SECONDS: f64 = 0.5;
+
+/// 关机或重启已经开始，不再创建进程
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+/// 收尾已经做完
+static TEARDOWN_DONE: AtomicBool = AtomicBool::new(false);
+
+/// 是否正在关机或重启
+pub fn shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+/// 关机、重启前的收尾：不再创建进程，要求用户进程退出，强制结束剩下的，停止调度，同步文件系统，刷新控制台
+///
+/// 只做一次，后来的调用者等第一次做完。每一步的等待都有时限，拿不到的锁就跳过，有进程占着内核锁也不会卡住
+fn teardown() {
+    if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+        while !TEARDOWN_DONE.load(Ordering::SeqCst) {
+            time::halt();
+        }
+        return;
+    }
+    debugln!("power: no new processes");
+    let caller = proc::id();
+    debugln!("power: asked {} processes to exit", proc::shutdown_terminate(caller));
+    wait_for_exit(caller, GRACE_SECONDS);
+    debugln!("power: killing {} remaining processes", proc::shutdown_kill(caller));
+    wait_for_exit(caller, KILL_SECONDS);
+    SCHEDULE.store(false, Ordering::SeqCst);
+    debugln!("power: scheduler stopped, {} processes left", proc::user_pids(caller).len());
+    debugln!("power: filesystem {}", if fs::sync() { "synced" } else { "busy, skipped" });
+    flush_console();
+    debugln!("power: console flushed");
+    TEARDOWN_DONE.store(true, Ordering::SeqCst);
+}
+
+/// 等调用者以外的用户进程全部退出，最多等`seconds`秒
+fn wait_for_exit(caller: usize, seconds: f64) {
+    let deadline = time::uptime() + seconds;
+    while !proc::user_pids(caller).is_empty() && time::uptime() < deadline {
+        time::halt();
+    }
+}
+
+/// 把还没渲染的控制台输出刷到屏幕上，串口输出是同步的不需要刷
+fn flush_console() {
+    if VIDEO_MODE.lock().is_text() {
+        return;
+    }
+    // 渲染到一半被打断的话就算了，不要在关机路径上死锁
+    if let Some(mut gd) = GD.try_lock() {
+        gd.render(0, 0, HEIGHT, WIDTH);
+    }
+}
+
+/// 重启：先收尾，再用8042键盘控制器的复位线，不行就三重错误
+pub fn reboot() -> ! {
+    teardown();
+    debugln!("power: rebooting");
+    reset()
+}
+
+/// 直接复位，不记日志也不刷控制台，任何锁被占着的时候都能用
+pub fn reset() -> ! {
+    interrupts::disable();
+    unsafe {
+        // 等待8042的输入缓冲区清空
+        while inb(0x64) & 0x02 != 0 {}
+        outb(0x64, 0xFE);
+
+        // 空的IDT让下一个异常变成三重错误
+        lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::new(0),
+        });
+        core::arch::asm!("int3");
+    }
+    hlt_loop()
+}
+
+/// 关机：先收尾，再依次尝试QEMU（新旧两种ACPI端口）、Bochs和isa-debug-exit
+pub fn shutdown() -> ! {
+    teardown();
+    debugln!("power: shutting down");
+    interrupts::disable();
+    unsafe {
+        outw(0x604, 0x2000);
+        outw(0xB004, 0x2000);
+        outw(0x4004, 0x3400);
+        outb(0xF4, 0x00);
+    }
+    hlt_loop()
+}
@@ -1,27 +1,43 @@
 use alloc::boxed::Box;
 use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::arch::asm;
-use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 
 use lazy_static::lazy_static;
-use object::{Object, ObjectSegment};
-use spin::{Mutex, RwLock};
+use object::elf::PF_X;
+use object::{Object, ObjectSegment, SegmentFlags};
+use spin::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use x86_64::registers::control::Cr3;
+use x86_64::registers::model_specific::{Efer, EferFlags};
 use x86_64::structures::idt::InterruptStackFrameValue;
-use x86_64::structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame};
+use x86_64::structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PageTableFlags, PhysFrame};
 use x86_64::VirtAddr;
 
+use cinea_os_kcore::heap::align_up;
+use cinea_os_kcore::symbol::lookup;
+use cinea_os_sysapi::fs::OpenFlags;
+use cinea_os_sysapi::fs::filename;
+use cinea_os_sysapi::syscall::{
+    ImageInfo, Limits, MapFlags, MapRange, ProcInfo, ProtFlags, Rusage, SpawnFlags, StackSize, Times, MAX_PAGEMAP_RANGES,
+};
 use cinea_os_sysapi::ExitCode;
 
 use crate::syskrnl::allocator::linked_list::LinkedListAllocator;
-use crate::syskrnl::allocator::{alloc_pages, fix_page_fault_in_userspace, Locked};
-use crate::syskrnl::fs::OpenFileHandle;
+use crate::syskrnl::allocator::{
+    dealloc_pages, dealloc_pages_from_mapper, fix_page_fault_in_userspace, protect_pages, HeapAllocator, IrqSafeGuard, Locked,
+};
+use crate::syskrnl::fs::{FileHandleRef, OpenFileHandle};
+use crate::syskrnl::ksyms;
+use crate::syskrnl::memory::oom::alloc_user_pages;
 use crate::syskrnl::schedule::roundroll::RoundRollScheduler;
 use crate::syskrnl::schedule::ProcessScheduler;
-use crate::{debugln, syskrnl};
+use crate::syskrnl::uaccess::BadAddress;
+use crate::syskrnl::workqueue::{self, WorkItem};
+use crate::{debugln, println, syskrnl};
 
 // const MAX_FILE_HANDLES: usize = 64;
 /// 最大进程数，先写2个，后面再改
@@ -30,7 +46,25 @@ const MAX_PROC_SIZE: usize = 10 << 20;
 #[allow(dead_code)]
 const MAX_FILE_HANDLES: usize = 64;
 
+/// 装载时映射的栈大小，不算栈顶上面的一页；再往下按需生长
+const INITIAL_STACK_SIZE: u64 = 4096;
+/// 离栈指针这么远以内的缺页才当作栈在生长，再远的当作野指针
+const STACK_GROW_REACH: u64 = 64 << 10;
+
+/// 保留的ELF符号表的大小上限，超出的符号不再保留
+const MAX_SYMBOLS_SIZE: usize = 64 << 10;
+/// 崩溃报告中调用栈的最大深度
+const MAX_BACKTRACE_DEPTH: usize = 16;
+/// 进程名的最大长度（字节）
+pub const MAX_NAME_LEN: usize = 32;
+
+/// 进程优先级（nice值）的范围，数值越小优先级越高
+pub const MIN_NICE: i8 = -10;
+pub const MAX_NICE: i8 = 10;
+
 pub static PID: AtomicUsize = AtomicUsize::new(0);
+/// 控制台的前台进程组，只有它能读取键盘输入
+static FOREGROUND_PGID: AtomicUsize = AtomicUsize::new(0);
 lazy_static! {
     static ref PID_POOL: Mutex<BTreeSet<usize>> = {
         let pool: BTreeSet<_> = (1..MAX_PROCS).collect();
@@ -38,23 +72,46 @@ lazy_static! {
     };
 }
 
-pub static PROC_HEAP_ADDR: AtomicUsize = AtomicUsize::new(0x0002_0000_0000);
+/// 用户堆从这里开始往上分配，进程的代码段都在它下面
+const PROC_HEAP_BASE: usize = 0x0002_0000_0000;
+pub static PROC_HEAP_ADDR: AtomicUsize = AtomicUsize::new(PROC_HEAP_BASE);
+/// 当前进程要在系统调用返回时退出
+static EXIT_ON_RETURN: AtomicBool = AtomicBool::new(false);
 const DEFAULT_HEAP_SIZE: usize = 0x1_000_000; // 默认堆内存大小:1MB
+/// 启动时存放参数的缓冲区大小，参数字符串和参数数组都放在里面
+const ARGS_SIZE: usize = 1024;
 
 lazy_static! {
     pub static ref SCHEDULER: Mutex<Box<dyn ProcessScheduler + 'static + Send>> = { Mutex::new(Box::new(RoundRollScheduler::new())) };
-    pub static ref PROCESS_TABLE: RwLock<[Box<Process>; MAX_PROCS]> = {
+    static ref PROCESS_TABLE: RwLock<[Box<Process>; MAX_PROCS]> = {
         let table: [Box<Process>; MAX_PROCS] = [(); MAX_PROCS].map(|_| Box::new(Process::new(0)));
         RwLock::new(table)
     };
 }
 
+type ProcessTable = [Box<Process>; MAX_PROCS];
+
+/// 读进程表，持有期间关中断
+fn read_table() -> IrqSafeGuard<RwLockReadGuard<'static, ProcessTable>> {
+    IrqSafeGuard::new(|| PROCESS_TABLE.read())
+}
+
+/// 写进程表，持有期间关中断
+fn write_table() -> IrqSafeGuard<RwLockWriteGuard<'static, ProcessTable>> {
+    IrqSafeGuard::new(|| PROCESS_TABLE.write())
+}
+
 #[derive(Clone, Debug)]
 pub struct ProcessData {
     env: BTreeMap<String, String>,
+    /// 工作目录，在根目录里面看到的样子
     dir: String,
+    /// 根目录：进程看到的`/`，路径不能越过它，子进程继承
+    root: String,
     user: Option<String>,
-    file_handles: Arc<Mutex<BTreeMap<usize, OpenFileHandle>>>,
+    file_handles: Arc<Mutex<BTreeMap<usize, FileHandleRef>>>,
+    /// 带`FD_CLOEXEC`标志的句柄号，exec时关闭，也不传给子进程
+    cloexec: BTreeSet<usize>,
 }
 
 #[repr(align(8), C)]
@@ -79,19 +136,116 @@ pub struct Registers {
 
 const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
 const BIN_MAGIC: [u8; 4] = [0x7F, b'B', b'I', b'N'];
+/// BIN格式的文件头：魔数之后是入口偏移和装载偏移，都是相对于代码段开头的小端u64
+const BIN_HEADER_SIZE: usize = 4 + 8 + 8;
+
+/// BIN程序的内容不能超过这里，再往上是保护页和最初的栈
+const BIN_LOAD_LIMIT: u64 = (MAX_PROC_SIZE - 3 * 4096) as u64;
+
+/// BIN格式的文件头
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BinHeader {
+    entry: u64,
+    load: u64,
+}
+
+/// 解析BIN文件，返回文件头和紧跟其后的程序内容
+///
+/// 程序必须整个落在栈下面，入口必须在程序内容里
+fn parse_bin(bin: &[u8], limit: u64) -> Result<(BinHeader, &[u8]), ()> {
+    if bin.len() < BIN_HEADER_SIZE || bin[0..4] != BIN_MAGIC {
+        return Err(());
+    }
+    let entry = u64::from_le_bytes(bin[4..12].try_into().unwrap());
+    let load = u64::from_le_bytes(bin[12..20].try_into().unwrap());
+    let payload = &bin[BIN_HEADER_SIZE..];
+    let end = load.checked_add(payload.len() as u64).ok_or(())?;
+    if end > limit || entry < load || entry >= end {
+        return Err(());
+    }
+    Ok((BinHeader { entry, load }, payload))
+}
+
+/// 装载器写完代码之后、跳进去执行之前调用：执行一条串行化指令，丢掉可能已经取到的旧指令
+///
+/// 同一个核上x86会自己发现对代码的改写，但装载器以后还要做重定位之类的改写，不能依赖这一点
+pub fn flush_instruction_pipeline() {
+    // cpuid是串行化指令，结果不要；rbx被LLVM保留，不能直接声明为输出
+    unsafe {
+        asm!(
+            "mov {tmp}, rbx",
+            "cpuid",
+            "mov rbx, {tmp}",
+            tmp = out(reg) _,
+            inout("eax") 0 => _,
+            out("ecx") _,
+            out("edx") _,
+            options(nostack, preserves_flags),
+        );
+    }
+}
+
+/// 生成BIN文件
+pub fn bin_image(entry: u64, load: u64, payload: &[u8]) -> Vec<u8> {
+    let mut bin = Vec::with_capacity(BIN_HEADER_SIZE + payload.len());
+    bin.extend_from_slice(&BIN_MAGIC);
+    bin.extend_from_slice(&entry.to_le_bytes());
+    bin.extend_from_slice(&load.to_le_bytes());
+    bin.extend_from_slice(payload);
+    bin
+}
 
 #[derive(Clone, Debug)]
 pub struct Process {
     pub id: usize,
+    /// 可执行代码的结束位置，相对于代码段
+    text_end: u64,
+    /// 用户注册的退出处理函数，第一次EXIT时先跳到这里
+    atexit: Option<u64>,
+    /// 用户注册的致命异常处理函数
+    fault_handler: Option<u64>,
+    /// 已经跳到了异常处理函数，再出错就直接杀死
+    in_fault_handler: bool,
+    /// 进程名，默认是程序文件名
+    name: String,
+    /// 启动参数
+    cmdline: Vec<String>,
     code_addr: u64,
     stack_addr: u64,
+    /// 已经映射的栈的最低地址，栈在缺页时往下长
+    stack_bottom: u64,
+    /// 程序映像映射到的结束位置，按页对齐
+    image_end: u64,
     entry_point: u64,
+    /// vDSO页是否映射上了，没有时用户态读时间要走系统调用
+    vdso: bool,
     page_table_frame: PhysFrame,
     stack_frame: InterruptStackFrameValue,
     registers: Registers,
     data: ProcessData,
-    #[allow(unused)]
     parent: usize,
+    /// 进程组
+    pgid: usize,
+    /// 优先级，见`MIN_NICE`和`MAX_NICE`
+    pub nice: i8,
+    /// 内存耗尽时不会被杀死
+    unkillable: bool,
+    /// 当前时间片还剩下的tick数
+    quantum_left: usize,
+    /// CPU使用统计
+    rusage: Rusage,
+    /// 已经退出的子进程（连同它们的子进程）用掉的tick数
+    children_ticks: usize,
+    /// 有人要求结束这个进程，它下次运行时退出
+    terminate_pending: bool,
+    /// 退出代码，交给在`RUN`里等它的父进程；不是自己调用EXIT退出的算失败
+    exit_code: ExitCode,
+    /// 资源限制，子进程继承
+    limits: Limits,
+    /// 进程占用的内存区域（起始地址，大小）
+    regions: Vec<(u64, usize)>,
+    /// ELF符号表（起始地址，结束地址，名字），地址相对于代码段，按起始地址排序
+    symbols: Arc<Vec<(u64, u64, String)>>,
     allocator: Arc<Locked<LinkedListAllocator>>,
 }
 
@@ -103,15 +257,7 @@ impl ProcessData {
         let file_handles = Arc::new(Mutex::new(BTreeMap::new()));
         let lock = file_handles.clone();
         let mut lock = lock.lock();
-        lock.insert(
-            0,
-            OpenFileHandle {
-                id: 0,
-                path: "/dev/stdout".to_string(),
-                write: true,
-                device: true,
-            },
-        );
+        lock.insert(0, Arc::new(Mutex::new(OpenFileHandle::system("/dev/stdout".to_string(), OpenFlags::WRITE))));
         // let mut file_handles = [(); MAX_FILE_HANDLES].map(|_| None);
         // file_handles[0] = Some(Box::new(Resource::Device(Device::Console(Console::new())))); // stdin
         // file_handles[1] = Some(Box::new(Resource::Device(Device::Console(Console::new())))); // stdout
@@ -120,8 +266,31 @@ impl ProcessData {
         Self {
             env,
             dir,
+            root: String::from("/"),
             user,
             file_handles,
+            cloexec: BTreeSet::new(),
+        }
+    }
+
+    /// 子进程和新程序继承的数据：句柄表复制一份，不带`FD_CLOEXEC`的句柄
+    ///
+    /// 复制的是句柄本身的引用，读写位置和打开方式仍然和原来的句柄号共享
+    fn inherit(&self) -> Self {
+        let handles = self
+            .file_handles
+            .lock()
+            .iter()
+            .filter(|(id, _)| !self.cloexec.contains(id))
+            .map(|(id, file)| (*id, file.clone()))
+            .collect();
+        Self {
+            env: self.env.clone(),
+            dir: self.dir.clone(),
+            root: self.root.clone(),
+            user: self.user.clone(),
+            file_handles: Arc::new(Mutex::new(handles)),
+            cloexec: BTreeSet::new(),
         }
     }
 }
@@ -139,15 +308,90 @@ impl Process {
             id,
             code_addr: 0,
             stack_addr: 0,
+            stack_bottom: 0,
+            image_end: 0,
             entry_point: 0,
+            vdso: false,
             stack_frame: isf,
             page_table_frame: Cr3::read().0,
             registers: Registers::default(),
             data: ProcessData::new("/", None),
             parent: 0,
+            pgid: 0,
+            nice: 0,
+            unkillable: false,
+            quantum_left: 0,
+            rusage: Rusage::default(),
+            children_ticks: 0,
+            terminate_pending: false,
+            exit_code: ExitCode::Failure,
+            limits: Limits::default(),
+            text_end: 0,
+            atexit: None,
+            fault_handler: None,
+            in_fault_handler: false,
+            name: String::from("kernel"),
+            cmdline: Vec::new(),
+            regions: Vec::new(),
+            symbols: Arc::new(Vec::new()),
             allocator: Arc::new(Locked::new(LinkedListAllocator::new())),
         }
     }
+
+    /// 把进程中的地址解析成`函数名+偏移`
+    fn symbolize(&self, addr: u64) -> String {
+        match lookup(&self.symbols, addr.wrapping_sub(self.code_addr)) {
+            Some((name, offset)) => format!("{}+{:#x}", name, offset),
+            None => format!("{:#x}", addr),
+        }
+    }
+
+    /// 地址是否落在进程的可执行代码里
+    fn in_text(&self, addr: u64) -> bool {
+        addr >= self.code_addr && addr < self.code_addr + self.text_end
+    }
+
+    /// 一段地址是否完整地落在进程的某个内存区域中
+    fn owns(&self, addr: u64, size: u64) -> bool {
+        self.regions
+            .iter()
+            .any(|(start, len)| addr >= *start && addr.saturating_add(size) <= start + *len as u64)
+    }
+
+    /// 在进程的内存区域里查找一段用户地址，见`translate_user_ptr`
+    fn translate(&self, addr: u64, len: usize, access: Access) -> Result<*mut u8, KernelError> {
+        // 空的切片可能是悬空指针，反正不会被访问
+        if len == 0 {
+            return Ok(addr as *mut u8);
+        }
+        let end = addr.checked_add(len as u64).ok_or(KernelError::BadAddress)?;
+        if addr == 0 || !self.owns(addr, len as u64) {
+            return Err(KernelError::BadAddress);
+        }
+        if access == Access::Write && addr < self.code_addr + self.text_end && end > self.code_addr {
+            return Err(KernelError::ReadOnly);
+        }
+        Ok(addr as *mut u8)
+    }
+
+    /// 栈最低能长到的地址：程序映像上面留一页保护页，资源限制更紧的话按限制
+    fn stack_limit(&self) -> u64 {
+        let floor = self.image_end + 4096;
+        match self.limits.max_stack {
+            Some(max) => floor.max(align_up(self.stack_top().saturating_sub(max as u64) as usize, 4096) as u64),
+            None => floor,
+        }
+    }
+
+    /// 栈顶，也就是代码区槽位的结束位置
+    fn stack_top(&self) -> u64 {
+        self.code_addr + MAX_PROC_SIZE as u64
+    }
+
+    /// 进程占用的内存大小
+    pub fn resident(&self) -> usize {
+        self.regions.iter().map(|(_, size)| size).sum()
+    }
 }
 
 /// 获取当前进程PID
@@ -162,71 +406,287 @@ pub fn set_id(id: usize) {
 
 /// 获取当前进程的环境变量
 pub fn env(key: &str) -> Option<String> {
-    let table = PROCESS_TABLE.read();
+    let table = read_table();
     let process = &table[id()];
     process.data.env.get(key).cloned()
 }
 
 /// 获取当前进程的环境变量
 pub fn envs() -> BTreeMap<String, String> {
-    let table = PROCESS_TABLE.read();
+    let table = read_table();
     let process = &table[id()];
     process.data.env.clone()
 }
 
 /// 获取当前进程的工作目录
 pub fn dir() -> String {
-    let table = PROCESS_TABLE.read();
+    let table = read_table();
     let process = &table[id()];
     process.data.dir.clone()
 }
 
+/// 获取当前进程的根目录
+pub fn root() -> String {
+    let table = read_table();
+    let process = &table[id()];
+    process.data.root.clone()
+}
+
 /// 获取当前进程的用户名
 pub fn user() -> Option<String> {
-    let table = PROCESS_TABLE.read();
+    let table = read_table();
     let process = &table[id()];
     process.data.user.clone()
 }
 
+/// 当前进程是否为root，见`is_root_proc`
+pub fn is_root() -> bool {
+    is_root_proc(&read_table()[id()])
+}
+
+/// 进程是否为root
+///
+/// 目前还没有登录系统，没有设置用户名的进程都视为root
+fn is_root_proc(proc: &Process) -> bool {
+    proc.data.user.as_deref().map_or(true, |user| user == "root")
+}
+
+/// 设置进程的优先级，`pid`为0时表示当前进程
+///
+/// 非root进程只能降低自己或子进程的优先级
+pub fn set_nice(pid: usize, nice: i8) -> Result<(), ExitCode> {
+    let me = id();
+    let pid = if pid == 0 { me } else { pid };
+    if pid >= MAX_PROCS || PID_POOL.lock().contains(&pid) {
+        return Err(ExitCode::Failure);
+    }
+    let root = is_root();
+
+    let mut table = write_table();
+    let proc = &mut table[pid];
+    if !root && ((pid != me && proc.parent != me) || nice < proc.nice) {
+        return Err(ExitCode::PermissionError);
+    }
+    proc.nice = nice;
+    drop(table);
+
+    SCHEDULER.lock().set_priority(pid, nice);
+    Ok(())
+}
+
+/// 时钟中断里调用：给当前进程记一个tick，返回时间片是否用完
+///
+/// 用完CPU限额的进程被要求结束，并且立刻要求轮换，让它尽快走到退出的路径上
+pub fn charge_tick() -> bool {
+    let mut table = write_table();
+    let proc = &mut table[id()];
+    proc.rusage.cpu_ticks += 1;
+    let used_up = syskrnl::schedule::charge(&mut proc.quantum_left);
+    if proc.limits.max_cpu_ticks.map_or(false, |max| proc.rusage.cpu_ticks >= max) && !proc.terminate_pending {
+        debugln!("pid {} ({}) used up its CPU limit: {:?}", proc.id, proc.name, ExitCode::LimitExceeded);
+        proc.terminate_pending = true;
+        proc.exit_code = ExitCode::LimitExceeded;
+        return true;
+    }
+    used_up
+}
+
+/// 给进程发放一个新的时间片，长度取调度器当前的设置
+pub fn start_slice(pid: usize) {
+    let mut table = write_table();
+    let proc = &mut table[pid];
+    proc.quantum_left = syskrnl::schedule::quantum_ticks();
+    proc.rusage.slices += 1;
+}
+
+/// 记录当前进程让出CPU，`voluntary`表示是它自己让出的而不是被抢占
+pub fn count_switch(voluntary: bool) {
+    let mut table = write_table();
+    let rusage = &mut table[id()].rusage;
+    if voluntary {
+        rusage.voluntary_switches += 1;
+    } else {
+        rusage.involuntary_switches += 1;
+    }
+}
+
+/// 当前进程的CPU使用统计
+pub fn rusage() -> Rusage {
+    read_table()[id()].rusage
+}
+
+/// 当前进程和它已退出的子进程用掉的tick数
+pub fn times() -> Times {
+    let table = read_table();
+    let proc = &table[id()];
+    Times {
+        ticks: proc.rusage.cpu_ticks,
+        children_ticks: proc.children_ticks,
+    }
+}
+
+/// 进程是否还在运行
+fn is_alive(pid: usize) -> bool {
+    pid == 0 || (pid < MAX_PROCS && !PID_POOL.lock().contains(&pid))
+}
+
+/// 进程组中是否还有进程
+fn group_alive(pgid: usize) -> bool {
+    let pool = PID_POOL.lock();
+    let table = read_table();
+    (0..MAX_PROCS).any(|pid| table[pid].pgid == pgid && !pool.contains(&pid))
+}
+
+/// 存活的进程数（含0号进程）
+pub fn count() -> usize {
+    MAX_PROCS - PID_POOL.lock().len()
+}
+
+/// 进程是否属于控制台的前台进程组
+pub fn in_foreground(pid: usize) -> bool {
+    let table = read_table();
+    pid < MAX_PROCS && table[pid].pgid == FOREGROUND_PGID.load(Ordering::SeqCst)
+}
+
+/// 获取控制台的前台进程组
+pub fn foreground_group() -> usize {
+    FOREGROUND_PGID.load(Ordering::SeqCst)
+}
+
+/// 设置控制台的前台进程组，只有前台进程组中的进程（或root）可以转交前台
+pub fn set_foreground_group(pgid: usize) -> Result<(), ExitCode> {
+    if !in_foreground(id()) && !is_root() {
+        return Err(ExitCode::PermissionError);
+    }
+    if !group_alive(pgid) {
+        return Err(ExitCode::Failure);
+    }
+    FOREGROUND_PGID.store(pgid, Ordering::SeqCst);
+    Ok(())
+}
+
+/// 设置进程的进程组，`pid`为0时表示当前进程，`pgid`为0时新建一个以`pid`为组长的进程组
+///
+/// 只能设置自己或子进程的进程组
+pub fn set_pgid(pid: usize, pgid: usize) -> Result<(), ExitCode> {
+    let me = id();
+    let pid = if pid == 0 { me } else { pid };
+    let pgid = if pgid == 0 { pid } else { pgid };
+    if pid == 0 || !is_alive(pid) || (pgid != pid && !group_alive(pgid)) {
+        return Err(ExitCode::Failure);
+    }
+
+    let mut table = write_table();
+    let proc = &mut table[pid];
+    if pid != me && proc.parent != me {
+        return Err(ExitCode::PermissionError);
+    }
+    proc.pgid = pgid;
+    Ok(())
+}
+
 /// 设置当前进程的环境变量
 pub fn set_env(key: &str, val: &str) {
-    let mut table = PROCESS_TABLE.write();
+    let mut table = write_table();
     let proc = &mut table[id()];
     proc.data.env.insert(key.into(), val.into());
 }
 
 /// 设置当前进程的工作目录
 pub fn set_dir(dir: &str) {
-    let mut table = PROCESS_TABLE.write();
+    let mut table = write_table();
     let proc = &mut table[id()];
     proc.data.dir = dir.into();
 }
 
 /// 设置当前进程的用户名
 pub fn set_user(user: &str) {
-    let mut table = PROCESS_TABLE.write();
+    let mut table = write_table();
     let proc = &mut table[id()];
     proc.data.user = Some(user.into())
 }
 
+/// 当前进程的句柄号是否带`FD_CLOEXEC`标志
+pub fn cloexec(fd: usize) -> bool {
+    read_table()[id()].data.cloexec.contains(&fd)
+}
+
+/// 设置或清除当前进程句柄号的`FD_CLOEXEC`标志
+pub fn set_cloexec(fd: usize, cloexec: bool) {
+    let mut table = write_table();
+    let proc = &mut table[id()];
+    if cloexec {
+        proc.data.cloexec.insert(fd);
+    } else {
+        proc.data.cloexec.remove(&fd);
+    }
+}
+
+/// 当前进程带`FD_CLOEXEC`标志的句柄号
+pub fn cloexec_fds() -> Vec<usize> {
+    read_table()[id()].data.cloexec.iter().copied().collect()
+}
+
 /// 获取当前进程的代码地址
 pub fn code_addr() -> u64 {
-    let table = PROCESS_TABLE.read();
+    let table = read_table();
     let process = &table[id()];
     process.code_addr
 }
 
 /// 设置当前进程的代码地址
 pub fn set_code_addr(addr: u64) {
-    let mut table = PROCESS_TABLE.write();
+    let mut table = write_table();
     let proc = &mut table[id()];
     proc.code_addr = addr;
 }
 
+/// 内核替用户进程访问一段内存的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// 用户地址翻译失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelError {
+    /// 范围不在进程的任何一个内存区域里
+    BadAddress,
+    /// 要写的范围落在代码里
+    ReadOnly,
+}
+
+impl From<KernelError> for BadAddress {
+    fn from(_: KernelError) -> Self {
+        BadAddress
+    }
+}
+
+/// 把进程`pid`给出的一段用户地址翻译成内核能访问的指针
+///
+/// 只在进程记录的内存区域里查找，整段必须落在同一个区域中，写访问不能碰代码。0号进程就是内核自己，地址原样返回；长度为0时也原样返回
+pub fn translate_user_ptr(pid: usize, addr: u64, len: usize, access: Access) -> Result<*mut u8, KernelError> {
+    // 栈上还没碰过的缓冲区：先把栈长到那里
+    if len != 0 && extend_stack(pid, addr).is_err() {
+        return Err(KernelError::BadAddress);
+    }
+    match pid {
+        0 => Ok(addr as *mut u8),
+        pid if pid < MAX_PROCS => read_table()[pid].translate(addr, len, access),
+        _ => Err(KernelError::BadAddress),
+    }
+}
+
 /// 偏移地址转换实际地址
+///
+/// 比代码基址小的地址一律当成偏移，堆地址这样本来就在代码下面的指针会被算错，已经由`translate_user_ptr`代替。
+/// 猜出来的地址和原样的地址不同时打一条警告，用来找出剩下的用法
+#[deprecated(note = "按内存区域查找的translate_user_ptr代替了偏移猜测")]
 pub fn ptr_from_addr(addr: u64) -> *mut u8 {
     let base = code_addr();
     if addr < base {
+        debugln!("warning: ptr_from_addr({:#x}) of pid {} guessed an offset from {:#x}", addr, id(), base);
         (base + addr) as *mut u8
     } else {
         addr as *mut u8
@@ -235,83 +695,708 @@ pub fn ptr_from_addr(addr: u64) -> *mut u8 {
 
 /// 获取当前进程的寄存器
 pub fn registers() -> Registers {
-    let table = PROCESS_TABLE.read();
+    let table = read_table();
     let process = &table[id()];
     process.registers
 }
 
 /// 设置当前进程的寄存器
 pub fn set_registers(regs: Registers) {
-    let mut table = PROCESS_TABLE.write();
+    let mut table = write_table();
     let proc = &mut table[id()];
     proc.registers = regs
 }
 
+/// 改写等待中的进程保存的rax，它恢复运行时作为系统调用的返回值
+pub fn set_return_value(pid: usize, value: usize) {
+    write_table()[pid].registers.rax = value;
+}
+
+/// 记下当前进程的退出代码
+pub fn set_exit_code(code: ExitCode) {
+    write_table()[id()].exit_code = code;
+}
+
 /// 获取当前进程的栈帧
 pub fn stack_frame() -> InterruptStackFrameValue {
-    let table = PROCESS_TABLE.read();
+    let table = read_table();
     let proc = &table[id()];
     proc.stack_frame
 }
 
 /// 设置当前进程的栈帧
 pub fn set_stack_frame(stack_frame: InterruptStackFrameValue) {
-    let mut table = PROCESS_TABLE.write();
+    let mut table = write_table();
     let proc = &mut table[id()];
     proc.stack_frame = stack_frame;
 }
 
 pub unsafe fn page_table_frame() -> PhysFrame {
-    let table = PROCESS_TABLE.read();
+    let table = read_table();
     let proc = &table[id()];
     proc.page_table_frame
 }
 
 pub unsafe fn set_page_table_frame(frame: PhysFrame) {
-    let mut table = PROCESS_TABLE.write();
+    let mut table = write_table();
     let proc = &mut table[id()];
     proc.page_table_frame = frame
 }
 
 /// 获取当前进程的堆分配器
 pub fn heap_allocator() -> Arc<Locked<LinkedListAllocator>> {
-    let table = PROCESS_TABLE.read();
+    let table = read_table();
     let proc = &table[id()];
     proc.allocator.clone()
 }
 
-/// 生长当前进程的堆
-pub fn allocator_grow(size: usize) {
-    let page_table = unsafe { page_table() };
+/// 为当前进程的堆映射`size`字节的新页面，返回起始地址，内存不足或者超过堆的限额时失败
+///
+/// 新页面由调用者交给堆分配器
+pub fn allocator_grow(size: usize) -> Result<usize, ()> {
+    if let Some(max) = limits().max_heap {
+        let heap: usize = heap_regions().iter().map(|(_, size)| size).sum();
+        if heap + size > max {
+            debugln!("pid {} heap limit {:#x} reached", id(), max);
+            return Err(());
+        }
+    }
+    let addr = PROC_HEAP_ADDR.fetch_add(size, Ordering::SeqCst);
+    if map_user_pages(unsafe { page_table_frame() }, addr as u64, size).is_err() {
+        debugln!("proc mem grow fail 1545");
+        return Err(());
+    }
+    write_table()[id()].regions.push((addr as u64, size));
+    Ok(addr)
+}
+
+/// 在页表`frame`上映射清零的页面，由调用者之后再登记到进程的内存区域里
+///
+/// 调用时不能持有进程表的锁，内存不足时可能需要杀死别的进程
+fn map_user_pages(frame: PhysFrame, addr: u64, size: usize) -> Result<(), ()> {
     let phys_mem_offset = unsafe { syskrnl::memory::PHYS_MEM_OFFSET };
-    let mut mapper = unsafe { OffsetPageTable::new(page_table, VirtAddr::new(phys_mem_offset)) };
+    let mut mapper = unsafe { OffsetPageTable::new(syskrnl::memory::create_page_table(frame), VirtAddr::new(phys_mem_offset)) };
+    alloc_user_pages(&mut mapper, addr, size, true)
+}
 
-    let table = PROCESS_TABLE.write();
-    let allocator = table[id()].allocator.clone();
-    let addr = PROC_HEAP_ADDR.fetch_add(size, Ordering::SeqCst);
-    alloc_pages(&mut mapper, addr as u64, size).expect("proc mem grow fail 1545");
-    unsafe {
-        allocator.lock().grow(addr, size);
-    };
+/// 当前进程的资源限制
+pub fn limits() -> Limits {
+    read_table()[id()].limits
+}
+
+/// 子进程的资源限制：继承父进程的，再按请求收紧；只有root可以放宽
+fn child_limits(inherited: Limits, requested: Option<Limits>, root: bool) -> Limits {
+    match requested {
+        Some(requested) if root => requested,
+        Some(requested) => inherited.tighten(&requested),
+        None => inherited,
+    }
+}
+
+/// 调整当前进程自己的资源限制，root可以随意设置，其他进程放宽任何一项都会失败
+///
+/// 已经超出新限制的资源不收回，只是不能再增长；CPU限额在下一个tick生效
+pub fn set_limits(requested: Limits) -> Result<(), ()> {
+    let root = is_root();
+    let mut table = write_table();
+    let proc = &mut table[id()];
+    proc.limits = adjusted_limits(proc.limits, requested, root).ok_or(())?;
+    Ok(())
+}
+
+/// 把`current`调整成`requested`的结果，不是root又要放宽的时候返回`None`
+fn adjusted_limits(current: Limits, requested: Limits, root: bool) -> Option<Limits> {
+    if root || current.tighten(&requested) == requested {
+        Some(requested)
+    } else {
+        None
+    }
+}
+
+/// 进程`pid`还活着的子进程数
+fn children_of(pid: usize) -> usize {
+    let pool = PID_POOL.lock();
+    let table = read_table();
+    (1..MAX_PROCS)
+        .filter(|child| *child != pid && !pool.contains(child) && table[*child].parent == pid)
+        .count()
 }
 
-pub fn file_handles() -> Arc<Mutex<BTreeMap<usize, OpenFileHandle>>> {
-    let table = PROCESS_TABLE.read();
+pub fn file_handles() -> Arc<Mutex<BTreeMap<usize, FileHandleRef>>> {
+    let table = read_table();
     let proc = &table[id()];
     proc.data.file_handles.clone()
 }
 
+/// 让当前进程在这次系统调用返回时退出，用于在系统调用里发现进程已经无法继续运行的情况
+pub fn exit_on_return() {
+    EXIT_ON_RETURN.store(true, Ordering::SeqCst);
+}
+
+/// 由系统调用处理程序调用，取出并清除退出标记
+pub fn take_exit_on_return() -> bool {
+    EXIT_ON_RETURN.swap(false, Ordering::SeqCst)
+}
+
+/// 范围是否完整地落在进程`pid`记录的某个内存区域里
+pub fn owns_range(pid: usize, addr: u64, size: usize) -> bool {
+    pid < MAX_PROCS && read_table()[pid].owns(addr, size as u64)
+}
+
+/// 当前进程映射了vDSO页
+pub fn has_vdso() -> bool {
+    read_table()[id()].vdso
+}
+
+/// 当前进程的堆区域（起始地址，大小）
+pub fn heap_regions() -> Vec<(u64, usize)> {
+    let table = read_table();
+    table[id()].regions.iter().filter(|(addr, _)| *addr >= PROC_HEAP_BASE as u64).copied().collect()
+}
+
+/// `[addr, addr+size)`整个落在当前进程的某一个堆区域里
+pub fn in_heap(addr: u64, size: usize) -> bool {
+    let end = match addr.checked_add(size as u64) {
+        Some(end) => end,
+        None => return false,
+    };
+    heap_regions().iter().any(|&(start, len)| start <= addr && end <= start + len as u64)
+}
+
+/// 把当前进程堆结尾`[addr, addr+size)`的页面还给系统，这段必须已经从堆分配器里拿走，见`release_tail`
+///
+/// 相邻堆区域的空闲空间会连成一片，所以这段可能跨过几个区域：区域整个落在里面的去掉，其余的截短
+pub fn heap_release(addr: u64, size: usize) -> Result<(), ()> {
+    let (pid, end) = (id(), addr + size as u64);
+    let pieces: Vec<(u64, u64)> = heap_regions()
+        .iter()
+        .map(|&(start, len)| (start.max(addr), (start + len as u64).min(end)))
+        .filter(|(start, end)| start < end)
+        .collect();
+    if pieces.iter().map(|(start, end)| end - start).sum::<u64>() != size as u64 {
+        return Err(());
+    }
+    for (start, end) in pieces {
+        dealloc_pages(pid, start, (end - start) as usize)?;
+    }
+    write_table()[pid].regions.retain_mut(|(start, len)| {
+        if *start < PROC_HEAP_BASE as u64 || *start + *len as u64 <= addr || *start >= end {
+            return true;
+        }
+        *len = addr.saturating_sub(*start) as usize;
+        *len > 0
+    });
+    Ok(())
+}
+
+/// 当前进程在栈底下面、离栈指针不远的地方缺页时，把栈往下长到那一页，之后重新执行出错的指令即可
+///
+/// 返回是否长了；缺页落在栈的限制以下（栈溢出）时返回`Err(限制)`
+pub fn grow_stack(addr: u64, rsp: u64) -> Result<bool, u64> {
+    if addr < rsp.saturating_sub(STACK_GROW_REACH) {
+        return Ok(false);
+    }
+    extend_stack(id(), addr)
+}
+
+/// 把进程`pid`的栈往下长到`addr`所在的页，`addr`不在栈底下面的保留区里时什么也不做，见`grow_stack`
+fn extend_stack(pid: usize, addr: u64) -> Result<bool, u64> {
+    if pid == 0 || pid >= MAX_PROCS {
+        return Ok(false);
+    }
+    let (bottom, limit, image_end, frame) = {
+        let table = read_table();
+        let proc = &table[pid];
+        (proc.stack_bottom, proc.stack_limit(), proc.image_end, proc.page_table_frame)
+    };
+    if addr >= bottom || addr < image_end {
+        return Ok(false);
+    }
+    if addr < limit {
+        return Err(limit);
+    }
+    let new_bottom = addr & !0xFFF;
+    if map_user_pages(frame, new_bottom, (bottom - new_bottom) as usize).is_err() {
+        debugln!("pid {} stack grow to {:#x} failed", pid, new_bottom);
+        return Ok(false);
+    }
+    let mut table = write_table();
+    let proc = &mut table[pid];
+    if let Some(region) = proc.regions.iter_mut().find(|(start, _)| *start == bottom) {
+        *region = (new_bottom, region.1 + (bottom - new_bottom) as usize);
+    }
+    proc.stack_bottom = new_bottom;
+    Ok(true)
+}
+
+/// 当前进程的栈已经映射的大小和最多能长到的大小
+pub fn stack_size() -> StackSize {
+    let table = read_table();
+    let proc = &table[id()];
+    StackSize {
+        current: (proc.stack_top() - proc.stack_bottom) as usize,
+        max: (proc.stack_top() - proc.stack_limit().min(proc.stack_bottom)) as usize,
+    }
+}
+
+/// 注册当前进程的退出处理函数，地址必须落在进程的可执行代码里；传0取消注册
+pub fn set_atexit(addr: u64) -> bool {
+    let mut table = write_table();
+    let proc = &mut table[id()];
+    if addr == 0 {
+        proc.atexit = None;
+        return true;
+    }
+    if !proc.in_text(addr) {
+        return false;
+    }
+    proc.atexit = Some(addr);
+    true
+}
+
+/// 注册当前进程的致命异常处理函数，地址必须落在进程的可执行代码里；传0取消注册
+pub fn set_fault_handler(addr: u64) -> bool {
+    let mut table = write_table();
+    let proc = &mut table[id()];
+    if addr == 0 {
+        proc.fault_handler = None;
+        return true;
+    }
+    if !proc.in_text(addr) {
+        return false;
+    }
+    proc.fault_handler = Some(addr);
+    true
+}
+
+/// 当前进程出了致命异常：注册过处理函数、而且不是处理函数自己出错的话，在用户栈上准备好调用它
+///
+/// 处理函数的参数是出错的地址（rdi）和出错时的RIP（rsi），它应当自己EXIT。
+/// 返回处理函数的入口和新的栈顶，调用者据此修改异常栈帧；返回`None`时应当杀死进程
+pub fn enter_fault_handler(fault_addr: u64, rip: u64, rsp: u64) -> Option<(u64, u64)> {
+    let handler = {
+        let mut table = write_table();
+        let proc = &mut table[id()];
+        let handler = proc.fault_handler?;
+        if proc.in_fault_handler {
+            return None;
+        }
+        proc.in_fault_handler = true;
+        handler
+    };
+    // 和退出处理函数一样：跳过红区、对齐，放一个0作返回地址；栈本身坏了的话就放弃
+    let new_rsp = rsp.checked_sub(128).and_then(|rsp| (rsp & !0xF).checked_sub(8))?;
+    if syskrnl::uaccess::copy_to_user(new_rsp, &0u64.to_ne_bytes()).is_err() {
+        return None;
+    }
+    debugln!("pid {} fault at {:#x} (rip {:#x}), entering its handler", id(), fault_addr, rip);
+    Some((handler, new_rsp))
+}
+
+/// 退出的第一阶段：注册过退出处理函数的话，先取消注册，再让进程带着退出码跳过去
+///
+/// 处理函数在原来的栈上运行，退出码放在rdi里，它应当再调用一次EXIT。
+/// 返回是否跳转了，调用前系统调用处理程序必须已经保存了现场
+pub fn run_atexit(code: usize) -> bool {
+    enter_atexit(id(), code)
+}
+
+/// 让进程`pid`保存的现场跳到它的退出处理函数，见`run_atexit`
+fn enter_atexit(pid: usize, code: usize) -> bool {
+    let (handler, mut frame) = {
+        let mut table = write_table();
+        match table[pid].atexit.take() {
+            Some(handler) => (handler, table[pid].stack_frame),
+            None => return false,
+        }
+    };
+    // 跳过红区并按调用约定对齐，再放一个0作返回地址：处理函数直接返回的话会触发异常被杀死
+    let rsp = match frame.stack_pointer.as_u64().checked_sub(128).and_then(|rsp| (rsp & !0xF).checked_sub(8)) {
+        Some(rsp) => rsp,
+        None => return false,
+    };
+    if syskrnl::uaccess::copy_to_user(rsp, &0u64.to_ne_bytes()).is_err() {
+        return false;
+    }
+    frame.instruction_pointer = VirtAddr::new(handler);
+    frame.stack_pointer = VirtAddr::new(rsp);
+    let mut table = write_table();
+    table[pid].stack_frame = frame;
+    table[pid].registers.rdi = code;
+    true
+}
+
+/// 修改当前进程一段内存的访问权限，范围必须完全落在进程自己的代码或栈里
+///
+/// 堆的页面由内核在分配、压缩和归还时管理，不能改
+pub fn mprotect(addr: u64, len: usize, prot: ProtFlags) -> Result<(), ExitCode> {
+    if addr % 4096 != 0 || len == 0 {
+        return Err(ExitCode::UsageError);
+    }
+    let len = len.checked_add(4095).ok_or(ExitCode::UsageError)? & !4095;
+    let end = addr.checked_add(len as u64).ok_or(ExitCode::UsageError)?;
+    if !read_table()[id()].owns(addr, len as u64) || heap_regions().iter().any(|&(start, size)| addr < start + size as u64 && start < end) {
+        return Err(ExitCode::PermissionError);
+    }
+    let mut flags = PageTableFlags::empty();
+    if prot.intersects(ProtFlags::READ | ProtFlags::WRITE) {
+        flags |= PageTableFlags::USER_ACCESSIBLE;
+    }
+    if prot.contains(ProtFlags::WRITE) {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    // 没有开启NXE时不可执行位是保留位，设置了会引发页错误
+    if !prot.contains(ProtFlags::EXEC) && Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE) {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    let phys_mem_offset = unsafe { syskrnl::memory::PHYS_MEM_OFFSET };
+    let mut mapper = unsafe { OffsetPageTable::new(page_table(), VirtAddr::new(phys_mem_offset)) };
+    protect_pages(&mut mapper, addr, len, flags).map_err(|_| ExitCode::Failure)
+}
+
 /// 进程退出
 pub fn exit() -> usize {
-    let table = PROCESS_TABLE.read();
-    let proc = &table[id()];
-    syskrnl::allocator::dealloc_pages(proc.code_addr, MAX_PROC_SIZE);
-    PID_POOL.lock().insert(id());
-    let next_pid = SCHEDULER.lock().terminate(proc);
+    let next_pid = teardown(id());
     debugln!("EXIT:{} -> {}", id(), next_pid);
     next_pid
 }
 
+/// 进程的父进程，进程不存在时返回`None`
+pub fn parent_of(pid: usize) -> Option<usize> {
+    if is_alive(pid) {
+        Some(read_table()[pid].parent)
+    } else {
+        None
+    }
+}
+
+/// 进程创建时带了`SpawnFlags::UNKILLABLE`，KILL和SysRq都不能结束它
+pub fn is_unkillable(pid: usize) -> bool {
+    is_alive(pid) && read_table()[pid].unkillable
+}
+
+/// 要求进程结束：它正在睡眠或等待的话立刻叫醒，让它尽快走到退出的路径上
+///
+/// 真正的退出发生在它下一次运行或者下一次系统调用的时候，0号进程不能结束
+pub fn request_terminate(pid: usize) -> bool {
+    if pid == 0 || !is_alive(pid) {
+        return false;
+    }
+    write_table()[pid].terminate_pending = true;
+    syskrnl::event::interrupt(pid);
+    true
+}
+
+/// 除0号进程和`except`以外存活的进程
+pub fn user_pids(except: usize) -> Vec<usize> {
+    let pool = PID_POOL.lock();
+    (1..MAX_PROCS).filter(|pid| *pid != except && !pool.contains(pid)).collect()
+}
+
+/// 关机第一步：要求`except`以外的用户进程退出，返回通知到的进程数
+///
+/// 停在用户态并且注册过退出处理函数的进程跳到处理函数，让它自己收尾后调用EXIT；其余的和`request_terminate`一样
+pub fn shutdown_terminate(except: usize) -> usize {
+    let pids = user_pids(except);
+    for pid in pids.iter().copied() {
+        let user_mode = read_table()[pid].stack_frame.code_segment & 3 == 3;
+        if user_mode && enter_atexit(pid, ExitCode::Failure as usize) {
+            syskrnl::event::interrupt(pid);
+        } else {
+            request_terminate(pid);
+        }
+    }
+    pids.len()
+}
+
+/// 关机第二步：过了宽限期还在的进程不再等退出处理函数，下次被调度就结束，返回找到的进程数
+///
+/// 进程表的写锁被占着时跳过标记，反正随后就停止调度了
+pub fn shutdown_kill(except: usize) -> usize {
+    let pids = user_pids(except);
+    if let Some(mut table) = PROCESS_TABLE.try_write() {
+        for pid in pids.iter() {
+            table[*pid].terminate_pending = true;
+        }
+    }
+    for pid in pids.iter() {
+        syskrnl::event::interrupt(*pid);
+    }
+    pids.len()
+}
+
+/// 进程名，进程不存在时返回空串
+pub fn name_of(pid: usize) -> String {
+    find_name(pid).unwrap_or_default()
+}
+
+/// 进程的装载位置，进程不存在时返回`None`
+pub fn image_info(pid: usize) -> Option<ImageInfo> {
+    if !is_alive(pid) {
+        return None;
+    }
+    let table = read_table();
+    let proc = &table[pid];
+    Some(ImageInfo {
+        code_addr: proc.code_addr,
+        entry_point: proc.entry_point,
+        text_end: proc.text_end,
+        image_end: proc.image_end,
+        stack_addr: proc.stack_addr,
+        stack_bottom: proc.stack_bottom,
+        vdso: proc.vdso,
+    })
+}
+
+/// 进程名，进程不存在时返回`None`
+pub fn find_name(pid: usize) -> Option<String> {
+    if is_alive(pid) {
+        Some(read_table()[pid].name.clone())
+    } else {
+        None
+    }
+}
+
+/// 去掉控制字符，并截断到`MAX_NAME_LEN`字节以内
+fn sanitize_name(name: &str) -> String {
+    let mut clean = String::new();
+    for ch in name.chars().filter(|ch| !ch.is_control()) {
+        if clean.len() + ch.len_utf8() > MAX_NAME_LEN {
+            break;
+        }
+        clean.push(ch);
+    }
+    clean
+}
+
+/// 给当前进程改名
+pub fn set_name(name: &str) {
+    write_table()[id()].name = sanitize_name(name);
+}
+
+/// 所有存活的进程
+pub fn list() -> Vec<ProcInfo> {
+    let pool = PID_POOL.lock();
+    let table = read_table();
+    (0..MAX_PROCS)
+        .filter(|pid| !pool.contains(pid))
+        .map(|pid| {
+            let proc = &table[pid];
+            ProcInfo { pid, parent: proc.parent, name: proc.name.clone(), cmdline: proc.cmdline.clone() }
+        })
+        .collect()
+}
+
+/// 进程页表里用户态访问得到的映射，进程不存在时返回`None`
+///
+/// 系统调用关着中断，走页表期间进程不会退出，页表也不会被回收
+pub fn pagemap(pid: usize) -> Option<Vec<MapRange>> {
+    if !is_alive(pid) {
+        return None;
+    }
+    let frame = read_table()[pid].page_table_frame;
+    let ranges = syskrnl::memory::user_mappings(frame, MAX_PAGEMAP_RANGES)
+        .into_iter()
+        .map(|(start, end, flags)| {
+            let mut map = MapFlags::empty();
+            map.set(MapFlags::PRESENT, flags.contains(PageTableFlags::PRESENT));
+            map.set(MapFlags::WRITABLE, flags.contains(PageTableFlags::WRITABLE));
+            map.set(MapFlags::USER, flags.contains(PageTableFlags::USER_ACCESSIBLE));
+            map.set(MapFlags::NO_EXECUTE, flags.contains(PageTableFlags::NO_EXECUTE));
+            map.set(MapFlags::HUGE, flags.contains(PageTableFlags::HUGE_PAGE));
+            MapRange { start, end, flags: map }
+        })
+        .collect();
+    Some(ranges)
+}
+
+/// 当前进程是否已经被要求结束
+pub fn terminate_pending() -> bool {
+    read_table()[id()].terminate_pending
+}
+
+/// 不等锁地读进程表：读锁被占着时直接读，内容可能正在被修改，只能用在SysRq这样的调试路径上
+fn with_table_snapshot<R>(f: impl FnOnce(&ProcessTable) -> R) -> R {
+    match PROCESS_TABLE.try_read() {
+        Some(table) => f(&table),
+        None => f(unsafe { &*PROCESS_TABLE.as_mut_ptr() }),
+    }
+}
+
+/// 快照里的进程是否存在：不去拿PID池的锁，0号进程之外有内存区域的就算活着
+fn snapshot_alive(proc: &Process, pid: usize) -> bool {
+    pid == 0 || (proc.id == pid && !proc.regions.is_empty())
+}
+
+/// SysRq：把进程表（PID、进程名、状态、保存的RIP）写到`w`，不拿锁也不分配内存
+pub fn emergency_dump(w: &mut impl core::fmt::Write) {
+    let current = id();
+    with_table_snapshot(|table| {
+        let _ = writeln!(w, "pid\tstate\trip\t\tname");
+        for (pid, proc) in table.iter().enumerate().filter(|(pid, proc)| snapshot_alive(proc, *pid)) {
+            let state = if pid == current {
+                "run"
+            } else if proc.terminate_pending {
+                "term"
+            } else {
+                "ready"
+            };
+            let _ = writeln!(w, "{}\t{}\t{:#x}\t{}", pid, state, proc.stack_frame.instruction_pointer.as_u64(), proc.name);
+        }
+    });
+}
+
+/// SysRq：要求前台进程组里的进程结束，返回找到的进程数
+///
+/// 拿得到写锁时立刻标记，正在空转的进程下次被调度就会退出；叫醒睡眠中的进程要拿别的锁，放到工作队列里做
+pub fn emergency_kill_foreground() -> usize {
+    let pgid = foreground_group();
+    if pgid == 0 {
+        return 0;
+    }
+    let mut targets = [0usize; MAX_PROCS];
+    let mut count = 0;
+    with_table_snapshot(|table| {
+        for (pid, proc) in table.iter().enumerate() {
+            if pid != 0 && snapshot_alive(proc, pid) && proc.pgid == pgid && !proc.unkillable {
+                targets[count] = pid;
+                count += 1;
+            }
+        }
+    });
+    if let Some(mut table) = PROCESS_TABLE.try_write() {
+        for pid in &targets[..count] {
+            table[*pid].terminate_pending = true;
+        }
+    }
+    for pid in &targets[..count] {
+        workqueue::push(WorkItem::new(terminate_deferred, *pid));
+    }
+    count
+}
+
+fn terminate_deferred(pid: usize) {
+    request_terminate(pid);
+}
+
+/// 杀死另一个进程
+pub fn kill(pid: usize) {
+    let next_pid = teardown(pid);
+    debugln!("KILL:{}, now:{}", pid, next_pid);
+}
+
+/// 回收进程的内存和PID，并将它移出调度器，返回接下来运行的进程
+fn teardown(pid: usize) -> usize {
+    let parent_alive = {
+        let parent = read_table()[pid].parent;
+        parent != pid && is_alive(parent)
+    };
+    // 区域还记在进程表里时释放，释放函数要靠它确认归属
+    let regions = read_table()[pid].regions.clone();
+    for (addr, size) in regions {
+        match dealloc_pages(pid, addr, size) {
+            Ok(0) => {}
+            Ok(failed) => debugln!("teardown: pid {} region {:#x}+{:#x} had {} unmapped pages", pid, addr, size, failed),
+            Err(_) => debugln!("teardown: pid {} has a bad region {:#x}+{:#x}", pid, addr, size),
+        }
+    }
+    let (pgid, parent, exit_code) = {
+        let mut table = write_table();
+        let proc = &mut table[pid];
+        proc.regions.clear();
+        // 代码区的页面已经取消映射，槽位可以给新进程用了
+        release_code_slot(proc.code_addr);
+        let ticks = proc.rusage.cpu_ticks + proc.children_ticks;
+        let (pgid, parent, exit_code) = (proc.pgid, proc.parent, proc.exit_code);
+        // 用掉的CPU记到父进程的子进程账上
+        if parent_alive {
+            table[parent].children_ticks += ticks;
+        }
+        (pgid, parent, exit_code)
+    };
+    // 关闭还开着的文件，继承来的句柄要等所有进程都关闭了才真正关闭
+    let handles = core::mem::take(&mut *read_table()[pid].data.file_handles.lock());
+    syskrnl::fs::release_all(handles);
+    PID_POOL.lock().insert(pid);
+    syskrnl::futex::remove(pid);
+    syskrnl::event::remove(pid);
+    syskrnl::msgq::remove(pid);
+    syskrnl::task::keyboard::release_raw(pid);
+    syskrnl::event::child_exited(pid, exit_code);
+    // 退出时还关着调度的话，替它恢复
+    syskrnl::schedule::release(pid);
+
+    // 前台进程组的最后一个进程退出了，把前台还给父进程所在的组，控制台不能没有主人
+    if pgid == foreground_group() && !group_alive(pgid) {
+        let parent_pgid = if is_alive(parent) { read_table()[parent].pgid } else { 0 };
+        FOREGROUND_PGID.store(parent_pgid, Ordering::SeqCst);
+    }
+
+    let table = read_table();
+    SCHEDULER.lock().terminate(&table[pid])
+}
+
+/// 用户进程崩溃时生成报告：崩溃的位置，以及沿保存的RBP回溯的调用栈
+pub fn crash_report(rip: u64, rbp: u64) -> String {
+    let table = read_table();
+    let proc = &table[id()];
+    let mut report = format!("pid {} ({}) crashed in {}", proc.id, proc.name, proc.symbolize(rip));
+    let mut rbp = rbp;
+    for depth in 0..MAX_BACKTRACE_DEPTH {
+        // 每个栈帧都必须在进程自己的内存里，否则就不再往下走
+        if rbp % 8 != 0 || !proc.owns(rbp, 16) {
+            break;
+        }
+        let (next_rbp, ret) = unsafe { (*(rbp as *const u64), *((rbp + 8) as *const u64)) };
+        if ret == 0 {
+            break;
+        }
+        report += &format!("\n  #{} {}", depth, proc.symbolize(ret));
+        // 栈向下生长，调用者的栈帧一定在更高的地址
+        if next_rbp <= rbp {
+            break;
+        }
+        rbp = next_rbp;
+    }
+    report
+}
+
+/// 参数能否放进启动时的参数缓冲区
+fn args_fit(args: &[&str]) -> bool {
+    let bytes: usize = args.iter().map(|arg| arg.len()).sum();
+    let align = core::mem::align_of::<&str>();
+    bytes + align + args.len() * core::mem::size_of::<&str>() <= ARGS_SIZE
+}
+
+/// 自检：创建一个进程再回收，PID和内存都应当还回来
+pub fn selftest_create_exit() -> bool {
+    let free_pids = PID_POOL.lock().len();
+    // 只有一条hlt指令的BIN程序，不会真的运行
+    let id = match Process::create(&bin_image(0, 0, &[0xF4])) {
+        Ok(id) => id,
+        Err(_) => return false,
+    };
+    let resident = read_table()[id].resident();
+    teardown(id);
+    let passed = resident > 0 && PID_POOL.lock().len() == free_pids && read_table()[id].resident() == 0;
+    println!("process create/exit round-trip {}", if passed { "passed" } else { "FAILED" });
+    passed
+}
+
+/// 选出内存耗尽时要杀死的进程，返回PID和占用的内存大小
+///
+/// 先在非root进程里选占用内存最多的，没有的话再选root进程；内核、当前进程和不可杀死的进程除外
+pub fn oom_victim() -> Option<(usize, usize)> {
+    let pool = PID_POOL.lock();
+    let table = read_table();
+    (1..MAX_PROCS)
+        .filter(|pid| *pid != id() && !pool.contains(pid))
+        .map(|pid| &table[pid])
+        .filter(|proc| !proc.unkillable && proc.resident() > 0)
+        .max_by_key(|proc| (!is_root_proc(proc), proc.resident()))
+        .map(|proc| (proc.id, proc.resident()))
+}
+
 pub unsafe fn page_table() -> &'static mut PageTable {
     syskrnl::memory::create_page_table(page_table_frame())
 }
@@ -320,21 +1405,105 @@ pub unsafe fn page_table() -> &'static mut PageTable {
  *  用户空间相关。祝我们好运！ *
  ***************************/
 
+/// 代码区的起点
+static CODE_BASE: AtomicU64 = AtomicU64::new(0);
+/// 代码区里用到过的最高位置，之上的空间还没有分出去过
 static CODE_ADDR: AtomicU64 = AtomicU64::new(0);
+/// 进程退出后空出来的代码区槽位
+static FREE_CODE_SLOTS: Mutex<BTreeSet<u64>> = Mutex::new(BTreeSet::new());
 
 /// 初始化进程代码地址，在内核初始化的时候调用
 pub fn init_process_addr(addr: u64) {
+    CODE_BASE.store(addr, Ordering::SeqCst);
     CODE_ADDR.store(addr, Ordering::SeqCst);
 }
 
+/// 分配一个`MAX_PROC_SIZE`大小的代码区槽位：优先用空出来的最低槽位，没有才往上走，碰到用户堆时失败
+fn alloc_code_slot() -> Result<u64, ()> {
+    let mut free = FREE_CODE_SLOTS.lock();
+    if let Some(slot) = free.pop_first() {
+        return Ok(slot);
+    }
+    let slot = CODE_ADDR.load(Ordering::SeqCst);
+    if slot + MAX_PROC_SIZE as u64 > PROC_HEAP_BASE as u64 {
+        debugln!("create: user code area is full");
+        return Err(());
+    }
+    CODE_ADDR.store(slot + MAX_PROC_SIZE as u64, Ordering::SeqCst);
+    Ok(slot)
+}
+
+/// 归还代码区槽位，槽位里的页面必须已经取消映射；不是槽位的地址（比如内核进程的0）忽略
+fn release_code_slot(slot: u64) {
+    let base = CODE_BASE.load(Ordering::SeqCst);
+    if slot < base || slot >= CODE_ADDR.load(Ordering::SeqCst) || (slot - base) % MAX_PROC_SIZE as u64 != 0 {
+        return;
+    }
+    FREE_CODE_SLOTS.lock().insert(slot);
+}
+
+/// 装载过程中占着的代码区槽位，装载失败时自动归还
+struct CodeSlot(Option<u64>);
+
+impl CodeSlot {
+    fn alloc() -> Result<Self, ()> {
+        alloc_code_slot().map(|slot| Self(Some(slot)))
+    }
+
+    fn addr(&self) -> u64 {
+        self.0.unwrap_or(0)
+    }
+
+    /// 装载成功，槽位交给进程，进程退出时再归还
+    fn keep(mut self) -> u64 {
+        self.0.take().unwrap_or(0)
+    }
+}
+
+impl Drop for CodeSlot {
+    fn drop(&mut self) {
+        if let Some(slot) = self.0.take() {
+            release_code_slot(slot);
+        }
+    }
+}
+
 impl Process {
-    /// 创建进程
-    pub fn spawn(bin: &[u8], args_ptr: usize, args_len: usize, args_cap: usize) -> Result<(), ExitCode> {
+    /// 创建进程，进程名取自程序的路径，`limits`在继承来的资源限制上再收紧
+    ///
+    /// `jail`是子进程的根目录，已经解析成了真正的路径，`None`时继承父进程的；
+    /// `redirects`里的句柄在子进程运行之前放到对应的句柄号上，原来的句柄随之释放；
+    /// `wait`时调用者睡到子进程退出，退出代码成为调用者这次系统调用的返回值
+    pub fn spawn(
+        path: &str, bin: &[u8], args_ptr: usize, args_len: usize, args_cap: usize, flags: SpawnFlags, limits: Option<Limits>, jail: Option<&str>,
+        redirects: Vec<(usize, FileHandleRef)>, wait: bool,
+    ) -> Result<(), ExitCode> {
+        let root = is_root();
         if let Ok(id) = Self::create(bin) {
-            let mut proc = {
-                let table = PROCESS_TABLE.read();
-                table[id].clone()
+            let (mut proc, replaced) = {
+                let mut table = write_table();
+                table[id].set_name_from_path(path);
+                table[id].limits = child_limits(table[id].limits, limits, root);
+                table[id].unkillable = flags.contains(SpawnFlags::UNKILLABLE);
+                if let Some(jail) = jail {
+                    table[id].data.root = jail.into();
+                    table[id].data.dir = String::from("/");
+                }
+                if flags.contains(SpawnFlags::FOREGROUND) {
+                    table[id].pgid = id;
+                    FOREGROUND_PGID.store(id, Ordering::SeqCst);
+                }
+                let replaced: BTreeMap<_, _> = {
+                    let mut handles = table[id].data.file_handles.lock();
+                    redirects.into_iter().filter_map(|(fd, file)| handles.insert(fd, file).map(|old| (fd, old))).collect()
+                };
+                (table[id].clone(), replaced)
             };
+            syskrnl::fs::release_all(replaced);
+            if wait {
+                // 调用者停止调度，子进程退出时由`event::child_exited`叫醒
+                syskrnl::event::wait_child(id);
+            }
             proc.exec(args_ptr, args_len, args_cap);
             Ok(())
         } else {
@@ -343,7 +1512,54 @@ impl Process {
     }
 
     fn create(bin: &[u8]) -> Result<usize, ()> {
-        let page_table_frame = syskrnl::memory::heaped_frame_allocator().allocate_frame().expect("frame alloc failed");
+        if syskrnl::power::shutting_down() {
+            debugln!("create: shutting down");
+            return Err(());
+        }
+        // 当前进程自己受子进程数的限制
+        if let Some(max) = limits().max_children {
+            if children_of(id()) >= max {
+                debugln!("create: pid {} already has {} children", id(), max);
+                return Err(());
+            }
+        }
+        // 先占一个进程表的空位，进程表满了就直接失败，不要浪费内存
+        let id = match PID_POOL.lock().pop_first() {
+            Some(id) => id,
+            None => {
+                debugln!("create: process table is full");
+                return Err(());
+            }
+        };
+        let ret = Self::create_with_id(id, bin);
+        if ret.is_err() {
+            PID_POOL.lock().insert(id);
+        }
+        ret
+    }
+
+    fn create_with_id(id: usize, bin: &[u8]) -> Result<usize, ()> {
+        let proc = Self::load(id, bin)?;
+        let mut table = write_table();
+        table[id] = Box::new(proc);
+
+        Ok(id)
+    }
+
+    /// 装载程序映像：建好页表、代码段、栈和堆，但还不放进进程表
+    ///
+    /// 父进程、进程组等信息取自当前进程
+    fn load(id: usize, bin: &[u8]) -> Result<Process, ()> {
+        if bin.len() < 4 || (bin[0..4] != ELF_MAGIC && bin[0..4] != BIN_MAGIC) {
+            // 文件头错误
+            return Err(());
+        }
+        if bin[0..4] == BIN_MAGIC {
+            // 先检查文件头，免得白白分配页表
+            parse_bin(bin, BIN_LOAD_LIMIT)?;
+        }
+
+        let page_table_frame = syskrnl::memory::heaped_frame_allocator().allocate_frame().ok_or(())?;
         let page_table = unsafe { syskrnl::memory::create_page_table(page_table_frame) };
         let kernel_page_table = unsafe { syskrnl::memory::active_page_table() };
 
@@ -357,9 +1573,15 @@ impl Process {
 
         // 特别地，打开用户页表的内核使用权限
         unsafe { fix_page_fault_in_userspace(&mut mapper) };
+        // 只读的vDSO页，用户态不用系统调用就能读时间；映射不上也能运行，只是读时间慢一些
+        let vdso = syskrnl::vdso::map_into(&mut mapper).is_ok();
+        if !vdso {
+            debugln!("proc: cannot map the vDSO page for pid {}", id);
+        }
 
         let proc_size = MAX_PROC_SIZE as u64;
-        let kernel_code_addr = CODE_ADDR.fetch_add(proc_size, Ordering::SeqCst);
+        let slot = CodeSlot::alloc()?;
+        let kernel_code_addr = slot.addr();
         let code_addr = kernel_code_addr;
         let stack_addr = code_addr + proc_size - 4096;
         // 紧跟在程序段后面
@@ -367,21 +1589,38 @@ impl Process {
         debugln!("stack_addr: {:#x}", stack_addr);
 
         let mut entry_point = 0;
+        let mut text_end = 0;
+        let mut image_end = code_addr;
+        let mut regions = Vec::new();
+        let mut symbols = Vec::new();
         let code_ptr = kernel_code_addr as *mut u8;
         let _code_size = bin.len();
         if bin[0..4] == ELF_MAGIC {
             // 进程代码是ELF格式的
             if let Ok(obj) = object::File::parse(bin) {
-                // 先在用户页表上分配
-                alloc_pages(&mut mapper, code_addr, proc_size as usize).expect("proc mem alloc 754");
+                // 只映射各个段占到的地方，剩下的留给栈往下长
+                let image_size = obj.segments().map(|segment| segment.address() + segment.size()).max().unwrap_or(0);
+                image_end = code_addr + align_up(image_size as usize, 4096) as u64;
+                if image_end + 4096 > stack_addr - 4096 {
+                    return Err(());
+                }
+                // 先在用户页表上分配并清零：段之间的空隙、页里段没有占到的地方和bss都不能留着上一个进程的数据
+                alloc_user_pages(&mut mapper, code_addr, (image_end - code_addr) as usize, true)?;
+                regions.push((code_addr, (image_end - code_addr) as usize));
                 // // 接下来，把用户页表的地址映射到内核页表上，并在内核页表上分配
                 // let user_code_phys_frame = mapper.translate_addr(VirtAddr::new(code_addr)).expect("Map fail 12341");
                 // alloc_pages_to_known_phys(&mut kernel_mapper, kernel_code_addr, proc_size as usize, user_code_phys_frame.as_u64(), true).expect("proc mem alloc 564");
 
                 entry_point = obj.entry();
+                symbols = ksyms::function_symbols(&obj, MAX_SYMBOLS_SIZE);
                 debugln!("entry_point:{:#x}", entry_point);
                 for segment in obj.segments() {
                     let addr = segment.address() as usize;
+                    if let SegmentFlags::Elf { p_flags } = segment.flags() {
+                        if p_flags & PF_X != 0 {
+                            text_end = text_end.max(segment.address() + segment.size());
+                        }
+                    }
                     if let Ok(data) = segment.data() {
                         debugln!(
                             "before flight? codeaddr,addr,datalen is {:#x},{:#x},{}",
@@ -398,27 +1637,50 @@ impl Process {
                         }
                     }
                 }
+            } else {
+                // ELF解析失败，不能留下一个没有代码的进程
+                return Err(());
             }
         } else if bin[0..4] == BIN_MAGIC {
-            for (i, b) in bin.iter().skip(4).enumerate() {
-                unsafe {
-                    core::ptr::write(code_ptr.add(i), *b);
-                }
-            }
+            // 进程代码是平坦的BIN格式，文件头之后的内容原样装到装载偏移处
+            let (header, payload) = parse_bin(bin, BIN_LOAD_LIMIT)?;
+            image_end = code_addr + align_up((header.load as usize).saturating_add(payload.len()), 4096) as u64;
+            // 装载偏移之前和载荷之后的地方也映射给了进程，要清零
+            alloc_user_pages(&mut mapper, code_addr, (image_end - code_addr) as usize, true)?;
+            regions.push((code_addr, (image_end - code_addr) as usize));
+            unsafe { core::ptr::copy_nonoverlapping(payload.as_ptr(), code_ptr.add(header.load as usize), payload.len()) };
+            entry_point = header.entry;
+            text_end = header.load + payload.len() as u64;
+            debugln!("entry_point:{:#x}", entry_point);
         } else {
             // 文件头错误
             return Err(());
         }
+        // 代码已经写好，之后只会跳进去执行
+        flush_instruction_pipeline();
+
+        // 栈顶上下各一页，再往下的栈在缺页时按需映射，见`grow_stack`
+        let stack_bottom = stack_addr - INITIAL_STACK_SIZE;
+        if alloc_user_pages(&mut mapper, stack_bottom, (code_addr + proc_size - stack_bottom) as usize, true).is_err() {
+            for (addr, size) in regions {
+                let _ = dealloc_pages_from_mapper(&mut mapper, addr, size);
+            }
+            return Err(());
+        }
+        regions.push((stack_bottom, (code_addr + proc_size - stack_bottom) as usize));
 
         // 父进程
         let parent = {
-            let table = PROCESS_TABLE.read();
+            let table = read_table();
             table[id()].clone()
         };
 
-        let data = parent.data.clone();
+        let data = parent.data.inherit();
         let registers = parent.registers;
         let stack_frame = parent.stack_frame;
+        let nice = parent.nice;
+        let pgid = parent.pgid;
+        let limits = parent.limits;
         let parent = parent.id;
 
         // 初始化进程的堆分配器
@@ -426,7 +1688,15 @@ impl Process {
         let heap_addr = PROC_HEAP_ADDR.fetch_add(DEFAULT_HEAP_SIZE, Ordering::SeqCst);
 
         // 先在用户页表上分配
-        alloc_pages(&mut mapper, heap_addr as u64, DEFAULT_HEAP_SIZE).expect("proc heap mem alloc failed 8520");
+        if alloc_user_pages(&mut mapper, heap_addr as u64, DEFAULT_HEAP_SIZE, true).is_err() {
+            debugln!("proc heap mem alloc failed 8520");
+            // 区域还没有记到进程表里，在新页表上直接撤销
+            for (addr, size) in regions {
+                let _ = dealloc_pages_from_mapper(&mut mapper, addr, size);
+            }
+            return Err(());
+        }
+        regions.push((heap_addr as u64, DEFAULT_HEAP_SIZE));
         // // 再映射到内核页表上
         // let heap_frame = mapper.translate_addr(VirtAddr::new(heap_addr as u64)).expect("map fail 7897");
         // alloc_pages_to_known_phys(&mut kernel_mapper, heap_addr as u64, DEFAULT_HEAP_SIZE, heap_frame.as_u64(), true).expect("proc heap mem alloc failed 3652");
@@ -434,53 +1704,115 @@ impl Process {
         unsafe { allocator.init(heap_addr, DEFAULT_HEAP_SIZE) };
         let allocator = Arc::new(Locked::new(allocator));
 
-        if let Some(id) = PID_POOL.lock().pop_first() {
-            let proc = Process {
-                id,
-                code_addr,
-                stack_addr,
-                data,
-                registers,
-                stack_frame,
-                entry_point,
-                parent,
-                allocator,
-                page_table_frame,
-            };
+        let proc = Process {
+            id,
+            code_addr: slot.keep(),
+            stack_addr,
+            stack_bottom,
+            image_end,
+            vdso,
+            data,
+            registers,
+            stack_frame,
+            entry_point,
+            parent,
+            pgid,
+            nice,
+            unkillable: false,
+            quantum_left: 0,
+            rusage: Rusage::default(),
+            children_ticks: 0,
+            terminate_pending: false,
+            exit_code: ExitCode::Failure,
+            limits,
+            text_end,
+            atexit: None,
+            fault_handler: None,
+            in_fault_handler: false,
+            name: format!("bin-{:#04x}", id),
+            cmdline: Vec::new(),
+            regions,
+            symbols: Arc::new(symbols),
+            allocator,
+            page_table_frame,
+        };
 
-            let mut table = PROCESS_TABLE.write();
-            table[id] = Box::new(proc);
+        Ok(proc)
+    }
 
-            Ok(id)
-        } else {
-            Err(())
+    /// 用新的程序映像替换当前进程，PID、父进程、进程组和打开的文件（带`FD_CLOEXEC`的除外）都保持不变
+    ///
+    /// 新映像完整装载好之后才会替换，失败时当前进程原封不动。
+    /// 成功后当前进程的现场已经指向新程序的入口，由系统调用处理程序切换过去
+    pub fn exec_replace(path: &str, bin: &[u8], args: &[&str]) -> Result<(), ExitCode> {
+        if !args_fit(args) {
+            return Err(ExitCode::ExecError);
+        }
+        let pid = id();
+        let mut proc = Self::load(pid, bin).map_err(|_| ExitCode::ExecError)?;
+        {
+            let table = read_table();
+            let old = &table[pid];
+            proc.parent = old.parent;
+            proc.pgid = old.pgid;
+            proc.unkillable = old.unkillable;
+            proc.quantum_left = old.quantum_left;
+            proc.rusage = old.rusage;
+            proc.children_ticks = old.children_ticks;
+            proc.terminate_pending = old.terminate_pending;
         }
+        proc.set_name_from_path(path);
+        proc.cmdline = args.iter().map(|arg| arg.to_string()).collect();
+
+        let (args_ptr, argc) = proc.copy_args(args);
+        proc.registers = Registers {
+            rdi: args_ptr as usize,
+            rsi: argc,
+            ..Registers::default()
+        };
+        proc.stack_frame = InterruptStackFrameValue {
+            instruction_pointer: VirtAddr::new(proc.code_addr + proc.entry_point),
+            code_segment: syskrnl::gdt::GDT.1.user_code_selector.0 as u64,
+            cpu_flags: 0x200,
+            stack_pointer: VirtAddr::new(proc.stack_addr),
+            stack_segment: syskrnl::gdt::GDT.1.user_data_selector.0 as u64,
+        };
+
+        // 旧映像的区域还记在进程表里时释放，之后再换上新映像
+        let (old_regions, old_code_addr) = {
+            let table = read_table();
+            (table[pid].regions.clone(), table[pid].code_addr)
+        };
+        for (addr, size) in old_regions {
+            if dealloc_pages(pid, addr, size).is_err() {
+                debugln!("exec: pid {} has a bad region {:#x}+{:#x}", pid, addr, size);
+            }
+        }
+        release_code_slot(old_code_addr);
+        // 新程序的句柄表已经不带这些句柄了，在旧的句柄表里正式关闭
+        syskrnl::fs::close_on_exec();
+        write_table()[pid] = Box::new(proc);
+        // 旧映像关掉的调度不能带到新程序里
+        syskrnl::schedule::release(pid);
+        debugln!("EXEC: pid {} entry {:#x}", pid, read_table()[pid].entry_point);
+        Ok(())
     }
 
-    // 切换到用户空间并执行程序
-    fn exec(&mut self, args_ptr: usize, args_len: usize, args_cap: usize) {
-        //syskrnl::allocator::alloc_pages(heap_addr, 1).expect("proc heap alloc");
-        let page_table = unsafe { page_table() };
-        let phys_mem_offset = unsafe { syskrnl::memory::PHYS_MEM_OFFSET };
-        let _mapper = unsafe { OffsetPageTable::new(page_table, VirtAddr::new(phys_mem_offset)) };
+    /// 用程序文件名作为进程名，取不到文件名时保留默认的名字
+    fn set_name_from_path(&mut self, path: &str) {
+        let name = sanitize_name(filename(path));
+        if !name.is_empty() {
+            self.name = name;
+        }
+    }
 
-        // 处理参数
-        // 重建指针-长度对
-        let ptr_len_pair: Vec<(usize, usize)> = unsafe { Vec::from_raw_parts(args_ptr as *mut (usize, usize), args_len, args_cap) };
-        // 重建参数数组
-        let args: Vec<&str> = ptr_len_pair
-            .iter()
-            .map(|pair| unsafe {
-                let slice = core::slice::from_raw_parts(pair.0 as *const u8, pair.1);
-                //debugln!("{:?}",slice);
-                core::str::from_utf8(slice).expect("utf8 fail 8547")
-            })
-            .collect();
+    /// 把参数复制到进程自己的堆上，返回参数数组的地址和个数
+    fn copy_args(&self, args: &[&str]) -> (u64, usize) {
         // 在子进程分配用于存放参数的堆内存
         let mut addr = unsafe {
             self.allocator
                 .lock()
-                .alloc(core::alloc::Layout::from_size_align(1024, 1).expect("Layout problem 8741"))
+                .alloc(core::alloc::Layout::from_size_align(ARGS_SIZE, 1).expect("Layout problem 8741"))
         } as u64;
         // 将参数复制到这些内存上
         let vec: Vec<&str> = args
@@ -505,7 +1837,31 @@ impl Process {
             s.copy_from_slice(args);
             s
         };
-        let args_ptr = args.as_ptr() as u64;
+        (args.as_ptr() as u64, args.len())
+    }
+
+    // 切换到用户空间并执行程序
+    fn exec(&mut self, args_ptr: usize, args_len: usize, args_cap: usize) {
+        //syskrnl::allocator::alloc_pages(heap_addr, 1).expect("proc heap alloc");
+        let page_table = unsafe { page_table() };
+        let phys_mem_offset = unsafe { syskrnl::memory::PHYS_MEM_OFFSET };
+        let _mapper = unsafe { OffsetPageTable::new(page_table, VirtAddr::new(phys_mem_offset)) };
+
+        // 处理参数
+        // 重建指针-长度对
+        let ptr_len_pair: Vec<(usize, usize)> = unsafe { Vec::from_raw_parts(args_ptr as *mut (usize, usize), args_len, args_cap) };
+        // 重建参数数组
+        let args: Vec<&str> = ptr_len_pair
+            .iter()
+            .map(|pair| unsafe {
+                let slice = core::slice::from_raw_parts(pair.0 as *const u8, pair.1);
+                //debugln!("{:?}",slice);
+                core::str::from_utf8(slice).expect("utf8 fail 8547")
+            })
+            .collect();
+        let (args_ptr, _) = self.copy_args(&args);
+        self.cmdline = args.iter().map(|arg| arg.to_string()).collect();
+        write_table()[self.id].cmdline = self.cmdline.clone();
 
         SCHEDULER.lock().add(self.clone(), 0);
         // if self.id != 1 {  // 不需要进入环三
@@ -538,3 +1894,291 @@ impl Process {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use cinea_os_sysapi::syscall::Limits;
+
+    use super::{
+        adjusted_limits, alloc_code_slot, bin_image, charge_tick, child_limits, cloexec, enter_fault_handler, extend_stack, find_name, id, image_info,
+        pagemap, parse_bin, read_table, release_code_slot, run_atexit, sanitize_name, set_atexit, set_fault_handler, start_slice, translate_user_ptr,
+        write_table, Access, BinHeader, KernelError, Process, ARGS_SIZE, BIN_LOAD_LIMIT, BIN_MAGIC, CODE_ADDR, MAX_NAME_LEN, MAX_PROCS, MAX_PROC_SIZE,
+        PID_POOL, PROC_HEAP_BASE,
+    };
+
+    #[test_case]
+    fn test_create_when_table_full() {
+        // 占满进程表
+        let mut taken = Vec::new();
+        while let Some(id) = PID_POOL.lock().pop_first() {
+            taken.push(id);
+        }
+        assert_eq!(taken.len(), MAX_PROCS - 1);
+
+        assert!(Process::create(&bin_image(0, 0, &[0xF4])).is_err());
+
+        for id in taken {
+            PID_POOL.lock().insert(id);
+        }
+        println!("[ok]  Process create when table full")
+    }
+
+    #[test_case]
+    fn test_parse_bin_header() {
+        let bin = bin_image(0x12, 0x10, &[0x90, 0x90, 0xF4]);
+        let (header, payload) = parse_bin(&bin, BIN_LOAD_LIMIT).unwrap();
+        assert_eq!(header, BinHeader { entry: 0x12, load: 0x10 });
+        assert_eq!(payload, &[0x90, 0x90, 0xF4]);
+        // 只有魔数、入口不在程序里、装不下
+        assert!(parse_bin(&BIN_MAGIC, BIN_LOAD_LIMIT).is_err());
+        assert!(parse_bin(&bin_image(0x13, 0x10, &[0xF4]), BIN_LOAD_LIMIT).is_err());
+        assert!(parse_bin(&bin_image(BIN_LOAD_LIMIT, BIN_LOAD_LIMIT, &[0xF4]), BIN_LOAD_LIMIT).is_err());
+        println!("[ok]  Parse BIN header")
+    }
+
+    #[test_case]
+    fn test_quantum_expires_after_ticks() {
+        // 时钟中断每个tick调用一次charge_tick，用完整个时间片才轮换
+        let ticks = crate::syskrnl::schedule::quantum_ticks();
+        start_slice(id());
+        for _ in 1..ticks {
+            assert!(!charge_tick());
+        }
+        assert!(charge_tick());
+        // 调度关着的时候时钟中断不轮换，之后的每个tick都应当再次要求轮换
+        assert!(charge_tick());
+        start_slice(id());
+        println!("[ok]  Quantum expires after its ticks")
+    }
+
+    #[test_case]
+    fn test_code_slots_are_reused() {
+        let a = alloc_code_slot().unwrap();
+        let b = alloc_code_slot().unwrap();
+        assert_eq!(b, a + MAX_PROC_SIZE as u64);
+        release_code_slot(b);
+        release_code_slot(a);
+        // 优先用最低的空槽位
+        assert_eq!(alloc_code_slot(), Ok(a));
+        release_code_slot(a);
+        // 反复创建退出，代码区不再往上走
+        let high = CODE_ADDR.load(core::sync::atomic::Ordering::SeqCst);
+        for _ in 0..1000 {
+            let slot = alloc_code_slot().unwrap();
+            release_code_slot(slot);
+        }
+        assert_eq!(CODE_ADDR.load(core::sync::atomic::Ordering::SeqCst), high);
+        // 不是槽位的地址不收
+        release_code_slot(0);
+        assert_eq!(alloc_code_slot(), Ok(a));
+        release_code_slot(a);
+        println!("[ok]  Code slots are reused")
+    }
+
+    #[test_case]
+    fn test_child_limits() {
+        let inherited = Limits { max_cpu_ticks: Some(100), ..Limits::default() };
+        let requested = Limits { max_cpu_ticks: Some(500), max_fds: Some(8), ..Limits::default() };
+        // 普通用户只能收紧，root可以放宽
+        let child = child_limits(inherited, Some(requested), false);
+        assert_eq!(child.max_cpu_ticks, Some(100));
+        assert_eq!(child.max_fds, Some(8));
+        assert_eq!(child_limits(inherited, Some(requested), true).max_cpu_ticks, Some(500));
+        assert_eq!(child_limits(inherited, None, false), inherited);
+        println!("[ok]  Child limits only tighten")
+    }
+
+    #[test_case]
+    fn test_adjusted_limits() {
+        let current = Limits { max_heap: Some(1 << 20), max_children: Some(4), ..Limits::default() };
+        let lower = Limits { max_heap: Some(1 << 16), max_fds: Some(8), ..current };
+        assert_eq!(adjusted_limits(current, lower, false), Some(lower));
+        // 调高或者去掉一项都算放宽
+        assert_eq!(adjusted_limits(current, Limits { max_children: Some(5), ..current }, false), None);
+        assert_eq!(adjusted_limits(current, Limits { max_heap: None, ..current }, false), None);
+        assert_eq!(adjusted_limits(current, Limits::default(), true), Some(Limits::default()));
+        println!("[ok]  Limits can only be lowered without root")
+    }
+
+    #[test_case]
+    fn test_cpu_limit_requests_terminate() {
+        let ticks = read_table()[id()].rusage.cpu_ticks;
+        write_table()[id()].limits.max_cpu_ticks = Some(ticks + 1);
+        assert!(charge_tick());
+        let terminate = read_table()[id()].terminate_pending;
+        {
+            let mut table = write_table();
+            table[id()].limits = Limits::default();
+            table[id()].terminate_pending = false;
+        }
+        start_slice(id());
+        assert!(terminate);
+        println!("[ok]  CPU limit requests terminate")
+    }
+
+    #[test_case]
+    fn test_exec_replace_rejects_bad_binary() {
+        let before = read_table()[id()].clone();
+        // 文件头不对、ELF解析不了、参数放不下，都不能动当前进程
+        assert!(Process::exec_replace("/bin/bad", &[0, 1, 2, 3], &[]).is_err());
+        assert!(Process::exec_replace("/bin/bad", &[0x7F, b'E', b'L', b'F', 0], &[]).is_err());
+        let long = "x".repeat(ARGS_SIZE);
+        assert!(Process::exec_replace("/bin/bad", &bin_image(0, 0, &[0xF4]), &[long.as_str()]).is_err());
+        let after = read_table()[id()].clone();
+        assert_eq!(before.entry_point, after.entry_point);
+        assert_eq!(before.regions, after.regions);
+        println!("[ok]  Exec rejects bad binary")
+    }
+
+    #[test_case]
+    fn test_sanitize_name() {
+        assert_eq!(sanitize_name("ps"), "ps");
+        assert_eq!(sanitize_name("a\nb\x1b[2Jc"), "ab[2Jc");
+        let long = "名".repeat(MAX_NAME_LEN);
+        let name = sanitize_name(long.as_str());
+        // 截断在字符边界上
+        assert_eq!(name.len(), MAX_NAME_LEN / 3 * 3);
+        println!("[ok]  Process name sanitize")
+    }
+
+    #[test_case]
+    fn test_find_name() {
+        assert!(find_name(0).is_some());
+        assert!(find_name(MAX_PROCS).is_none());
+        println!("[ok]  Process find name")
+    }
+
+    #[test_case]
+    fn test_atexit_rejects_non_code() {
+        // 内核进程没有用户代码，任何地址都不是可执行代码
+        assert!(!set_atexit(0x1000));
+        assert!(read_table()[id()].atexit.is_none());
+        assert!(set_atexit(0));
+        // 没有注册时直接走真正的退出
+        assert!(!run_atexit(0));
+        println!("[ok]  Process atexit validation")
+    }
+
+    #[test_case]
+    fn test_fault_handler_validation() {
+        assert!(!set_fault_handler(0x1000));
+        assert!(set_fault_handler(0));
+        // 没有处理函数时交回去杀死进程，也不留下标记
+        assert_eq!(enter_fault_handler(0, 0, 0x1000), None);
+        assert!(!read_table()[id()].in_fault_handler);
+        println!("[ok]  Process fault handler validation")
+    }
+
+    #[test_case]
+    fn test_translate_user_ptr() {
+        let mut proc = Process::new(1);
+        let (code, heap) = (0x1_0280_0000u64, PROC_HEAP_BASE as u64);
+        proc.code_addr = code;
+        proc.text_end = 0x2000;
+        proc.regions = vec![(code, MAX_PROC_SIZE), (heap, 0x1000)];
+        // 堆在代码上面，按原样的地址查到，不会被当成偏移
+        assert_eq!(proc.translate(heap + 0x10, 0x100, Access::Write), Ok((heap + 0x10) as *mut u8));
+        let stack = code + MAX_PROC_SIZE as u64 - 4096;
+        assert_eq!(proc.translate(stack, 64, Access::Write), Ok(stack as *mut u8));
+        // 代码可以读不能写
+        assert_eq!(proc.translate(code + 0x100, 16, Access::Read), Ok((code + 0x100) as *mut u8));
+        assert_eq!(proc.translate(code + 0x100, 16, Access::Write), Err(KernelError::ReadOnly));
+        // 区域外、跨出区域和以前会被当成偏移的小地址
+        assert_eq!(proc.translate(heap + 0x1000, 1, Access::Read), Err(KernelError::BadAddress));
+        assert_eq!(proc.translate(heap + 0xFF0, 0x20, Access::Read), Err(KernelError::BadAddress));
+        assert_eq!(proc.translate(0x100, 16, Access::Read), Err(KernelError::BadAddress));
+        assert_eq!(translate_user_ptr(MAX_PROCS, heap, 1, Access::Read), Err(KernelError::BadAddress));
+        println!("[ok]  Process translate user pointers")
+    }
+
+    #[test_case]
+    fn test_cloexec_not_inherited() {
+        use cinea_os_sysapi::fs::{OpenFlags, FD_CLOEXEC, F_GETFD, F_SETFD};
+
+        use crate::syskrnl::fs;
+
+        let keep = fs::open("/dev/null", OpenFlags::empty()).unwrap();
+        let private = fs::open("/dev/null", OpenFlags::empty()).unwrap();
+        assert_eq!(fs::fcntl(private, F_SETFD, FD_CLOEXEC).unwrap(), FD_CLOEXEC);
+        assert_eq!(fs::fcntl(private, F_GETFD, 0).unwrap(), FD_CLOEXEC);
+        assert_eq!(fs::fcntl(keep, F_GETFD, 0).unwrap(), 0);
+        assert!(fs::fcntl(0, F_SETFD, FD_CLOEXEC).is_err());
+        // 子进程拿到的是句柄表的副本，不带标记的句柄
+        let data = read_table()[id()].data.inherit();
+        let handles = data.file_handles.lock();
+        assert!(handles.contains_key(&keep) && !handles.contains_key(&private));
+        assert!(data.cloexec.is_empty());
+        drop(handles);
+        drop(data);
+        // 关闭后标记也清掉
+        fs::close(private).unwrap();
+        fs::close(keep).unwrap();
+        assert!(!cloexec(private));
+        println!("[ok]  Process cloexec handles not inherited")
+    }
+
+    #[test_case]
+    fn test_pagemap_ranges() {
+        assert!(pagemap(MAX_PROCS).is_none());
+        // 段按地址排列、互不重叠、按页对齐，相邻的段标志不同
+        let ranges = pagemap(0).unwrap();
+        for range in ranges.iter() {
+            assert!(range.start < range.end && range.start % 4096 == 0 && range.end % 4096 == 0);
+        }
+        for pair in ranges.windows(2) {
+            assert!(pair[0].end <= pair[1].start);
+            assert!(pair[0].end < pair[1].start || pair[0].flags != pair[1].flags);
+        }
+        println!("[ok]  Process pagemap ranges")
+    }
+
+    #[test_case]
+    fn test_image_info() {
+        assert!(image_info(MAX_PROCS).is_none());
+        let info = image_info(id()).unwrap();
+        let table = read_table();
+        assert_eq!((info.code_addr, info.entry_point, info.stack_addr), (table[id()].code_addr, table[id()].entry_point, table[id()].stack_addr));
+        println!("[ok]  Process image info")
+    }
+
+    #[test_case]
+    fn test_jail_paths() {
+        use alloc::string::String;
+
+        use crate::syskrnl::fs::resolve;
+
+        let saved = core::mem::replace(&mut write_table()[id()].data.root, String::from("/jail"));
+        assert_eq!(resolve("/etc/motd").unwrap(), "/jail/etc/motd");
+        assert_eq!(resolve("/").unwrap(), "/jail");
+        assert!(resolve("../../etc").is_err());
+        assert!(resolve("/bin/../../etc").is_err());
+        // 子进程继承根目录
+        assert_eq!(read_table()[id()].data.inherit().root, "/jail");
+        write_table()[id()].data.root = saved;
+        println!("[ok]  Process jailed paths")
+    }
+
+    #[test_case]
+    fn test_stack_limit() {
+        let mut proc = Process::new(1);
+        let code = 0x1_0280_0000u64;
+        let top = code + MAX_PROC_SIZE as u64;
+        proc.code_addr = code;
+        proc.image_end = code + 0x3000;
+        // 没有限制时一直能长到映像上面的保护页
+        assert_eq!(proc.stack_limit(), code + 0x4000);
+        proc.limits.max_stack = Some(0x10000);
+        assert_eq!(proc.stack_limit(), top - 0x10000);
+        // 不按页对齐的限制往小里取整
+        proc.limits.max_stack = Some(0x10800);
+        assert_eq!(proc.stack_limit(), top - 0x10000);
+        // 再大的限制也不能长进映像
+        proc.limits.max_stack = Some(usize::MAX);
+        assert_eq!(proc.stack_limit(), code + 0x4000);
+        assert_eq!(extend_stack(0, top - 0x8000), Ok(false));
+        println!("[ok]  Process stack limit")
+    }
+}
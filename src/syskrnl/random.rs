@@ -0,0 +1,57 @@
+//! 内核熵源
+//!
+//! CPU 支持 RDRAND 时直接使用硬件随机数，否则退化为以 TSC 播种并不断搅拌的 xorshift 生成器
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use x86_64::instructions::random::RdRand;
+
+use crate::syskrnl::time::tsc::rdtsc;
+
+static STATE: AtomicU64 = AtomicU64::new(0);
+
+/// 软件后备生成器，每次调用都混入当前 TSC
+fn xorshift() -> u64 {
+    let mut x = STATE.load(Ordering::Relaxed) ^ rdtsc();
+    if x == 0 {
+        x = 0x9E37_79B9_7F4A_7C15;
+    }
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    STATE.store(x, Ordering::Relaxed);
+    x
+}
+
+/// 取一个 64 位随机数
+pub fn next_u64() -> u64 {
+    if let Some(rng) = RdRand::new() {
+        if let Some(value) = rng.get_u64() {
+            return value;
+        }
+    }
+    xorshift()
+}
+
+/// 用随机字节填满缓冲区
+pub fn fill(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+        let bytes = next_u64().to_le_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::fill;
+
+    #[test_case]
+    fn test_random_fill() {
+        let mut a = [0u8; 32];
+        let mut b = [0u8; 32];
+        fill(&mut a);
+        fill(&mut b);
+        assert_ne!(a, b);
+        println!("[ok]  random fill");
+    }
+}
@@ -0,0 +1,129 @@
+//! 系统负载统计
+//!
+//! 时钟中断每个tick记下CPU花在哪里（空闲、用户代码、内核代码）和可运行的进程数，
+//! 每秒用这一秒的平均可运行进程数更新1/5/15分钟的指数加权平均。
+//! 时钟中断里不保证有FPU状态，全部用定点数计算；只有时钟中断写这些计数，读的一方看到的最多差一个tick
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use cinea_os_sysapi::syscall::LOAD_SHIFT;
+
+use crate::syskrnl::time::TICKS_PER_SECOND;
+
+/// 定点数的1
+pub const FIXED_1: usize = 1 << LOAD_SHIFT;
+/// 每秒的衰减系数exp(-1/60)、exp(-1/300)、exp(-1/900)，定点数
+const EXP: [usize; 3] = [64453, 65318, 65463];
+
+/// tick的去处
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickKind {
+    Idle = 0,
+    User = 1,
+    Kernel = 2,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+/// 0号进程是否停在空闲循环的hlt上
+static IDLE: AtomicBool = AtomicBool::new(false);
+/// 开机以来各类tick数
+static TOTAL: [AtomicUsize; 3] = [ZERO; 3];
+/// 本秒内各类tick数
+static WINDOW: [AtomicUsize; 3] = [ZERO; 3];
+/// 上一秒各类tick的百分比
+static PERCENT: [AtomicUsize; 3] = [ZERO; 3];
+/// 本秒内可运行进程数之和
+static SAMPLE_SUM: AtomicUsize = AtomicUsize::new(0);
+/// 本秒内已经采样的tick数
+static SAMPLES: AtomicUsize = AtomicUsize::new(0);
+/// 1/5/15分钟负载，定点数
+static LOADS: [AtomicUsize; 3] = [ZERO; 3];
+
+/// 空闲循环进入、离开hlt时调用
+pub fn set_idle(idle: bool) {
+    IDLE.store(idle, Ordering::Relaxed);
+}
+
+/// 判断被时钟打断的是哪类代码：用户态的是用户代码，停在空闲循环里的0号进程是空闲，其余都算内核
+pub fn classify(pid: usize, user_mode: bool) -> TickKind {
+    if user_mode {
+        TickKind::User
+    } else if pid == 0 && IDLE.load(Ordering::Relaxed) {
+        TickKind::Idle
+    } else {
+        TickKind::Kernel
+    }
+}
+
+/// 一步指数加权平均：load * exp + active * (1 - exp)，上升时向上取整，否则永远到不了目标值
+pub fn calc_load(load: usize, exp: usize, active: usize) -> usize {
+    let mut new = load * exp + active * (FIXED_1 - exp);
+    if active >= load {
+        new += FIXED_1 - 1;
+    }
+    new >> LOAD_SHIFT
+}
+
+/// 时钟中断每个tick调用一次
+pub fn tick(kind: TickKind, runnable: usize) {
+    TOTAL[kind as usize].fetch_add(1, Ordering::Relaxed);
+    WINDOW[kind as usize].fetch_add(1, Ordering::Relaxed);
+    let sum = SAMPLE_SUM.fetch_add(runnable, Ordering::Relaxed) + runnable;
+    let samples = SAMPLES.fetch_add(1, Ordering::Relaxed) + 1;
+    if samples < TICKS_PER_SECOND {
+        return;
+    }
+
+    let active = (sum << LOAD_SHIFT) / samples;
+    for (load, exp) in LOADS.iter().zip(EXP) {
+        load.store(calc_load(load.load(Ordering::Relaxed), exp, active), Ordering::Relaxed);
+    }
+    for (window, percent) in WINDOW.iter().zip(PERCENT.iter()) {
+        percent.store(window.swap(0, Ordering::Relaxed) * 100 / samples, Ordering::Relaxed);
+    }
+    SAMPLE_SUM.store(0, Ordering::Relaxed);
+    SAMPLES.store(0, Ordering::Relaxed);
+}
+
+/// 1/5/15分钟负载，定点数
+pub fn loads() -> [usize; 3] {
+    core::array::from_fn(|i| LOADS[i].load(Ordering::Relaxed))
+}
+
+/// 上一秒空闲、用户、内核各占的百分比
+pub fn percents() -> [usize; 3] {
+    core::array::from_fn(|i| PERCENT[i].load(Ordering::Relaxed))
+}
+
+/// 开机以来空闲、用户、内核各自的tick数
+pub fn totals() -> [usize; 3] {
+    core::array::from_fn(|i| TOTAL[i].load(Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::println;
+
+    use super::*;
+
+    #[test_case]
+    fn test_calc_load_converges() {
+        // 一直有一个可运行的进程，一分钟后1分钟负载超过1-1/e
+        let mut load = 0;
+        for _ in 0..60 {
+            load = calc_load(load, EXP[0], FIXED_1);
+        }
+        assert!(load > FIXED_1 * 63 / 100 && load <= FIXED_1);
+        // 足够久之后正好到1，空闲之后回落到0
+        for _ in 0..6000 {
+            load = calc_load(load, EXP[0], FIXED_1);
+        }
+        assert_eq!(load, FIXED_1);
+        for _ in 0..6000 {
+            load = calc_load(load, EXP[0], 0);
+        }
+        assert_eq!(load, 0);
+        println!("[ok]  Load average converges")
+    }
+}
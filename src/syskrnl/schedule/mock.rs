@@ -0,0 +1,177 @@
+//! 确定性的调度测试：用手动拨动的时钟代替PIT，一个Tick一个Tick地走
+//!
+//! 每个Tick调用的是和时钟中断同一个`schedule::tick`，只是时钟、定时器、调度器都是自己的一份，时间片记在机器上而不是进程表里
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use spin::Mutex;
+
+use crate::syskrnl::event::{self, EventQueue};
+use crate::syskrnl::schedule::roundroll::RoundRollScheduler;
+use crate::syskrnl::schedule::{self, ProcessScheduler};
+use crate::syskrnl::time::timer::{TimerAction, TimerQueue};
+use crate::syskrnl::time::TickSource;
+
+/// 只有调用`advance`才会走的时钟
+pub struct MockClock {
+    ticks: usize,
+}
+
+impl MockClock {
+    pub const fn new() -> Self {
+        Self { ticks: 0 }
+    }
+
+    /// 拨过`ticks`个Tick
+    pub fn advance(&mut self, ticks: usize) {
+        self.ticks += ticks;
+    }
+}
+
+impl TickSource for MockClock {
+    fn ticks(&self) -> usize {
+        self.ticks
+    }
+}
+
+/// 一台只有调度相关部件的模拟机器，开始时运行的是0号进程
+pub struct MockMachine {
+    pub clock: MockClock,
+    pub scheduler: Mutex<Box<RoundRollScheduler>>,
+    timers: Mutex<TimerQueue>,
+    events: EventQueue,
+    /// 时间片长度（tick）
+    quantum: usize,
+    /// 当前时间片还剩的tick
+    left: usize,
+    /// 正在运行的进程
+    current: usize,
+}
+
+impl MockMachine {
+    pub fn new(quantum: usize) -> Self {
+        Self {
+            clock: MockClock::new(),
+            scheduler: Mutex::new(Box::new(RoundRollScheduler::new())),
+            timers: Mutex::new(TimerQueue::new()),
+            events: EventQueue::new(),
+            quantum,
+            left: quantum,
+            current: 0,
+        }
+    }
+
+    /// 正在运行的进程
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// 加入一个可运行的进程
+    pub fn spawn(&mut self, pid: usize, nice: i8) {
+        self.scheduler.lock().add(pid, nice);
+    }
+
+    /// 走一个Tick，返回之后运行的进程
+    pub fn tick(&mut self) -> usize {
+        self.clock.advance(1);
+        let left = &mut self.left;
+        if let Some(next) = schedule::tick(&self.clock, &self.timers, &self.scheduler, || schedule::charge(left), || true) {
+            self.switch(next);
+        }
+        self.current
+    }
+
+    /// 走`ticks`个Tick，返回每个Tick之后运行的进程
+    pub fn run(&mut self, ticks: usize) -> Vec<usize> {
+        (0..ticks).map(|_| self.tick()).collect()
+    }
+
+    /// 正在运行的进程睡`ticks`个Tick，返回接下来运行的进程
+    pub fn sleep(&mut self, ticks: usize) -> usize {
+        let pid = self.current;
+        let next = self.scheduler.lock().wait();
+        self.timers.lock().push(self.clock.ticks() + ticks, TimerAction::Wakeup(pid));
+        self.switch(next);
+        next
+    }
+
+    /// 正在运行的进程等子进程`child`退出，同`RUN`
+    pub fn wait_child(&mut self, child: usize) -> usize {
+        let next = self.events.wait_in(&mut **self.scheduler.lock(), self.current, event::child_event(child));
+        self.switch(next);
+        next
+    }
+
+    /// 进程`pid`退出，返回被叫醒的父进程
+    pub fn exit(&mut self, pid: usize) -> Option<usize> {
+        self.scheduler.lock().remove(pid);
+        if self.current == pid {
+            let next = self.scheduler.lock().now();
+            self.switch(next);
+        }
+        let waiter = self.events.wakeup(event::child_event(pid))?;
+        self.scheduler.lock().wakeup(waiter);
+        Some(waiter)
+    }
+
+    fn switch(&mut self, next: usize) {
+        self.current = next;
+        self.left = self.quantum;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::println;
+    use crate::syskrnl::schedule::ProcessScheduler;
+    use crate::syskrnl::time::TickSource;
+
+    use super::MockMachine;
+
+    #[test_case]
+    fn test_mock_next_pick() {
+        let mut machine = MockMachine::new(2);
+        machine.spawn(1, 0);
+        machine.spawn(2, 0);
+        // 每个进程跑满两个Tick再轮到下一个，0号进程也在环里
+        assert_eq!(machine.run(8), [0, 1, 1, 2, 2, 0, 0, 1]);
+        println!("[ok]  mock scheduler picks in turn");
+    }
+
+    #[test_case]
+    fn test_mock_sleep_wakes_at_deadline() {
+        let mut machine = MockMachine::new(2);
+        machine.spawn(1, 0);
+        machine.spawn(2, 0);
+        machine.run(2);
+        assert_eq!(machine.current(), 1);
+        // 第2个Tick睡5个Tick，第7个Tick醒
+        assert_eq!(machine.sleep(5), 2);
+        for _ in 3..7 {
+            assert_ne!(machine.tick(), 1);
+            assert_eq!(machine.scheduler.lock().runnable(), 1);
+        }
+        machine.tick();
+        assert_eq!(machine.clock.ticks(), 7);
+        assert_eq!(machine.scheduler.lock().runnable(), 2);
+        assert!(machine.run(4).contains(&1));
+        println!("[ok]  mock sleep wakes at deadline tick");
+    }
+
+    #[test_case]
+    fn test_mock_wait_child() {
+        let mut machine = MockMachine::new(2);
+        machine.spawn(1, 0);
+        machine.spawn(2, 0);
+        machine.run(2);
+        assert_eq!(machine.wait_child(2), 2);
+        assert!(!machine.run(12).contains(&1));
+        assert_eq!(machine.exit(2), Some(1));
+        assert_eq!(machine.scheduler.lock().runnable(), 1);
+        assert!(machine.run(4).contains(&1));
+        // 没有人等的进程退出不叫醒谁
+        assert_eq!(machine.exit(1), None);
+        println!("[ok]  mock wait unblocks on child exit");
+    }
+}
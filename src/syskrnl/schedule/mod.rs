@@ -1,7 +1,19 @@
+pub mod loadavg;
+#[cfg(test)]
+pub mod mock;
 pub mod roundroll;
 
-use crate::syskrnl::proc::Process;
+use crate::debugln;
+use crate::syskrnl::proc::{self, Process};
+use crate::syskrnl::time::timer::{self, TimerQueue};
+use crate::syskrnl::time::TickSource;
+use crate::syskrnl::{config, time};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use core::fmt::Debug;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
 
 /// 进程调度器
 pub trait ProcessScheduler: Send + Debug {
@@ -23,4 +35,189 @@ pub trait ProcessScheduler: Send + Debug {
 
     /// 进程唤醒
     fn wakeup(&mut self, process: usize) -> usize;
+
+    /// 调整进程优先级
+    fn set_priority(&mut self, process: usize, nice: i8);
+
+    /// 可运行（没有在等待）的用户进程数，不算0号进程
+    fn runnable(&self) -> usize;
+}
+
+/// 默认时间片长度（毫秒），启动配置`sched.quantum_ms`可以修改
+pub const DEFAULT_QUANTUM_MS: usize = 10;
+/// 时间片长度上限（毫秒）
+pub const MAX_QUANTUM_MS: usize = 1000;
+
+/// 时间片长度（毫秒）
+static QUANTUM_MS: AtomicUsize = AtomicUsize::new(DEFAULT_QUANTUM_MS);
+
+/// 读取启动配置里的时间片长度
+pub fn init() {
+    if let Some(ms) = config::get_usize("sched.quantum_ms") {
+        if set_quantum_ms(ms).is_err() {
+            debugln!("sched: invalid sched.quantum_ms {}, using {}ms", ms, quantum_ms());
+        }
+    }
+}
+
+/// 设置时间片长度，从下一个时间片开始生效，正在运行的进程不受影响
+pub fn set_quantum_ms(ms: usize) -> Result<(), ()> {
+    if ms == 0 || ms > MAX_QUANTUM_MS {
+        return Err(());
+    }
+    QUANTUM_MS.store(ms, Ordering::SeqCst);
+    Ok(())
+}
+
+/// 时间片长度（毫秒）
+pub fn quantum_ms() -> usize {
+    QUANTUM_MS.load(Ordering::SeqCst)
+}
+
+/// 时间片长度（tick），至少为1
+pub fn quantum_ticks() -> usize {
+    (quantum_ms() * time::TICKS_PER_SECOND / 1000).max(1)
+}
+
+/// 从时间片里扣掉一个Tick，返回时间片是否用完
+pub fn charge(quantum_left: &mut usize) -> bool {
+    *quantum_left = quantum_left.saturating_sub(1);
+    *quantum_left == 0
+}
+
+/// 时钟每走一个Tick的调度工作，时钟中断和测试用的`mock::MockMachine`走的都是这里
+///
+/// 先按`clock`的时间执行`timers`里到期的定时器（包括进程的睡眠），再用`charge_tick`给正在运行的进程记一个Tick，
+/// 时间片用完并且`preempt`允许时让`scheduler`轮换。返回接下来运行的进程，不轮换时返回`None`，切换现场由调用者负责
+pub fn tick<S: ProcessScheduler + ?Sized>(
+    clock: &dyn TickSource,
+    timers: &Mutex<TimerQueue>,
+    scheduler: &Mutex<Box<S>>,
+    charge_tick: impl FnOnce() -> bool,
+    preempt: impl FnOnce() -> bool,
+) -> Option<usize> {
+    timer::run_expired(clock, timers, scheduler);
+    if charge_tick() && preempt() {
+        Some(scheduler.lock().timeup())
+    } else {
+        None
+    }
+}
+
+/// 关闭调度的总层数，时钟中断只看这个
+static DISABLE_DEPTH: AtomicUsize = AtomicUsize::new(0);
+/// 每个进程各自持有的层数：pid -> 层数
+static DISABLE_OWNERS: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+/// 当前进程关闭调度，可以嵌套
+pub fn disable() {
+    disable_for(proc::id());
+}
+
+/// 当前进程撤销一层关闭；所有层都撤销后才恢复调度
+///
+/// 进程只能撤销自己持有的层，不能替别人恢复调度
+pub fn enable() {
+    enable_for(proc::id());
+}
+
+/// 调度是否被关闭
+pub fn is_disabled() -> bool {
+    DISABLE_DEPTH.load(Ordering::SeqCst) > 0
+}
+
+fn disable_for(pid: usize) {
+    without_interrupts(|| {
+        *DISABLE_OWNERS.lock().entry(pid).or_insert(0) += 1;
+        DISABLE_DEPTH.fetch_add(1, Ordering::SeqCst);
+    })
+}
+
+fn enable_for(pid: usize) {
+    without_interrupts(|| {
+        let mut owners = DISABLE_OWNERS.lock();
+        if let Some(count) = owners.get_mut(&pid) {
+            *count -= 1;
+            if *count == 0 {
+                owners.remove(&pid);
+            }
+            DISABLE_DEPTH.fetch_sub(1, Ordering::SeqCst);
+        }
+    })
+}
+
+/// 进程退出时强制释放它持有的层数，返回释放的层数
+pub fn release(pid: usize) -> usize {
+    let count = without_interrupts(|| DISABLE_OWNERS.lock().remove(&pid).unwrap_or(0));
+    if count > 0 {
+        DISABLE_DEPTH.fetch_sub(count, Ordering::SeqCst);
+        debugln!("sched: pid {} exited with scheduling disabled ({} levels), re-enabling", pid, count);
+    }
+    count
+}
+
+/// 由时钟中断调用：关得太久时强行恢复调度。拿不到锁就下次再说
+pub fn force_enable() {
+    if let Some(mut owners) = DISABLE_OWNERS.try_lock() {
+        debugln!("sched: scheduling disabled for too long by {:?}, re-enabling", owners);
+        owners.clear();
+        DISABLE_DEPTH.store(0, Ordering::SeqCst);
+    }
+}
+
+/// 内核临界区用的守卫：创建时关闭调度，离开作用域时恢复
+pub struct SchedGuard {
+    pid: usize,
+}
+
+impl SchedGuard {
+    pub fn new() -> Self {
+        let pid = proc::id();
+        disable_for(pid);
+        Self { pid }
+    }
+}
+
+impl Drop for SchedGuard {
+    fn drop(&mut self) {
+        enable_for(self.pid);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{disable_for, enable_for, is_disabled, quantum_ms, quantum_ticks, release, set_quantum_ms, SchedGuard, DEFAULT_QUANTUM_MS};
+
+    #[test_case]
+    fn test_sched_disable_released_on_exit() {
+        // 一个进程STOP两次、RESTART一次后退出
+        let pid = 7;
+        disable_for(pid);
+        disable_for(pid);
+        enable_for(pid);
+        assert!(is_disabled());
+        // 别的进程不能替它恢复
+        enable_for(pid + 1);
+        assert!(is_disabled());
+        assert_eq!(release(pid), 1);
+        assert!(!is_disabled());
+
+        {
+            let _guard = SchedGuard::new();
+            assert!(is_disabled());
+        }
+        assert!(!is_disabled());
+        println!("[ok]  Schedule disable released on exit")
+    }
+
+    #[test_case]
+    fn test_set_quantum() {
+        assert!(set_quantum_ms(0).is_err());
+        assert!(set_quantum_ms(5000).is_err());
+        assert_eq!(quantum_ms(), DEFAULT_QUANTUM_MS);
+        set_quantum_ms(50).unwrap();
+        assert_eq!(quantum_ticks(), 50);
+        set_quantum_ms(DEFAULT_QUANTUM_MS).unwrap();
+        println!("[ok]  Schedule quantum setting")
+    }
 }
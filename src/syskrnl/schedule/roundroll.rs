@@ -5,6 +5,16 @@ use alloc::vec::Vec;
 use crate::syskrnl::proc::Process;
 use crate::syskrnl::schedule::ProcessScheduler;
 
+/// nice为0的进程每轮获得的时间片数
+const BASE_CREDITS: isize = 11;
+/// nice每变化1，每轮时间片数的变化量
+const CREDITS_PER_NICE: isize = 1;
+
+/// 计算每轮的时间片数，至少为1，保证不会饿死
+fn credits_of(nice: i8) -> usize {
+    (BASE_CREDITS - CREDITS_PER_NICE * nice as isize).max(1) as usize
+}
+
 #[derive(Debug)]
 struct RoundRollNode {
     /// pid
@@ -21,6 +31,12 @@ struct RoundRollNode {
 
     /// 指向上一任务的指针
     prev: usize,
+
+    /// 优先级
+    nice: i8,
+
+    /// 本轮剩余的时间片数
+    credits: usize,
 }
 
 /// 轮转算法
@@ -50,6 +66,8 @@ impl RoundRollNode {
             skip: false,
             next: 0,
             prev: 0,
+            nice: 0,
+            credits: credits_of(0),
         }
     }
 }
@@ -93,9 +111,13 @@ impl RoundRollScheduler {
     }
 
     /// 加入新进程
-    pub fn add(&mut self, process_id: usize) {
+    pub fn add(&mut self, process_id: usize, nice: i8) {
         let node = self.alloc();
         self.table[node].pid = process_id;
+        self.table[node].empty = false;
+        self.table[node].skip = false;
+        self.table[node].nice = nice;
+        self.table[node].credits = credits_of(nice);
         let prev = self.table[self.head].prev;
         self.table[prev].next = node;
         self.table[self.head].prev = node;
@@ -130,11 +152,37 @@ impl RoundRollScheduler {
         }
         self.now()
     }
+
+    /// 向后进一步，跳过本轮时间片已用完的进程，所有可运行进程都用完时开始新的一轮
+    pub fn step_weighted(&mut self) -> usize {
+        let start = self.cursor;
+        let mut cursor = self.table[start].next;
+        loop {
+            let node = &self.table[cursor];
+            if !node.skip && node.credits > 0 {
+                self.cursor = cursor;
+                return self.now();
+            }
+            if cursor == start {
+                break;
+            }
+            cursor = node.next;
+        }
+        self.refill();
+        self.step()
+    }
+
+    /// 开始新的一轮，重新发放时间片
+    fn refill(&mut self) {
+        for node in self.table.iter_mut().filter(|node| !node.empty) {
+            node.credits = credits_of(node.nice);
+        }
+    }
 }
 
 impl ProcessScheduler for RoundRollScheduler {
     fn add(&mut self, process: Process, _priority: u32) -> usize {
-        self.add(process.id);
+        self.add(process.id, process.nice);
         self.cursor = *self.map.get(&process.id).unwrap();
         self.now()
     }
@@ -145,11 +193,13 @@ impl ProcessScheduler for RoundRollScheduler {
     }
 
     fn timeup(&mut self) -> usize {
-        self.step()
+        let node = &mut self.table[self.cursor];
+        node.credits = node.credits.saturating_sub(1);
+        self.step_weighted()
     }
 
     fn giveup(&mut self) -> usize {
-        self.step()
+        self.step_weighted()
     }
 
     fn wait(&mut self) -> usize {
@@ -164,4 +214,53 @@ impl ProcessScheduler for RoundRollScheduler {
         }
         self.now()
     }
+
+    fn set_priority(&mut self, process: usize, nice: i8) {
+        if let Some(node) = self.map.get(&process) {
+            let node = &mut self.table[*node];
+            node.nice = nice;
+            node.credits = node.credits.min(credits_of(nice));
+        }
+    }
+
+    fn runnable(&self) -> usize {
+        self.table.iter().filter(|node| !node.empty && !node.skip && node.pid != 0).count()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::println;
+
+    use super::*;
+
+    #[test_case]
+    fn test_weighted_round_roll() {
+        let mut scheduler = RoundRollScheduler::new();
+        scheduler.add(1, -10);
+        scheduler.add(2, 10);
+        let mut counts = [0usize; 3];
+        for _ in 0..330 {
+            counts[ProcessScheduler::timeup(&mut scheduler)] += 1;
+        }
+        assert!(counts[2] > 0);
+        assert!(counts[1] > counts[0]);
+        assert!(counts[0] > counts[2]);
+        println!("[ok]  weighted round roll scheduler");
+    }
+
+    #[test_case]
+    fn test_runnable_skips_waiting() {
+        let mut scheduler = RoundRollScheduler::new();
+        scheduler.add(1, 0);
+        scheduler.add(2, 0);
+        assert_eq!(ProcessScheduler::runnable(&scheduler), 2);
+        scheduler.cursor = scheduler.map[&1];
+        ProcessScheduler::wait(&mut scheduler);
+        assert_eq!(ProcessScheduler::runnable(&scheduler), 1);
+        ProcessScheduler::wakeup(&mut scheduler, 1);
+        scheduler.remove(2);
+        assert_eq!(ProcessScheduler::runnable(&scheduler), 1);
+        println!("[ok]  round roll runnable count");
+    }
 }
@@ -1,6 +1,8 @@
+use serde::Serialize;
 use x86_64::instructions::interrupts;
 
 use cinea_os_sysapi::call::*;
+use cinea_os_sysapi::envelope::Response;
 use cinea_os_sysapi::ExitCode;
 
 /// 系统调用
@@ -14,9 +16,8 @@ pub fn dispatcher(syscall_id: usize, arg1: usize, arg2: usize, arg3: usize, arg4
         EXIT => service::exit(ExitCode::from(arg1)),
         SPAWN => service::spawn(arg1, arg2, arg3, arg4) as usize,
         INFO => service::info(arg1),
-        DUP => unimplemented!(),
-        DELETE => unimplemented!(),
-        STOP => unimplemented!(),
+        DUP => service::dup(arg1),
+        DELETE => service::delete(arg1),
         SLEEP => {
             service::sleep(f64::from_bits(arg1 as u64));
             0
@@ -44,6 +45,17 @@ pub fn dispatcher(syscall_id: usize, arg1: usize, arg2: usize, arg3: usize, arg4
         WRITE_PATH => service::write_path(arg1),
         READ_PATH => service::read_path(arg1),
         SPAWN_FROM_PATH => service::spawn_from_path(arg1),
+        SPAWN_REDIRECTED => service::spawn_redirected(arg1),
+        RUN => service::run(arg1),
+        EXEC => service::exec(arg1),
+        SEEK => service::seek(arg1, arg2, arg3),
+        FCNTL => service::fcntl(arg1, arg2, arg3),
+        POLL => service::poll(arg1, arg2, arg3),
+        MSGQ_CREATE => service::msgq_create(arg1),
+        MSGQ_SEND => service::msgq_send(arg1, arg2, arg3, arg4),
+        MSGQ_RECV => service::msgq_recv(arg1, arg2, arg3),
+        HEAP_COMPACT => service::heap_compact(arg1, arg2),
+        WRITEV => service::writev(arg1, arg2, arg3),
         CREATE_WINDOW => service::create_window(arg1),
         DISPLAY_FONT_STRING => service::display_font_string(arg1),
         LOAD_FONT => service::load_font(arg1),
@@ -51,8 +63,39 @@ pub fn dispatcher(syscall_id: usize, arg1: usize, arg2: usize, arg3: usize, arg4
         REGISTER_TIMER => service::register_timer(arg1),
         GUI_SUBSCRIBE_TIME_UPDATE => service::gui_time_update_register(),
         READ_TIME => service::read_time(),
+        SETPRIORITY => service::set_priority(arg1, arg2),
+        SETPGID => service::set_pgid(arg1, arg2),
+        TCSETPGRP => service::set_foreground(arg1),
+        SELFTEST => service::selftest(),
+        STATUSBAR => service::set_status_bar(arg1),
+        MEMINFO => service::meminfo(),
+        UPTIME => service::uptime_ns(),
+        REBOOT => service::reboot(),
+        SHUTDOWN => service::shutdown(),
+        REALTIME => service::realtime(),
+        SETQUANTUM => service::set_quantum(arg1),
+        RUSAGE => service::rusage(),
+        IRQSTAT => service::irqstat(),
+        SYSINFO => service::sysinfo(),
+        TIMES => service::times(),
+        KILL => service::kill(arg1),
+        SET_PROC_NAME => service::set_proc_name(arg1),
+        PROC_LIST => service::proc_list(arg1),
+        PAGEMAP => service::pagemap(arg1),
+        STACKSIZE => service::stack_size(),
+        SETRLIMIT => service::set_limits(arg1),
+        GETRLIMIT => service::limits(),
+        KEYMODE => service::set_key_mode(arg1),
+        READKEY => service::read_key(arg1),
+        FLUSH => service::flush(),
+        CONSOLE_BUFFERED => service::set_console_buffered(arg1),
+        GET_PROC_NAME => service::get_proc_name(arg1),
+        IMAGEINFO => service::image_info(arg1),
+        ATEXIT_SET => service::atexit_set(arg1),
+        FAULT_HANDLER_SET => service::fault_handler_set(arg1),
+        MPROTECT => service::mprotect(arg1, arg2, arg3),
         GUI_SUBSCRIBE_KEYBOARD => service::gui_time_update_register(),
-        _ => panic!("unknown syscall id: {}", syscall_id),
+        _ => service::no_such_syscall(syscall_id),
     })
 }
 
@@ -63,20 +106,66 @@ macro_rules! syscall_serialized_ret {
             cinea_os_sysapi::call::syscall_serialized($($arg)*)
         }else{
             let allocator = $crate::syskrnl::proc::heap_allocator().clone();
-            cinea_os_sysapi::call::syscall_serialized_for_userspace($($arg)*, |x| unsafe { allocator.lock().alloc(x) })
+            cinea_os_sysapi::call::syscall_serialized_for_userspace($($arg)*, |x| unsafe { allocator.lock_halting().alloc(x) })
         }
     };
 }
 
+/// 读出并解开用户进程发来的参数，失败时直接返回
+///
+/// 只给指针时回复`Err(FileError)`；回复类型不是`Result<_, FileError>`的调用要给出自己的失败回复
 #[macro_export]
 macro_rules! syscall_deserialize {
+    ($ptr:expr, $err:expr) => {{
+        use cinea_os_sysapi::call::syscall_deserialized;
+        match $crate::syskrnl::uaccess::read_serialized($ptr) {
+            Ok(vec_data) => match syscall_deserialized(&vec_data) {
+                Ok(obj) => obj,
+                Err(_) => return $err,
+            },
+            Err(_) => return $err,
+        }
+    }};
     ($ptr:expr) => {{
-        use cinea_os_sysapi::call::{syscall_deserialized, syscall_deserialized_prepare};
-        let vec_data = syscall_deserialized_prepare($ptr);
-        syscall_deserialized(&vec_data).unwrap()
+        use cinea_os_sysapi::call::syscall_deserialized;
+        use cinea_os_sysapi::fs::FileError;
+        match $crate::syskrnl::uaccess::read_serialized($ptr) {
+            Ok(vec_data) => match syscall_deserialized(&vec_data) {
+                Ok(obj) => obj,
+                // 参数解不出来也不能在内核里panic
+                Err(_) => return $crate::syscall_serialized_ret!(&Err::<(), FileError>(FileError::MalformedError)),
+            },
+            // 参数不可读时直接返回错误，文件系统调用的`Result`都能按`FileError`解出来
+            Err(_) => return $crate::syscall_serialized_ret!(&Err::<(), FileError>(FileError::BadAddressError)),
+        }
     }};
 }
 
+/// 读出用户进程发来的`Request`信封，版本不对、参数不可读或解不出来时直接回复错误状态
+#[macro_export]
+macro_rules! syscall_request {
+    ($ptr:expr) => {{
+        use cinea_os_sysapi::envelope::{decode_request, STATUS_BAD_ADDRESS};
+        match $crate::syskrnl::uaccess::read_serialized($ptr) {
+            Ok(vec_data) => match decode_request(vec_data.as_slice()) {
+                Ok(body) => body,
+                Err(err) => return $crate::syskrnl::syscall::kernel_reply_err(err.status()),
+            },
+            Err(_) => return $crate::syskrnl::syscall::kernel_reply_err(STATUS_BAD_ADDRESS),
+        }
+    }};
+}
+
+/// 成功回复，带上结果
+pub fn kernel_reply_ok<T: Serialize>(body: T) -> usize {
+    syscall_serialized_ret!(&Response::ok(body))
+}
+
+/// 失败回复，只有状态
+pub fn kernel_reply_err(status: i32) -> usize {
+    syscall_serialized_ret!(&Response::<()>::err(status))
+}
+
 #[cfg(test)]
 mod test {
     use alloc::vec::Vec;
@@ -106,4 +195,40 @@ mod test {
         assert_eq!(obj, obj2);
         println!("[ok]  System Call test_serde")
     }
+
+    #[test_case]
+    fn test_unknown_syscall() {
+        use cinea_os_sysapi::call::{NO_SUCH_SYSCALL, STOP};
+
+        assert_eq!(super::dispatcher(0xFFFF, 0, 0, 0, 0), NO_SUCH_SYSCALL);
+        // 定义了号码但没有实现的也一样
+        assert_eq!(super::dispatcher(STOP, 0, 0, 0, 0), NO_SUCH_SYSCALL);
+        println!("[ok]  System Call unknown number")
+    }
+
+    #[test_case]
+    fn test_envelope_round_trip() {
+        use cinea_os_sysapi::envelope::*;
+
+        let obj = TestUse { a: 100, b: 20 };
+        let bytes = postcard::to_allocvec(&Request::new(&obj)).unwrap();
+        assert_eq!(decode_request::<TestUse>(&bytes), Ok(TestUse { a: 100, b: 20 }));
+        let bytes = postcard::to_allocvec(&Response::ok(&obj)).unwrap();
+        assert_eq!(decode_response::<TestUse>(&bytes), Ok(TestUse { a: 100, b: 20 }));
+        let bytes = postcard::to_allocvec(&Response::<()>::err(STATUS_MALFORMED)).unwrap();
+        assert_eq!(decode_response::<TestUse>(&bytes), Err(EnvelopeError::Status(STATUS_MALFORMED)));
+
+        // 不认识的版本在解析内容之前就被拒绝
+        let bytes = postcard::to_allocvec(&Request { version: ENVELOPE_VERSION + 1, body: "whatever" }).unwrap();
+        assert_eq!(decode_request::<TestUse>(&bytes), Err(EnvelopeError::BadVersion(ENVELOPE_VERSION + 1)));
+
+        // 截断或改坏的数据只返回错误，不会panic
+        let bytes = postcard::to_allocvec(&Request::new(&obj)).unwrap();
+        assert_eq!(decode_request::<TestUse>(&bytes[..bytes.len() - 1]), Err(EnvelopeError::Malformed));
+        assert_eq!(decode_request::<TestUse>(&[]), Err(EnvelopeError::Malformed));
+        let mut bad = bytes.clone();
+        bad[1] = 0xFF;
+        assert!(decode_request::<TestUse>(&bad).is_err());
+        println!("[ok]  System Call envelope round trip")
+    }
 }
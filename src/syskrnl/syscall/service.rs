@@ -1,129 +1,586 @@
 use alloc::string::String;
+use alloc::vec;
 use alloc::vec::Vec;
-use core::ptr::slice_from_raw_parts_mut;
-use core::sync::atomic::Ordering;
 
 use embedded_graphics::pixelcolor::raw::RawU24;
 use embedded_graphics::pixelcolor::Rgb888;
 
-use cinea_os_sysapi::fs::read_all_from_path;
+#[cfg(not(feature = "heap_debug"))]
+use cinea_os_kcore::heap::Movable;
+use cinea_os_kcore::heap::RegionProvider;
+use cinea_os_sysapi::call::NO_SUCH_SYSCALL;
+use cinea_os_sysapi::envelope::STATUS_PERMISSION;
+use cinea_os_sysapi::fs::{FileError, IoVec, OpenFlags, PollFd, MAX_IOV, MAX_IOV_BYTES, MAX_POLL_FDS};
 use cinea_os_sysapi::gui::WindowGraphicMemory;
-use cinea_os_sysapi::syscall::PanicInfo;
+use cinea_os_sysapi::keyboard::KeyFlags;
+use cinea_os_sysapi::msgq::{MsgFlags, MsgqError, MAX_MSG_SIZE};
+use cinea_os_sysapi::syscall::{Compaction, Limits, MemInfo, PanicInfo, ProtFlags, SpawnFlags, SysInfo};
 use cinea_os_sysapi::time::{Date, DateTime, Time};
 use cinea_os_sysapi::ExitCode;
 
+#[cfg(feature = "heap_debug")]
+use crate::syskrnl::allocator::heap_guard;
+use crate::syskrnl::allocator::HeapAllocator;
 use crate::syskrnl::event::{EVENT_QUEUE, GUI_EID_START};
 use crate::syskrnl::gui::{font, WINDOW_MANAGER};
-use crate::syskrnl::proc::Process;
+use crate::syskrnl::proc::{Access, Process};
+use crate::syskrnl::schedule::{loadavg, SchedGuard};
 use crate::syskrnl::task::keyboard;
-use crate::syskrnl::{clock, event, proc};
-use crate::{debugln, print, println, syscall_deserialize, syscall_serialized_ret, syskrnl};
+use crate::syskrnl::{clock, event, msgq, proc, uaccess};
+use crate::{debugln, println, syscall_deserialize, syscall_request, syscall_serialized_ret, syskrnl};
 
-pub fn exit(_code: ExitCode) -> usize {
+use super::{kernel_reply_err, kernel_reply_ok};
+
+/// 注册过退出处理函数的话先跳过去，返回的还是当前进程；被要求结束的进程不再运行它
+pub fn exit(code: ExitCode) -> usize {
+    if !proc::terminate_pending() && proc::run_atexit(code as usize) {
+        return proc::id();
+    }
+    syskrnl::io::console_flush();
+    proc::set_exit_code(code);
     syskrnl::proc::exit()
 }
 
+/// 修改当前进程一段内存的访问权限
+pub fn mprotect(addr: usize, len: usize, prot: usize) -> usize {
+    match proc::mprotect(addr as u64, len, ProtFlags::from_bits_truncate(prot as u32)) {
+        Ok(()) => ExitCode::Success as usize,
+        Err(code) => code as usize,
+    }
+}
+
+/// 注册致命异常处理函数
+pub fn fault_handler_set(addr: usize) -> usize {
+    if proc::set_fault_handler(addr as u64) {
+        ExitCode::Success as usize
+    } else {
+        ExitCode::Failure as usize
+    }
+}
+
+/// 注册退出处理函数
+pub fn atexit_set(addr: usize) -> usize {
+    if proc::set_atexit(addr as u64) {
+        ExitCode::Success as usize
+    } else {
+        ExitCode::Failure as usize
+    }
+}
+
 pub fn sleep(seconds: f64) {
     syskrnl::time::sleep(seconds);
 }
 
+/// 未知的系统调用号，记下号码和进程，内核和用户程序的ABI对不上时方便排查
+pub fn no_such_syscall(n: usize) -> usize {
+    debugln!("syscall: unknown number {:#x} from pid {}", n, proc::id());
+    NO_SUCH_SYSCALL
+}
+
 /// FIXME 在未来，要改正。现在是测试用途
 pub fn spawn(number: usize, args_ptr: usize, args_len: usize, args_cap: usize) -> ExitCode {
     debugln!("{:#x},{}", args_ptr, args_len);
-    let subprocess: &[u8] = match number {
-        0x00 => include_bytes!("../../../dsk/bin/hello"),
-        0x01 => include_bytes!("../../../dsk/bin/infprint"),
-        0x02 => include_bytes!("../../../dsk/bin/taffy"),
+    if !args_readable(args_ptr, args_len) {
+        return ExitCode::ExecError;
+    }
+    let path = match number {
+        0x00 => "/bin/hello",
+        0x01 => "/bin/infprint",
+        0x02 => "/bin/taffy",
         _ => {
             println!("spawn: invalid number");
             return ExitCode::OpenError;
         }
     };
-    if let Err(code) = Process::spawn(subprocess, args_ptr, args_len, args_cap) {
+    let subprocess = match syskrnl::fs::resolve(path).and_then(|path| syskrnl::fs::read_to_end(path.as_str())) {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            println!("spawn: cannot read {}", path);
+            return ExitCode::ReadError;
+        }
+    };
+    if let Err(code) = Process::spawn(path, subprocess.as_slice(), args_ptr, args_len, args_cap, SpawnFlags::empty(), None, None, Vec::new(), false) {
         code
     } else {
         ExitCode::Success
     }
 }
 
+/// 检查调用者给出的参数数组和每个参数都在它自己的内存里，`Process::exec`会直接按这些指针读
+fn args_readable(args_ptr: usize, args_len: usize) -> bool {
+    let pid = proc::id();
+    let pair = core::mem::size_of::<(usize, usize)>();
+    let pairs = match args_len.checked_mul(pair).map(|size| proc::translate_user_ptr(pid, args_ptr as u64, size, Access::Read)) {
+        Some(Ok(ptr)) => ptr as *const (usize, usize),
+        _ => return false,
+    };
+    (0..args_len).all(|i| {
+        let mut raw = [0u8; 2 * core::mem::size_of::<usize>()];
+        if uaccess::copy_from_user(&mut raw, unsafe { pairs.add(i) } as u64).is_err() {
+            return false;
+        }
+        let (ptr, len) = raw.split_at(core::mem::size_of::<usize>());
+        let (ptr, len) = (usize::from_ne_bytes(ptr.try_into().unwrap()), usize::from_ne_bytes(len.try_into().unwrap()));
+        proc::translate_user_ptr(pid, ptr as u64, len, Access::Read).is_ok()
+    })
+}
+
 pub fn spawn_from_path(ptr: usize) -> usize {
-    let obj: (String, Vec<String>) = syscall_deserialize!(ptr);
+    let obj: (String, Vec<String>, u32, Option<Limits>, Option<String>) = syscall_deserialize!(ptr, syscall_serialized_ret!(&false));
+    syscall_serialized_ret!(&spawn_path(obj.0.as_str(), obj.1, obj.2, obj.3, obj.4, &[], false))
+}
 
-    if let Ok(program_bytes) = read_all_from_path(obj.0.as_str()) {
+/// 和`spawn_from_path`一样，只是子进程的系统句柄号换成调用者的句柄
+pub fn spawn_redirected(ptr: usize) -> usize {
+    let obj: (String, Vec<String>, u32, Option<Limits>, Option<String>, Vec<(usize, usize)>) =
+        syscall_deserialize!(ptr, syscall_serialized_ret!(&false));
+    syscall_serialized_ret!(&spawn_path(obj.0.as_str(), obj.1, obj.2, obj.3, obj.4, obj.5.as_slice(), false))
+}
+
+/// 创建子进程并一直等到它退出，返回它的退出代码
+///
+/// 成功创建时不会从这里返回：调用者睡下，子进程退出时把退出代码写进调用者保存的rax
+pub fn run(ptr: usize) -> usize {
+    let obj: (String, Vec<String>, u32, Option<Limits>) = syscall_deserialize!(ptr, ExitCode::ExecError as usize);
+    spawn_path(obj.0.as_str(), obj.1, obj.2, obj.3, None, &[], true);
+    ExitCode::ExecError as usize
+}
+
+fn spawn_path(
+    path: &str, args: Vec<String>, flags: u32, limits: Option<Limits>, jail: Option<String>, redirects: &[(usize, usize)], wait: bool,
+) -> bool {
+    let flags = SpawnFlags::from_bits_truncate(flags);
+    if (flags.contains(SpawnFlags::UNKILLABLE) && !proc::is_root()) || (flags.contains(SpawnFlags::FOREGROUND) && !proc::in_foreground(proc::id())) {
+        return false;
+    }
+    // 只有root能把子进程关起来；目录按调用者自己的根目录解析，所以关着的进程只能关得更小
+    let jail = match jail {
+        None => None,
+        Some(_) if !proc::is_root() => return false,
+        Some(dir) => match syskrnl::fs::resolve(dir.as_str()).and_then(|dir| syskrnl::fs::metadata(dir.as_str()).map(|meta| (dir, meta))) {
+            Ok((dir, meta)) if meta.is_dir() => Some(dir),
+            _ => return false,
+        },
+    };
+    // 在创建子进程之前取出句柄，句柄号不对时什么都还没有发生
+    let redirects = match syskrnl::fs::redirections(redirects) {
+        Ok(redirects) => redirects,
+        Err(_) => return false,
+    };
+
+    if let Ok(program_bytes) = syskrnl::fs::resolve(path).and_then(|path| syskrnl::fs::read_to_end(path.as_str())) {
         // 为了兼容旧代码，姑且做一层转换吧
-        let trans_args: Vec<_> = obj.1.iter().map(|x| (x.as_ptr() as usize, x.len())).collect();
+        let trans_args: Vec<_> = args.iter().map(|x| (x.as_ptr() as usize, x.len())).collect();
         let (a, b, c) = trans_args.into_raw_parts();
 
-        if let Err(_) = Process::spawn(program_bytes.as_slice(), a as usize, b, c) {
-            syscall_serialized_ret!(&false)
-        } else {
-            syscall_serialized_ret!(&true)
-        }
+        Process::spawn(path, program_bytes.as_slice(), a as usize, b, c, flags, limits, jail.as_deref(), redirects, wait).is_ok()
     } else {
-        syscall_serialized_ret!(&false)
+        false
+    }
+}
+
+/// 用另一个程序替换当前进程，成功时返回0：旧映像已经没了，这个返回值不会有人看到
+pub fn exec(ptr: usize) -> usize {
+    let obj: (String, Vec<String>) = syscall_deserialize!(ptr, syscall_serialized_ret!(&false));
+    let program_bytes = match syskrnl::fs::resolve(obj.0.as_str()).and_then(|path| syskrnl::fs::read_to_end(path.as_str())) {
+        Ok(bytes) => bytes,
+        Err(_) => return syscall_serialized_ret!(&false),
+    };
+    let args: Vec<&str> = obj.1.iter().map(|arg| arg.as_str()).collect();
+    match Process::exec_replace(obj.0.as_str(), program_bytes.as_slice(), args.as_slice()) {
+        Ok(()) => 0,
+        Err(_) => syscall_serialized_ret!(&false),
     }
 }
 
 pub fn log(msg: usize, len: usize) -> usize {
-    let ptr = syskrnl::proc::ptr_from_addr(msg as u64); // cnmd不看人家源码根本想不到
-                                                        //debugln!("log: ptr:{:p} ori_ptr:{:#x}",ptr,msg);
-    let msg = unsafe { core::slice::from_raw_parts(ptr, len) };
-    match core::str::from_utf8(msg) {
+    let ptr = match proc::translate_user_ptr(proc::id(), msg as u64, len, Access::Read) {
+        Ok(ptr) => ptr,
+        Err(_) => return usize::MAX,
+    };
+    let mut msg = vec![0u8; len];
+    if uaccess::copy_from_user(&mut msg, ptr as u64).is_err() {
+        return usize::MAX;
+    }
+    match core::str::from_utf8(&msg) {
         Err(_) => {
             println!("log: invalid utf8 string");
             1
         }
         Ok(s) => {
-            print!("{}", s);
+            syskrnl::io::console_write(s);
             0
         }
     }
 }
 
+/// 分配用户堆内存，失败时返回0（空指针）
 pub fn alloc(size: usize, align: usize) -> usize {
     // debugln!("ALLOC proc_id:{}",syskrnl::proc::id());
-    let allocator = syskrnl::proc::heap_allocator();
-    if allocator.lock().free_space() < size {
-        // 需要生长，计算生长的大小
-        let grow_size = size - allocator.lock().free_space();
-        // 对齐到页的4KB
-        let grow_size = (grow_size + 0xfff) & !0xfff;
-        // 生长
-        syskrnl::proc::allocator_grow(grow_size);
-    }
-    let ptr = unsafe {
-        allocator
-            .lock()
-            .alloc(core::alloc::Layout::from_size_align(size, align).expect("proc mem alloc fail 5478"))
+    let layout = match core::alloc::Layout::from_size_align(size, align) {
+        Ok(layout) => layout,
+        Err(_) => return 0,
+    };
+    alloc_block(layout)
+}
+
+#[cfg(not(feature = "heap_debug"))]
+fn alloc_block(layout: core::alloc::Layout) -> usize {
+    alloc_layout(layout)
+}
+
+/// 调试模式下多分配一点，在区块前后放好金丝雀
+#[cfg(feature = "heap_debug")]
+fn alloc_block(layout: core::alloc::Layout) -> usize {
+    let (inner, _) = match heap_guard::guarded_layout(layout) {
+        Some(guarded) => guarded,
+        None => return 0,
     };
+    match alloc_layout(inner) {
+        0 => 0,
+        base => unsafe { heap_guard::arm(base as *mut u8, layout) as usize },
+    }
+}
+
+fn alloc_layout(layout: core::alloc::Layout) -> usize {
+    let allocator = syskrnl::proc::heap_allocator();
+    #[cfg(feature = "heap_debug")]
+    if let Err(corruption) = heap_guard::check_free_list(&allocator.lock_halting(), &proc::heap_regions()) {
+        heap_guard::report(corruption);
+        return 0;
+    }
+    // 空闲空间不够时生长，内存耗尽时返回空指针，由用户程序自己处理
+    let ptr = unsafe { allocator.lock_halting().alloc_or_grow(layout, &mut UserHeapPages) };
     ptr as usize
 }
 
+/// 用户堆不够时映射新的页面
+struct UserHeapPages;
+
+impl RegionProvider for UserHeapPages {
+    fn provide(&mut self, min_size: usize) -> Option<(usize, usize)> {
+        // 对齐到页的4KB
+        let size = (min_size + 0xfff) & !0xfff;
+        syskrnl::proc::allocator_grow(size).ok().map(|addr| (addr, size))
+    }
+}
+
 pub fn free(ptr: usize, size: usize, align: usize) {
+    // 和alloc一样，坏的大小或对齐不能让内核panic，这样的区块本来也不可能分配出去
+    let layout = match core::alloc::Layout::from_size_align(size, align) {
+        Ok(layout) => layout,
+        Err(_) => return,
+    };
     let allocator = syskrnl::proc::heap_allocator();
+    #[cfg(feature = "heap_debug")]
+    let (ptr, layout) = match heap_guard::check_free_list(&allocator.lock_halting(), &proc::heap_regions())
+        .and_then(|_| unsafe { heap_guard::disarm(ptr as *mut u8, layout) })
+    {
+        Ok((base, inner)) => (base as usize, inner),
+        Err(corruption) => {
+            heap_guard::report(corruption);
+            return;
+        }
+    };
     unsafe {
-        let mut lock = allocator.lock();
-        lock.dealloc(
-            ptr as *mut u8,
-            core::alloc::Layout::from_size_align(size, align).expect("proc layout fail 5472"),
-        )
+        let mut lock = allocator.lock_halting();
+        lock.dealloc(ptr as *mut u8, layout)
+    }
+}
+
+/// 一次压缩最多登记的区块数
+const MAX_COMPACT_BLOCKS: usize = 4096;
+
+pub fn heap_compact(table: usize, count: usize) -> usize {
+    syscall_serialized_ret!(&compact_heap(table, count))
+}
+
+/// 调试模式下区块前后有金丝雀，用户登记的布局和堆里真实的对不上，不支持压缩
+#[cfg(feature = "heap_debug")]
+fn compact_heap(_table: usize, _count: usize) -> Option<Compaction> {
+    None
+}
+
+#[cfg(not(feature = "heap_debug"))]
+fn compact_heap(table: usize, count: usize) -> Option<Compaction> {
+    if count > MAX_COMPACT_BLOCKS {
+        return None;
+    }
+    let bytes = count * core::mem::size_of::<Movable>();
+    // 先确认表能写回，免得搬完了才发现没法告诉用户新地址
+    let ptr = proc::translate_user_ptr(proc::id(), table as u64, bytes, Access::Write).ok()? as u64;
+    let mut blocks = vec![Movable::default(); count];
+    // Movable全是usize，任何字节都是合法的值
+    let raw = unsafe { core::slice::from_raw_parts_mut(blocks.as_mut_ptr() as *mut u8, bytes) };
+    uaccess::copy_from_user(raw, ptr).ok()?;
+    // 分配器只认自己的空闲链表，登记的区块必须整个在调用者的堆里，否则会搬动堆外面的内存
+    if !blocks.iter().all(|block| proc::in_heap(block.ptr as u64, block.size)) {
+        return None;
+    }
+
+    let allocator = proc::heap_allocator();
+    let mut heap = allocator.lock_halting();
+    let moved = unsafe { heap.compact(&mut blocks) }.ok()?;
+    let tails: Vec<(usize, usize)> = proc::heap_regions()
+        .iter()
+        .filter_map(|&(start, size)| {
+            let end = start as usize + size;
+            heap.release_tail(end, 0x1000).map(|cut| (cut, end - cut))
+        })
+        .collect();
+    drop(heap);
+
+    let raw = unsafe { core::slice::from_raw_parts(blocks.as_ptr() as *const u8, bytes) };
+    if uaccess::copy_to_user(ptr, raw).is_err() {
+        debugln!("heap_compact: pid {} lost its handle table", proc::id());
+    }
+    let released = tails.iter().filter(|&&(addr, size)| proc::heap_release(addr as u64, size).is_ok()).map(|(_, size)| size).sum();
+    Some(Compaction { moved, released })
+}
+
+pub fn set_priority(pid: usize, nice: usize) -> usize {
+    let nice = nice as isize;
+    if nice < proc::MIN_NICE as isize || nice > proc::MAX_NICE as isize {
+        return ExitCode::UsageError as usize;
+    }
+    match proc::set_nice(pid, nice as i8) {
+        Ok(()) => ExitCode::Success as usize,
+        Err(code) => code as usize,
+    }
+}
+
+pub fn set_pgid(pid: usize, pgid: usize) -> usize {
+    match proc::set_pgid(pid, pgid) {
+        Ok(()) => ExitCode::Success as usize,
+        Err(code) => code as usize,
+    }
+}
+
+pub fn set_foreground(pgid: usize) -> usize {
+    match proc::set_foreground_group(pgid) {
+        Ok(()) => ExitCode::Success as usize,
+        Err(code) => code as usize,
+    }
+}
+
+/// 运行内核自检，期间停止调度，结束后恢复原来的调度状态
+pub fn selftest() -> usize {
+    if !proc::is_root() {
+        return ExitCode::PermissionError as usize;
+    }
+    let guard = SchedGuard::new();
+    let passed = syskrnl::allocator::test_allocator() && proc::selftest_create_exit();
+    drop(guard);
+    if passed {
+        ExitCode::Success as usize
+    } else {
+        ExitCode::Failure as usize
+    }
+}
+
+/// 启动以来的纳秒数，见`cinea_os_sysapi::vdso`
+pub fn uptime_ns() -> usize {
+    syskrnl::vdso::now_ns() as usize
+}
+
+/// 当前的Unix时间戳（秒），来自CMOS RTC
+pub fn realtime() -> usize {
+    syskrnl::time::unix_timestamp() as usize
+}
+
+/// 物理内存和内核堆的使用情况
+pub fn meminfo() -> usize {
+    let allocator = syskrnl::allocator::ALLOCATOR.lock_irqsave();
+    let (heap_total, heap_used) = (allocator.size(), allocator.allocated());
+    drop(allocator);
+    syscall_serialized_ret!(&MemInfo {
+        total: syskrnl::memory::total_memory(),
+        free: syskrnl::memory::free_memory(),
+        heap_total,
+        heap_used,
+    })
+}
+
+/// 重启，仅限root，成功时不返回
+pub fn reboot() -> usize {
+    if !proc::is_root() {
+        return ExitCode::PermissionError as usize;
+    }
+    syskrnl::power::reboot()
+}
+
+/// 关机，仅限root，成功时不返回
+pub fn shutdown() -> usize {
+    if !proc::is_root() {
+        return ExitCode::PermissionError as usize;
+    }
+    syskrnl::power::shutdown()
+}
+
+/// 开关内核状态栏，仅限root
+pub fn set_status_bar(on: usize) -> usize {
+    if !proc::is_root() {
+        return ExitCode::PermissionError as usize;
+    }
+    syskrnl::gui::status_bar::set_enabled(on != 0);
+    ExitCode::Success as usize
+}
+
+/// 输出控制台缓冲区里还没输出的内容
+pub fn flush() -> usize {
+    syskrnl::io::console_flush();
+    ExitCode::Success as usize
+}
+
+/// 开关控制台输出的行缓冲，仅限root
+pub fn set_console_buffered(on: usize) -> usize {
+    if !proc::is_root() {
+        return ExitCode::PermissionError as usize;
+    }
+    syskrnl::io::set_console_buffered(on != 0);
+    ExitCode::Success as usize
+}
+
+/// 设置时间片长度（毫秒），仅限root
+pub fn set_quantum(ms: usize) -> usize {
+    if !proc::is_root() {
+        return ExitCode::PermissionError as usize;
+    }
+    match syskrnl::schedule::set_quantum_ms(ms) {
+        Ok(()) => ExitCode::Success as usize,
+        Err(()) => ExitCode::Failure as usize,
+    }
+}
+
+/// 要求进程结束，root可以结束任何可以结束的进程，其他用户只能结束自己的子进程
+pub fn kill(pid: usize) -> usize {
+    match proc::parent_of(pid) {
+        None => ExitCode::Failure as usize,
+        Some(parent) if !proc::is_root() && parent != proc::id() => ExitCode::PermissionError as usize,
+        Some(_) if proc::is_unkillable(pid) => ExitCode::PermissionError as usize,
+        Some(_) if proc::request_terminate(pid) => ExitCode::Success as usize,
+        Some(_) => ExitCode::Failure as usize,
+    }
+}
+
+/// 给当前进程改名
+pub fn set_proc_name(ptr: usize) -> usize {
+    let name: String = syscall_request!(ptr);
+    proc::set_name(name.as_str());
+    kernel_reply_ok(())
+}
+
+/// 查询进程名，不给PID时查询当前进程
+pub fn get_proc_name(ptr: usize) -> usize {
+    let pid: Option<usize> = syscall_request!(ptr);
+    let pid = pid.unwrap_or_else(proc::id);
+    kernel_reply_ok(proc::find_name(pid))
+}
+
+/// 进程的装载位置，别的进程仅限root查看
+pub fn image_info(ptr: usize) -> usize {
+    let pid: Option<usize> = syscall_request!(ptr);
+    let pid = pid.unwrap_or_else(proc::id);
+    if pid != proc::id() && !proc::is_root() {
+        return kernel_reply_err(STATUS_PERMISSION);
+    }
+    kernel_reply_ok(proc::image_info(pid))
+}
+
+/// 列出所有进程
+pub fn proc_list(ptr: usize) -> usize {
+    let _: () = syscall_request!(ptr);
+    kernel_reply_ok(proc::list())
+}
+
+/// 列出进程的页面映射，仅限root
+pub fn pagemap(ptr: usize) -> usize {
+    let pid: usize = syscall_request!(ptr);
+    if !proc::is_root() {
+        return kernel_reply_err(STATUS_PERMISSION);
+    }
+    kernel_reply_ok(proc::pagemap(pid))
+}
+
+/// 当前进程的CPU使用统计
+pub fn rusage() -> usize {
+    syscall_serialized_ret!(&proc::rusage())
+}
+
+/// 当前进程栈的大小
+pub fn stack_size() -> usize {
+    syscall_serialized_ret!(&proc::stack_size())
+}
+
+/// 把键盘切到原始模式，或者还给控制台
+pub fn set_key_mode(raw: usize) -> usize {
+    match keyboard::set_raw(proc::id(), raw != 0) {
+        Ok(()) => ExitCode::Success as usize,
+        Err(code) => code as usize,
+    }
+}
+
+/// 读下一个原始按键事件
+pub fn read_key(flags: usize) -> usize {
+    syscall_serialized_ret!(&keyboard::read_raw(proc::id(), KeyFlags::from_bits_truncate(flags as u32)))
+}
+
+/// 调整当前进程自己的资源限制，只有root能放宽
+pub fn set_limits(ptr: usize) -> usize {
+    let limits: Limits = syscall_deserialize!(ptr, ExitCode::UsageError as usize);
+    match proc::set_limits(limits) {
+        Ok(()) => ExitCode::Success as usize,
+        Err(()) => ExitCode::PermissionError as usize,
     }
 }
 
+/// 当前进程的资源限制
+pub fn limits() -> usize {
+    syscall_serialized_ret!(&proc::limits())
+}
+
+/// 当前进程和已退出的子进程用掉的tick数
+pub fn times() -> usize {
+    syscall_serialized_ret!(&proc::times())
+}
+
+/// 中断和调度统计
+pub fn irqstat() -> usize {
+    syscall_serialized_ret!(&syskrnl::interrupts::irq_stat())
+}
+
+/// 系统负载、CPU去处、物理帧和进程数
+pub fn sysinfo() -> usize {
+    let [idle_percent, user_percent, kernel_percent] = loadavg::percents();
+    let [idle_ticks, user_ticks, kernel_ticks] = loadavg::totals();
+    syscall_serialized_ret!(&SysInfo {
+        uptime_ns: syskrnl::vdso::now_ns() as usize,
+        loads: loadavg::loads(),
+        idle_percent,
+        user_percent,
+        kernel_percent,
+        idle_ticks,
+        user_ticks,
+        kernel_ticks,
+        total_frames: syskrnl::memory::total_memory() / 4096,
+        free_frames: syskrnl::memory::free_frames(),
+        procs: proc::count(),
+    })
+}
+
 pub fn stop_schedule() {
-    syskrnl::interrupts::NO_SCHEDULE.store(true, Ordering::SeqCst);
+    syskrnl::schedule::disable();
 }
 
 pub fn restart_schedule() {
-    syskrnl::interrupts::NO_SCHEDULE.store(false, Ordering::SeqCst);
+    syskrnl::schedule::enable();
 }
 
 #[doc(hidden)]
 pub fn test_serde(ptr: usize) -> usize {
-    use cinea_os_sysapi::call::{_TestSerde, syscall_deserialized, syscall_deserialized_prepare};
+    use cinea_os_sysapi::call::_TestSerde;
 
-    let vec_data = syscall_deserialized_prepare(ptr);
-    let obj: _TestSerde = syscall_deserialized(&vec_data).unwrap();
+    let obj: _TestSerde = syscall_request!(ptr);
     println!("以下是内核通过系统调用接收到的数据：\n{:?}", obj);
 
     let obj_to_send = _TestSerde {
@@ -132,29 +589,103 @@ pub fn test_serde(ptr: usize) -> usize {
     };
 
     println!("以下是内核通过系统调用返回给用户进程的数据：\n{:?}", obj_to_send);
-    let ptr_back = syscall_serialized_ret!(&obj_to_send);
-
-    ptr_back
+    kernel_reply_ok(obj_to_send)
 }
 
 pub fn list(ptr: usize) -> usize {
-    let obj: String = syscall_deserialize!(ptr);
+    let obj: String = syscall_request!(ptr);
+    kernel_reply_ok(syskrnl::fs::resolve(obj.as_str()).and_then(|path| syskrnl::fs::list(path.as_str())))
+}
 
-    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::list(obj.as_str()));
+pub fn open(ptr: usize) -> usize {
+    // 标志位的第0位与旧版的write参数兼容
+    let obj: (String, u32) = syscall_deserialize!(ptr);
+
+    let flags = OpenFlags::from_bits_truncate(obj.1);
+    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::resolve(obj.0.as_str()).and_then(|path| syskrnl::fs::open(path.as_str(), flags)));
     ptr_back
 }
 
-pub fn open(ptr: usize) -> usize {
-    let obj: (String, bool) = syscall_deserialize!(ptr);
+pub fn dup(fd: usize) -> usize {
+    syscall_serialized_ret!(&syskrnl::fs::dup(fd))
+}
+
+pub fn fcntl(fd: usize, cmd: usize, arg: usize) -> usize {
+    syscall_serialized_ret!(&syskrnl::fs::fcntl(fd, cmd, arg))
+}
+
+pub fn poll(fds_ptr: usize, count: usize, timeout: usize) -> usize {
+    syscall_serialized_ret!(&poll_fds(fds_ptr, count, timeout))
+}
+
+/// 读出用户的句柄数组再等待，见`syskrnl::fs::poll`
+fn poll_fds(fds_ptr: usize, count: usize, timeout: usize) -> Result<u64, FileError> {
+    if count > MAX_POLL_FDS {
+        return Err(FileError::TooManyHandlesError);
+    }
+    let word = core::mem::size_of::<usize>();
+    let mut raw = vec![0u8; count * core::mem::size_of::<PollFd>()];
+    let ptr = proc::translate_user_ptr(proc::id(), fds_ptr as u64, raw.len(), Access::Read).map_err(|_| FileError::BadAddressError)?;
+    uaccess::copy_from_user(&mut raw, ptr as u64).map_err(|_| FileError::BadAddressError)?;
+    let fds: Vec<PollFd> = raw
+        .chunks_exact(2 * word)
+        .map(|chunk| PollFd {
+            fd: usize::from_ne_bytes(chunk[..word].try_into().unwrap()),
+            events: usize::from_ne_bytes(chunk[word..].try_into().unwrap()),
+        })
+        .collect();
+    syskrnl::fs::poll(&fds, timeout)
+}
+
+/// 创建消息队列，见`syskrnl::msgq`
+pub fn msgq_create(capacity: usize) -> usize {
+    syscall_serialized_ret!(&msgq::create(capacity))
+}
 
-    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::open(obj.0.as_str(), obj.1));
+/// 消息先整条复制到内核里再入队，等待期间用户缓冲区可以随意改动
+pub fn msgq_send(qid: usize, buf: usize, len: usize, flags: usize) -> usize {
+    let flags = MsgFlags::from_bits_truncate(flags as u32);
+    syscall_serialized_ret!(&copy_message(buf, len).and_then(|msg| msgq::send(qid, &msg, flags)))
+}
+
+fn copy_message(buf: usize, len: usize) -> Result<Vec<u8>, MsgqError> {
+    if len > MAX_MSG_SIZE {
+        return Err(MsgqError::TooLarge);
+    }
+    let mut msg = vec![0u8; len];
+    let ptr = proc::translate_user_ptr(proc::id(), buf as u64, len, Access::Read).map_err(|_| MsgqError::BadAddress)?;
+    uaccess::copy_from_user(&mut msg, ptr as u64).map_err(|_| MsgqError::BadAddress)?;
+    Ok(msg)
+}
+
+pub fn msgq_recv(qid: usize, buf: usize, len: usize) -> usize {
+    syscall_serialized_ret!(&recv_message(qid, buf, len))
+}
+
+/// 等待之前先检查缓冲区，免得消息取出来了才发现写不回去
+fn recv_message(qid: usize, buf: usize, len: usize) -> Result<usize, MsgqError> {
+    let len = len.min(MAX_MSG_SIZE);
+    let ptr = proc::translate_user_ptr(proc::id(), buf as u64, len, Access::Write).map_err(|_| MsgqError::BadAddress)?;
+    let msg = msgq::recv(qid, len)?;
+    uaccess::copy_to_user(ptr as u64, &msg).map_err(|_| MsgqError::BadAddress)?;
+    Ok(msg.len())
+}
+
+pub fn seek(fd: usize, offset: usize, whence: usize) -> usize {
+    syscall_serialized_ret!(&syskrnl::fs::seek(fd, offset as isize, whence))
+}
+
+pub fn delete(ptr: usize) -> usize {
+    let obj: String = syscall_deserialize!(ptr);
+
+    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::resolve(obj.as_str()).and_then(|path| syskrnl::fs::delete(path.as_str())));
     ptr_back
 }
 
 pub fn info(ptr: usize) -> usize {
     let obj: String = syscall_deserialize!(ptr);
 
-    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::info(obj.as_str()));
+    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::resolve(obj.as_str()).and_then(|path| syskrnl::fs::info(path.as_str())));
     ptr_back
 }
 
@@ -167,23 +698,101 @@ pub fn write_all(ptr: usize) -> usize {
 pub fn read(ptr: usize) -> usize {
     // 这个有点复杂了
     let obj: (usize, usize, usize) = syscall_deserialize!(ptr); // 参数1：句柄，2：地址，3：长度
-    let slice = unsafe { &mut *slice_from_raw_parts_mut(obj.1 as *mut u8, obj.2) };
-    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::read(obj.0, slice));
-    ptr_back
+    let ret = read_buffer(obj.1, obj.2).and_then(|(dst, mut buf)| {
+        let len = syskrnl::fs::read(obj.0, &mut buf)?;
+        read_back(dst, &buf[..len])
+    });
+    syscall_serialized_ret!(&ret)
+}
+
+/// 把多段用户缓冲区依次写入同一个句柄，返回（写入的总字节数，出错原因）
+///
+/// 系统调用在关中断的情况下执行，各段之间不会插入别的进程的输出
+pub fn writev(fd: usize, iov_ptr: usize, iov_len: usize) -> usize {
+    syscall_serialized_ret!(&writev_segments(fd, iov_ptr, iov_len))
+}
+
+/// 先检查段表本身和总长度，超限时什么都不写；第k段出错时前k段已经写入
+fn writev_segments(fd: usize, iov_ptr: usize, iov_len: usize) -> (usize, Option<FileError>) {
+    if iov_len > MAX_IOV {
+        return (0, Some(FileError::TooManySegmentsError));
+    }
+    if iov_len == 0 {
+        return (0, None);
+    }
+    let word = core::mem::size_of::<usize>();
+    let mut raw = vec![0u8; iov_len * core::mem::size_of::<IoVec>()];
+    let iov_ptr = match proc::translate_user_ptr(proc::id(), iov_ptr as u64, raw.len(), Access::Read) {
+        Ok(ptr) => ptr,
+        Err(_) => return (0, Some(FileError::BadAddressError)),
+    };
+    if uaccess::copy_from_user(&mut raw, iov_ptr as u64).is_err() {
+        return (0, Some(FileError::BadAddressError));
+    }
+    let iov: Vec<IoVec> = raw
+        .chunks_exact(2 * word)
+        .map(|chunk| IoVec {
+            ptr: usize::from_ne_bytes(chunk[..word].try_into().unwrap()),
+            len: usize::from_ne_bytes(chunk[word..].try_into().unwrap()),
+        })
+        .collect();
+    let total = iov.iter().try_fold(0usize, |total, segment| total.checked_add(segment.len));
+    if total.map_or(true, |total| total > MAX_IOV_BYTES) {
+        return (0, Some(FileError::TooLargeError));
+    }
+
+    let mut written = 0;
+    for segment in iov.iter().filter(|segment| segment.len > 0) {
+        let mut buf = vec![0u8; segment.len];
+        let ptr = match proc::translate_user_ptr(proc::id(), segment.ptr as u64, segment.len, Access::Read) {
+            Ok(ptr) => ptr,
+            Err(_) => return (written, Some(FileError::BadAddressError)),
+        };
+        if uaccess::copy_from_user(&mut buf, ptr as u64).is_err() {
+            return (written, Some(FileError::BadAddressError));
+        }
+        match syskrnl::fs::write_all(fd, buf.as_slice()) {
+            Ok(len) => written += len,
+            Err(err) => return (written, Some(err)),
+        }
+    }
+    (written, None)
 }
 
 pub fn write_path(ptr: usize) -> usize {
     let obj: (String, Vec<u8>) = syscall_deserialize!(ptr);
-    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::write_with_path(obj.0.as_str(), obj.1.as_slice()));
+    let path = syskrnl::fs::resolve(obj.0.as_str());
+    let ptr_back = syscall_serialized_ret!(&path.and_then(|path| syskrnl::fs::write_with_path(path.as_str(), obj.1.as_slice())));
     ptr_back
 }
 
 pub fn read_path(ptr: usize) -> usize {
     // 这个有点复杂了
     let obj: (String, usize, usize) = syscall_deserialize!(ptr); // 参数1：文件路径，2：地址，3：长度
-    let slice = unsafe { &mut *slice_from_raw_parts_mut(obj.1 as *mut u8, obj.2) };
-    let ptr_back = syscall_serialized_ret!(&syskrnl::fs::read_with_path(obj.0.as_str(), slice));
-    ptr_back
+    let ret = read_buffer(obj.1, obj.2).and_then(|(dst, mut buf)| {
+        let path = syskrnl::fs::resolve(obj.0.as_str())?;
+        let len = syskrnl::fs::read_with_path(path.as_str(), &mut buf)?;
+        read_back(dst, &buf[..len])
+    });
+    syscall_serialized_ret!(&ret)
+}
+
+/// 一次READ最多读的字节数，中转缓冲区在内核堆上，更长的请求只读这么多
+const MAX_READ_SIZE: usize = 4 * 1024 * 1024;
+
+/// 先确认用户的缓冲区可写再分配中转缓冲区，长度不能由用户随意决定内核堆的分配
+///
+/// 返回（翻译后的目标地址，中转缓冲区）
+fn read_buffer(addr: usize, len: usize) -> Result<(u64, Vec<u8>), FileError> {
+    let len = len.min(MAX_READ_SIZE);
+    let dst = proc::translate_user_ptr(proc::id(), addr as u64, len, Access::Write).map_err(|_| FileError::BadAddressError)?;
+    Ok((dst as u64, vec![0u8; len]))
+}
+
+/// 把读到的数据复制回用户的缓冲区
+fn read_back(dst: u64, data: &[u8]) -> Result<usize, FileError> {
+    uaccess::copy_to_user(dst, data).map_err(|_| FileError::BadAddressError)?;
+    Ok(data.len())
 }
 
 pub fn panic(ptr: usize) -> usize {
@@ -193,7 +802,7 @@ pub fn panic(ptr: usize) -> usize {
 }
 
 pub fn create_window(ptr: usize) -> usize {
-    let obj: (String, usize) = syscall_deserialize!(ptr);
+    let obj: (String, usize) = syscall_deserialize!(ptr, syscall_serialized_ret!(&false));
     syscall_serialized_ret!(&syskrnl::gui::WINDOW_MANAGER.lock().create_window(obj.0.as_str(), obj.1))
 }
 
@@ -206,8 +815,9 @@ pub fn display_font_string(ptr: usize) -> usize {
 }
 
 pub fn load_font(ptr: usize) -> usize {
-    let obj: (String, String) = syscall_deserialize!(ptr);
-    let ret = font::load_font(obj.0.as_str(), obj.1.as_str());
+    let obj: (String, String) = syscall_deserialize!(ptr, syscall_serialized_ret!(&false));
+    // 字体文件和别的文件一样按调用者的根目录解析，关着的进程读不到外面的文件
+    let ret = syskrnl::fs::resolve(obj.1.as_str()).and_then(|path| font::load_font(obj.0.as_str(), path.as_str()));
     syscall_serialized_ret!(&ret.is_ok())
 }
 
@@ -242,3 +852,39 @@ pub fn gui_keyboard_register() -> usize {
     EVENT_QUEUE.lock().wait_for_register_only(GUI_EID_START + pid);
     0
 }
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+
+    use cinea_os_sysapi::fs::{FileError, IoVec, OpenFlags, MAX_IOV};
+
+    use super::writev_segments;
+    use crate::syskrnl;
+
+    #[test_case]
+    fn test_writev_partial_failure() {
+        let fd = syskrnl::fs::open("/dev/null", OpenFlags::WRITE).unwrap();
+        let (a, b) = ([1u8; 5], [2u8; 7]);
+        let mut iov = [
+            IoVec { ptr: a.as_ptr() as usize, len: a.len() },
+            IoVec { ptr: b.as_ptr() as usize, len: b.len() },
+            IoVec { ptr: 0x0004_0000_0000, len: 4 },
+        ];
+        assert!(matches!(writev_segments(fd, iov.as_ptr() as usize, 2), (12, None)));
+        // 第3段不可访问：前两段照常写入
+        assert!(matches!(
+            writev_segments(fd, iov.as_ptr() as usize, 3),
+            (12, Some(FileError::BadAddressError))
+        ));
+        iov[0].len = usize::MAX;
+        assert!(matches!(writev_segments(fd, iov.as_ptr() as usize, 2), (0, Some(FileError::TooLargeError))));
+        let many: Vec<IoVec> = (0..=MAX_IOV).map(|_| IoVec { ptr: b.as_ptr() as usize, len: 1 }).collect();
+        assert!(matches!(
+            writev_segments(fd, many.as_ptr() as usize, many.len()),
+            (0, Some(FileError::TooManySegmentsError))
+        ));
+        syskrnl::fs::close(fd).unwrap();
+        println!("[ok]  Writev partial failure");
+    }
+}
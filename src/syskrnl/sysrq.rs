@@ -0,0 +1,127 @@
+//! 紧急调试键（类似Linux的Magic SysRq）
+//!
+//! 在键盘中断里直接处理Ctrl+Alt+字母，不经过正常的输入投递，用户程序卡死时也能用：
+//!
+//! - `P`：进程表（PID、状态、保存的RIP、进程名）
+//! - `M`：内核堆和物理帧统计
+//! - `K`：结束前台进程组
+//! - `B`：通过8042复位线重启
+//!
+//! 输出只写串口。处理时任何内核锁都可能被占着，所以不分配内存，共享状态一律try_lock，拿不到就跳过
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::syskrnl::allocator::{HeapAllocator, ALLOCATOR};
+use crate::syskrnl::io::qemu::QemuWriter;
+use crate::syskrnl::{memory, power, proc};
+
+/// 扫描码集1中的按下和松开
+const CTRL_DOWN: u8 = 0x1D;
+const CTRL_UP: u8 = 0x9D;
+const ALT_DOWN: u8 = 0x38;
+const ALT_UP: u8 = 0xB8;
+const KEY_P: u8 = 0x19;
+const KEY_M: u8 = 0x32;
+const KEY_K: u8 = 0x25;
+const KEY_B: u8 = 0x30;
+
+/// 开机画面上的说明
+pub const HELP: &str = "SysRq (Ctrl+Alt+...): P 进程表  M 内存  K 结束前台进程  B 重启，输出到串口";
+
+static CTRL: AtomicBool = AtomicBool::new(false);
+static ALT: AtomicBool = AtomicBool::new(false);
+
+/// 调试键的动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    DumpProcs,
+    DumpMemory,
+    KillForeground,
+    Reboot,
+}
+
+/// 记录修饰键的状态，Ctrl和Alt都按着时按下的字母键对应一个动作
+///
+/// 右侧的Ctrl和Alt带0xE0前缀，前缀后面的扫描码和左侧的相同，一起处理
+fn track(scancode: u8) -> Option<Action> {
+    match scancode {
+        CTRL_DOWN => CTRL.store(true, Ordering::Relaxed),
+        CTRL_UP => CTRL.store(false, Ordering::Relaxed),
+        ALT_DOWN => ALT.store(true, Ordering::Relaxed),
+        ALT_UP => ALT.store(false, Ordering::Relaxed),
+        _ if CTRL.load(Ordering::Relaxed) && ALT.load(Ordering::Relaxed) => {
+            return match scancode {
+                KEY_P => Some(Action::DumpProcs),
+                KEY_M => Some(Action::DumpMemory),
+                KEY_K => Some(Action::KillForeground),
+                KEY_B => Some(Action::Reboot),
+                _ => None,
+            };
+        }
+        _ => {}
+    }
+    None
+}
+
+/// 键盘中断里对每个扫描码调用，返回`true`表示这个扫描码是调试键，已经处理，不要再投递
+pub fn handle_scancode(scancode: u8) -> bool {
+    let action = match track(scancode) {
+        Some(action) => action,
+        None => return false,
+    };
+    let mut w = QemuWriter();
+    let _ = writeln!(w, "\n[SysRq] {:?}", action);
+    match action {
+        Action::DumpProcs => proc::emergency_dump(&mut w),
+        Action::DumpMemory => dump_memory(&mut w),
+        Action::KillForeground => {
+            let _ = writeln!(w, "{} processes asked to terminate", proc::emergency_kill_foreground());
+        }
+        Action::Reboot => power::reset(),
+    }
+    true
+}
+
+/// 内核堆和物理帧，锁被占着的那一项跳过
+fn dump_memory(w: &mut QemuWriter) {
+    match ALLOCATOR.try_lock() {
+        Some(allocator) => {
+            let _ = writeln!(w, "heap: {} of {} bytes used", allocator.allocated(), allocator.size());
+        }
+        None => {
+            let _ = writeln!(w, "heap: allocator busy, skipped");
+        }
+    }
+    match memory::try_free_frames() {
+        Some(free) => {
+            let _ = writeln!(w, "frames: {} free of {}", free, memory::total_frames());
+        }
+        None => {
+            let _ = writeln!(w, "frames: {} in total, free list busy, skipped", memory::total_frames());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::println;
+
+    use super::*;
+
+    #[test_case]
+    fn test_track_needs_both_modifiers() {
+        assert_eq!(track(KEY_K), None);
+        track(CTRL_DOWN);
+        assert_eq!(track(KEY_K), None);
+        track(ALT_DOWN);
+        assert_eq!(track(KEY_K), Some(Action::KillForeground));
+        assert_eq!(track(KEY_P), Some(Action::DumpProcs));
+        // 字母键松开和别的键不算
+        assert_eq!(track(KEY_K | 0x80), None);
+        track(ALT_UP);
+        track(CTRL_UP);
+        assert_eq!(track(KEY_B), None);
+        println!("[ok]  SysRq needs Ctrl and Alt")
+    }
+}
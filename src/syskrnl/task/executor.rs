@@ -5,6 +5,9 @@ use core::task::{Context, Poll, Waker};
 
 use crossbeam::queue::ArrayQueue;
 
+use crate::syskrnl::gui::status_bar;
+use crate::syskrnl::schedule::loadavg;
+use crate::syskrnl::workqueue;
 use crate::syskrnl::task::{Task, TaskId};
 
 pub struct Executor {
@@ -63,6 +66,8 @@ impl Executor {
     pub fn run(&mut self) -> ! {
         loop {
             self.run_ready_tasks();
+            workqueue::run_pending();
+            status_bar::run_pending();
             self.sleep_if_idle();
         }
     }
@@ -73,7 +78,9 @@ impl Executor {
         disable();
 
         if self.task_queue.is_empty() {
+            loadavg::set_idle(true);
             enable_and_hlt();
+            loadavg::set_idle(false);
         } else {
             enable();
         }
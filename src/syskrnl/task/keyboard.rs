@@ -1,5 +1,6 @@
 use alloc::vec::Vec;
 use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use core::task::{Context, Poll};
 
 use conquer_once::spin::OnceCell;
@@ -7,25 +8,44 @@ use crossbeam::queue::ArrayQueue;
 use futures_util::task::AtomicWaker;
 use futures_util::{Stream, StreamExt};
 use lazy_static::lazy_static;
-use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, Keyboard, ScancodeSet1};
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, KeyState, Keyboard, ScancodeSet1};
 use spin::Mutex;
 use x86::io::inb;
 use x86_64::instructions::interrupts;
 
 use cinea_os_sysapi::event::*;
+use cinea_os_sysapi::ExitCode;
+use cinea_os_sysapi::keyboard::{KeyError, KeyEvent, KeyFlags, Modifiers, RAW_KEY_QUEUE_SIZE};
 
 use crate::syskrnl;
 use crate::syskrnl::clock::GUI_TIME_UPDATE_EVENT_NEEDER;
 use crate::syskrnl::event;
-use crate::syskrnl::proc::SCHEDULER;
+use crate::syskrnl::proc::{self, SCHEDULER};
+use crate::syskrnl::sysrq;
+use crate::syskrnl::workqueue::{self, WorkItem};
 
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 static WAKER: AtomicWaker = AtomicWaker::new();
 
-/// 键盘中断处理函数
+/// 占着原始模式的进程，0表示键盘处在普通的控制台模式
+static RAW_OWNER: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    /// 原始模式下的按键事件；键盘任务和系统调用两边都会碰它，所以不能用会被抢占的锁
+    static ref RAW_KEYS: ArrayQueue<KeyEvent> = ArrayQueue::new(RAW_KEY_QUEUE_SIZE);
+}
+
+/// 键盘中断处理函数，只读出扫描码，交给工作队列投递；SysRq调试键就地处理，不再投递
 fn keyboard_interrupt_handler() {
     let scancode: u8 = unsafe { inb(0x60) };
-    add_scancode(scancode);
+    if sysrq::handle_scancode(scancode) {
+        return;
+    }
+    workqueue::push(WorkItem::new(deliver_scancode, scancode as usize));
+}
+
+fn deliver_scancode(scancode: usize) {
+    add_scancode(scancode as u8);
 }
 
 pub fn init() {
@@ -80,13 +100,63 @@ impl Stream for ScancodeStream {
     }
 }
 
+/// 从扫描码字节里拼出原始按键：记住`0xE0`前缀，以及修饰键的状态
+#[derive(Default)]
+struct RawDecoder {
+    extended: bool,
+    modifiers: Modifiers,
+}
+
+impl RawDecoder {
+    /// 送进一个扫描码字节，拼出完整的按键时返回（扫描码，是否按下）
+    fn feed(&mut self, byte: u8) -> Option<(u16, bool)> {
+        if byte == 0xE0 {
+            self.extended = true;
+            return None;
+        }
+        let prefix = if core::mem::take(&mut self.extended) { 0xE000 } else { 0 };
+        Some((prefix | (byte & 0x7F) as u16, byte & 0x80 == 0))
+    }
+
+    fn update_modifiers(&mut self, code: KeyCode, pressed: bool) {
+        let flag = match code {
+            KeyCode::ShiftLeft => Modifiers::LSHIFT,
+            KeyCode::ShiftRight => Modifiers::RSHIFT,
+            KeyCode::ControlLeft => Modifiers::LCTRL,
+            KeyCode::ControlRight => Modifiers::RCTRL,
+            KeyCode::AltLeft => Modifiers::LALT,
+            KeyCode::AltRight => Modifiers::RALT,
+            // 大写锁定按一下切换一次
+            KeyCode::CapsLock if pressed => return self.modifiers.toggle(Modifiers::CAPSLOCK),
+            _ => return,
+        };
+        self.modifiers.set(flag, pressed);
+    }
+}
+
 pub async fn key_presses_handler() {
     let mut scancodes = ScancodeStream::new();
     let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::MapLettersToUnicode);
+    let mut raw = RawDecoder::default();
 
     while let Some(scancode) = scancodes.next().await {
+        let raw_key = raw.feed(scancode);
         if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
+            let (code, pressed) = (key_event.code, matches!(key_event.state, KeyState::Down));
+            raw.update_modifiers(code, pressed);
+            // 原始模式下解码器照样要走一遍，切回来时修饰键的状态才是对的
+            let decoded = keyboard.process_keyevent(key_event);
+            if RAW_OWNER.load(Ordering::SeqCst) != 0 {
+                if let Some((scancode, _)) = raw_key {
+                    let ch = match decoded {
+                        Some(DecodedKey::Unicode(ch)) => Some(ch),
+                        _ => None,
+                    };
+                    push_raw_key(KeyEvent { scancode, pressed, keycode: code as u16, ch, modifiers: raw.modifiers });
+                }
+                continue;
+            }
+            if let Some(key) = decoded {
                 // debugln!("{:?}",key);
                 match key {
                     DecodedKey::Unicode(character) => key_event_handler(character),
@@ -97,6 +167,59 @@ pub async fn key_presses_handler() {
     }
 }
 
+fn push_raw_key(key: KeyEvent) {
+    if RAW_KEYS.push(key).is_err() {
+        debugln!("警告：原始按键队列已满; 正在丢弃键盘输入");
+    }
+}
+
+/// 把键盘切到原始模式或者切回控制台模式
+///
+/// 只有前台进程能占用原始模式，别的进程占着时失败；重新打开时丢掉还没读走的事件
+pub fn set_raw(pid: usize, raw: bool) -> Result<(), ExitCode> {
+    if !raw {
+        return match RAW_OWNER.compare_exchange(pid, 0, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => Ok(()),
+            Err(0) => Ok(()),
+            Err(_) => Err(ExitCode::PermissionError),
+        };
+    }
+    if !proc::in_foreground(pid) {
+        return Err(ExitCode::PermissionError);
+    }
+    match RAW_OWNER.compare_exchange(0, pid, Ordering::SeqCst, Ordering::SeqCst) {
+        Ok(_) => {}
+        Err(owner) if owner == pid => {}
+        Err(_) => return Err(ExitCode::Failure),
+    }
+    while RAW_KEYS.pop().is_some() {}
+    Ok(())
+}
+
+/// 进程退出时把它占着的原始模式还给控制台
+pub fn release_raw(pid: usize) {
+    let _ = RAW_OWNER.compare_exchange(pid, 0, Ordering::SeqCst, Ordering::SeqCst);
+}
+
+/// 取出下一个原始按键事件，没有时让出CPU等待，除非指定了`NONBLOCK`
+pub fn read_raw(pid: usize, flags: KeyFlags) -> Result<KeyEvent, KeyError> {
+    loop {
+        if RAW_OWNER.load(Ordering::SeqCst) != pid {
+            return Err(KeyError::NotRaw);
+        }
+        if let Some(key) = RAW_KEYS.pop() {
+            return Ok(key);
+        }
+        if flags.contains(KeyFlags::NONBLOCK) {
+            return Err(KeyError::WouldBlock);
+        }
+        if proc::terminate_pending() {
+            return Err(KeyError::Interrupted);
+        }
+        syskrnl::time::halt();
+    }
+}
+
 lazy_static! {
     pub static ref GUI_UNDK_KEY_EVENT_SUBSCRIBER: Mutex<Vec<usize>> = { Mutex::new(Vec::new()) };
 }
@@ -113,8 +236,41 @@ fn undk_handler(ch: KeyCode) {
     }
 }
 
+/// 有进程在等键盘事件时交给它，否则留给读控制台的进程
 fn key_event_handler(ch: char) {
-    if let Some(pid) = event::EVENT_QUEUE.lock().wakeup_with_ret(KEYBOARD_INPUT, ch as u32 as usize) {
-        SCHEDULER.lock().wakeup(pid);
+    match event::EVENT_QUEUE.lock().wakeup_with_ret(KEYBOARD_INPUT, ch as u32 as usize) {
+        Some(pid) => {
+            SCHEDULER.lock().wakeup(pid);
+        }
+        None => syskrnl::io::push_console_input(ch),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pc_keyboard::KeyCode;
+
+    use cinea_os_sysapi::keyboard::Modifiers;
+
+    use super::RawDecoder;
+
+    #[test_case]
+    fn test_raw_decoder() {
+        let mut raw = RawDecoder::default();
+        // A按下、松开，右Ctrl带着0xE0前缀
+        assert_eq!(raw.feed(0x1E), Some((0x1E, true)));
+        assert_eq!(raw.feed(0x9E), Some((0x1E, false)));
+        assert_eq!(raw.feed(0xE0), None);
+        assert_eq!(raw.feed(0x9D), Some((0xE01D, false)));
+        assert_eq!(raw.feed(0x1D), Some((0x1D, true)));
+
+        raw.update_modifiers(KeyCode::ShiftLeft, true);
+        raw.update_modifiers(KeyCode::ShiftRight, true);
+        raw.update_modifiers(KeyCode::ShiftLeft, false);
+        assert!(raw.modifiers.shift());
+        raw.update_modifiers(KeyCode::CapsLock, true);
+        raw.update_modifiers(KeyCode::CapsLock, false);
+        assert_eq!(raw.modifiers, Modifiers::RSHIFT | Modifiers::CAPSLOCK);
+        println!("[ok]  Raw keyboard decoder");
     }
 }
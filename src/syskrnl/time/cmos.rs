@@ -1,11 +1,16 @@
 use core::sync::atomic::{AtomicUsize, Ordering};
+
+use cinea_os_kcore::date::bcd_to_binary;
+
 // 从CMOS读取日期和时间
 use crate::syskrnl;
 use crate::syskrnl::interrupts::set_irq_handler;
 use x86::io::{inb, outb};
 use x86_64::instructions::interrupts;
 
-const CURRENT_YEAR: u32 = 2023;
+/// 世纪寄存器（ACPI FADT中的惯例位置），不存在时按20xx处理
+const CENTURY_REGISTER: u8 = 0x32;
+const DEFAULT_CENTURY: u32 = 20;
 
 static LAST_RTC_UPDATE: AtomicUsize = AtomicUsize::new(0);
 
@@ -39,6 +44,17 @@ fn get_rtc_register(reg: u8) -> u8 {
     }
 }
 
+/// 读世纪寄存器；没有这个寄存器时读出来的通常是0或0xFF，不像样就当作20xx
+fn read_century(binary: bool) -> u32 {
+    let raw = get_rtc_register(CENTURY_REGISTER);
+    let century = if binary { raw } else { bcd_to_binary(raw) } as u32;
+    if (19..=29).contains(&century) {
+        century
+    } else {
+        DEFAULT_CENTURY
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
 pub struct RawTime {
     pub second: u8,
@@ -78,13 +94,14 @@ pub fn read_rtc() -> RawTime {
 
     // 处理时间格式
     let register_b = get_rtc_register(0x0B);
-    if (register_b & 0x04) == 0 {
-        time.second = (time.second & 0x0F) + ((time.second / 16) * 10);
-        time.minute = (time.minute & 0x0F) + ((time.minute / 16) * 10);
-        time.hour = ((time.hour & 0x0F) + (((time.hour & 0x70) / 16) * 10)) | (time.hour & 0x80);
-        time.day = (time.day & 0x0F) + ((time.day / 16) * 10);
-        time.month = (time.month & 0x0F) + ((time.month / 16) * 10);
-        time.year = (time.year & 0x0F) + ((time.year / 16) * 10);
+    let binary = (register_b & 0x04) != 0;
+    if !binary {
+        time.second = bcd_to_binary(time.second);
+        time.minute = bcd_to_binary(time.minute);
+        time.hour = bcd_to_binary(time.hour & 0x7F) | (time.hour & 0x80);
+        time.day = bcd_to_binary(time.day);
+        time.month = bcd_to_binary(time.month);
+        time.year = bcd_to_binary(time.year as u8) as u32;
     }
 
     // 如有必要，转换为24小时制
@@ -93,11 +110,16 @@ pub fn read_rtc() -> RawTime {
     }
 
     // 计算完整的4位数年份
-    time.year += CURRENT_YEAR - (CURRENT_YEAR % 100);
+    time.year += read_century(binary) * 100;
 
     time
 }
 
+/// 最近一次RTC更新中断时的Tick数
+pub fn last_update() -> usize {
+    LAST_RTC_UPDATE.load(Ordering::Relaxed)
+}
+
 /// 禁用NMI（非屏蔽中断）
 fn disable_nmi() {
     unsafe {
@@ -1,9 +1,11 @@
 use cinea_os_sysapi::fs::FileIO;
 use x86_64::instructions::interrupts;
 
+pub use cinea_os_kcore::date::to_unix_timestamp;
 pub use datetime::*;
 pub use pit::PIT_PER_SECOND as TICKS_PER_SECOND;
-pub use sleep::{add_sleep, check_wakeup};
+pub use sleep::add_sleep;
+pub use timer::{after, wakeup_after};
 
 use crate::syskrnl::time::cmos::{read_rtc, RawTime};
 
@@ -11,6 +13,7 @@ pub mod cmos;
 mod datetime;
 mod pit;
 mod sleep;
+pub mod timer;
 pub mod tsc;
 
 const TIME_ZONE: u8 = 8;
@@ -32,12 +35,39 @@ pub fn time_between_ticks() -> f64 {
 }
 
 pub fn last_rtc_update() -> usize {
-    unimplemented!()
+    cmos::last_update()
+}
+
+/// 当前的UTC时间：(年, 月, 日, 时, 分, 秒)
+pub fn now() -> (u32, u8, u8, u8, u8, u8) {
+    let tm = read_rtc();
+    (tm.year, tm.month, tm.day, tm.hour, tm.minute, tm.second)
+}
+
+/// 当前的Unix时间戳（秒）
+pub fn unix_timestamp() -> u64 {
+    let (year, month, day, hour, minute, second) = now();
+    to_unix_timestamp(year, month, day, hour, minute, second)
+}
+
+/// Tick的来源，内核里是PIT，测试里可以换成手动拨动的时钟（见`schedule::mock`）
+pub trait TickSource {
+    /// 到现在为止经过的Tick数
+    fn ticks(&self) -> usize;
+}
+
+/// PIT时钟中断的计数
+pub struct Pit;
+
+impl TickSource for Pit {
+    fn ticks(&self) -> usize {
+        pit::get_ticks()
+    }
 }
 
 /// 获取启动后经过的Tick数
 pub fn ticks() -> usize {
-    pit::get_ticks()
+    Pit.ticks()
 }
 
 /// 获取系统启动到现在的时间
@@ -45,6 +75,11 @@ pub fn uptime() -> f64 {
     pit::get_uptime()
 }
 
+/// 把Tick数换算成纳秒
+pub fn ticks_to_ns(ticks: usize) -> u64 {
+    pit::ticks_to_ns(ticks)
+}
+
 /// Halt
 pub fn halt() {
     let disabled = !interrupts::are_enabled();
@@ -88,3 +123,17 @@ impl FileIO for UpTimeDevice {
         Ok(0)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::to_unix_timestamp;
+
+    #[test_case]
+    fn test_unix_timestamp() {
+        assert_eq!(to_unix_timestamp(1970, 1, 1, 0, 0, 0), 0);
+        assert_eq!(to_unix_timestamp(2000, 3, 1, 0, 0, 0), 951868800);
+        assert_eq!(to_unix_timestamp(2023, 5, 30, 12, 34, 56), 1685450096);
+        assert_eq!(to_unix_timestamp(2024, 2, 29, 23, 59, 59), 1709251199);
+        println!("[ok]  Time unix timestamp")
+    }
+}
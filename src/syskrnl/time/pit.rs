@@ -6,6 +6,7 @@ use x86_64::instructions::interrupts::without_interrupts;
 use crate::syskrnl;
 use crate::syskrnl::graphic::GD;
 use crate::syskrnl::gui::cursor::MOUSE_CURSOR;
+use crate::syskrnl::gui::status_bar;
 use crate::syskrnl::gui::{RENDER_OK, WINDOW_MANAGER};
 
 /// `PIT_FREQUENCY`的值是x86架构默认的
@@ -43,6 +44,12 @@ pub fn set_pit_frequency_divider(divider: u16, channel: u8) {
 /// PIT中断处理程序
 pub fn pit_interrupt_handler() {
     let time = PIT_TICKS.fetch_add(1, Ordering::Relaxed);
+    syskrnl::vdso::update(time + 1);
+
+    // 每秒更新一次状态栏，绘制留给非中断上下文
+    if time % PIT_PER_SECOND == 0 {
+        status_bar::request_stats_update();
+    }
 
     // 每1/25秒渲染一次
     if RENDER_OK.load(Ordering::Relaxed) && time % 40 == 0 {
@@ -78,6 +85,12 @@ pub fn get_uptime() -> f64 {
     (get_ticks() as f64) * PIT_INTERVAL
 }
 
+/// 只用整数运算，可以在中断里调用
+pub fn ticks_to_ns(ticks: usize) -> u64 {
+    // PIT_INTERVAL = PIT_DIVIDER * 3 / 3_579_545 秒
+    (ticks as u128 * PIT_DIVIDER as u128 * 3_000_000_000 / 3_579_545) as u64
+}
+
 pub fn time_between_ticks() -> f64 {
     PIT_INTERVAL
 }
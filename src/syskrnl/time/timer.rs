@@ -0,0 +1,152 @@
+//! 一次性的定时回调，按截止Tick排成最小堆，由时钟中断取出到期的执行
+
+use alloc::boxed::Box;
+use alloc::collections::BinaryHeap;
+use core::cmp::Ordering;
+
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::syskrnl::schedule::ProcessScheduler;
+use crate::syskrnl::time::{self, TickSource};
+
+/// 到期后要做的事
+pub enum TimerAction {
+    /// 在时钟中断里执行的回调，必须短小且不能阻塞
+    Callback(Box<dyn FnOnce() + Send>),
+    /// 唤醒一个进程
+    Wakeup(usize),
+}
+
+struct Timer {
+    deadline: usize,
+    seq: u64,
+    action: TimerAction,
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Timer {}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Timer {
+    /// BinaryHeap是最大堆，反过来比较就成了最小堆
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.deadline, other.seq).cmp(&(self.deadline, self.seq))
+    }
+}
+
+/// 按截止Tick排好的定时器，时间由调用者给出，不依赖真正的时钟
+pub struct TimerQueue {
+    timers: BinaryHeap<Timer>,
+    /// 同一Tick到期的按注册顺序执行
+    next_seq: u64,
+}
+
+impl TimerQueue {
+    pub const fn new() -> Self {
+        Self { timers: BinaryHeap::new(), next_seq: 0 }
+    }
+
+    /// 登记一个在`deadline`到期的定时器
+    pub fn push(&mut self, deadline: usize, action: TimerAction) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.timers.push(Timer { deadline, seq, action });
+    }
+
+    /// 取出一个到`now`为止已经到期的定时器
+    pub fn pop_expired(&mut self, now: usize) -> Option<TimerAction> {
+        match self.timers.peek() {
+            Some(timer) if timer.deadline <= now => self.timers.pop().map(|timer| timer.action),
+            _ => None,
+        }
+    }
+}
+
+/// 内核的定时器，由时钟中断执行
+pub static TIMERS: Mutex<TimerQueue> = Mutex::new(TimerQueue::new());
+
+fn register(ticks: usize, action: TimerAction) {
+    let deadline = time::ticks() + ticks;
+    // 关中断，时钟中断不会在持锁期间进来
+    without_interrupts(|| TIMERS.lock().push(deadline, action));
+}
+
+/// `ticks`个Tick后在时钟中断里执行`callback`
+pub fn after<F>(ticks: usize, callback: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    register(ticks, TimerAction::Callback(Box::new(callback)));
+}
+
+/// `ticks`个Tick后唤醒进程`pid`
+pub fn wakeup_after(ticks: usize, pid: usize) {
+    register(ticks, TimerAction::Wakeup(pid));
+}
+
+/// 按`clock`的时间执行`timers`里所有到期的定时器，要唤醒的进程交给`scheduler`，见`schedule::tick`
+///
+/// 每次只在锁里弹出一个，回调执行时不持锁，回调里可以再注册新的定时器
+pub fn run_expired<S: ProcessScheduler + ?Sized>(clock: &dyn TickSource, timers: &Mutex<TimerQueue>, scheduler: &Mutex<Box<S>>) {
+    let now = clock.ticks();
+    loop {
+        let action = timers.lock().pop_expired(now);
+        match action {
+            None => break,
+            Some(TimerAction::Callback(callback)) => callback(),
+            Some(TimerAction::Wakeup(pid)) => {
+                scheduler.lock().wakeup(pid);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+
+    use spin::Mutex;
+
+    use super::{after, TimerAction, TimerQueue};
+    use crate::syskrnl::time;
+
+    #[test_case]
+    fn test_timer_deadline_order() {
+        static FIRED: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+        after(5, || FIRED.lock().push(3));
+        after(1, || FIRED.lock().push(1));
+        after(1, || FIRED.lock().push(2));
+        time::sleep(0.02);
+        assert_eq!(*FIRED.lock(), [1, 2, 3]);
+        println!("[ok]  Timer deadline order")
+    }
+
+    #[test_case]
+    fn test_timer_queue_pop_expired() {
+        let mut queue = TimerQueue::new();
+        queue.push(7, TimerAction::Wakeup(2));
+        queue.push(3, TimerAction::Wakeup(1));
+        queue.push(7, TimerAction::Wakeup(3));
+        let pop = |queue: &mut TimerQueue, now| match queue.pop_expired(now) {
+            Some(TimerAction::Wakeup(pid)) => Some(pid),
+            _ => None,
+        };
+        assert_eq!(pop(&mut queue, 2), None);
+        assert_eq!(pop(&mut queue, 3), Some(1));
+        assert_eq!(pop(&mut queue, 6), None);
+        assert_eq!(pop(&mut queue, 7), Some(2));
+        assert_eq!(pop(&mut queue, 7), Some(3));
+        println!("[ok]  Timer queue pops by deadline")
+    }
+}
@@ -2,6 +2,9 @@ use core::sync::atomic::{AtomicU64, Ordering};
 use core::{arch, hint};
 
 pub static CLOCKS_PER_NANOSECOND: AtomicU64 = AtomicU64::new(0);
+/// TSC换算成纳秒的定点系数：纳秒 = (TSC增量 * NS_MULT) >> NS_SHIFT，不会像整数的每纳秒递增数那样把2.4GHz截成2，0表示还没有校准
+pub static NS_MULT: AtomicU64 = AtomicU64::new(0);
+pub const NS_SHIFT: u32 = 32;
 
 pub fn rdtsc() -> u64 {
     unsafe {
@@ -25,4 +28,9 @@ pub fn init() {
     super::sleep(calibration_time as f64 / 1e6);
     let b = rdtsc();
     CLOCKS_PER_NANOSECOND.store((b - a) / calibration_time, Ordering::Relaxed);
+    let clocks = (b - a) as u128;
+    if clocks > 0 {
+        let ns = calibration_time as u128 * 1000;
+        NS_MULT.store(((ns << NS_SHIFT) / clocks).min(u64::MAX as u128) as u64, Ordering::Relaxed);
+    }
 }
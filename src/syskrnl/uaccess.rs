@@ -0,0 +1,147 @@
+use alloc::alloc::Layout;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+
+use crate::syskrnl;
+use crate::syskrnl::proc::{translate_user_ptr, Access};
+
+/// 访问用户内存时发生了页错误，或者地址本身就不合法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BadAddress;
+
+// 内核与用户内存之间的复制。参数：rdi-目标，rsi-源，rdx-长度；返回：rax 0-成功，1-页错误
+// `__uaccess_copy_fault`处的指令发生页错误时，页错误处理程序会让它从`__uaccess_copy_fixup`继续执行
+global_asm!(
+    ".global __uaccess_copy",
+    ".global __uaccess_copy_fault",
+    ".global __uaccess_copy_fixup",
+    "__uaccess_copy:",
+    "    mov rcx, rdx",
+    "__uaccess_copy_fault:",
+    "    rep movsb",
+    "    xor eax, eax",
+    "    ret",
+    "__uaccess_copy_fixup:",
+    "    mov eax, 1",
+    "    ret",
+);
+
+extern "sysv64" {
+    fn __uaccess_copy(dst: *mut u8, src: *const u8, len: usize) -> usize;
+}
+
+extern "C" {
+    static __uaccess_copy_fault: u8;
+    static __uaccess_copy_fixup: u8;
+}
+
+/// 异常表：（可能发生页错误的指令地址，恢复执行的地址）
+fn exception_table() -> [(u64, u64); 1] {
+    unsafe {
+        [(
+            &__uaccess_copy_fault as *const u8 as u64,
+            &__uaccess_copy_fixup as *const u8 as u64,
+        )]
+    }
+}
+
+/// 由页错误处理程序调用，出错的指令在异常表中时返回恢复执行的地址
+pub fn fixup(fault_ip: u64) -> Option<u64> {
+    exception_table()
+        .iter()
+        .find(|(ip, _)| *ip == fault_ip)
+        .map(|(_, recovery_ip)| *recovery_ip)
+}
+
+/// 检查用户给出的地址范围是否像样：非空且不会溢出
+fn check_range(addr: u64, len: usize) -> Result<(), BadAddress> {
+    if addr == 0 || addr.checked_add(len as u64).is_none() {
+        Err(BadAddress)
+    } else {
+        Ok(())
+    }
+}
+
+/// 从用户内存复制到内核，用户内存不可访问时返回错误而不是panic
+pub fn copy_from_user(dst: &mut [u8], src_addr: u64) -> Result<(), BadAddress> {
+    check_range(src_addr, dst.len())?;
+    match unsafe { __uaccess_copy(dst.as_mut_ptr(), src_addr as *const u8, dst.len()) } {
+        0 => Ok(()),
+        _ => Err(BadAddress),
+    }
+}
+
+/// 从内核复制到用户内存，用户内存不可访问时返回错误而不是panic
+pub fn copy_to_user(dst_addr: u64, src: &[u8]) -> Result<(), BadAddress> {
+    check_range(dst_addr, src.len())?;
+    match unsafe { __uaccess_copy(dst_addr as *mut u8, src.as_ptr(), src.len()) } {
+        0 => Ok(()),
+        _ => Err(BadAddress),
+    }
+}
+
+/// 系统调用参数序列化后的最大长度，中转缓冲区在内核堆上
+const MAX_SERIALIZED_SIZE: usize = 8 * 1024 * 1024;
+
+/// 读出用户进程序列化好的系统调用参数，见`cinea_os_sysapi::call::syscall_serialized`
+///
+/// 参数所在的内存是用户进程为这次调用分配的，读完后在这里释放
+pub fn read_serialized(ptr: usize) -> Result<Vec<u8>, BadAddress> {
+    let pid = syskrnl::proc::id();
+    let mut header = [0u8; 3 * core::mem::size_of::<usize>()];
+    copy_from_user(&mut header, translate_user_ptr(pid, ptr as u64, header.len(), Access::Read)? as u64)?;
+    let mut parts = header
+        .chunks_exact(core::mem::size_of::<usize>())
+        .map(|chunk| usize::from_ne_bytes(chunk.try_into().unwrap()));
+    let (addr, len, cap) = (parts.next().unwrap(), parts.next().unwrap(), parts.next().unwrap());
+    if len > cap || len > MAX_SERIALIZED_SIZE {
+        return Err(BadAddress);
+    }
+
+    let mut buffers = vec![(ptr, Layout::array::<usize>(3).unwrap())];
+    if cap > 0 {
+        // 容量为0的Vec没有分配过内存
+        buffers.push((addr, Layout::array::<u8>(cap).map_err(|_| BadAddress)?));
+    }
+    // 这些内存要还给用户进程的堆分配器，不在它的堆里的话就是伪造的
+    if pid != 0 && !buffers.iter().all(|(ptr, layout)| syskrnl::proc::in_heap(*ptr as u64, layout.size())) {
+        return Err(BadAddress);
+    }
+
+    let src = translate_user_ptr(pid, addr as u64, len, Access::Read)?;
+    let mut data = vec![0u8; len];
+    copy_from_user(&mut data, src as u64)?;
+
+    if pid == 0 {
+        for (ptr, layout) in buffers {
+            unsafe { alloc::alloc::dealloc(ptr as *mut u8, layout) };
+        }
+    } else {
+        let allocator = syskrnl::proc::heap_allocator();
+        let mut allocator = allocator.lock_halting();
+        for (ptr, layout) in buffers {
+            unsafe { allocator.dealloc(ptr as *mut u8, layout) };
+        }
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{copy_from_user, copy_to_user, BadAddress};
+
+    #[test_case]
+    fn test_copy_from_unmapped_user_memory() {
+        // 看起来像用户堆，但从来没有被映射过
+        let addr = 0x0004_0000_0000u64;
+        let mut buf = [0u8; 16];
+        assert_eq!(copy_from_user(&mut buf, addr), Err(BadAddress));
+        assert_eq!(copy_to_user(addr, &buf), Err(BadAddress));
+
+        let src = [0x5Au8; 16];
+        assert_eq!(copy_from_user(&mut buf, src.as_ptr() as u64), Ok(()));
+        assert_eq!(buf, src);
+        println!("[ok]  Uaccess copy from unmapped memory")
+    }
+}
@@ -0,0 +1,69 @@
+use core::sync::atomic::Ordering;
+
+use x86_64::structures::paging::{Mapper, OffsetPageTable, Page, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::VirtAddr;
+
+use cinea_os_sysapi::vdso::{VdsoData, VdsoSnapshot, VDSO_ADDR};
+
+use crate::syskrnl::memory;
+use crate::syskrnl::time::{self, tsc};
+
+/// vDSO页，独占一整页，以只读方式映射到每个进程的`VDSO_ADDR`
+#[repr(C, align(4096))]
+struct VdsoPage(VdsoData);
+
+static VDSO_PAGE: VdsoPage = VdsoPage(VdsoData::new());
+
+/// 由时钟中断调用，发布最新的时间
+pub fn update(ticks: usize) {
+    VDSO_PAGE.0.write(VdsoSnapshot {
+        ticks: ticks as u64,
+        tick_ns: time::ticks_to_ns(1),
+        base_ns: time::ticks_to_ns(ticks),
+        base_tsc: tsc::rdtsc(),
+        tsc_mult: tsc::NS_MULT.load(Ordering::Relaxed),
+        tsc_shift: tsc::NS_SHIFT as u64,
+    });
+}
+
+/// 启动以来的纳秒数，和用户态`fast_now_ns`的算法一致
+pub fn now_ns() -> u64 {
+    VDSO_PAGE.0.read().now_ns(tsc::rdtsc())
+}
+
+/// `[addr, addr+len)`整个落在vDSO页里
+pub fn contains(addr: u64, len: usize) -> bool {
+    addr >= VDSO_ADDR && addr.checked_add(len as u64).map_or(false, |end| end <= VDSO_ADDR + 4096)
+}
+
+/// 把vDSO页只读地映射到进程页表上
+pub fn map_into(mapper: &mut OffsetPageTable) -> Result<(), ()> {
+    let phys = memory::virt_to_phys(VirtAddr::from_ptr(&VDSO_PAGE)).ok_or(())?;
+    let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(VDSO_ADDR));
+    let frame = PhysFrame::containing_address(phys);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    let mut frame_allocator = memory::heaped_frame_allocator();
+    unsafe { mapper.map_to(page, frame, flags, &mut *frame_allocator) }.map_err(|_| ())?.flush();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{now_ns, VDSO_PAGE};
+    use crate::syskrnl::time;
+
+    #[test_case]
+    fn test_vdso_now_is_monotonic() {
+        assert!(VDSO_PAGE.0.is_valid());
+        assert_eq!(&VDSO_PAGE as *const _ as usize % 4096, 0);
+        let mut last = now_ns();
+        for _ in 0..1000 {
+            let now = now_ns();
+            assert!(now >= last);
+            last = now;
+        }
+        time::sleep(0.01);
+        assert!(now_ns() > last);
+        println!("[ok]  vDSO now is monotonic")
+    }
+}
@@ -3,11 +3,11 @@
 use core::fmt;
 
 use crate::println;
+use crate::syskrnl::allocator::IrqSafeGuard;
 use lazy_static::lazy_static;
 use spin::Mutex;
 use volatile::Volatile;
 use x86::io::outb;
-use x86_64::instructions::interrupts;
 
 /// VGA标准颜色
 #[allow(dead_code)]
@@ -54,6 +54,9 @@ struct ScreenChar {
 const BUFFER_HEIGHT: usize = 25;
 const BUFFER_WIDTH: usize = 80;
 const TAB_SIZE: usize = 4;
+/// 第0行保留给状态栏，普通输出和滚屏都不碰它
+const STATUS_ROW: usize = 0;
+const FIRST_ROW: usize = STATUS_ROW + 1;
 
 /// VGA屏幕
 #[repr(transparent)]
@@ -121,7 +124,7 @@ impl Writer {
 
         if self.row_position >= BUFFER_HEIGHT {
             // 向上滚屏
-            for row in 0..BUFFER_HEIGHT - 1 {
+            for row in FIRST_ROW..BUFFER_HEIGHT - 1 {
                 for col in 0..BUFFER_WIDTH {
                     self.buffer.chars[row.clone()][col.clone()].write(self.buffer.chars[row.clone() + 1][col.clone()].read());
                 }
@@ -131,6 +134,23 @@ impl Writer {
         }
     }
 
+    /// 覆写状态栏所在的一行，不移动光标
+    pub fn write_status_row(&mut self, s: &str) {
+        let color_code = ColorCode::new(Color::Black, Color::LightGray);
+        let mut bytes = s.bytes();
+        for col in 0..BUFFER_WIDTH {
+            let ascii_character = match bytes.next() {
+                Some(byte @ 0x20..=0x7e) => byte,
+                Some(_) => 0xfe,
+                None => b' ',
+            };
+            self.buffer.chars[STATUS_ROW][col].write(ScreenChar {
+                ascii_character,
+                color_code,
+            });
+        }
+    }
+
     fn backspace(&mut self) {
         if self.column_position > 0 {
             self.column_position -= 1;
@@ -166,7 +186,7 @@ impl Writer {
 
 lazy_static! {
     pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        row_position: 0,
+        row_position: FIRST_ROW,
         column_position: 0,
         color_code: ColorCode::new(Color::LightCyan, Color::Black),
         buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
@@ -185,9 +205,12 @@ pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
 
     // 防止死锁
-    interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
-    })
+    IrqSafeGuard::new(|| WRITER.lock()).write_fmt(args).unwrap();
+}
+
+/// 写状态栏，只在写一行的时间内持有输出器的锁
+pub fn write_status_row(s: &str) {
+    IrqSafeGuard::new(|| WRITER.lock()).write_status_row(s);
 }
 
 // #[macro_export]
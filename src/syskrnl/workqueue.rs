@@ -0,0 +1,120 @@
+//! 延后执行的工作队列
+//!
+//! 中断处理程序只把工作放进有界队列，真正的处理在普通的内核上下文里完成：
+//! 系统调用返回前和0号进程的空闲循环里。队列满时丢弃并计数，绝不在中断里等待
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use conquer_once::spin::OnceCell;
+use crossbeam::queue::ArrayQueue;
+
+/// 队列容量
+const CAPACITY: usize = 256;
+
+/// 一项工作：函数和它的参数
+#[derive(Clone, Copy)]
+pub struct WorkItem {
+    func: fn(usize),
+    arg: usize,
+}
+
+impl WorkItem {
+    pub const fn new(func: fn(usize), arg: usize) -> Self {
+        Self { func, arg }
+    }
+
+    fn run(self) {
+        (self.func)(self.arg)
+    }
+}
+
+static QUEUE: OnceCell<ArrayQueue<WorkItem>> = OnceCell::uninit();
+/// 因为队列满而丢弃的工作数
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// 分配队列，在堆可用之后、注册会放入工作的中断处理程序之前调用，中断里不能分配内存
+pub fn init() {
+    QUEUE.init_once(|| ArrayQueue::new(CAPACITY));
+}
+
+/// 放入一项工作，可以在中断里调用；队列满或者还没有初始化时丢弃，返回是否放入
+pub fn push(item: WorkItem) -> bool {
+    push_to(QUEUE.try_get().ok(), &DROPPED, item)
+}
+
+fn push_to(queue: Option<&ArrayQueue<WorkItem>>, dropped: &AtomicUsize, item: WorkItem) -> bool {
+    match queue {
+        Some(queue) if queue.push(item).is_ok() => true,
+        _ => {
+            dropped.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+}
+
+/// 执行队列中的工作，只能在非中断上下文中调用
+///
+/// 最多执行一轮队列容量那么多项，执行期间中断新放入的工作留到下次
+pub fn run_pending() {
+    if let Ok(queue) = QUEUE.try_get() {
+        run_from(queue);
+    }
+}
+
+fn run_from(queue: &ArrayQueue<WorkItem>) {
+    for _ in 0..CAPACITY {
+        match queue.pop() {
+            Some(item) => item.run(),
+            None => break,
+        }
+    }
+}
+
+/// 开机以来丢弃的工作数
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod test {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use crossbeam::queue::ArrayQueue;
+
+    use super::{push_to, run_from, WorkItem, CAPACITY};
+
+    static SUM: AtomicUsize = AtomicUsize::new(0);
+
+    fn add(arg: usize) {
+        SUM.fetch_add(arg, Ordering::Relaxed);
+    }
+
+    #[test_case]
+    fn test_workqueue_overflow_drops() {
+        // 用自己的队列，时钟和键盘中断往全局队列里放的工作不会混进来
+        let queue = ArrayQueue::new(CAPACITY);
+        let dropped = AtomicUsize::new(0);
+        SUM.store(0, Ordering::Relaxed);
+        // 正常负载：一个不丢
+        for _ in 0..CAPACITY {
+            assert!(push_to(Some(&queue), &dropped, WorkItem::new(add, 1)));
+        }
+        run_from(&queue);
+        assert_eq!(SUM.load(Ordering::Relaxed), CAPACITY);
+        assert_eq!(dropped.load(Ordering::Relaxed), 0);
+
+        // 远超容量：多出来的被丢弃并计数，不会卡住
+        SUM.store(0, Ordering::Relaxed);
+        for _ in 0..CAPACITY + 10 {
+            push_to(Some(&queue), &dropped, WorkItem::new(add, 1));
+        }
+        run_from(&queue);
+        assert_eq!(SUM.load(Ordering::Relaxed), CAPACITY);
+        assert_eq!(dropped.load(Ordering::Relaxed), 10);
+
+        // 还没有初始化的队列
+        assert!(!push_to(None, &dropped, WorkItem::new(add, 1)));
+        assert_eq!(dropped.load(Ordering::Relaxed), 11);
+        println!("[ok]  workqueue overflow drops");
+    }
+}
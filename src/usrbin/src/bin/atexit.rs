@@ -0,0 +1,25 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use cinea_os_sysapi::{allocator, entry_point, syscall};
+use cinea_os_userspace::print;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+fn flush() {
+    print!("flushed\n");
+}
+
+/// 注册退出处理函数后直接返回，`flushed`应当恰好出现一次
+fn main(_args: &[&str]) {
+    if let Err(code) = syscall::atexit(flush) {
+        print!("atexit: register failed ({})\n", code as usize);
+        return;
+    }
+    print!("atexit: exiting\n");
+}
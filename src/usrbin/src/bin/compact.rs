@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::alloc::Layout;
+
+use cinea_os_sysapi::allocator::{MovableHandle, MovableHeap};
+use cinea_os_sysapi::syscall::meminfo;
+use cinea_os_sysapi::{allocator, entry_point};
+use cinea_os_userspace::print;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+/// 把堆弄碎再压缩：检查区块搬动之后内容没变，堆结尾空出来的整页还给了系统
+fn main(args: &[&str]) {
+    let count = args.get(0).and_then(|arg| usize::from_str_radix(arg, 10).ok()).unwrap_or(1024);
+    let mut heap = MovableHeap::new();
+    let mut blocks: Vec<(MovableHandle, u8)> = Vec::new();
+    for i in 0..count {
+        let layout = Layout::from_size_align(1000 + i % 7 * 300, 8 << (i % 4)).unwrap();
+        let Some(handle) = heap.alloc(layout) else {
+            print!("compact: out of memory after {} blocks\n", i);
+            return;
+        };
+        heap.bytes_mut(handle).unwrap().fill(i as u8);
+        blocks.push((handle, i as u8));
+    }
+    // 每三个放掉两个，留下满堆的洞
+    let mut kept = Vec::new();
+    for (i, (handle, tag)) in blocks.into_iter().enumerate() {
+        if i % 3 == 0 {
+            kept.push((handle, tag));
+        } else {
+            heap.free(handle);
+        }
+    }
+
+    let before = meminfo().free;
+    let Some(result) = heap.compact() else {
+        print!("compact: the kernel refused to compact\n");
+        return;
+    };
+    let after = meminfo().free;
+    print!("compact: moved {} bytes, released {} bytes, free memory {} -> {}\n", result.moved, result.released, before, after);
+
+    let broken = kept.iter().filter(|(handle, tag)| heap.bytes(*handle).map_or(true, |b| b.iter().any(|x| x != tag))).count();
+    if broken > 0 {
+        print!("compact: FAILED, {} blocks lost their contents\n", broken);
+    } else if result.released == 0 {
+        print!("compact: FAILED, no tail pages were unmapped\n");
+    } else {
+        print!("compact: ok\n");
+    }
+}
@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use cinea_os_sysapi::syscall::{self, set_fault_handler};
+use cinea_os_sysapi::{allocator, entry_point, ExitCode};
+use cinea_os_userspace::print;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+// 故意崩溃的调用链，用来检查内核的崩溃报告能不能解析出三层调用栈
+
+#[inline(never)]
+fn level_three(ptr: *mut u64) {
+    unsafe { core::ptr::write_volatile(ptr, 0xdead) };
+}
+
+#[inline(never)]
+fn level_two(ptr: *mut u64) {
+    level_three(ptr);
+}
+
+#[inline(never)]
+fn level_one(ptr: *mut u64) {
+    level_two(ptr);
+}
+
+/// 每层在栈上占1KiB，一直递归到栈溢出，栈在途中按需长大
+#[inline(never)]
+#[allow(unconditional_recursion)]
+fn recurse(depth: usize) -> usize {
+    let frame = core::hint::black_box([depth as u8; 1024]);
+    if depth % 1024 == 0 {
+        let stack = syscall::stack_size();
+        print!("crash: depth {}, stack {} of {} bytes\n", depth, stack.current, stack.max);
+    }
+    recurse(depth + 1) + frame[0] as usize
+}
+
+extern "sysv64" fn on_fault(fault_addr: usize, rip: usize) -> ! {
+    print!("crash: caught fault at {:#x} (rip {:#x}), exiting cleanly\n", fault_addr, rip);
+    syscall::exit(ExitCode::Failure)
+}
+
+/// `crash handle` 先注册异常处理函数，进程应当打印一行后自己退出，而不是被杀死；
+/// `crash stack` 无限递归，应当以栈溢出的报告结束
+fn main(args: &[&str]) {
+    if args.get(0) == Some(&"stack") {
+        recurse(0);
+        return;
+    }
+    if args.get(0) == Some(&"handle") && set_fault_handler(Some(on_fault)).is_err() {
+        print!("crash: cannot register the fault handler\n");
+        return;
+    }
+    level_one(core::ptr::null_mut());
+}
@@ -0,0 +1,25 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use cinea_os_sysapi::{allocator, entry_point, syscall};
+use cinea_os_userspace::print;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+/// 显示物理内存和内核堆的使用情况（KB）
+fn main(_args: &[&str]) {
+    let info = syscall::meminfo();
+    print!("\ttotal\tused\tfree\n");
+    print!("Mem:\t{}\t{}\t{}\n", info.total >> 10, (info.total - info.free) >> 10, info.free >> 10);
+    print!(
+        "Heap:\t{}\t{}\t{}\n",
+        info.heap_total >> 10,
+        info.heap_used >> 10,
+        (info.heap_total - info.heap_used) >> 10
+    );
+}
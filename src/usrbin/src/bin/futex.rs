@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use cinea_os_sysapi::event::{futex_wait, futex_wake, sleep, FUTEX_AGAIN};
+use cinea_os_sysapi::fs::spawn_from_path;
+use cinea_os_sysapi::sync::FutexMutex;
+use cinea_os_sysapi::vdso::{VdsoData, VDSO_ADDR};
+use cinea_os_sysapi::{allocator, entry_point, syscall};
+use cinea_os_userspace::print;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+const ROUNDS: u32 = 10000;
+
+/// 子进程找等待者的次数，每次间隔10ms
+const WAKE_TRIES: usize = 100;
+
+static COUNTER: FutexMutex<u32> = FutexMutex::new(0);
+
+/// 检查futex系统调用和`FutexMutex`
+///
+/// `futex` 先在一个进程里检查不会挂起的路径，再启动`futex wake`，自己挂起等它唤醒
+fn main(args: &[&str]) {
+    if args.get(0) == Some(&"wake") {
+        wake();
+        return;
+    }
+    let word = AtomicU32::new(1);
+    // 值已经变了，不应当挂起
+    let again = futex_wait(&word, 0) == FUTEX_AGAIN;
+    // 没有人在等
+    let nobody = futex_wake(&word, 1) == 0;
+
+    for _ in 0..ROUNDS {
+        *COUNTER.lock() += 1;
+    }
+    let counted = *COUNTER.lock() == ROUNDS;
+
+    let woken = match shared_word() {
+        Some(word) => spawn_from_path("/bin/futex", vec![String::from("wake")]) && futex_wait(word, word.load(Ordering::Relaxed)) == 0,
+        None => {
+            print!("futex: no vDSO page, skipping the parent/child test\n");
+            true
+        }
+    };
+
+    if again && nobody && counted && woken {
+        print!("futex: ok\n");
+    } else {
+        print!("futex: FAILED (again {}, nobody {}, counted {}, woken {})\n", again, nobody, counted, woken);
+    }
+}
+
+/// 进程之间还没有可写的共享内存，借用vDSO页上`version`的低32位：每个进程映射的是同一个物理页，值也不会变
+fn shared_word() -> Option<&'static AtomicU32> {
+    match syscall::image_info(None) {
+        Ok(Some(info)) if info.vdso => {
+            let addr = VDSO_ADDR as usize + core::mem::offset_of!(VdsoData, version);
+            Some(unsafe { &*(addr as *const AtomicU32) })
+        }
+        _ => None,
+    }
+}
+
+/// 子进程：父进程可能还没来得及挂起，唤醒到一个为止
+fn wake() {
+    let word = match shared_word() {
+        Some(word) => word,
+        None => return,
+    };
+    for _ in 0..WAKE_TRIES {
+        if futex_wake(word, 1) == 1 {
+            return;
+        }
+        sleep(10);
+    }
+    print!("futex wake: nobody was waiting\n");
+}
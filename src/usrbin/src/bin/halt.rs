@@ -0,0 +1,18 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use cinea_os_sysapi::{allocator, entry_point, syscall};
+use cinea_os_userspace::print;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+/// halt：收尾后关机，只有失败时才会回来
+fn main(_args: &[&str]) {
+    let code = syscall::shutdown();
+    print!("halt: failed ({})\n", code as usize);
+}
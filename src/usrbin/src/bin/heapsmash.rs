@@ -0,0 +1,26 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use cinea_os_sysapi::{allocator, entry_point};
+use cinea_os_userspace::print;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+/// 故意在分配的区块后面多写一个字节，用来检查内核的`heap_debug`模式能否在释放时发现
+///
+/// 内核没有打开`heap_debug`时这个越界写不会被发现，程序会正常结束
+fn main(_args: &[&str]) {
+    let mut buf: Vec<u8> = Vec::with_capacity(16);
+    let ptr = buf.as_mut_ptr();
+    print!("heapsmash: writing one byte past the block at {}\n", ptr as usize);
+    unsafe { core::ptr::write_volatile(ptr.add(16), 0x42) };
+    drop(buf);
+    print!("heapsmash: the overflow was not caught\n");
+}
@@ -0,0 +1,37 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use cinea_os_sysapi::keyboard::{read_key, set_raw, KeyFlags};
+use cinea_os_sysapi::{allocator, entry_point};
+use cinea_os_userspace::print;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+/// Esc的扫描码
+const ESCAPE: u16 = 0x01;
+
+/// 原始模式的演示：打印每一次按下和松开，按Esc退出
+fn main(_args: &[&str]) {
+    if set_raw(true).is_err() {
+        print!("keys: cannot take the keyboard, run me in the foreground\n");
+        return;
+    }
+    print!("keys: press Esc to quit\n");
+    while let Ok(key) = read_key(KeyFlags::empty()) {
+        let action = if key.pressed { "down" } else { "up" };
+        print!("scancode {} {} keycode {} modifiers {}", key.scancode, action, key.keycode, key.modifiers.bits());
+        if let Some(ch) = key.ch.filter(|ch| !ch.is_control()) {
+            print!(" '{}'", ch);
+        }
+        print!("\n");
+        if key.scancode == ESCAPE && !key.pressed {
+            break;
+        }
+    }
+    let _ = set_raw(false);
+}
@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec;
+
+use cinea_os_sysapi::fs::run_with_limits;
+use cinea_os_sysapi::syscall::{Limits, SpawnFlags};
+use cinea_os_sysapi::{allocator, entry_point, ExitCode};
+use cinea_os_userspace::print;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+/// 子进程的CPU限额（tick）
+const CPU_LIMIT: usize = 100;
+
+/// 验证创建时的CPU限额：启动一个死循环的子进程，它应当在用完限额后被自动回收，而本进程继续运行
+///
+/// `limits spin` 是那个死循环
+fn main(args: &[&str]) {
+    if args.get(0) == Some(&"spin") {
+        loop {
+            core::hint::spin_loop();
+        }
+    }
+
+    let limits = Limits { max_cpu_ticks: Some(CPU_LIMIT), ..Limits::default() };
+    match run_with_limits("/bin/limits", vec![String::from("spin")], SpawnFlags::empty(), Some(limits)) {
+        ExitCode::LimitExceeded => print!("[ok]  limits: child reaped after using up its CPU limit\n"),
+        ExitCode::ExecError => print!("limits: cannot spawn the child\n"),
+        code => print!("[failed] limits: child exited with code {}\n", code as u8),
+    }
+}
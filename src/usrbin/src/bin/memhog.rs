@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec;
+
+use ufmt::uwrite;
+
+use cinea_os_sysapi::{allocator, entry_point, syscall::log};
+use cinea_os_userspace::std::StringWriter;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+/// 不停地申请内存且从不释放，用来测试内存耗尽时的OOM处理
+fn main(args: &[&str]) {
+    let mut strout = StringWriter::new();
+    let chunk_mb = args.get(0).and_then(|arg| usize::from_str_radix(arg, 10).ok()).unwrap_or(1);
+    let mut total = 0;
+    loop {
+        let chunk = vec![0xAAu8; chunk_mb << 20];
+        core::mem::forget(chunk);
+        total += chunk_mb;
+        uwrite!(strout, "memhog: {} MB allocated\n", total).unwrap();
+        log(strout.value().as_bytes());
+        strout.clear();
+        cinea_os_sysapi::event::sleep(100);
+    }
+}
@@ -0,0 +1,68 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+
+use cinea_os_sysapi::envelope::{EnvelopeError, STATUS_PERMISSION};
+use cinea_os_sysapi::syscall::{MapFlags, MapRange, MAX_PAGEMAP_RANGES};
+use cinea_os_sysapi::{allocator, entry_point, syscall};
+use cinea_os_userspace::print;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+/// 像`/proc/<pid>/maps`那样列出进程用户态访问得到的页面映射，`pmap <pid>`，仅限root
+fn main(args: &[&str]) {
+    let pid = match args.get(0).map(|n| n.parse::<usize>()) {
+        Some(Ok(pid)) => pid,
+        _ => {
+            print!("usage: pmap <pid>\n");
+            return;
+        }
+    };
+    let ranges = match syscall::pagemap(pid) {
+        Ok(Some(ranges)) => ranges,
+        Ok(None) => {
+            print!("pmap: no process {}\n", pid);
+            return;
+        }
+        Err(EnvelopeError::Status(STATUS_PERMISSION)) => {
+            print!("pmap: only root can read page maps\n");
+            return;
+        }
+        Err(_) => {
+            print!("pmap: cannot read the page map of {}\n", pid);
+            return;
+        }
+    };
+    let mut total = 0;
+    for range in ranges.iter() {
+        print!("{}\n", line(range).as_str());
+        total += range.end - range.start;
+    }
+    if ranges.len() >= MAX_PAGEMAP_RANGES {
+        print!("... stopped after {} ranges\n", MAX_PAGEMAP_RANGES);
+    }
+    print!("{} ranges, {}K mapped\n", ranges.len(), total / 1024);
+}
+
+/// 一段映射：地址范围、权限（读、写、执行、用户态u或仅内核k）、大页标记H和大小，ufmt不支持宽度，用core的格式化
+fn line(range: &MapRange) -> String {
+    let flag = |on: bool, c: char| if on { c } else { '-' };
+    format!(
+        "{:016x}-{:016x} {}{}{}{}{} {:>8}K",
+        range.start,
+        range.end,
+        flag(range.flags.contains(MapFlags::PRESENT), 'r'),
+        flag(range.flags.contains(MapFlags::WRITABLE), 'w'),
+        flag(!range.flags.contains(MapFlags::NO_EXECUTE), 'x'),
+        if range.flags.contains(MapFlags::USER) { 'u' } else { 'k' },
+        flag(range.flags.contains(MapFlags::HUGE), 'H'),
+        (range.end - range.start) / 1024
+    )
+}
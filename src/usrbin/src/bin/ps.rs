@@ -0,0 +1,27 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use cinea_os_sysapi::{allocator, entry_point, syscall};
+use cinea_os_userspace::print;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+/// 列出所有进程：PID、进程名和启动参数
+fn main(_args: &[&str]) {
+    let procs = match syscall::proc_list() {
+        Ok(procs) => procs,
+        Err(_) => {
+            print!("ps: cannot list processes\n");
+            return;
+        }
+    };
+    print!("pid\tname\tcmdline\n");
+    for proc in procs {
+        print!("{}\t{}\t{}\n", proc.pid, proc.name.as_str(), proc.cmdline.join(" ").as_str());
+    }
+}
@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec;
+
+use cinea_os_sysapi::fs::spawn_from_path;
+use cinea_os_sysapi::vdso::fast_now_ns;
+use cinea_os_sysapi::{allocator, entry_point, syscall};
+use cinea_os_userspace::print;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+/// 每轮测试中忙等进程运行的时间
+const SPIN_MS: u64 = 1000;
+
+/// 比较不同时间片长度下的进程切换次数
+///
+/// `quantum` 依次用1ms和50ms各跑一轮；`quantum <ms>` 只设置时间片长度
+fn main(args: &[&str]) {
+    match args.get(0) {
+        Some(&"spin") => spin(),
+        Some(ms) => match ms.parse::<usize>() {
+            Ok(ms) => set(ms),
+            Err(_) => print!("usage: quantum [ms]\n"),
+        },
+        None => {
+            let old = syscall::irqstat().quantum_ms;
+            bench(1);
+            bench(50);
+            set(old);
+        }
+    }
+}
+
+fn set(ms: usize) {
+    if let Err(code) = syscall::set_quantum(ms) {
+        print!("quantum: cannot set {}ms ({})\n", ms, code as usize);
+    }
+}
+
+/// 两个CPU密集的进程同时跑，统计这段时间里的进程切换次数
+fn bench(ms: usize) {
+    set(ms);
+    let before = syscall::irqstat();
+    for _ in 0..2 {
+        spawn_from_path("/bin/quantum", vec![String::from("spin")]);
+    }
+    syscall::sleep((SPIN_MS + 200) as f64 / 1000.0);
+    let after = syscall::irqstat();
+    print!(
+        "quantum {}ms: {} context switches, {} timer irqs\n",
+        after.quantum_ms,
+        after.context_switches - before.context_switches,
+        after.irqs[0] - before.irqs[0]
+    );
+}
+
+/// 忙等`SPIN_MS`毫秒后退出，并报告自己用掉的时间片
+fn spin() {
+    let end = fast_now_ns() + SPIN_MS * 1_000_000;
+    while fast_now_ns() < end {}
+    let usage = syscall::rusage();
+    print!(
+        "spin: {} ticks in {} slices, preempted {} times\n",
+        usage.cpu_ticks, usage.slices, usage.involuntary_switches
+    );
+}
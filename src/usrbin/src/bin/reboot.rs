@@ -0,0 +1,18 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use cinea_os_sysapi::{allocator, entry_point, syscall};
+use cinea_os_userspace::print;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+/// reboot：收尾后重启，只有失败时才会回来
+fn main(_args: &[&str]) {
+    let code = syscall::reboot();
+    print!("reboot: failed ({})\n", code as usize);
+}
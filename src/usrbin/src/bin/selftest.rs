@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use cinea_os_sysapi::vdso::fast_now_ns;
+use cinea_os_sysapi::{allocator, entry_point, syscall};
+use cinea_os_userspace::print;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+/// 让内核跑一遍自检，不用重新编译内核就能验证构建
+fn main(_args: &[&str]) {
+    match syscall::selftest() {
+        Ok(()) => print!("selftest: passed\n"),
+        Err(code) => print!("selftest: failed ({})\n", code as usize),
+    }
+    bench_time();
+}
+
+const BENCH_CALLS: u64 = 1_000_000;
+
+/// 比较vDSO读时间和系统调用读时间的开销，同时检查vDSO时间单调
+fn bench_time() {
+    let start = fast_now_ns();
+    let mut last = start;
+    let mut monotonic = true;
+    for _ in 0..BENCH_CALLS {
+        let now = fast_now_ns();
+        monotonic &= now >= last;
+        last = now;
+    }
+    let vdso_ns = fast_now_ns() - start;
+
+    let start = fast_now_ns();
+    for _ in 0..BENCH_CALLS {
+        syscall::uptime_ns();
+    }
+    let syscall_ns = fast_now_ns() - start;
+
+    print!("bench: fast_now_ns {} ns/call, uptime syscall {} ns/call\n", vdso_ns / BENCH_CALLS, syscall_ns / BENCH_CALLS);
+    if !monotonic {
+        print!("bench: fast_now_ns went backwards\n");
+    }
+}
@@ -8,9 +8,9 @@ use alloc::vec::Vec;
 use core::ops::Add;
 
 use cinea_os_sysapi::{allocator, entry_point};
-use cinea_os_sysapi::fs::spawn_from_path;
+use cinea_os_sysapi::fs::spawn_from_path_with_flags;
 use cinea_os_sysapi::stdin::get_line_string;
-use cinea_os_sysapi::syscall::spawn;
+use cinea_os_sysapi::syscall::{flush, spawn, SpawnFlags};
 use cinea_os_userspace::print;
 
 use crate::ResolveError::BrokenQuote;
@@ -109,6 +109,8 @@ fn main(_args: &[&str]) {
 
     loop {
         print!("{} $ ", nowdir.as_str());
+        // 提示符没有换行，不刷新的话要等到读键盘时才显示
+        flush();
 
         let cmd = get_line_string(false);
         match resolve_command(cmd.as_str()) {
@@ -126,7 +128,9 @@ fn main(_args: &[&str]) {
                 }
                 print!("\n-------------------\n");
                 let exec_path = String::from("/bin/").add(resolved[0].as_str());
-                if !spawn_from_path(exec_path.as_str(), resolved.as_slice()[1..].iter().cloned().collect()) {
+                // 子进程自成一组并占据前台，退出后前台自动还给shell
+                let args = resolved.as_slice()[1..].iter().cloned().collect();
+                if !spawn_from_path_with_flags(exec_path.as_str(), args, SpawnFlags::FOREGROUND) {
                     print!("程序\"{}\"没有找到", resolved[0].as_str());
                 }
             }
@@ -0,0 +1,25 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use cinea_os_sysapi::{allocator, entry_point, syscall};
+use cinea_os_userspace::print;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+/// shutdown：关机；shutdown -r：重启
+fn main(args: &[&str]) {
+    let code = match args.get(0) {
+        None => syscall::shutdown(),
+        Some(&"-r") => syscall::reboot(),
+        Some(_) => {
+            print!("usage: shutdown [-r]\n");
+            return;
+        }
+    };
+    print!("shutdown: failed ({})\n", code as usize);
+}
@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+
+use cinea_os_sysapi::syscall::SysInfo;
+use cinea_os_sysapi::{allocator, entry_point, event, syscall};
+use cinea_os_userspace::print;
+
+entry_point!(main);
+
+#[global_allocator]
+static ALLOCATOR: allocator::UserProcAllocator = allocator::UserProcAllocator;
+
+/// 系统负载和进程列表，`top [次数]` 每秒刷新一次，默认只显示一次
+fn main(args: &[&str]) {
+    let rounds = match args.get(0).map(|n| n.parse::<usize>()) {
+        None => 1,
+        Some(Ok(n)) if n > 0 => n,
+        Some(_) => {
+            print!("usage: top [rounds]\n");
+            return;
+        }
+    };
+    for round in 0..rounds {
+        if round > 0 && !event::sleep(1000) {
+            return;
+        }
+        show();
+    }
+}
+
+fn show() {
+    let info = syscall::sysinfo();
+    print!(
+        "up {}s, {} procs, load average: {} {} {}\n",
+        info.uptime_ns / 1_000_000_000,
+        info.procs,
+        load(&info, 0).as_str(),
+        load(&info, 1).as_str(),
+        load(&info, 2).as_str()
+    );
+    print!("cpu: {}% user, {}% kernel, {}% idle\n", info.user_percent, info.kernel_percent, info.idle_percent);
+    print!("frames: {} free of {}\n\n", info.free_frames, info.total_frames);
+
+    let procs = match syscall::proc_list() {
+        Ok(procs) => procs,
+        Err(_) => {
+            print!("top: cannot list processes\n");
+            return;
+        }
+    };
+    print!("pid\tppid\tname\n");
+    for proc in procs {
+        print!("{}\t{}\t{}\n", proc.pid, proc.parent, proc.name.as_str());
+    }
+}
+
+/// 负载格式化成两位小数，ufmt不支持宽度，用core的格式化
+fn load(info: &SysInfo, i: usize) -> String {
+    let (whole, hundredths) = info.load(i);
+    format!("{}.{:02}", whole, hundredths)
+}
@@ -1,7 +1,12 @@
 use core::convert::Infallible;
 use alloc::string::String;
+use alloc::vec::Vec;
+use cinea_os_sysapi::fs::{write_vectored, MAX_IOV};
 use ufmt::uWrite;
 
+/// 标准输出的句柄
+const STDOUT: usize = 0;
+
 pub struct StdWriter;
 
 impl uWrite for StdWriter {
@@ -13,6 +18,37 @@ impl uWrite for StdWriter {
     }
 }
 
+/// 先收集格式化出的各段，`flush`时用一次WRITEV全部写出，`print!`用它
+pub struct VectoredWriter {
+    segments: Vec<String>,
+}
+
+impl uWrite for VectoredWriter {
+    type Error = Infallible;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        if !s.is_empty() {
+            self.segments.push(String::from(s));
+        }
+        Ok(())
+    }
+}
+
+impl VectoredWriter {
+    pub fn new() -> Self {
+        Self { segments: Vec::new() }
+    }
+
+    /// 写出收集到的所有段，段数超过上限时分几次写
+    pub fn flush(&mut self) {
+        for chunk in self.segments.chunks(MAX_IOV) {
+            let bufs: Vec<&[u8]> = chunk.iter().map(|segment| segment.as_bytes()).collect();
+            let _ = write_vectored(STDOUT, bufs.as_slice());
+        }
+        self.segments.clear();
+    }
+}
+
 pub struct StringWriter{
     value: String
 }
@@ -43,8 +79,9 @@ impl StringWriter {
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => ({
-        let mut std_writer = $crate::std::StdWriter;
+        let mut std_writer = $crate::std::VectoredWriter::new();
         ufmt::uwrite!(std_writer, $($arg)*).unwrap();
+        std_writer.flush();
     })
 }
 
@@ -3,4 +3,4 @@ pub mod io;
 
 pub mod fs;
 
-pub use io::{StdWriter, StringWriter};
+pub use io::{StdWriter, StringWriter, VectoredWriter};
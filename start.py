@@ -3,6 +3,8 @@ import sys
 
 FS = "datadisk.img"
 FS_SOURCE = "dsk"
+INITRAMFS = "initramfs.tar"
+INITRAMFS_DIRS = ["bin", "etc"]
 ALWAYS_FETCH_TOOLS = False
 ALWAYS_RECOMPILE_TOOLS = False
 ALWAYS_RECOMPILE = False
@@ -12,8 +14,11 @@ if sys.version_info.major < 3:
     os.system("python3 " + __file__)
     exit(0)
 
+import io
 import shutil
 import platform
+import struct
+import tarfile
 
 
 def get_latest_modified_time(directory):
@@ -29,6 +34,36 @@ def get_latest_modified_time(directory):
     return latest_time
 
 
+def strip_to_symbols(elf):
+    """只保留ELF64的符号表和字符串表，内核用它们把崩溃地址解析成函数名"""
+    if elf[:4] != b"\x7fELF" or elf[4] != 2 or elf[5] != 1:
+        return None
+    e_shoff, = struct.unpack_from("<Q", elf, 0x28)
+    e_shentsize, e_shnum = struct.unpack_from("<HH", elf, 0x3A)
+    sections = [struct.unpack_from("<IIQQQQIIQQ", elf, e_shoff + i * e_shentsize) for i in range(e_shnum)]
+    symtab = next((sh for sh in sections if sh[1] == 2), None)  # SHT_SYMTAB
+    if symtab is None:
+        return None
+    strtab = sections[symtab[6]]
+
+    shstrtab = b"\0.symtab\0.strtab\0.shstrtab\0"
+    body = bytearray()
+    headers = [(0, 0, 0, 0, 0, 0, 0, 0, 0, 0)]
+    for name, sh, link in ((1, symtab, 2), (9, strtab, 0)):
+        offset = 64 + len(body)
+        body += elf[sh[4]:sh[4] + sh[5]]
+        body += b"\0" * (-len(body) % 8)
+        headers.append((name, sh[1], 0, 0, offset, sh[5], link, sh[7], sh[8], sh[9]))
+    headers.append((17, 3, 0, 0, 64 + len(body), len(shstrtab), 0, 0, 1, 0))
+    body += shstrtab
+    body += b"\0" * (-len(body) % 8)
+
+    header = bytearray(elf[:64])
+    struct.pack_into("<QQQ", header, 0x18, 0, 0, 64 + len(body))  # e_entry, e_phoff, e_shoff
+    struct.pack_into("<HHHHH", header, 0x36, 0, 0, 64, len(headers), len(headers) - 1)
+    return bytes(header + body + b"".join(struct.pack("<IIQQQQIIQQ", *sh) for sh in headers))
+
+
 PWD = os.path.dirname(os.path.abspath(__file__))
 EXE_PREFIX = "" if platform.system() == "Windows" else "./"
 EXE_SUFFIX = ".exe" if platform.system() == "Windows" else ""
@@ -39,6 +74,9 @@ if len(argv) < 2:
     exit(1)
 
 BOOT_IMAGE = argv[1]
+# bootimage把内核ELF放在启动镜像旁边，去掉前缀和后缀就是它的名字
+KERNEL_ELF = os.path.join(os.path.dirname(BOOT_IMAGE),
+                          os.path.basename(BOOT_IMAGE).removeprefix("bootimage-").removesuffix(".bin"))
 
 print("Checking need for compile FS Helper...")
 FS_COMPLIER = EXE_PREFIX + "fs-compiler" + EXE_SUFFIX
@@ -77,8 +115,29 @@ if ALWAYS_RECOMPILE or re_compile:
 else:
     print("File System is already newest.")
 
+print("Packing the initramfs...")
+# 用户程序放在initramfs里，更换它们只需要重新打包，不需要重新编译内核
+with tarfile.open(INITRAMFS, "w", format=tarfile.USTAR_FORMAT) as archive:
+    for directory in INITRAMFS_DIRS:
+        source = os.path.join(FS_SOURCE, directory)
+        if os.path.isdir(source):
+            archive.add(source, arcname=directory)
+    # 完整的内核ELF带着调试信息，太大了，只放符号表
+    symbols = None
+    if os.path.isfile(KERNEL_ELF):
+        with open(KERNEL_ELF, "rb") as kernel:
+            symbols = strip_to_symbols(kernel.read())
+    if symbols is not None:
+        info = tarfile.TarInfo("boot/kernel")
+        info.size = len(symbols)
+        archive.addfile(info, io.BytesIO(symbols))
+    else:
+        print("Kernel symbols not found, panics will show raw addresses.")
+
 print("Starting QEMU...", flush=True)
 os.system(f"qemu-system-x86_64 -drive format=raw,file={BOOT_IMAGE} -serial \
           stdio -m 1G -monitor telnet:localhost:4444,server,nowait \
           -drive id=data_disk,format=raw,file=datadisk.img,if=none \
-          -device ahci,id=ahci -device ide-hd,drive=data_disk,bus=ahci.0")
+          -drive id=initramfs,format=raw,file={INITRAMFS},if=none,readonly=on \
+          -device ahci,id=ahci -device ide-hd,drive=data_disk,bus=ahci.0 \
+          -device ide-hd,drive=initramfs,bus=ahci.1")